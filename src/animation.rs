@@ -0,0 +1,199 @@
+// Pieces sliding to where a move takes them instead of jumping there: the piece moved, from its
+// square or from its hand, and the piece it took, on its way to the taker's hand. Only where they
+// are at a time here; the renderer draws them over the board, which hides them where they land.
+
+use crate::layout::{reserve_origin, square_origin};
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::time::{Duration, Instant};
+
+/// where a piece slides from or to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Square(Position),
+    /// a piece of a hand, and its rank among the identical ones (the last is drawn on top)
+    Hand(Piece, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slide {
+    /// the piece as drawn while it moves
+    pub piece: Piece,
+    pub from: Place,
+    pub to: Place,
+    /// the piece of the board after the move, hidden until the slide is over
+    pub landed: Piece,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    slides: Vec<Slide>,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Animation {
+    /// the slides of a move played from `before`, which gave `after`
+    pub fn of_move(
+        before: &Board,
+        after: &Board,
+        mv: &str,
+        duration: Duration,
+    ) -> Result<Animation, String> {
+        let movement = sfen::parse_movement(mv)?;
+        let color = before.get_color();
+        let landed = after
+            .is_occupied_by(movement.end)
+            .ok_or_else(|| format!("no piece where {} lands", mv))?;
+        let mut slides = Vec::new();
+        let (piece, from) = match movement.start {
+            Some(start) => {
+                let piece = before
+                    .is_occupied_by(start)
+                    .ok_or_else(|| format!("no piece where {} starts", mv))?;
+                (piece, Place::Square(start))
+            }
+            None => {
+                let piece = in_hand(color, movement.piecetype);
+                let count = sfen::hand_count(before, color, movement.piecetype);
+                (piece, Place::Hand(piece, count.saturating_sub(1)))
+            }
+        };
+        slides.push(Slide {
+            piece,
+            from,
+            to: Place::Square(movement.end),
+            landed,
+        });
+        if let Some(taken) = before.is_occupied_by(movement.end) {
+            let piece = in_hand(color, taken.piecetype);
+            let count = sfen::hand_count(after, color, taken.piecetype);
+            slides.push(Slide {
+                piece,
+                from: Place::Square(movement.end),
+                to: Place::Hand(piece, count.saturating_sub(1)),
+                landed: piece,
+            });
+        }
+        Ok(Animation {
+            slides,
+            start: Instant::now(),
+            duration,
+        })
+    }
+
+    pub fn done(&self) -> bool {
+        self.start.elapsed() >= self.duration
+    }
+
+    /// the pieces of the board still on their way, not to be drawn where they land
+    pub fn hidden(&self) -> Vec<Piece> {
+        self.slides.iter().map(|slide| slide.landed).collect()
+    }
+
+    /// the pieces moving and where they are now (top left corner)
+    pub fn pieces(&self, flip: bool) -> Vec<(Piece, (i32, i32))> {
+        self.at(self.start.elapsed(), flip)
+    }
+
+    /// the pieces moving and where they are, `elapsed` after the start
+    pub fn at(&self, elapsed: Duration, flip: bool) -> Vec<(Piece, (i32, i32))> {
+        let t = if self.duration.as_secs_f32() > 0.0 {
+            (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+        } else {
+            1.0
+        };
+        // fast, then slowing down as it lands
+        let t = 1.0 - (1.0 - t).powi(3);
+        self.slides
+            .iter()
+            .map(|slide| {
+                let (x0, y0) = origin(slide.from, flip);
+                let (x1, y1) = origin(slide.to, flip);
+                let between = |a: i32, b: i32| a + ((b - a) as f32 * t).round() as i32;
+                (slide.piece, (between(x0, x1), between(y0, y1)))
+            })
+            .collect()
+    }
+}
+
+fn in_hand(color: Color, piecetype: PieceType) -> Piece {
+    Piece {
+        color,
+        piecetype,
+        promoted: false,
+        position: None,
+    }
+}
+
+fn origin(place: Place, flip: bool) -> (i32, i32) {
+    match place {
+        Place::Square(pos) => square_origin(pos, flip),
+        Place::Hand(piece, rank) => reserve_origin(&piece, rank, flip),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+    use crate::layout::SRC_RESERVE_HEIGTH;
+
+    fn square(usi: &str) -> Position {
+        sfen::parse_usi_square(usi).unwrap()
+    }
+
+    fn animate(sfen: &str, usi: &str) -> Animation {
+        let before = sfen::from_sfen(sfen).unwrap().0;
+        let mv = sfen::from_usi(usi, &before).unwrap();
+        let after = before.play_move_unchecked(&mv);
+        Animation::of_move(&before, &after, &mv, Duration::from_millis(200)).unwrap()
+    }
+
+    #[test]
+    fn pieces_slide_from_square_to_square() {
+        let animation = animate(&sfen::to_sfen(&Handicap::Even.board(), 1), "7g7f");
+        let from = square_origin(square("7g"), false);
+        let to = square_origin(square("7f"), false);
+        let start = animation.at(Duration::from_millis(0), false);
+        assert_eq!(start.len(), 1);
+        assert_eq!(start[0].1, from);
+        // half way in time, more than half way in space
+        let (_, (x, y)) = animation.at(Duration::from_millis(100), false)[0];
+        assert_eq!(x, from.0);
+        assert!(y > (from.1 + to.1) / 2 && y < to.1);
+        assert_eq!(animation.at(Duration::from_secs(1), false)[0].1, to);
+        assert_eq!(animation.hidden()[0].position, Some(square("7f")));
+    }
+
+    #[test]
+    fn captures_go_to_the_hand_of_the_taker() {
+        let animation = animate("4k4/9/4p4/9/9/9/9/4R4/4K4 b - 1", "5h5c+");
+        let end = animation.at(Duration::from_secs(1), false);
+        assert_eq!(end.len(), 2);
+        // the rook slides unpromoted, the pawn turns into sente's
+        assert!(!end[0].0.promoted);
+        assert!(animation.hidden()[0].promoted);
+        let pawn = in_hand(Color::White, PieceType::Pawn);
+        assert_eq!(end[1], (pawn, reserve_origin(&pawn, 0, false)));
+        // sente's hand is at the top
+        assert!(end[1].1 .1 < SRC_RESERVE_HEIGTH as i32);
+    }
+
+    #[test]
+    fn drops_come_from_the_hand() {
+        let animation = animate("4k4/9/9/9/9/9/9/9/4K4 b 2G 1", "G*5b");
+        let gold = in_hand(Color::White, PieceType::Gold);
+        let start = animation.at(Duration::from_millis(0), true);
+        // the second gold, on top of the first one
+        assert_eq!(start[0], (gold, reserve_origin(&gold, 1, true)));
+        assert_eq!(
+            animation.at(Duration::from_secs(1), true)[0].1,
+            square_origin(square("5b"), true)
+        );
+    }
+}
@@ -0,0 +1,53 @@
+// The pieces' sprites. Portable builds (`--features portable`) carry them inside the
+// executable and load them from memory, so that it can be run from anywhere; the others read them
+// from src/sprites, the game being started from the root of the repository.
+
+use sdl2::render::{Texture, TextureCreator};
+
+#[cfg(feature = "portable")]
+use sdl2::image::ImageRWops;
+#[cfg(not(feature = "portable"))]
+use sdl2::image::LoadTexture;
+#[cfg(feature = "portable")]
+use sdl2::rwops::RWops;
+
+#[cfg(not(feature = "portable"))]
+use std::path::Path;
+
+#[cfg(feature = "portable")]
+macro_rules! sprites {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_bytes!(concat!("sprites/", $name, ".png")) as &[u8])),*]
+    };
+}
+
+/// the sprites built in, by name
+#[cfg(feature = "portable")]
+const SPRITES: &[(&str, &[u8])] = sprites!(
+    "white/p", "white/pp", "white/l", "white/lp", "white/n", "white/np", "white/s", "white/sp",
+    "white/g", "white/b", "white/bp", "white/r", "white/rp", "white/k", "black/p", "black/pp",
+    "black/l", "black/lp", "black/n", "black/np", "black/s", "black/sp", "black/g", "black/b",
+    "black/bp", "black/r", "black/rp", "black/k",
+);
+
+/// the texture of a sprite, named after its file in src/sprites: "white/p", "black/rp"...
+pub fn load_sprite<'t, T>(
+    texture_creator: &'t TextureCreator<T>,
+    name: &str,
+) -> Result<Texture<'t>, String> {
+    #[cfg(feature = "portable")]
+    {
+        let bytes = SPRITES
+            .iter()
+            .find(|(sprite, _)| *sprite == name)
+            .map(|(_, bytes)| *bytes)
+            .ok_or_else(|| format!("no sprite {} in this build", name))?;
+        let rwops = RWops::from_bytes(bytes)?;
+        let surface = rwops.load_png()?;
+        texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "portable"))]
+    texture_creator.load_texture(Path::new(&format!("src/sprites/{}.png", name)))
+}
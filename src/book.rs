@@ -0,0 +1,255 @@
+// Opening books: the moves known in a position, each with a weight.
+//
+// Books are read from YaneuraOu's text format: a header line, then for every position a
+// "sfen <position>" line followed by one line per move, "<move> <ponder> <value> <depth> <count>"
+// (USI moves, "none" when there is no ponder move), the count being the weight:
+//
+//   #YANEURAOU-DB2016 1.00
+//   sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1
+//   7g7f none 0 0 12
+//   2g2f none 0 0 7
+//
+// They can also be built from a collection of KIF games, counting the moves played in the first
+// `BOOK_PLIES` plies of every game.
+
+use crate::kif;
+use crate::opponent::Opponent;
+use crate::record::GameRecord;
+use crate::rules;
+use crate::sfen;
+use crate::tsume;
+
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use shogai::board::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const HEADER: &str = "#YANEURAOU-DB2016 1.00";
+
+/// plies of every game counted when building a book from KIF files
+pub const BOOK_PLIES: usize = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookMove {
+    pub usi: String,
+    /// weight of the move: how often it was played, or how much the book likes it
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    /// by position (SFEN without the move number), the most frequent moves first
+    positions: HashMap<String, Vec<BookMove>>,
+}
+
+/// the position part of a SFEN: board, side to move and hands
+fn key(sfen: &str) -> String {
+    sfen.split_whitespace()
+        .take(3)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Book {
+    /// read a book in YaneuraOu's format
+    pub fn parse(text: &str) -> Result<Book, String> {
+        let mut book = Book::default();
+        let mut position: Option<String> = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(sfen) = line.strip_prefix("sfen ") {
+                sfen::from_sfen(sfen).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                position = Some(key(sfen));
+                continue;
+            }
+            let position = position
+                .as_ref()
+                .ok_or_else(|| format!("line {}: move before any position", i + 1))?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let count = match fields.get(4) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid count '{}'", i + 1, count))?,
+                None => 1,
+            };
+            book.add(position, fields[0], count);
+        }
+        book.sort();
+        Ok(book)
+    }
+
+    /// count the first `plies` moves of a game
+    pub fn add_record(&mut self, record: &GameRecord, plies: usize) {
+        let mut board = record.start.clone();
+        for mv in record.moves.iter().take(plies) {
+            if let Ok(usi) = sfen::to_usi(mv) {
+                self.add(&key(&sfen::to_sfen(&board, 1)), &usi, 1);
+            }
+            board = board.play_move_unchecked(mv);
+        }
+    }
+
+    fn add(&mut self, position: &str, usi: &str, count: u32) {
+        let moves = self.positions.entry(position.to_string()).or_default();
+        match moves.iter_mut().find(|m| m.usi == usi) {
+            Some(known) => known.count += count,
+            None => moves.push(BookMove {
+                usi: usi.to_string(),
+                count,
+            }),
+        }
+    }
+
+    fn sort(&mut self) {
+        for moves in self.positions.values_mut() {
+            moves.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.usi.cmp(&b.usi)));
+        }
+    }
+
+    /// read a book file, or build one from a KIF file or a directory of KIF files
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Book, String> {
+        let path = path.as_ref();
+        let files: Vec<PathBuf> = if path.is_dir() {
+            fs::read_dir(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| tsume::is_kif(path))
+                .collect()
+        } else if tsume::is_kif(path) {
+            vec![path.to_path_buf()]
+        } else {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            return Book::parse(&text).map_err(|e| format!("{}: {}", path.display(), e));
+        };
+        let mut book = Book::default();
+        for file in &files {
+            let record = kif::read_file(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+            book.add_record(&record, BOOK_PLIES);
+        }
+        book.sort();
+        Ok(book)
+    }
+
+    /// the book in YaneuraOu's format, positions sorted so that the same book gives the same text
+    pub fn write(&self) -> String {
+        let mut positions: Vec<&String> = self.positions.keys().collect();
+        positions.sort();
+        let mut text = format!("{}\n", HEADER);
+        for position in positions {
+            text.push_str(&format!("sfen {} 1\n", position));
+            for mv in &self.positions[position] {
+                text.push_str(&format!("{} none 0 0 {}\n", mv.usi, mv.count));
+            }
+        }
+        text
+    }
+
+    /// number of positions
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// the legal book moves of the position, in shogai notation, the most frequent first
+    pub fn moves(&self, board: &Board) -> Vec<(String, u32)> {
+        let moves = match self.positions.get(&key(&sfen::to_sfen(board, 1))) {
+            Some(moves) => moves,
+            None => return Vec::new(),
+        };
+        moves
+            .iter()
+            .filter_map(|m| {
+                let mv = sfen::from_usi(&m.usi, board).ok()?;
+                let mv = rules::normalize(board, &mv);
+                rules::is_legal(board, &mv).then_some((mv, m.count))
+            })
+            .collect()
+    }
+
+    /// a book move of the position, picked at random according to the weights
+    pub fn choose(&self, board: &Board) -> Option<String> {
+        self.moves(board)
+            .choose_weighted(&mut thread_rng(), |(_, count)| *count)
+            .ok()
+            .map(|(mv, _)| mv.clone())
+    }
+}
+
+/// an opponent playing from the book while it can, and thinking by itself afterwards
+pub struct BookPlayer {
+    book: Arc<Book>,
+    engine: Box<dyn Opponent>,
+}
+
+impl BookPlayer {
+    pub fn new(book: Arc<Book>, engine: Box<dyn Opponent>) -> Self {
+        BookPlayer { book, engine }
+    }
+}
+
+impl Opponent for BookPlayer {
+    fn name(&self) -> String {
+        self.engine.name()
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        self.book
+            .choose(board)
+            .or_else(|| self.engine.choose_move(board))
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.engine.set_time_budget(budget);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+
+    #[test]
+    fn read_build_and_write() {
+        let text = format!(
+            "{}\nsfen {}\n7g7f none 0 32 3\n2g2f 8c8d 0 32 9\n",
+            HEADER,
+            sfen::STARTPOS
+        );
+        let book = Book::parse(&text).unwrap();
+        let board = Board::new();
+        let moves = book.moves(&board);
+        assert_eq!(
+            moves,
+            vec![(String::from("P8c-8d"), 9), (String::from("P3c-3d"), 3)]
+        );
+        assert!(book.choose(&board).is_some());
+        assert_eq!(book.moves(&board.play_move_unchecked("P3c-3d")), Vec::new());
+
+        // the same moves counted from games
+        let mut built = Book::default();
+        for (usi, count) in [("7g7f", 3), ("2g2f", 9)] {
+            for _ in 0..count {
+                let mut record = GameRecord::new(Handicap::Even, "a", "b");
+                record.push(&sfen::from_usi(usi, &board).unwrap());
+                built.add_record(&record, BOOK_PLIES);
+            }
+        }
+        built.sort();
+        assert_eq!(built.len(), 1);
+        assert_eq!(built.moves(&board), moves);
+        assert_eq!(Book::parse(&built.write()).unwrap().moves(&board), moves);
+    }
+}
@@ -0,0 +1,352 @@
+// Command line handling. Kept dependency-free on purpose: there are only a handful of options.
+
+use crate::clock::TimeControl;
+use crate::database::Winner;
+use crate::handicap::Handicap;
+use crate::impasse;
+use crate::opponent::{Level, Limits};
+use crate::sfen;
+
+use shogai::piece::Color;
+use shogai::position::Position;
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: shogui [options]
+
+options:
+  --level <random|greedy|normal|hard|expert>   strength of the computer opponent
+  --movetime <ms>                               time limit per computer move
+  --nodes <n>                                   node limit per computer move
+  --handicap <even|lance|right-lance|bishop|rook|rook-lance|2-piece|4-piece|6-piece|8-piece|10-piece>
+                                                pieces removed from the stronger side (gote)
+  --side <sente|gote>                           side played by the human (default: gote)
+  --edit                                        start by setting up a position
+  --tsume <path>                                solve tsume problems from a KIF file, a directory
+                                                of KIF files or a file with one SFEN per line
+  --time <main>+<byoyomi>                       clock of hosted network games and engine
+                                                matches, in seconds, e.g. 600+30 (games
+                                                against the computer are untimed)
+  --impasse <24|27>                             entering king declaration rule (default: 27)
+  --host <port>                                 wait for another shogui to join a network game
+  --join <address[:port]>                       join a network game (default port 4080)
+  --name <name>                                 your name in network games (and CSA login)
+  --csa <address[:port]>                        play on a CSA server (default port 4081)
+  --password <password>                         password for the CSA server
+  --csa-engine <level>                          let a built-in engine play on the CSA server,
+                                                without a window
+  --book <path>                                 opening book for the computer: a YaneuraOu book
+                                                file, or a KIF file or directory of KIF files
+  --save-book <path>                            write the book (built from KIF files...) in
+                                                YaneuraOu's format and quit
+  --db <dir>                                    game database: the KIF files of a directory,
+                                                browsed in the database panel (F)
+  --query                                       list the games of the database kept by the
+                                                filters below, without a window
+  --player <name>                               only games of this player (part of the name)
+  --date <yyyy[/mm[/dd]]>                       only games of this year, month or day
+  --opening <name>                              only games of this opening (戦型)
+  --result <sente|gote|draw>                    only games won by sente, by gote, or drawn
+  --position <sfen>                             with --query, the games reaching this position
+                                                and the moves played from it; with --export-png
+                                                or --export-svg, the position drawn
+  --export-png <path>                           save a diagram of the position (or of the start of
+                                                the game) as PNG and quit
+  --kif <path>                                  a game record, for --export-gif and --export-frames
+  --export-gif <path>                           save the game of --kif as an animated GIF and quit
+  --export-frames <dir>                         save each position of the game of --kif as a PNG
+                                                (000.png, 001.png...) and quit
+  --delay <ms>                                  time each move is shown in the GIF (default: 1000)
+  --no-captions                                 don't write the moves under the board
+  --export-svg <path>                           the same as SVG, for print
+  --highlights <square,square,...>              squares coloured in the SVG diagram, e.g. 7f,2f
+  --arrows <move,move,...>                      arrows drawn in the SVG diagram, e.g. 7g7f,2g2f
+  --caption <text>                              written under the SVG diagram
+  --engines <name,name,...>                     play a match between built-in levels and registered
+                                                USI engines, without a window (every pairing plays,
+                                                see --gauntlet)
+  --gauntlet                                    only pair the first engine with each of the others
+  --games <n>                                   games per pairing, colours alternating (default: 2)
+  --openings <path>                             start positions: a KIF file or one SFEN per line,
+                                                each one played with both colours
+  --max-moves <n>                               moves after which a match game is drawn
+                                                (default: 320)
+  --adjudicate <score>                          end a match game once an evaluation past this
+                                                (a pawn is 100) held for 10 moves
+  --records <dir>                               where match records are saved (default: match)
+  --record-input <path>                         write the clicks and keys of the game (and the
+                                                opponent's moves) to a file they can be replayed
+                                                from, see src/replay.rs
+  --add-engine <path>                           register a USI engine, offered as an opponent and
+                                                set up in the settings (Ctrl+O)
+  -h, --help                                    print this help";
+
+/// which end of a network game this instance is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Network {
+    Host(u16),
+    /// address of the host, with or without port
+    Join(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    /// None means the level is asked for at startup
+    pub level: Option<Level>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>,
+    /// None means the kind of game is asked for at startup
+    pub handicap: Option<Handicap>,
+    /// shogai color of the human player
+    pub human: Color,
+    /// start in the position editor
+    pub edit: bool,
+    /// tsume problems to solve instead of playing a game
+    pub tsume: Option<PathBuf>,
+    pub time: Option<TimeControl>,
+    pub impasse: impasse::Rule,
+    pub network: Option<Network>,
+    pub name: Option<String>,
+    /// CSA server to play on
+    pub csa: Option<String>,
+    pub password: Option<String>,
+    /// engine playing on the CSA server instead of the human
+    pub csa_engine: Option<Level>,
+    pub book: Option<PathBuf>,
+    pub save_book: Option<PathBuf>,
+    /// directory of the game database
+    pub database: Option<PathBuf>,
+    pub query: bool,
+    pub player: Option<String>,
+    pub date: Option<String>,
+    pub opening: Option<String>,
+    pub winner: Option<Winner>,
+    /// position searched in the database, as SFEN
+    pub position: Option<String>,
+    /// where the diagram of --position goes
+    pub export_png: Option<PathBuf>,
+    pub export_svg: Option<PathBuf>,
+    /// drawn on the SVG diagram
+    pub highlights: Vec<Position>,
+    pub arrows: Vec<(Position, Position)>,
+    pub caption: Option<String>,
+    /// the game exported move by move, as a GIF or PNG frames
+    pub kif: Option<PathBuf>,
+    pub export_gif: Option<PathBuf>,
+    pub export_frames: Option<PathBuf>,
+    /// time a frame of the GIF is shown
+    pub delay: Duration,
+    /// the moves written under the frames
+    pub captions: bool,
+    /// engines playing a match (levels or registered USI engines), none to play with a window
+    pub engines: Vec<String>,
+    pub gauntlet: bool,
+    /// games per pairing of a match
+    pub games: u32,
+    pub openings: Option<PathBuf>,
+    /// plies after which a match game is drawn
+    pub max_moves: usize,
+    pub adjudicate: Option<i32>,
+    pub records: Option<PathBuf>,
+    /// where the input of the game is recorded
+    pub record_input: Option<PathBuf>,
+    /// USI engine to register
+    pub add_engine: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            level: None,
+            movetime: None,
+            nodes: None,
+            handicap: None,
+            human: Color::Black,
+            edit: false,
+            tsume: None,
+            time: None,
+            impasse: impasse::Rule::default(),
+            network: None,
+            name: None,
+            csa: None,
+            password: None,
+            csa_engine: None,
+            book: None,
+            save_book: None,
+            database: None,
+            query: false,
+            player: None,
+            date: None,
+            opening: None,
+            winner: None,
+            position: None,
+            export_png: None,
+            export_svg: None,
+            highlights: Vec::new(),
+            arrows: Vec::new(),
+            caption: None,
+            kif: None,
+            export_gif: None,
+            export_frames: None,
+            delay: Duration::from_secs(1),
+            captions: true,
+            engines: Vec::new(),
+            gauntlet: false,
+            games: 2,
+            openings: None,
+            max_moves: 320,
+            adjudicate: None,
+            records: None,
+            record_input: None,
+            add_engine: None,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--level" => parsed.level = Some(value(&arg, args.next())?.parse()?),
+                "--movetime" => {
+                    let ms = number(&arg, args.next())?;
+                    parsed.movetime = Some(Duration::from_millis(ms));
+                }
+                "--nodes" => parsed.nodes = Some(number(&arg, args.next())?),
+                "--handicap" => parsed.handicap = Some(value(&arg, args.next())?.parse()?),
+                "--side" => {
+                    parsed.human = match value(&arg, args.next())?.as_str() {
+                        // sente is shogai's White, see sfen.rs
+                        "sente" => Color::White,
+                        "gote" => Color::Black,
+                        other => return Err(format!("unknown side '{}'", other)),
+                    }
+                }
+                "--edit" => parsed.edit = true,
+                "--tsume" => parsed.tsume = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--time" => parsed.time = Some(value(&arg, args.next())?.parse()?),
+                "--impasse" => parsed.impasse = value(&arg, args.next())?.parse()?,
+                "--host" => {
+                    let port = number(&arg, args.next())?;
+                    let port = u16::try_from(port).map_err(|_| format!("invalid port {}", port))?;
+                    parsed.network = Some(Network::Host(port));
+                }
+                "--join" => parsed.network = Some(Network::Join(value(&arg, args.next())?)),
+                "--name" => parsed.name = Some(value(&arg, args.next())?),
+                "--csa" => parsed.csa = Some(value(&arg, args.next())?),
+                "--password" => parsed.password = Some(value(&arg, args.next())?),
+                "--csa-engine" => parsed.csa_engine = Some(value(&arg, args.next())?.parse()?),
+                "--book" => parsed.book = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--save-book" => parsed.save_book = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--db" => parsed.database = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--query" => parsed.query = true,
+                "--player" => parsed.player = Some(value(&arg, args.next())?),
+                "--date" => parsed.date = Some(value(&arg, args.next())?),
+                "--opening" => parsed.opening = Some(value(&arg, args.next())?),
+                "--result" => parsed.winner = Some(value(&arg, args.next())?.parse()?),
+                "--position" => parsed.position = Some(value(&arg, args.next())?),
+                "--export-png" => {
+                    parsed.export_png = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--export-svg" => {
+                    parsed.export_svg = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--highlights" => {
+                    parsed.highlights = value(&arg, args.next())?
+                        .split(',')
+                        .map(|square| sfen::parse_usi_square(square.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                "--arrows" => {
+                    parsed.arrows = value(&arg, args.next())?
+                        .split(',')
+                        .map(|arrow| squares(arrow.trim()))
+                        .collect::<Result<_, _>>()?
+                }
+                "--caption" => parsed.caption = Some(value(&arg, args.next())?),
+                "--kif" => parsed.kif = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--export-gif" => {
+                    parsed.export_gif = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--export-frames" => {
+                    parsed.export_frames = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--delay" => {
+                    let ms = number(&arg, args.next())?;
+                    parsed.delay = Duration::from_millis(ms);
+                }
+                "--no-captions" => parsed.captions = false,
+                "--engines" => {
+                    parsed.engines = value(&arg, args.next())?
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect()
+                }
+                "--gauntlet" => parsed.gauntlet = true,
+                "--games" => {
+                    let games = number(&arg, args.next())?;
+                    parsed.games =
+                        u32::try_from(games).map_err(|_| String::from("too many games"))?;
+                }
+                "--openings" => parsed.openings = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--max-moves" => parsed.max_moves = number(&arg, args.next())? as usize,
+                "--adjudicate" => {
+                    let score = number(&arg, args.next())?;
+                    parsed.adjudicate =
+                        Some(i32::try_from(score).map_err(|_| format!("invalid score {}", score))?);
+                }
+                "--records" => parsed.records = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--record-input" => {
+                    parsed.record_input = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--add-engine" => {
+                    parsed.add_engine = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "-h" | "--help" => parsed.help = true,
+                other => return Err(format!("unknown option '{}'\n{}", other, USAGE)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// the player's name, from --name or the login name
+    pub fn player_name(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| String::from("You"))
+    }
+
+    /// limits of the given level, overridden by the ones given on the command line
+    pub fn limits(&self, level: Level) -> Limits {
+        let default = level.default_limits();
+        Limits {
+            movetime: self.movetime.or(default.movetime),
+            nodes: self.nodes.or(default.nodes),
+        }
+    }
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for {}", option))
+}
+
+/// the two squares of an arrow: "7g7f"
+fn squares(arrow: &str) -> Result<(Position, Position), String> {
+    match (arrow.get(..2), arrow.get(2..)) {
+        (Some(from), Some(to)) => Ok((sfen::parse_usi_square(from)?, sfen::parse_usi_square(to)?)),
+        _ => Err(format!("invalid arrow '{}' (7g7f for instance)", arrow)),
+    }
+}
+
+fn number(option: &str, v: Option<String>) -> Result<u64, String> {
+    value(option, v)?
+        .parse()
+        .map_err(|_| format!("{} expects a number", option))
+}
@@ -0,0 +1,190 @@
+// Game clocks: main time followed by byoyomi (a fixed time for every move once the main time is
+// spent), the usual time control in shogi.
+
+use crate::sfen;
+
+use shogai::piece::Color;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimeControl {
+    pub main: Duration,
+    pub byoyomi: Duration,
+}
+
+impl TimeControl {
+    /// no time limit at all
+    pub fn is_unlimited(&self) -> bool {
+        self.main == Duration::from_secs(0) && self.byoyomi == Duration::from_secs(0)
+    }
+}
+
+/// "<main seconds>+<byoyomi seconds>", "600+30" for ten minutes and 30 seconds per move
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{}", self.main.as_secs(), self.byoyomi.as_secs())
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.splitn(2, '+');
+        let seconds = |part: Option<&str>| -> Result<Duration, String> {
+            match part {
+                None => Ok(Duration::from_secs(0)),
+                Some(part) => part
+                    .trim()
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| format!("invalid time control '{}'", s)),
+            }
+        };
+        Ok(TimeControl {
+            main: seconds(parts.next())?,
+            byoyomi: seconds(parts.next())?,
+        })
+    }
+}
+
+fn index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// the two players' clocks, only one of them running at a time
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    /// main time left, by player
+    main: [Duration; 2],
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock {
+            control,
+            main: [control.main; 2],
+            running: None,
+        }
+    }
+
+    fn elapsed(&self, color: Color) -> Duration {
+        match self.running {
+            Some((running, since)) if running == color => since.elapsed(),
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    /// main time left, and byoyomi left for the current move
+    pub fn remaining(&self, color: Color) -> (Duration, Duration) {
+        let main = self.main[index(color)];
+        let elapsed = self.elapsed(color);
+        if elapsed <= main {
+            (main - elapsed, self.control.byoyomi)
+        } else {
+            (
+                Duration::from_secs(0),
+                self.control.byoyomi.saturating_sub(elapsed - main),
+            )
+        }
+    }
+
+    /// main time left, as told to the other side of a network game
+    pub fn main_time(&self, color: Color) -> Duration {
+        self.remaining(color).0
+    }
+
+    /// take the time reported by the other side of a network game
+    pub fn set_main_time(&mut self, color: Color, main: Duration) {
+        self.main[index(color)] = main;
+        if let Some((running, _)) = self.running {
+            if running == color {
+                self.running = Some((color, Instant::now()));
+            }
+        }
+    }
+
+    pub fn start(&mut self, color: Color) {
+        if self.running.is_none() {
+            self.running = Some((color, Instant::now()));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((color, _)) = self.running {
+            self.main[index(color)] = self.remaining(color).0;
+            self.running = None;
+        }
+    }
+
+    /// stop the clock of the player who just moved and start the other one
+    pub fn switch(&mut self) {
+        if let Some((color, _)) = self.running {
+            self.stop();
+            let mut next = color;
+            next.invert();
+            self.start(next);
+        }
+    }
+
+    /// whether the player ran out of time
+    pub fn flagged(&self, color: Color) -> bool {
+        if self.control.is_unlimited() {
+            return false;
+        }
+        let (main, byoyomi) = self.remaining(color);
+        main == Duration::from_secs(0) && byoyomi == Duration::from_secs(0)
+    }
+
+    /// the seconds left when there are 10 or fewer on the running clock of `color`: of the
+    /// byoyomi, or of the main time without one
+    pub fn countdown(&self, color: Color) -> Option<u32> {
+        match self.running {
+            Some((running, _)) if running == color && !self.control.is_unlimited() => {}
+            _ => return None,
+        }
+        let (main, byoyomi) = self.remaining(color);
+        let left = if self.control.byoyomi == Duration::from_secs(0) {
+            main
+        } else if main == Duration::from_secs(0) {
+            byoyomi
+        } else {
+            return None;
+        };
+        let seconds = (left.as_millis() as u64).div_ceil(1000) as u32;
+        Some(seconds).filter(|seconds| (1..=10).contains(seconds))
+    }
+
+    /// "9:58" during the main time, "byoyomi 25" after
+    pub fn display(&self, color: Color) -> String {
+        if self.control.is_unlimited() {
+            return String::from("-");
+        }
+        let (main, byoyomi) = self.remaining(color);
+        if main > Duration::from_secs(0) || self.control.byoyomi == Duration::from_secs(0) {
+            let seconds = (main.as_millis() as u64).div_ceil(1000);
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        } else {
+            format!("byoyomi {}", (byoyomi.as_millis() as u64).div_ceil(1000))
+        }
+    }
+
+    /// both clocks, sente first
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {} / {} {}",
+            sfen::side_name(Color::White),
+            self.display(Color::White),
+            sfen::side_name(Color::Black),
+            self.display(Color::Black)
+        )
+    }
+}
@@ -0,0 +1,651 @@
+// The player's commands applied to the game, without the window: what they do to the position,
+// the editor and the other side (the computer, a network peer, a CSA server or a tsume problem),
+// and what is left for the window to do (another game, a dialog, the panels...). The message
+// boxes go through the `Dialogs` trait, so all of it can be tested without SDL.
+
+use crate::csa::CsaGame;
+use crate::editor::{EditAction, Editor};
+use crate::game::{Driver, GameState};
+use crate::impasse;
+use crate::input::Command;
+use crate::net::{Message, NetGame};
+use crate::opponent;
+use crate::render::Panel;
+use crate::result::GameResult;
+use crate::rules;
+use crate::sfen;
+use crate::tsume::{self, Trainer};
+
+use shogai::piece::Color;
+
+/// what to play once a game is over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// the game given on the command line (or chosen in the dialogs)
+    First,
+    /// a game chosen again in the dialogs (or the next one offered by the CSA server)
+    NewGame,
+    /// the same game again, sides swapped in even games
+    Rematch,
+}
+
+/// the questions and notices of the window, answered by the player (or by a test)
+pub trait Dialogs {
+    /// the index of the choice taken, None when the question was dismissed
+    fn ask(
+        &mut self,
+        title: &str,
+        message: &str,
+        choices: &[String],
+    ) -> Result<Option<usize>, String>;
+
+    fn tell(&mut self, title: &str, message: &str, warning: bool) -> Result<(), String>;
+
+    /// whether the piece the player moves promotes
+    fn promote(&mut self) -> Result<bool, String>;
+}
+
+/// what the window is left to do after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Done,
+    Ended(GameResult),
+    Next(Restart),
+    /// open or close a study panel, when it has something to show
+    TogglePanel(Panel),
+    /// another problem, or the same one from its start
+    ProblemChanged,
+    Save,
+    ExportDiagram,
+    Settings,
+    Menu,
+    Quit,
+}
+
+/// the game and the sides a command can reach, borrowed for the frame
+pub struct Controller<'a> {
+    pub state: &'a mut GameState,
+    pub editor: &'a mut Option<Editor>,
+    pub driver: &'a mut Driver,
+    pub opponent_name: &'a str,
+    pub trainer: Option<&'a mut Trainer>,
+    pub net: Option<&'a mut NetGame>,
+    pub csa: Option<&'a mut CsaGame>,
+}
+
+impl Controller<'_> {
+    pub fn command(
+        &mut self,
+        command: Command,
+        dialogs: &mut dyn Dialogs,
+    ) -> Result<Reply, String> {
+        let state = &mut *self.state;
+        let playing = state.result.is_none();
+        let thinking = self.driver.thinking();
+        let tsume = self.trainer.is_some();
+        // a game against the computer, or a position being edited
+        let local = !tsume && self.net.is_none() && self.csa.is_none();
+        let reply = match command {
+            Command::Chat(line) => {
+                if let Some(net) = self.net.as_deref_mut() {
+                    net.send(&Message::Chat(line.clone()));
+                    net.status = format!("you: {}", line);
+                }
+                Reply::Done
+            }
+            Command::Resign if playing && !tsume => {
+                let choices = [String::from("Resign"), String::from("Continue")];
+                if dialogs.ask("Resign", "Resign this game?", &choices)? != Some(0) {
+                    return Ok(Reply::Done);
+                }
+                // the server answers with the result, the game ends then
+                if let Some(csa) = self.csa.as_deref_mut() {
+                    if let Err(e) = csa.client.resign() {
+                        csa.error = Some(e);
+                    }
+                    return Ok(Reply::Done);
+                }
+                if let Some(net) = self.net.as_deref_mut() {
+                    net.send(&Message::Resign);
+                }
+                Reply::Ended(GameResult::Resignation {
+                    winner: rules::opponent(state.human),
+                })
+            }
+            Command::OfferDraw if playing && !tsume && self.csa.is_none() => {
+                if let Some(net) = self.net.as_deref_mut() {
+                    net.send(&Message::DrawOffer);
+                    net.status = String::from("draw offered");
+                    Reply::Done
+                } else if opponent::accepts_draw(&state.board, state.human) {
+                    Reply::Ended(GameResult::DrawAgreed)
+                } else {
+                    let message = format!("{} declines the draw", self.opponent_name);
+                    dialogs.tell("Draw offer", &message, false)?;
+                    Reply::Done
+                }
+            }
+            // entering king declaration, on the player's own turn
+            Command::Declare if state.human_turn() && !tsume => {
+                if let Some(csa) = self.csa.as_deref_mut() {
+                    // the server checks it and answers with the result
+                    if let Err(e) = csa.client.declare() {
+                        csa.error = Some(e);
+                    }
+                    return Ok(Reply::Done);
+                }
+                match impasse::declaration(&state.board, state.rule) {
+                    Ok(declared) => {
+                        if let Some(net) = self.net.as_deref_mut() {
+                            net.send(&Message::Declare);
+                        }
+                        Reply::Ended(declared)
+                    }
+                    Err(e) => {
+                        let message = format!("You can't declare: {}", e);
+                        dialogs.tell("Declaration", &message, true)?;
+                        Reply::Done
+                    }
+                }
+            }
+            // after the game: another one, or the same again
+            Command::Next | Command::Again
+                if !playing && (self.net.is_none() || command == Command::Again) =>
+            {
+                if let Some(net) = self.net.as_deref_mut() {
+                    // both sides have to ask for it
+                    if !net.rematch_sent {
+                        net.send(&Message::Rematch);
+                        net.rematch_sent = true;
+                        net.status = String::from("rematch offered");
+                    }
+                    return Ok(Reply::Done);
+                }
+                Reply::Next(if command == Command::Again {
+                    Restart::Rematch
+                } else {
+                    Restart::NewGame
+                })
+            }
+            Command::Edit(EditAction::Start) => {
+                let edited = self
+                    .editor
+                    .as_ref()
+                    .map(|ed| (ed.board().clone(), ed.validate()));
+                match edited {
+                    Some((board, Ok(()))) => {
+                        state.set_position(board);
+                        *self.editor = None;
+                        Reply::Done
+                    }
+                    Some((_, Err(e))) => {
+                        dialogs.tell("Invalid position", &e.to_string(), true)?;
+                        Reply::Done
+                    }
+                    None => Reply::Done,
+                }
+            }
+            Command::Edit(EditAction::Cancel) => {
+                *self.editor = None;
+                // back to the game, forget whatever was selected before editing
+                state.clear_selection();
+                Reply::Done
+            }
+            Command::Edit(action) => {
+                if let Some(ed) = self.editor.as_mut() {
+                    ed.apply(action);
+                }
+                Reply::Done
+            }
+            // the book and database panels, to study openings
+            Command::TogglePanel(wanted) if local => Reply::TogglePanel(wanted),
+            Command::OpenEditor if !thinking && local => {
+                *self.editor = Some(Editor::new(state.board.clone()));
+                Reply::Done
+            }
+            // the search takes a while, the hint is shown once the driver found it
+            Command::Hint if !thinking && tsume && state.human_turn() => {
+                let (board, attacker) = (state.board.clone(), state.human);
+                self.driver
+                    .search_hint(move || tsume::hint(&board, attacker));
+                Reply::Done
+            }
+            Command::Again if !thinking && tsume => Reply::ProblemChanged,
+            Command::Next | Command::Previous if !thinking => match self.trainer.as_deref_mut() {
+                Some(trainer) => {
+                    if command == Command::Next {
+                        trainer.next();
+                    } else {
+                        trainer.previous();
+                    }
+                    Reply::ProblemChanged
+                }
+                None => Reply::Done,
+            },
+            Command::Quit => {
+                if let Some(net) = self.net.as_deref_mut() {
+                    net.send(&Message::Bye);
+                }
+                if let Some(csa) = self.csa.as_deref_mut() {
+                    let _ = csa.client.logout();
+                }
+                Reply::Quit
+            }
+            Command::Save => Reply::Save,
+            Command::ExportDiagram => Reply::ExportDiagram,
+            Command::Settings => Reply::Settings,
+            Command::Menu => Reply::Menu,
+            Command::Mark(mark) => {
+                state.record.toggle_mark(mark);
+                Reply::Done
+            }
+            Command::Click(target) if state.human_turn() => {
+                let before = state.board.clone();
+                let mut asked = Ok(());
+                let clicked = state.click(target, || {
+                    dialogs.promote().unwrap_or_else(|e| {
+                        asked = Err(e);
+                        false
+                    })
+                });
+                asked?;
+                match clicked {
+                    Ok(Some(mv)) => {
+                        if let Some(net) = self.net.as_deref_mut() {
+                            state.clock.switch();
+                            net.send(&Message::Move(sfen::to_usi(&mv)?));
+                            net.send(&Message::Clock(
+                                state.clock.main_time(Color::White),
+                                state.clock.main_time(Color::Black),
+                            ));
+                        }
+                        if let Some(csa) = self.csa.as_deref_mut() {
+                            state.clock.switch();
+                            if let Err(e) = csa.client.send_move(&before, &mv) {
+                                csa.error = Some(e);
+                            }
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => dialogs.tell("Tsume", &e, true)?,
+                }
+                Reply::Done
+            }
+            _ => Reply::Done,
+        };
+        Ok(reply)
+    }
+
+    /// once the player or the other side moved: how the problem or the game stands. None
+    /// without a new move.
+    pub fn played(&mut self, dialogs: &mut dyn Dialogs) -> Result<Option<Reply>, String> {
+        let state = &mut *self.state;
+        if !state.take_played() {
+            return Ok(None);
+        }
+        self.driver.forget_hint();
+        if let Some(trainer) = self.trainer.as_deref_mut() {
+            let reply = match trainer.judge(state)? {
+                Some(true) => {
+                    let message = "Checkmate, well done! On to the next problem.";
+                    dialogs.tell("Solved", message, false)?;
+                    Reply::ProblemChanged
+                }
+                Some(false) => {
+                    let message = "No check left, the king got away. Try again (H for a hint).";
+                    dialogs.tell("Failed", message, false)?;
+                    Reply::ProblemChanged
+                }
+                None => Reply::Done,
+            };
+            return Ok(Some(reply));
+        }
+        state.points = impasse::summary(&state.board);
+        // (the CSA server decides these itself)
+        if self.csa.is_none() {
+            if let Some(judged) = state.judge() {
+                return Ok(Some(Reply::Ended(judged)));
+            }
+        }
+        Ok(Some(Reply::Done))
+    }
+
+    /// the problem of the trainer set up from its start, the defender's replies handed to the
+    /// driver; a wrong solution given with it is told
+    pub fn set_up_problem(&mut self, dialogs: &mut dyn Dialogs) -> Result<(), String> {
+        let trainer = match self.trainer.as_deref() {
+            Some(trainer) => trainer,
+            None => return Ok(()),
+        };
+        self.driver.replace(trainer.set_up(self.state));
+        let problem = trainer.problem();
+        if !problem.solution.is_empty() {
+            if let Err(e) = tsume::verify_solution(problem) {
+                let message = format!("The solution given with {} is wrong: {}", problem.name, e);
+                dialogs.tell("Tsume", &message, true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the game is over: the computer stops, a CSA game's record is saved
+    pub fn end(&mut self, result: GameResult) {
+        self.state.end(result);
+        self.driver.stop();
+        if let Some(csa) = self.csa.as_deref_mut() {
+            csa.status = match self.state.record.save_csa("game.csa") {
+                Ok(()) => String::from("record saved to game.csa"),
+                Err(e) => format!("could not save the record: {}", e),
+            };
+        }
+    }
+
+    /// the window's title: the game, how it stands and what is left to do, with the line of the
+    /// study panel when one is open
+    pub fn title(&self, game_title: &str, typing: Option<&str>, panel: Option<&str>) -> String {
+        if let Some(ed) = self.editor.as_ref() {
+            let side = sfen::side_name(ed.side_to_move());
+            return format!("Shogi - editing ({} to move)", side);
+        }
+        if let Some(net) = self.net.as_deref() {
+            return net.title(game_title, self.state, typing);
+        }
+        if let Some(csa) = self.csa.as_deref() {
+            return csa.title(game_title, self.state);
+        }
+        let mut title = match self.trainer.as_deref() {
+            Some(trainer) => trainer.title(),
+            None => game_title.to_string(),
+        };
+        if let Some(result) = self.state.result {
+            title = format!(
+                "{} | {} | N: new game, R: rematch, Esc: quit",
+                title, result
+            );
+            if !self.driver.status.is_empty() {
+                title = format!("{} | {}", title, self.driver.status);
+            }
+            return title;
+        }
+        if let Some(points) = &self.state.points {
+            title = format!("{} | {}", title, points);
+        }
+        if let Some(panel) = panel {
+            title = format!("{} | {}", title, panel);
+        }
+        title
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, TimeControl};
+    use crate::game::Target;
+    use crate::record::GameRecord;
+    use crate::tsume::{Problem, Progress};
+
+    /// answers the questions from a list, and keeps what it was told
+    #[derive(Default)]
+    struct Scripted {
+        answers: Vec<Option<usize>>,
+        told: Vec<String>,
+    }
+
+    impl Dialogs for Scripted {
+        fn ask(&mut self, _: &str, _: &str, _: &[String]) -> Result<Option<usize>, String> {
+            Ok(self.answers.remove(0))
+        }
+
+        fn tell(&mut self, _: &str, message: &str, _: bool) -> Result<(), String> {
+            self.told.push(message.to_string());
+            Ok(())
+        }
+
+        fn promote(&mut self) -> Result<bool, String> {
+            Ok(true)
+        }
+    }
+
+    fn state(position: &str) -> GameState {
+        let (board, _) = sfen::from_sfen(position).unwrap();
+        let record = GameRecord::from_position(board.clone(), "You", "Computer");
+        let clock = Clock::new(TimeControl::default());
+        GameState::new(board, Color::White, record, clock, impasse::Rule::default())
+    }
+
+    fn against_computer<'a>(
+        state: &'a mut GameState,
+        editor: &'a mut Option<Editor>,
+        driver: &'a mut Driver,
+    ) -> Controller<'a> {
+        Controller {
+            state,
+            editor,
+            driver,
+            opponent_name: "normal",
+            trainer: None,
+            net: None,
+            csa: None,
+        }
+    }
+
+    fn square(usi: &str) -> Target {
+        Target::Square(sfen::parse_usi_square(usi).unwrap())
+    }
+
+    #[test]
+    fn game_commands() {
+        let (mut editor, mut driver) = (None, Driver::new(None));
+        let mut dialogs = Scripted {
+            answers: vec![Some(1), Some(0)],
+            ..Scripted::default()
+        };
+        let mut game = state("startpos");
+        let mut controller = against_computer(&mut game, &mut editor, &mut driver);
+
+        // two clicks play a move, that is then judged
+        assert_eq!(controller.played(&mut dialogs), Ok(None));
+        for target in [square("7g"), square("7f")] {
+            assert_eq!(
+                controller.command(Command::Click(target), &mut dialogs),
+                Ok(Reply::Done)
+            );
+        }
+        assert_eq!(controller.state.record.moves.len(), 1);
+        assert_eq!(controller.played(&mut dialogs), Ok(Some(Reply::Done)));
+
+        // not the player's turn, nothing to declare
+        assert_eq!(
+            controller.command(Command::Declare, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert!(dialogs.told.is_empty());
+        // no rematch before the game is over
+        assert_eq!(
+            controller.command(Command::Again, &mut dialogs),
+            Ok(Reply::Done)
+        );
+
+        // the resignation is asked to be confirmed
+        assert_eq!(
+            controller.command(Command::Resign, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        let resigned = GameResult::Resignation {
+            winner: Color::Black,
+        };
+        assert_eq!(
+            controller.command(Command::Resign, &mut dialogs),
+            Ok(Reply::Ended(resigned))
+        );
+        controller.end(resigned);
+        let title = controller.title("Shogi - vs normal", None, None);
+        assert!(title.starts_with("Shogi - vs normal | "));
+        assert!(title.ends_with("N: new game, R: rematch, Esc: quit"));
+        assert_eq!(
+            controller.command(Command::Again, &mut dialogs),
+            Ok(Reply::Next(Restart::Rematch))
+        );
+        assert_eq!(
+            controller.command(Command::Next, &mut dialogs),
+            Ok(Reply::Next(Restart::NewGame))
+        );
+        assert_eq!(
+            controller.command(Command::Quit, &mut dialogs),
+            Ok(Reply::Quit)
+        );
+    }
+
+    #[test]
+    fn draws_and_declarations() {
+        let (mut editor, mut driver) = (None, Driver::new(None));
+        let mut dialogs = Scripted::default();
+
+        // the computer, a rook up, declines
+        let mut game = state("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/7R1/LNSGKGSNL b - 1");
+        let mut controller = against_computer(&mut game, &mut editor, &mut driver);
+        assert_eq!(
+            controller.command(Command::OfferDraw, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert_eq!(dialogs.told, ["normal declines the draw"]);
+
+        let mut game = state("lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1");
+        let mut controller = against_computer(&mut game, &mut editor, &mut driver);
+        assert_eq!(
+            controller.command(Command::OfferDraw, &mut dialogs),
+            Ok(Reply::Ended(GameResult::DrawAgreed))
+        );
+
+        assert_eq!(
+            controller.command(Command::Declare, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert!(dialogs.told[1].starts_with("You can't declare"));
+    }
+
+    #[test]
+    fn editing_a_position() {
+        let (mut editor, mut driver) = (None, Driver::new(None));
+        let mut dialogs = Scripted::default();
+        let mut game = state("startpos");
+        let mut controller = against_computer(&mut game, &mut editor, &mut driver);
+
+        assert_eq!(
+            controller.command(Command::OpenEditor, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert!(controller.editor.is_some());
+        assert_eq!(
+            controller.title("Shogi", None, None),
+            "Shogi - editing (sente to move)"
+        );
+        controller
+            .command(Command::Edit(EditAction::Clear), &mut dialogs)
+            .unwrap();
+        assert_eq!(
+            controller.command(Command::Edit(EditAction::Start), &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert_eq!(dialogs.told, ["sente has no king"]);
+
+        controller
+            .command(Command::Edit(EditAction::Reset), &mut dialogs)
+            .unwrap();
+        controller
+            .command(Command::Edit(EditAction::SwitchSide), &mut dialogs)
+            .unwrap();
+        assert_eq!(
+            controller.command(Command::Edit(EditAction::Start), &mut dialogs),
+            Ok(Reply::Done)
+        );
+        assert!(controller.editor.is_none());
+        assert_eq!(controller.state.board.get_color(), Color::Black);
+        assert_eq!(
+            controller.title("Shogi", None, Some("book")),
+            "Shogi | book"
+        );
+        assert_eq!(
+            controller.command(Command::TogglePanel(Panel::Book), &mut dialogs),
+            Ok(Reply::TogglePanel(Panel::Book))
+        );
+    }
+
+    #[test]
+    fn tsume_commands() {
+        let problems = vec![
+            Problem::new(
+                "one",
+                sfen::from_sfen("8k/9/8P/9/9/9/9/9/9 b G 1").unwrap().0,
+                vec![],
+            ),
+            Problem::new(
+                "two",
+                sfen::from_sfen("8k/9/7PP/9/9/9/9/9/9 b G 1").unwrap().0,
+                vec![],
+            ),
+        ];
+        let mut trainer = Trainer::new(problems, Progress::default());
+        let mut game = state("startpos");
+        let (mut editor, mut driver, mut dialogs) = (None, Driver::new(None), Scripted::default());
+        let mut controller = Controller {
+            trainer: Some(&mut trainer),
+            ..against_computer(&mut game, &mut editor, &mut driver)
+        };
+        controller.set_up_problem(&mut dialogs).unwrap();
+        assert!(sfen::to_sfen(&controller.state.board, 1).starts_with("8k/9/8P/"));
+        assert!(controller.title("Shogi", None, None).contains("one"));
+
+        // no resigning, drawing or editing a problem
+        for command in [Command::Resign, Command::OfferDraw, Command::OpenEditor] {
+            assert_eq!(controller.command(command, &mut dialogs), Ok(Reply::Done));
+        }
+        assert!(controller.editor.is_none());
+
+        // the hint is searched for on the driver's thread
+        assert_eq!(
+            controller.command(Command::Hint, &mut dialogs),
+            Ok(Reply::Done)
+        );
+        let hint = loop {
+            match controller.driver.hint() {
+                Some(found) => break found,
+                None => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        let mate = sfen::from_usi("G*1b", &controller.state.board).unwrap();
+        assert_eq!(hint.map(|mv| mv.to_string()), Some(mate));
+        assert_eq!(controller.driver.hint(), None);
+
+        let gold = sfen::parse_usi_square("1b").unwrap();
+        controller
+            .command(
+                Command::Click(Target::Hand(shogai::piece::PieceType::Gold)),
+                &mut dialogs,
+            )
+            .unwrap();
+        controller
+            .command(Command::Click(Target::Square(gold)), &mut dialogs)
+            .unwrap();
+        assert_eq!(
+            controller.played(&mut dialogs),
+            Ok(Some(Reply::ProblemChanged))
+        );
+        assert_eq!(
+            dialogs.told,
+            ["Checkmate, well done! On to the next problem."]
+        );
+        assert_eq!(controller.trainer.as_ref().unwrap().problem().name, "two");
+
+        assert_eq!(
+            controller.command(Command::Previous, &mut dialogs),
+            Ok(Reply::ProblemChanged)
+        );
+        assert_eq!(controller.trainer.as_ref().unwrap().problem().name, "one");
+        assert_eq!(
+            controller.command(Command::Again, &mut dialogs),
+            Ok(Reply::ProblemChanged)
+        );
+    }
+}
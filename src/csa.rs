@@ -0,0 +1,1009 @@
+// CSA, the formats of the Computer Shogi Association: the move notation ("+7776FU"), the record
+// format (.csa files) and the network protocol spoken by computer-shogi servers (floodgate and
+// most tournament servers).
+//
+// A client logs in, receives a Game_Summary describing the game (sides, time control, starting
+// position), agrees to it, then sends its moves and receives both players' moves back with the
+// time they took, until a "#WIN", "#LOSE", "#DRAW" (or "#CHUDAN", "#CENSORED") result line.
+
+use crate::cli::Args;
+use crate::clock::{Clock, TimeControl};
+use crate::game::GameState;
+use crate::impasse;
+use crate::net::{self, Connection};
+use crate::opponent::{Level, Opponent};
+use crate::record::GameRecord;
+use crate::result::GameResult;
+use crate::rules;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 4081;
+
+const PIECES: [(&str, PieceType, bool); 14] = [
+    ("FU", PieceType::Pawn, false),
+    ("KY", PieceType::Lance, false),
+    ("KE", PieceType::Knight, false),
+    ("GI", PieceType::Silver, false),
+    ("KI", PieceType::Gold, false),
+    ("KA", PieceType::Bishop, false),
+    ("HI", PieceType::Rook, false),
+    ("OU", PieceType::King, false),
+    ("TO", PieceType::Pawn, true),
+    ("NY", PieceType::Lance, true),
+    ("NK", PieceType::Knight, true),
+    ("NG", PieceType::Silver, true),
+    ("UM", PieceType::Bishop, true),
+    ("RY", PieceType::Rook, true),
+];
+
+fn piece_code(piecetype: PieceType, promoted: bool) -> &'static str {
+    PIECES
+        .iter()
+        .find(|(_, p, promo)| *p == piecetype && (*promo == promoted || *p == PieceType::King))
+        .map_or("", |(code, _, _)| code)
+}
+
+fn piece_from_code(code: &str) -> Option<(PieceType, bool)> {
+    PIECES
+        .iter()
+        .find(|(c, _, _)| *c == code)
+        .map(|(_, piecetype, promoted)| (*piecetype, *promoted))
+}
+
+/// '+' for sente, '-' for gote
+pub fn sign(color: Color) -> char {
+    match color {
+        Color::White => '+',
+        Color::Black => '-',
+    }
+}
+
+fn color_from_sign(sign: char) -> Option<Color> {
+    match sign {
+        '+' => Some(Color::White),
+        '-' => Some(Color::Black),
+        _ => None,
+    }
+}
+
+fn square(pos: Position) -> String {
+    let (file, rank) = sfen::file_rank(pos);
+    format!("{}{}", file, rank)
+}
+
+/// CSA notation of a move in shogai notation, "+7776FU" for P7g-7f
+pub fn to_csa(board: &Board, mv: &str) -> Result<String, String> {
+    let movement = sfen::parse_movement(mv)?;
+    let (start, promoted) = match movement.start {
+        None => (String::from("00"), false),
+        Some(start) => {
+            let piece = board
+                .is_occupied_by(start)
+                .ok_or(format!("no piece to move for {}", mv))?;
+            (square(start), piece.promoted || movement.promotion)
+        }
+    };
+    Ok(format!(
+        "{}{}{}{}",
+        sign(board.get_color()),
+        start,
+        square(movement.end),
+        piece_code(movement.piecetype, promoted)
+    ))
+}
+
+/// shogai notation of a CSA move (anything after a comma, such as the time, is ignored)
+pub fn from_csa(board: &Board, csa: &str) -> Result<String, String> {
+    let text = csa.split(',').next().unwrap_or("");
+    let chars: Vec<char> = text.chars().collect();
+    let invalid = || format!("invalid CSA move '{}'", csa);
+    if chars.len() != 7 {
+        return Err(invalid());
+    }
+    if color_from_sign(chars[0]) != Some(board.get_color()) {
+        return Err(format!("{} is not a move of the side to move", csa));
+    }
+    let digit = |i: usize| chars[i].to_digit(10).map(|d| d as u8).ok_or_else(invalid);
+    let (from_file, from_rank, to_file, to_rank) = (digit(1)?, digit(2)?, digit(3)?, digit(4)?);
+    let code: String = chars[5..].iter().collect();
+    let (piecetype, promoted) = piece_from_code(&code).ok_or_else(invalid)?;
+    if to_file == 0 || to_rank == 0 {
+        return Err(invalid());
+    }
+    let end = sfen::position(to_file, to_rank);
+
+    let movement = if from_file == 0 && from_rank == 0 {
+        Movement {
+            piecetype,
+            start: None,
+            end,
+            promotion: false,
+            force_capture: false,
+            withdraw: false,
+            restart: false,
+        }
+    } else {
+        if from_file == 0 || from_rank == 0 {
+            return Err(invalid());
+        }
+        let start = sfen::position(from_file, from_rank);
+        let piece = board.is_occupied_by(start).ok_or(format!(
+            "no piece on {}{} for {}",
+            from_file, from_rank, csa
+        ))?;
+        Movement {
+            piecetype: piece.piecetype,
+            start: Some(start),
+            end,
+            promotion: promoted && !piece.promoted,
+            force_capture: false,
+            withdraw: false,
+            restart: false,
+        }
+    };
+    Ok(movement.to_string())
+}
+
+/// the position as CSA "P" lines, followed by the side to move
+pub fn write_position(board: &Board) -> String {
+    let mut text = String::new();
+    if sfen::to_sfen(board, 1) == sfen::STARTPOS {
+        text.push_str("PI\n");
+    } else {
+        for rank in 1..=9 {
+            text.push_str(&format!("P{}", rank));
+            for file in (1..=9).rev() {
+                match board.is_occupied_by(sfen::position(file, rank)) {
+                    Some(piece) => text.push_str(&format!(
+                        "{}{}",
+                        sign(piece.color),
+                        piece_code(piece.piecetype, piece.promoted)
+                    )),
+                    None => text.push_str(" * "),
+                }
+            }
+            text.push('\n');
+        }
+        for &color in &[Color::White, Color::Black] {
+            let mut hand = String::new();
+            for &(code, piecetype, promoted) in PIECES.iter().rev() {
+                if promoted || piecetype == PieceType::King {
+                    continue;
+                }
+                for _ in 0..sfen::hand_count(board, color, piecetype) {
+                    hand.push_str("00");
+                    hand.push_str(code);
+                }
+            }
+            if !hand.is_empty() {
+                text.push_str(&format!("P{}{}\n", sign(color), hand));
+            }
+        }
+    }
+    text.push(sign(board.get_color()));
+    text.push('\n');
+    text
+}
+
+/// read "P" lines (PI, P1 to P9, P+ and P-) and the side to move
+pub fn read_position<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> Result<Board, String> {
+    let mut board = Board::empty();
+    let mut to_move = None;
+    let mut rest_to = None;
+    let add = |board: &mut Board, color: Color, code: &str, position: Option<Position>| {
+        let (piecetype, promoted) =
+            piece_from_code(code).ok_or(format!("unknown CSA piece '{}'", code))?;
+        board.add_piece(Piece {
+            color,
+            piecetype,
+            promoted,
+            position,
+        });
+        Ok::<(), String>(())
+    };
+
+    for line in lines {
+        let line = line.trim_end();
+        if line == "+" || line == "-" {
+            to_move = color_from_sign(line.chars().next().unwrap_or('+'));
+        } else if let Some(removed) = line.strip_prefix("PI") {
+            // the even position, minus the listed pieces ("PI82HI22KA")
+            let (start, _) = sfen::from_sfen(sfen::STARTPOS)?;
+            for piece in start.iter() {
+                let listed = piece.position.is_some_and(|pos| {
+                    removed
+                        .as_bytes()
+                        .chunks(4)
+                        .any(|chunk| chunk.len() == 4 && chunk[..2] == *square(pos).as_bytes())
+                });
+                if !listed {
+                    board.add_piece(*piece);
+                }
+            }
+        } else if line.starts_with("P+") || line.starts_with("P-") {
+            let color = color_from_sign(line.chars().nth(1).unwrap_or('+')).unwrap_or(Color::White);
+            for chunk in line.as_bytes()[2..].chunks(4) {
+                let chunk = std::str::from_utf8(chunk).map_err(|e| e.to_string())?;
+                if chunk.len() != 4 {
+                    return Err(format!("invalid CSA position line '{}'", line));
+                }
+                if chunk == "00AL" {
+                    rest_to = Some(color);
+                    continue;
+                }
+                let position = match &chunk[..2] {
+                    "00" => None,
+                    place => {
+                        let digits: Vec<u8> = place.bytes().map(|b| b.wrapping_sub(b'0')).collect();
+                        if digits.iter().any(|&d| d == 0 || d > 9) {
+                            return Err(format!("invalid CSA position line '{}'", line));
+                        }
+                        Some(sfen::position(digits[0], digits[1]))
+                    }
+                };
+                add(&mut board, color, &chunk[2..], position)?;
+            }
+        } else if line.len() > 1 && line.starts_with('P') {
+            let rank = line[1..2]
+                .parse::<u8>()
+                .map_err(|_| format!("invalid CSA position line '{}'", line))?;
+            let cells: Vec<char> = line.chars().skip(2).collect();
+            for (i, cell) in cells.chunks(3).take(9).enumerate() {
+                let cell: String = cell.iter().collect();
+                let file = 9 - i as u8;
+                if cell.trim() == "*" || cell.trim().is_empty() {
+                    continue;
+                }
+                let color = color_from_sign(cell.chars().next().unwrap_or(' '))
+                    .ok_or(format!("invalid CSA position line '{}'", line))?;
+                add(
+                    &mut board,
+                    color,
+                    &cell[1..],
+                    Some(sfen::position(file, rank)),
+                )?;
+            }
+        }
+    }
+
+    // "00AL": every piece not placed yet goes to that hand
+    if let Some(color) = rest_to {
+        for &(code, piecetype, promoted) in PIECES.iter() {
+            if promoted || piecetype == PieceType::King {
+                continue;
+            }
+            let total = match piecetype {
+                PieceType::Pawn => 18,
+                PieceType::Bishop | PieceType::Rook => 2,
+                _ => 4,
+            };
+            let used = board.iter().filter(|p| p.piecetype == piecetype).count();
+            for _ in used..total {
+                add(&mut board, color, code, None)?;
+            }
+        }
+    }
+    board.turn = to_move.unwrap_or(Color::White);
+    Ok(board)
+}
+
+/// a CSA record (version 2.2) of the game, with the special move ending it if it's over
+/// ("%TORYO", "%TIME_UP"...)
+pub fn write(record: &GameRecord) -> String {
+    let mut text = String::from("V2.2\n");
+    text.push_str(&format!("N+{}\nN-{}\n", record.sente, record.gote));
+    text.push_str(&write_position(&record.start));
+    let mut board = record.start.clone();
+    for (ply, mv) in record.moves.iter().enumerate() {
+        match to_csa(&board, mv) {
+            Ok(csa) => text.push_str(&csa),
+            Err(_) => break,
+        }
+        text.push('\n');
+        if let Some(time) = record.time(ply) {
+            text.push_str(&format!("T{}\n", time.as_secs()));
+        }
+        board = board.play_move_unchecked(mv);
+    }
+    if let Some(result) = record.result {
+        text.push_str(result.csa_move());
+        text.push('\n');
+    }
+    text
+}
+
+/// what the server told about the game to play
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub game_id: String,
+    pub sente: String,
+    pub gote: String,
+    /// shogai color played by this client
+    pub color: Color,
+    pub control: TimeControl,
+    /// unit of the times given by the server
+    pub time_unit: Duration,
+    pub start: Board,
+    /// moves already played (when a game is resumed), with their time
+    pub moves: Vec<(String, Option<Duration>)>,
+}
+
+/// "1sec", "1min" or "1msec"
+fn time_unit(value: &str) -> Duration {
+    let digits = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    let number = value[..digits].parse().unwrap_or(1);
+    match &value[digits..] {
+        "min" => Duration::from_secs(60 * number),
+        "msec" => Duration::from_millis(number),
+        _ => Duration::from_secs(number),
+    }
+}
+
+/// "T12" part of a move line
+fn move_time(line: &str, unit: Duration) -> Option<Duration> {
+    let time = line.split(',').nth(1)?.strip_prefix('T')?;
+    time.trim().parse::<u32>().ok().map(|t| unit * t)
+}
+
+impl Summary {
+    /// parse the lines between "BEGIN Game_Summary" and "END Game_Summary"
+    pub fn parse(lines: &[String]) -> Result<Summary, String> {
+        let mut game_id = String::new();
+        let mut names = (String::new(), String::new());
+        let mut color = None;
+        let mut unit = Duration::from_secs(1);
+        let mut total = 0;
+        let mut byoyomi = 0;
+        let mut position = Vec::new();
+        let mut move_lines = Vec::new();
+        let mut in_position = false;
+
+        for line in lines {
+            let line = line.trim_end();
+            if line == "BEGIN Position" {
+                in_position = true;
+                continue;
+            }
+            if line == "END Position" {
+                in_position = false;
+                continue;
+            }
+            if in_position {
+                if line.len() > 1 && (line.starts_with('+') || line.starts_with('-')) {
+                    move_lines.push(line);
+                } else {
+                    position.push(line);
+                }
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => continue,
+            };
+            match key {
+                "Game_ID" => game_id = value.to_string(),
+                "Name+" => names.0 = value.to_string(),
+                "Name-" => names.1 = value.to_string(),
+                "Your_Turn" => color = value.chars().next().and_then(color_from_sign),
+                "Time_Unit" => unit = time_unit(value),
+                "Total_Time" => total = value.trim().parse().unwrap_or(0),
+                "Byoyomi" => byoyomi = value.trim().parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+
+        let start = read_position(position)?;
+        let mut board = start.clone();
+        let mut moves = Vec::new();
+        for line in move_lines {
+            let mv = from_csa(&board, line)?;
+            board = board.play_move_unchecked(&mv);
+            moves.push((mv, move_time(line, unit)));
+        }
+        Ok(Summary {
+            game_id,
+            sente: names.0,
+            gote: names.1,
+            color: color.ok_or("the game summary has no Your_Turn")?,
+            control: TimeControl {
+                main: unit * total,
+                byoyomi: unit * byoyomi,
+            },
+            time_unit: unit,
+            start,
+            moves,
+        })
+    }
+
+    /// a record of the game so far
+    pub fn record(&self) -> GameRecord {
+        let mut record = GameRecord::from_position(self.start.clone(), &self.sente, &self.gote);
+        for (mv, time) in &self.moves {
+            match time {
+                Some(time) => record.push_timed(mv, *time),
+                None => record.push(mv),
+            }
+        }
+        record
+    }
+}
+
+/// the result of a game for this client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Lose,
+    Draw,
+    /// interrupted ("#CHUDAN") or cancelled ("#CENSORED")
+    Interrupted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Summary(Box<Summary>),
+    /// both sides agreed, the game starts
+    Start,
+    /// the game was rejected (by the other side, or by this one)
+    Reject,
+    /// a move of either side as confirmed by the server, with the time it took
+    Move {
+        csa: String,
+        time: Option<Duration>,
+    },
+    /// a special move such as "%TORYO" (resignation) or "%KACHI" (win declaration)
+    Special(String),
+    /// end of the game, with the reason given before the result ("#RESIGN", "#TIME_UP",
+    /// "#SENNICHITE"...)
+    End {
+        reason: Option<String>,
+        outcome: Outcome,
+    },
+}
+
+/// a client logged in to a CSA server
+pub struct Client {
+    connection: Connection<String>,
+    name: String,
+    /// lines of a Game_Summary being received
+    summary: Option<Vec<String>>,
+    unit: Duration,
+    reason: Option<String>,
+}
+
+impl Client {
+    pub fn login(address: &str, name: &str, password: &str) -> Result<Client, String> {
+        let mut connection = net::connect(address, DEFAULT_PORT)?;
+        connection.send(&format!("LOGIN {} {}", name, password))?;
+        loop {
+            match connection.receive(Duration::from_secs(30)) {
+                Some(Ok(line)) if line.starts_with("LOGIN:") && line.ends_with(" OK") => break,
+                Some(Ok(line)) if line.starts_with("LOGIN:") => {
+                    return Err(format!("login refused: {}", line))
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Err(String::from("no answer to LOGIN")),
+            }
+        }
+        Ok(Client {
+            connection,
+            name: name.to_string(),
+            summary: None,
+            unit: Duration::from_secs(1),
+            reason: None,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        self.connection.send(&line.to_string())
+    }
+
+    pub fn agree(&mut self, summary: &Summary) -> Result<(), String> {
+        self.send(&format!("AGREE {}", summary.game_id))
+    }
+
+    pub fn reject(&mut self, summary: &Summary) -> Result<(), String> {
+        self.send(&format!("REJECT {}", summary.game_id))
+    }
+
+    /// send a move in shogai notation
+    pub fn send_move(&mut self, board: &Board, mv: &str) -> Result<(), String> {
+        let csa = to_csa(board, mv)?;
+        self.send(&csa)
+    }
+
+    pub fn resign(&mut self) -> Result<(), String> {
+        self.send("%TORYO")
+    }
+
+    /// declare an entering king win, the server checks it
+    pub fn declare(&mut self) -> Result<(), String> {
+        self.send("%KACHI")
+    }
+
+    pub fn logout(&mut self) -> Result<(), String> {
+        self.send("LOGOUT")
+    }
+
+    fn handle(&mut self, line: &str) -> Result<Option<Event>, String> {
+        if let Some(lines) = self.summary.as_mut() {
+            if line == "END Game_Summary" {
+                let summary = Summary::parse(lines)?;
+                self.unit = summary.time_unit;
+                self.summary = None;
+                return Ok(Some(Event::Summary(Box::new(summary))));
+            }
+            lines.push(line.to_string());
+            return Ok(None);
+        }
+
+        let event = if line == "BEGIN Game_Summary" {
+            self.summary = Some(Vec::new());
+            None
+        } else if line.starts_with("START:") {
+            Some(Event::Start)
+        } else if line.starts_with("REJECT:") {
+            Some(Event::Reject)
+        } else if line.starts_with('+') || line.starts_with('-') {
+            Some(Event::Move {
+                csa: line.split(',').next().unwrap_or("").to_string(),
+                time: move_time(line, self.unit),
+            })
+        } else if line.starts_with('%') {
+            Some(Event::Special(
+                line.split(',').next().unwrap_or("").to_string(),
+            ))
+        } else if line.starts_with('#') {
+            let outcome = match line {
+                "#WIN" => Some(Outcome::Win),
+                "#LOSE" => Some(Outcome::Lose),
+                "#DRAW" => Some(Outcome::Draw),
+                "#CHUDAN" | "#CENSORED" => Some(Outcome::Interrupted),
+                _ => None,
+            };
+            match outcome {
+                Some(outcome) => Some(Event::End {
+                    reason: self.reason.take(),
+                    outcome,
+                }),
+                None => {
+                    self.reason = Some(line.to_string());
+                    None
+                }
+            }
+        } else {
+            // keep-alive empty lines, LOGOUT answers...
+            None
+        };
+        Ok(event)
+    }
+
+    /// the events received so far, without waiting
+    pub fn poll(&mut self) -> Result<Vec<Event>, String> {
+        let mut events = Vec::new();
+        for line in self.connection.poll() {
+            if let Some(event) = self.handle(&line?)? {
+                events.push(event);
+            }
+        }
+        if events.is_empty() && self.connection.is_closed() {
+            return Err(String::from("the server closed the connection"));
+        }
+        Ok(events)
+    }
+
+    /// wait for the next event
+    pub fn next_event(&mut self) -> Result<Event, String> {
+        loop {
+            match self.connection.receive(Duration::from_millis(100)) {
+                Some(line) => {
+                    if let Some(event) = self.handle(&line?)? {
+                        return Ok(event);
+                    }
+                }
+                None if self.connection.is_closed() => {
+                    return Err(String::from("the server closed the connection"))
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// a game on a CSA server played in the window, once agreed to
+pub struct CsaGame {
+    pub client: Client,
+    pub summary: Summary,
+    /// time used by sente and gote, as counted by the server
+    pub used: [Duration; 2],
+    /// last notice, shown in the title
+    pub status: String,
+    pub error: Option<String>,
+}
+
+impl CsaGame {
+    /// count the time the server says a move took, and set the clock from it
+    fn spend(&mut self, color: Color, time: Duration, clock: &mut Clock) {
+        let used = &mut self.used[(color == Color::Black) as usize];
+        *used += time;
+        clock.set_main_time(color, self.summary.control.main.saturating_sub(*used));
+    }
+
+    /// play the moves the server confirmed since the last frame. Returns the result once the
+    /// server ends the game, or the connection is lost.
+    pub fn poll(&mut self, state: &mut GameState) -> Option<GameResult> {
+        let received = self.client.poll().unwrap_or_else(|e| {
+            self.error = Some(e);
+            Vec::new()
+        });
+        let mut ended = None;
+        for event in received {
+            if let Some(result) = self.receive(event, state) {
+                ended = Some(result);
+            }
+        }
+        if state.result.is_none() && ended.is_none() {
+            if let Some(e) = self.error.take() {
+                self.status = e;
+                ended = Some(GameResult::Abort);
+            }
+        }
+        ended
+    }
+
+    /// one event of the server
+    fn receive(&mut self, event: Event, state: &mut GameState) -> Option<GameResult> {
+        let human = state.human;
+        match event {
+            Event::Move { csa: line, time } => {
+                let mover = if line.starts_with(sign(human)) {
+                    human
+                } else {
+                    rules::opponent(human)
+                };
+                if mover == human {
+                    // our own move, played already, coming back with its time
+                    if let Some(time) = time {
+                        state.record.set_last_time(time);
+                    }
+                } else {
+                    match from_csa(&state.board, &line) {
+                        Ok(mv) if rules::is_legal(&state.board, &mv) => {
+                            state.play(&mv);
+                            if let Some(time) = time {
+                                state.record.set_last_time(time);
+                            }
+                            state.clock.switch();
+                        }
+                        _ => self.status = format!("the server sent an illegal move {}", line),
+                    }
+                }
+                if let Some(time) = time {
+                    self.spend(mover, time, &mut state.clock);
+                }
+                None
+            }
+            Event::End { reason, outcome } => {
+                Some(GameResult::from_csa(outcome, reason.as_deref(), human))
+            }
+            _ => None,
+        }
+    }
+
+    /// the title of the window: the clocks or the result, and the last notice
+    pub fn title(&self, game_title: &str, state: &GameState) -> String {
+        match state.result {
+            Some(result) => format!(
+                "{} | {} | N: next game, Esc: quit | {}",
+                game_title, result, self.status
+            ),
+            None => match &state.points {
+                Some(points) => format!(
+                    "{} | {} | {} | {}",
+                    game_title,
+                    state.clock.summary(),
+                    points,
+                    self.status
+                ),
+                None => format!(
+                    "{} | {} | {}",
+                    game_title,
+                    state.clock.summary(),
+                    self.status
+                ),
+            },
+        }
+    }
+}
+
+/// how a game played by `play` ended
+#[derive(Debug, Clone)]
+pub struct Finished {
+    pub summary: Summary,
+    /// the game, its result included
+    pub record: GameRecord,
+    pub outcome: Outcome,
+}
+
+/// play one game with a computer player: wait for a game, agree to it and play it to the end
+pub fn play(client: &mut Client, engine: &mut dyn Opponent) -> Result<Finished, String> {
+    let summary = loop {
+        if let Event::Summary(summary) = client.next_event()? {
+            break *summary;
+        }
+    };
+    client.agree(&summary)?;
+    loop {
+        match client.next_event()? {
+            Event::Start => break,
+            Event::Reject => return Err(format!("game {} was rejected", summary.game_id)),
+            _ => {}
+        }
+    }
+
+    let mut record = summary.record();
+    let mut board = record.current();
+    // whether our move was sent and not confirmed yet
+    let mut sent = false;
+    loop {
+        if board.get_color() == summary.color && !sent {
+            match engine.choose_move(&board) {
+                Some(mv) if mv == impasse::DECLARE => client.declare()?,
+                Some(mv) => client.send_move(&board, &mv)?,
+                None => client.resign()?,
+            }
+            sent = true;
+        }
+        match client.next_event()? {
+            Event::Move { csa, time } => {
+                let mv = from_csa(&board, &csa)?;
+                if !rules::is_legal(&board, &mv) {
+                    return Err(format!("the server sent an illegal move: {}", csa));
+                }
+                board = board.play_move_unchecked(&mv);
+                match time {
+                    Some(time) => record.push_timed(&mv, time),
+                    None => record.push(&mv),
+                }
+                sent = false;
+            }
+            Event::End { reason, outcome } => {
+                let result = GameResult::from_csa(outcome, reason.as_deref(), summary.color);
+                record.result = Some(result);
+                return Ok(Finished {
+                    summary,
+                    record,
+                    outcome,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// play one game on a CSA server with a built-in engine, without opening a window
+pub fn run(args: &Args) -> Result<(), String> {
+    let server = args.csa.as_deref().ok_or("no CSA server given")?;
+    let level = args.csa_engine.unwrap_or(Level::Normal);
+    let password = args.password.as_deref().unwrap_or("");
+    let mut client = Client::login(server, &args.player_name(), password)?;
+    println!(
+        "logged in to {} as {}, waiting for a game",
+        server,
+        client.name()
+    );
+
+    let mut engine = level.build(args.limits(level));
+    let finished = play(&mut client, engine.as_mut())?;
+    let _ = client.logout();
+
+    // game ids are made of letters, digits and a few separators, keep it that way
+    let name: String = finished
+        .summary
+        .game_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || "-_+.".contains(*c))
+        .collect();
+    let path = if name.is_empty() {
+        String::from("game.csa")
+    } else {
+        format!("{}.csa", name)
+    };
+    finished
+        .record
+        .save_csa(&path)
+        .map_err(|e| format!("can't save {}: {}", path, e))?;
+    println!(
+        "{} after {} moves (playing {}), record saved to {}",
+        finished.record.result.unwrap_or(GameResult::Abort),
+        finished.record.moves.len(),
+        sfen::side_name(finished.summary.color),
+        path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn notation() {
+        let board = Board::new();
+        assert_eq!(to_csa(&board, "P3c-3d"), Ok(String::from("+7776FU")));
+        assert_eq!(from_csa(&board, "+7776FU,T3"), Ok(String::from("P3c-3d")));
+        assert!(from_csa(&board, "-3334FU").is_err());
+
+        // moves and positions of a whole game survive the round trip
+        let mut board = Board::new();
+        for ply in 0..120 {
+            let read = read_position(write_position(&board).lines()).unwrap();
+            assert_eq!(sfen::to_sfen(&read, 1), sfen::to_sfen(&board, 1));
+            let moves = rules::legal_moves(&board);
+            let mv = match moves.get(ply * 7 % moves.len().max(1)) {
+                Some(mv) => mv.clone(),
+                None => break,
+            };
+            let csa = to_csa(&board, &mv).unwrap();
+            assert_eq!(from_csa(&board, &csa), Ok(mv.clone()));
+            board = board.play_move_unchecked(&mv);
+        }
+    }
+
+    /// a server that plays the first legal move it finds and resigns after `moves` moves of
+    /// the client
+    fn mock_server(listener: TcpListener, moves: usize) -> Vec<String> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut received = Vec::new();
+        let mut read = |received: &mut Vec<String>| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            received.push(line.trim_end().to_string());
+            line.trim_end().to_string()
+        };
+
+        assert_eq!(read(&mut received), "LOGIN tester secret");
+        writeln!(writer, "LOGIN:tester OK").unwrap();
+        let summary = "BEGIN Game_Summary\nProtocol_Version:1.2\nGame_ID:mock-1\n\
+            Name+:tester\nName-:mock\nYour_Turn:+\nTo_Move:+\nBEGIN Time\nTime_Unit:1sec\n\
+            Total_Time:600\nByoyomi:10\nEND Time\nBEGIN Position\nPI\n+\nEND Position\n\
+            END Game_Summary";
+        writeln!(writer, "{}", summary).unwrap();
+        assert_eq!(read(&mut received), "AGREE mock-1");
+        writeln!(writer, "START:mock-1").unwrap();
+
+        let mut board = Board::new();
+        for played in 1..=moves {
+            let csa = read(&mut received);
+            board = board.play_move_unchecked(&from_csa(&board, &csa).unwrap());
+            writeln!(writer, "{},T1", csa).unwrap();
+            if played == moves {
+                writeln!(writer, "%TORYO,T0\n#RESIGN\n#WIN").unwrap();
+                break;
+            }
+            let reply = rules::legal_moves(&board)[0].clone();
+            writeln!(writer, "{},T2", to_csa(&board, &reply).unwrap()).unwrap();
+            board = board.play_move_unchecked(&reply);
+        }
+        received
+    }
+
+    #[test]
+    fn game_against_mock_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || mock_server(listener, 3));
+
+        let mut client = Client::login(&address, "tester", "secret").unwrap();
+        let mut engine = Level::Greedy.build(Level::Greedy.default_limits());
+        let finished = play(&mut client, engine.as_mut()).unwrap();
+        let received = server.join().unwrap();
+
+        assert_eq!(finished.outcome, Outcome::Win);
+        assert_eq!(
+            finished.record.result,
+            Some(GameResult::Resignation {
+                winner: Color::White
+            })
+        );
+        assert_eq!(finished.summary.control.main, Duration::from_secs(600));
+        assert_eq!(finished.record.moves.len(), 5);
+        assert_eq!(finished.record.time(0), Some(Duration::from_secs(1)));
+        assert_eq!(finished.record.time(1), Some(Duration::from_secs(2)));
+        // everything the client sent after agreeing was a move
+        assert_eq!(received.len(), 5);
+
+        let text = write(&finished.record);
+        assert!(text.starts_with("V2.2\nN+tester\nN-mock\nPI\n+\n+"));
+        assert!(text.ends_with("T1\n%TORYO\n"));
+    }
+
+    #[test]
+    fn game_in_the_window() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            writeln!(writer, "LOGIN:tester OK").unwrap();
+            // the player's move back, the reply, a move out of turn, and the end
+            let lines = "+7776FU,T3\n-3334FU,T5\n-9394FU,T1\n%TORYO,T0\n#RESIGN\n#WIN";
+            writeln!(writer, "{}", lines).unwrap();
+            // wait for the client to leave
+            reader.read_line(&mut line).unwrap();
+        });
+
+        let client = Client::login(&address, "tester", "secret").unwrap();
+        let summary: Vec<String> = "Protocol_Version:1.2\nGame_ID:mock-2\nName+:tester\n\
+            Name-:mock\nYour_Turn:+\nTo_Move:+\nBEGIN Time\nTime_Unit:1sec\nTotal_Time:600\n\
+            Byoyomi:10\nEND Time\nBEGIN Position\nPI\n+\nEND Position"
+            .lines()
+            .map(String::from)
+            .collect();
+        let summary = Summary::parse(&summary).unwrap();
+        let mut state = GameState::new(
+            Board::new(),
+            summary.color,
+            summary.record(),
+            Clock::new(summary.control),
+            impasse::Rule::default(),
+        );
+        let mut game = CsaGame {
+            client,
+            summary,
+            used: [Duration::from_secs(0); 2],
+            status: String::new(),
+            error: None,
+        };
+        state.play(&from_csa(&state.board, "+7776FU").unwrap());
+        state.clock.start(Color::Black);
+
+        let mut ended = None;
+        for _ in 0..500 {
+            ended = game.poll(&mut state);
+            if ended.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            ended,
+            Some(GameResult::Resignation {
+                winner: Color::White
+            })
+        );
+        assert_eq!(state.record.moves.len(), 2);
+        assert_eq!(state.record.time(0), Some(Duration::from_secs(3)));
+        assert_eq!(state.record.time(1), Some(Duration::from_secs(5)));
+        assert_eq!(game.used, [Duration::from_secs(3), Duration::from_secs(6)]);
+        // the server's count, less what passed since on the running clock
+        let left = state.clock.main_time(Color::White);
+        assert!(left <= Duration::from_secs(597) && left > Duration::from_secs(590));
+        assert_eq!(game.status, "the server sent an illegal move -9394FU");
+        assert!(game
+            .title("Shogi - vs mock", &state)
+            .ends_with("| the server sent an illegal move -9394FU"));
+
+        game.client.logout().unwrap();
+        server.join().unwrap();
+    }
+}
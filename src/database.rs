@@ -0,0 +1,403 @@
+// A database of game records: the KIF files of a directory (and its subdirectories), indexed by
+// position so that the games reaching a position, and the moves played from it, are found with a
+// hash lookup.
+//
+// Games can be filtered by player, date, opening (the 戦型 header) and result, both in the GUI's
+// database panel and with the `--query` command.
+
+use crate::cli::Args;
+use crate::kif;
+use crate::record::GameRecord;
+use crate::repetition;
+use crate::result::GameResult;
+use crate::sfen;
+use crate::tsume;
+
+use shogai::board::*;
+use shogai::piece::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// the side that won, or a draw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Winner {
+    Sente,
+    Gote,
+    Draw,
+}
+
+impl Winner {
+    /// None for games without a result (or aborted)
+    fn of(record: &GameRecord) -> Option<Winner> {
+        let result = record
+            .result
+            .filter(|&result| result != GameResult::Abort)?;
+        Some(match result.winner() {
+            Some(Color::White) => Winner::Sente,
+            Some(Color::Black) => Winner::Gote,
+            None => Winner::Draw,
+        })
+    }
+}
+
+impl FromStr for Winner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "sente" => Ok(Winner::Sente),
+            "gote" => Ok(Winner::Gote),
+            "draw" => Ok(Winner::Draw),
+            _ => Err(format!("unknown result '{}' (sente, gote or draw)", s)),
+        }
+    }
+}
+
+/// which games to look at; an empty filter keeps them all
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// part of the name of either player, ignoring case
+    pub player: Option<String>,
+    /// start of the date, "2024" or "2024/03" ("2024-03" works too)
+    pub date: Option<String>,
+    /// part of the opening name
+    pub opening: Option<String>,
+    pub winner: Option<Winner>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &GameRecord) -> bool {
+        let player = self.player.as_ref().is_none_or(|player| {
+            let player = player.to_lowercase();
+            record.sente.to_lowercase().contains(&player)
+                || record.gote.to_lowercase().contains(&player)
+        });
+        let date = self.date.as_ref().is_none_or(|date| {
+            record
+                .date
+                .as_ref()
+                .is_some_and(|d| d.replace('-', "/").starts_with(&date.replace('-', "/")))
+        });
+        let opening = self.opening.as_ref().is_none_or(|opening| {
+            record
+                .opening
+                .as_ref()
+                .is_some_and(|o| o.contains(opening.as_str()))
+        });
+        let winner = self
+            .winner
+            .is_none_or(|winner| Winner::of(record) == Some(winner));
+        player && date && opening && winner
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub path: PathBuf,
+    pub record: GameRecord,
+}
+
+/// "2024/03/09 10:00:00  Taro vs Hanako  矢倉  Sente wins by resignation  87 moves"
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let record = &self.record;
+        write!(
+            f,
+            "{}  {} vs {}",
+            record.date.as_deref().unwrap_or("-"),
+            record.sente,
+            record.gote
+        )?;
+        if let Some(opening) = &record.opening {
+            write!(f, "  {}", opening)?;
+        }
+        match record.result {
+            Some(result) => write!(f, "  {}", result)?,
+            None => write!(f, "  no result")?,
+        }
+        write!(f, "  {} moves", record.moves.len())
+    }
+}
+
+/// what happened after a move in the games of the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveStats {
+    /// in shogai notation
+    pub mv: String,
+    pub games: u32,
+    pub sente_wins: u32,
+    pub gote_wins: u32,
+    pub draws: u32,
+}
+
+impl MoveStats {
+    /// "７六歩 120 games: sente 50%, gote 45%, draws 5%"
+    pub fn describe(&self, board: &Board) -> String {
+        let notation =
+            kif::move_notation(board, &self.mv, None).unwrap_or_else(|_| self.mv.clone());
+        let notation = notation.split('(').next().unwrap_or_default();
+        let share = |count: u32| count * 100 / self.games.max(1);
+        format!(
+            "{} {} game{}: sente {}%, gote {}%, draws {}%",
+            notation,
+            self.games,
+            if self.games == 1 { "" } else { "s" },
+            share(self.sente_wins),
+            share(self.gote_wins),
+            share(self.draws)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    games: Vec<Game>,
+    /// by position hash, the games (index and ply) where it appeared
+    positions: HashMap<u64, Vec<(usize, usize)>>,
+    /// files that could not be read, with why
+    pub skipped: Vec<String>,
+}
+
+/// the KIF files of a directory and its subdirectories, sorted
+fn kif_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            kif_files(&path, files)?;
+        } else if tsume::is_kif(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl Database {
+    /// index every KIF file under `dir`, skipping the ones that can't be read
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Database, String> {
+        let mut files = Vec::new();
+        kif_files(dir.as_ref(), &mut files)?;
+        let mut database = Database::default();
+        for path in files {
+            match kif::read_file(&path) {
+                Ok(record) => database.add(path, record),
+                Err(e) => database.skipped.push(e),
+            }
+        }
+        Ok(database)
+    }
+
+    pub fn add(&mut self, path: PathBuf, record: GameRecord) {
+        let index = self.games.len();
+        let mut board = record.start.clone();
+        for ply in 0..=record.moves.len() {
+            self.positions
+                .entry(repetition::hash(&board))
+                .or_default()
+                .push((index, ply));
+            if let Some(mv) = record.moves.get(ply) {
+                board = board.play_move_unchecked(mv);
+            }
+        }
+        self.games.push(Game { path, record });
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// the games kept by the filter
+    pub fn filter(&self, filter: &Filter) -> Vec<&Game> {
+        self.games
+            .iter()
+            .filter(|game| filter.matches(&game.record))
+            .collect()
+    }
+
+    /// the games kept by the filter reaching the position, with the ply it appeared at (the
+    /// first time, when it appeared more than once)
+    pub fn search(&self, board: &Board, filter: &Filter) -> Vec<(&Game, usize)> {
+        let mut found: Vec<(&Game, usize)> = Vec::new();
+        for &(index, ply) in self
+            .positions
+            .get(&repetition::hash(board))
+            .into_iter()
+            .flatten()
+        {
+            let game = &self.games[index];
+            let seen = found
+                .last()
+                .is_some_and(|(last, _)| std::ptr::eq(*last, game));
+            if !seen && filter.matches(&game.record) {
+                found.push((game, ply));
+            }
+        }
+        found
+    }
+
+    /// the moves played from the position in the games kept by the filter, the most played first
+    pub fn next_moves(&self, board: &Board, filter: &Filter) -> Vec<MoveStats> {
+        let mut stats: Vec<MoveStats> = Vec::new();
+        for (game, ply) in self.search(board, filter) {
+            let mv = match game.record.moves.get(ply) {
+                Some(mv) => mv,
+                None => continue,
+            };
+            let index = match stats.iter().position(|s| &s.mv == mv) {
+                Some(index) => index,
+                None => {
+                    stats.push(MoveStats {
+                        mv: mv.clone(),
+                        games: 0,
+                        sente_wins: 0,
+                        gote_wins: 0,
+                        draws: 0,
+                    });
+                    stats.len() - 1
+                }
+            };
+            let entry = &mut stats[index];
+            entry.games += 1;
+            match Winner::of(&game.record) {
+                Some(Winner::Sente) => entry.sente_wins += 1,
+                Some(Winner::Gote) => entry.gote_wins += 1,
+                Some(Winner::Draw) => entry.draws += 1,
+                None => {}
+            }
+        }
+        stats.sort_by(|a, b| b.games.cmp(&a.games).then_with(|| a.mv.cmp(&b.mv)));
+        stats
+    }
+}
+
+/// the filter given on the command line
+pub fn filter_of(args: &Args) -> Filter {
+    Filter {
+        player: args.player.clone(),
+        date: args.date.clone(),
+        opening: args.opening.clone(),
+        winner: args.winner,
+    }
+}
+
+/// answer the query given on the command line: the games kept by the filters, or the games
+/// reaching a position with the moves played from it
+pub fn run(args: &Args) -> Result<(), String> {
+    let dir = args
+        .database
+        .as_ref()
+        .ok_or("--query needs a --db directory")?;
+    let database = Database::load(dir)?;
+    for skipped in &database.skipped {
+        eprintln!("skipped {}", skipped);
+    }
+    let filter = filter_of(args);
+    match &args.position {
+        None => {
+            let games = database.filter(&filter);
+            for game in &games {
+                println!("{}  {}", game.path.display(), game);
+            }
+            println!("{} of {} games", games.len(), database.len());
+        }
+        Some(position) => {
+            let (board, _) = sfen::from_sfen(position)?;
+            let found = database.search(&board, &filter);
+            for (game, ply) in &found {
+                println!("{}  {}  (move {})", game.path.display(), game, ply + 1);
+            }
+            println!(
+                "{} of {} games reach the position",
+                found.len(),
+                database.len()
+            );
+            for stats in database.next_moves(&board, &filter) {
+                println!("  {}", stats.describe(&board));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+
+    fn game(sente: &str, gote: &str, usi: &[&str], winner: Color) -> GameRecord {
+        let mut record = GameRecord::new(Handicap::Even, sente, gote);
+        let mut board = Board::new();
+        for usi in usi {
+            let mv = sfen::from_usi(usi, &board).unwrap();
+            board = board.play_move_unchecked(&mv);
+            record.push(&mv);
+        }
+        record.result = Some(GameResult::Resignation { winner });
+        record.date = Some(String::from("2024/03/09 10:00:00"));
+        record
+    }
+
+    #[test]
+    fn filters_and_position_search() {
+        let mut database = Database::default();
+        let mut aborted = game("Saburo", "Shiro", &["2g2f", "8c8d"], Color::White);
+        aborted.result = Some(GameResult::Abort);
+        let games = [
+            game("Taro", "Hanako", &["7g7f", "3c3d", "2g2f"], Color::White),
+            game("Hanako", "Jiro", &["2g2f", "3c3d", "7g7f"], Color::Black),
+            game("Jiro", "Taro", &["7g7f", "8c8d"], Color::White),
+            aborted,
+        ];
+        for (i, record) in games.iter().enumerate() {
+            database.add(PathBuf::from(format!("{}.kif", i)), record.clone());
+        }
+
+        let filter = |player: &str| Filter {
+            player: Some(player.to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(database.filter(&filter("taro")).len(), 2);
+        assert_eq!(database.filter(&filter("nobody")).len(), 0);
+        let sente_wins = Filter {
+            winner: Some(Winner::Sente),
+            date: Some(String::from("2024-03")),
+            ..Filter::default()
+        };
+        assert_eq!(database.filter(&sente_wins).len(), 2);
+        // aborted games have no winner, they aren't draws
+        let draws = Filter {
+            winner: Some(Winner::Draw),
+            ..Filter::default()
+        };
+        assert_eq!(database.filter(&draws).len(), 0);
+
+        // the same position reached with the moves in another order
+        let board = Board::new();
+        let after = ["7g7f", "3c3d", "2g2f"]
+            .iter()
+            .fold(board.clone(), |board, usi| {
+                board.play_move_unchecked(&sfen::from_usi(usi, &board).unwrap())
+            });
+        assert_eq!(database.search(&after, &Filter::default()).len(), 2);
+
+        let stats = database.next_moves(&board, &Filter::default());
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].mv, sfen::from_usi("7g7f", &board).unwrap());
+        assert_eq!((stats[0].games, stats[0].sente_wins), (2, 2));
+        assert_eq!(
+            stats[1].describe(&board),
+            "２六歩 2 games: sente 0%, gote 50%, draws 0%"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// Board diagrams for print: the position as SVG, with a vector grid, the coordinates, the hands and
+// their counts, and optionally highlighted squares, arrows and a caption. The PNG diagrams are the
+// window's own drawing, see headless.rs.
+
+use crate::cli::Args;
+use crate::handicap::Handicap;
+use crate::kif;
+use crate::settings::Settings;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// size of a square, in SVG units
+const SQUARE: i32 = 40;
+const MARGIN: i32 = 20;
+/// height of a line of text: the hands, the file numbers, the caption
+const LINE: i32 = 30;
+const BOARD_TOP: i32 = MARGIN + 2 * LINE;
+const BOARD_BOTTOM: i32 = BOARD_TOP + 9 * SQUARE;
+const WIDTH: i32 = 2 * MARGIN + 9 * SQUARE + LINE;
+
+const FONT: &str = "'Noto Serif CJK JP', 'Hiragino Mincho ProN', 'Yu Mincho', serif";
+const FILES: [&str; 9] = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+#[derive(Debug, Clone)]
+pub struct Diagram {
+    pub board: Board,
+    /// gote's side at the bottom
+    pub flip: bool,
+    /// squares drawn in colour, under the pieces
+    pub highlights: Vec<Position>,
+    /// from a square to another, over the pieces
+    pub arrows: Vec<(Position, Position)>,
+    pub caption: Option<String>,
+}
+
+impl Diagram {
+    pub fn new(board: Board) -> Self {
+        Diagram {
+            board,
+            flip: false,
+            highlights: Vec::new(),
+            arrows: Vec::new(),
+            caption: None,
+        }
+    }
+
+    pub fn svg(&self) -> String {
+        let height = BOARD_BOTTOM + LINE + self.caption.as_ref().map_or(MARGIN, |_| LINE + MARGIN);
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\" font-family=\"{font}\">",
+            w = WIDTH,
+            h = height,
+            font = FONT
+        );
+        svg.push_str(
+            "<defs><marker id=\"head\" viewBox=\"0 0 10 10\" refX=\"5\" refY=\"5\" \
+             markerWidth=\"3\" markerHeight=\"3\" orient=\"auto\">\
+             <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#2e7d32\"/></marker></defs>\n",
+        );
+        let _ = writeln!(
+            svg,
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            WIDTH, height
+        );
+
+        // the hand of the side at the top, above the file numbers
+        let (top, bottom) = if self.flip {
+            (Color::White, Color::Black)
+        } else {
+            (Color::Black, Color::White)
+        };
+        text(
+            &mut svg,
+            MARGIN,
+            MARGIN + LINE / 2,
+            "start",
+            18,
+            &self.hand(top),
+        );
+        for column in 0..9 {
+            let file = if self.flip { column } else { 8 - column };
+            let x = MARGIN + column * SQUARE + SQUARE / 2;
+            text(
+                &mut svg,
+                x,
+                BOARD_TOP - 10,
+                "middle",
+                16,
+                FILES[file as usize],
+            );
+        }
+        for row in 0..9 {
+            let rank = if self.flip { 8 - row } else { row };
+            let y = BOARD_TOP + row * SQUARE + SQUARE / 2 + 6;
+            let x = MARGIN + 9 * SQUARE + LINE / 2;
+            text(&mut svg, x, y, "middle", 16, kif::RANKS[rank as usize]);
+        }
+
+        for &pos in &self.highlights {
+            let (x, y) = self.origin(pos);
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" fill=\"#ffd54f\" \
+                 fill-opacity=\"0.6\"/>",
+                x,
+                y,
+                s = SQUARE
+            );
+        }
+        self.grid(&mut svg);
+        for piece in self.board.iter().filter(|p| p.position.is_some()) {
+            self.piece(&mut svg, piece);
+        }
+        for &(from, to) in &self.arrows {
+            let (x1, y1) = self.center(from);
+            let (x2, y2) = self.center(to);
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#2e7d32\" \
+                 stroke-width=\"6\" stroke-opacity=\"0.8\" stroke-linecap=\"round\" \
+                 marker-end=\"url(#head)\"/>",
+                x1, y1, x2, y2
+            );
+        }
+
+        let y = BOARD_BOTTOM + LINE * 2 / 3;
+        text(&mut svg, MARGIN, y, "start", 18, &self.hand(bottom));
+        if let Some(caption) = &self.caption {
+            let y = BOARD_BOTTOM + LINE + LINE * 2 / 3;
+            text(&mut svg, WIDTH / 2, y, "middle", 18, caption);
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn save_svg<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.svg()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// top left corner of a square
+    fn origin(&self, pos: Position) -> (i32, i32) {
+        let (file, rank) = sfen::file_rank(pos);
+        let (column, row) = if self.flip {
+            (file as i32 - 1, 9 - rank as i32)
+        } else {
+            (9 - file as i32, rank as i32 - 1)
+        };
+        (MARGIN + column * SQUARE, BOARD_TOP + row * SQUARE)
+    }
+
+    fn center(&self, pos: Position) -> (i32, i32) {
+        let (x, y) = self.origin(pos);
+        (x + SQUARE / 2, y + SQUARE / 2)
+    }
+
+    /// the lines, the thicker border and the four stars
+    fn grid(&self, svg: &mut String) {
+        for i in 1..9 {
+            let x = MARGIN + i * SQUARE;
+            let y = BOARD_TOP + i * SQUARE;
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\" stroke=\"black\"/>\
+                 <line x1=\"{}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"black\"/>",
+                BOARD_TOP,
+                BOARD_BOTTOM,
+                MARGIN,
+                MARGIN + 9 * SQUARE,
+                x = x,
+                y = y
+            );
+        }
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{}\" y=\"{}\" width=\"{s}\" height=\"{s}\" fill=\"none\" stroke=\"black\" \
+             stroke-width=\"2.5\"/>",
+            MARGIN,
+            BOARD_TOP,
+            s = 9 * SQUARE
+        );
+        for &(i, j) in &[(3, 3), (3, 6), (6, 3), (6, 6)] {
+            let _ = writeln!(
+                svg,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"3\"/>",
+                MARGIN + i * SQUARE,
+                BOARD_TOP + j * SQUARE
+            );
+        }
+    }
+
+    /// a piece, pointing at the other side: its pentagon and its name
+    fn piece(&self, svg: &mut String, piece: &Piece) {
+        let pos = match piece.position {
+            Some(pos) => pos,
+            None => return,
+        };
+        let (x, y) = self.center(pos);
+        let upside_down = (piece.color == Color::Black) != self.flip;
+        let _ = write!(
+            svg,
+            "<g transform=\"rotate({} {} {})\">",
+            if upside_down { 180 } else { 0 },
+            x,
+            y
+        );
+        let _ = write!(
+            svg,
+            "<polygon points=\"{},{} {},{} {},{} {},{} {},{}\" fill=\"#f3dcaa\" stroke=\"black\"/>",
+            x,
+            y - 17,
+            x + 12,
+            y - 12,
+            x + 15,
+            y + 17,
+            x - 15,
+            y + 17,
+            x - 12,
+            y - 12
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"22\" fill=\"{}\">{}</text></g>",
+            x,
+            y + 10,
+            if piece.promoted { "#c00000" } else { "black" },
+            kif::diagram_name(piece)
+        );
+    }
+
+    /// "☗先手 持駒：飛　歩三"
+    fn hand(&self, color: Color) -> String {
+        let mark = match color {
+            Color::White => "☗先手",
+            Color::Black => "☖後手",
+        };
+        format!("{} 持駒：{}", mark, kif::hand(&self.board, color))
+    }
+}
+
+fn text(svg: &mut String, x: i32, y: i32, anchor: &str, size: u32, text: &str) {
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"{}\" font-size=\"{}\">{}</text>",
+        x,
+        y,
+        anchor,
+        size,
+        escape(text)
+    );
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// the position of --position, or the start of the game the command line asks for
+pub fn board_of(args: &Args) -> Result<Board, String> {
+    match &args.position {
+        Some(position) => Ok(sfen::from_sfen(position)?.0),
+        None => Ok(args.handicap.unwrap_or(Handicap::Even).board()),
+    }
+}
+
+/// --export-svg: the diagram of the position of the command line, with its highlights, arrows
+/// and caption, the board turned as in the settings
+pub fn export_svg(args: &Args, path: &Path) -> Result<(), String> {
+    let mut diagram = Diagram::new(board_of(args)?);
+    diagram.flip = Settings::load().flip;
+    diagram.highlights = args.highlights.clone();
+    diagram.arrows = args.arrows.clone();
+    diagram.caption = args.caption.clone();
+    diagram.save_svg(path)?;
+    println!("diagram saved to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(usi: &str) -> Position {
+        sfen::parse_usi_square(usi).unwrap()
+    }
+
+    #[test]
+    fn every_piece_is_drawn() {
+        let svg = Diagram::new(Handicap::Even.board()).svg();
+        assert_eq!(svg.matches("<polygon").count(), 40);
+        assert_eq!(svg.matches(">玉<").count(), 2);
+        // gote's 20 pieces are upside down
+        assert_eq!(svg.matches("rotate(180").count(), 20);
+        assert!(svg.contains("☗先手 持駒：なし"));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn hands_have_their_counts() {
+        let board = sfen::from_sfen("4k4/9/9/9/9/9/9/9/4K4 b R3Pb 1").unwrap().0;
+        let svg = Diagram::new(board).svg();
+        assert!(svg.contains("☗先手 持駒：飛　歩三"));
+        assert!(svg.contains("☖後手 持駒：角"));
+    }
+
+    #[test]
+    fn flipped_boards_have_gote_at_the_bottom() {
+        let mut diagram = Diagram::new(Handicap::Even.board());
+        assert_eq!(
+            diagram.center(square("9a")),
+            (MARGIN + SQUARE / 2, BOARD_TOP + SQUARE / 2)
+        );
+        diagram.flip = true;
+        assert_eq!(
+            diagram.center(square("1i")),
+            (MARGIN + SQUARE / 2, BOARD_TOP + SQUARE / 2)
+        );
+        let svg = diagram.svg();
+        assert_eq!(svg.matches("rotate(180").count(), 20);
+        // sente's hand is written first, at the top
+        assert!(svg.find("☗先手").unwrap() < svg.find("☖後手").unwrap());
+    }
+
+    #[test]
+    fn highlights_arrows_and_caption() {
+        let mut diagram = Diagram::new(Handicap::Even.board());
+        diagram.highlights = vec![square("7f")];
+        diagram.arrows = vec![(square("7g"), square("7f")), (square("2g"), square("2f"))];
+        diagram.caption = Some(String::from("Black <to> play & win"));
+        let svg = diagram.svg();
+        assert_eq!(svg.matches("fill=\"#ffd54f\"").count(), 1);
+        assert_eq!(svg.matches("marker-end").count(), 2);
+        assert!(svg.contains("Black &lt;to&gt; play &amp; win"));
+    }
+}
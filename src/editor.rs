@@ -0,0 +1,489 @@
+// Position setup: free placement of pieces, without any of the rules of the game, and the checks
+// the result has to pass before a game can start from it.
+
+use crate::rules;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::fmt;
+
+/// a place a piece can be taken from or put on while editing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spot {
+    Square(Position),
+    Hand(Color, PieceType),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    MissingKing(Color),
+    TooManyKings(Color),
+    TooManyPieces(PieceType),
+    KingInHand(Color),
+    /// color and standard file of the doubled pawns
+    Nifu(Color, u8),
+    ImmovablePiece(Position),
+    OpponentInCheck,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::MissingKing(color) => write!(f, "{} has no king", sfen::side_name(*color)),
+            EditError::TooManyKings(color) => {
+                write!(f, "{} has more than one king", sfen::side_name(*color))
+            }
+            EditError::TooManyPieces(piecetype) => {
+                write!(f, "There are more {:?}s than in a shogi set", piecetype)
+            }
+            EditError::KingInHand(color) => {
+                write!(f, "{} has a king in hand", sfen::side_name(*color))
+            }
+            EditError::Nifu(color, file) => write!(
+                f,
+                "{} has two unpromoted pawns on file {} (nifu)",
+                sfen::side_name(*color),
+                file
+            ),
+            EditError::ImmovablePiece(pos) => {
+                write!(f, "The piece on {} can never move", sfen::usi_square(*pos))
+            }
+            EditError::OpponentInCheck => {
+                write!(
+                    f,
+                    "The side not to move is in check, its king could be taken"
+                )
+            }
+        }
+    }
+}
+
+/// number of pieces of each kind in a shogi set
+fn set_count(piecetype: PieceType) -> usize {
+    match piecetype {
+        PieceType::Pawn => 18,
+        PieceType::Lance | PieceType::Knight | PieceType::Silver | PieceType::Gold => 4,
+        PieceType::Bishop | PieceType::Rook | PieceType::King => 2,
+    }
+}
+
+pub fn can_promote(piecetype: PieceType) -> bool {
+    piecetype != PieceType::Gold && piecetype != PieceType::King
+}
+
+/// what the mouse and the keys do to the position being edited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditAction {
+    /// pick the piece of a spot up (the left button going down)
+    Pick(Option<Spot>),
+    /// put the piece picked up down (the left button going up)
+    Put(Option<Spot>),
+    TogglePromotion(Position),
+    SwitchOwner(Position),
+    Place(Position, Piece),
+    Remove(Spot),
+    SwitchSide,
+    Clear,
+    /// the even starting position
+    Reset,
+    /// play from the position (left to the caller)
+    Start,
+    /// leave the editor (left to the caller)
+    Cancel,
+}
+
+pub struct Editor {
+    board: Board,
+    /// the spot of the piece picked up
+    drag: Option<Spot>,
+}
+
+impl Editor {
+    pub fn new(board: Board) -> Self {
+        Editor { board, drag: None }
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// same board, without the given piece
+    fn without(&self, removed: Piece) -> Board {
+        let mut board = Board::empty();
+        board.turn = self.board.turn;
+        board.rules = self.board.rules;
+        let mut skipped = false;
+        for piece in self.board.iter() {
+            if !skipped && *piece == removed {
+                skipped = true;
+                continue;
+            }
+            board.add_piece(*piece);
+        }
+        board
+    }
+
+    /// the piece standing on a spot (any of them for hands)
+    pub fn piece_at(&self, spot: Spot) -> Option<Piece> {
+        match spot {
+            Spot::Square(pos) => self.board.is_occupied_by(pos),
+            Spot::Hand(color, piecetype) => self
+                .board
+                .iter()
+                .find(|p| p.position.is_none() && p.color == color && p.piecetype == piecetype)
+                .copied(),
+        }
+    }
+
+    pub fn remove(&mut self, spot: Spot) -> Option<Piece> {
+        let piece = self.piece_at(spot)?;
+        self.board = self.without(piece);
+        Some(piece)
+    }
+
+    /// put a piece on a square, replacing what was there. Fails if the set has no such piece
+    /// left.
+    pub fn place(&mut self, pos: Position, piece: Piece) -> bool {
+        let replaced = self.remove(Spot::Square(pos));
+        let count = self
+            .board
+            .iter()
+            .filter(|p| p.piecetype == piece.piecetype)
+            .count();
+        if count >= set_count(piece.piecetype) {
+            if let Some(replaced) = replaced {
+                self.board.add_piece(replaced);
+            }
+            return false;
+        }
+        self.board.add_piece(Piece {
+            position: Some(pos),
+            ..piece
+        });
+        true
+    }
+
+    /// move a piece between squares and hands. Pieces put in a hand change owner and lose their
+    /// promotion, pieces taken from a hand keep the hand's owner. Kings can't go in hands.
+    pub fn move_piece(&mut self, from: Spot, to: Spot) -> bool {
+        let piece = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+        if from == to {
+            return false;
+        }
+        match to {
+            Spot::Square(pos) => {
+                self.remove(from);
+                // the piece was just taken out, so there is room for it in the set
+                self.place(pos, piece);
+            }
+            Spot::Hand(color, _) => {
+                if piece.piecetype == PieceType::King {
+                    return false;
+                }
+                self.remove(from);
+                self.board.add_piece(Piece {
+                    color,
+                    position: None,
+                    promoted: false,
+                    ..piece
+                });
+            }
+        }
+        true
+    }
+
+    pub fn toggle_promotion(&mut self, pos: Position) {
+        if let Some(piece) = self.board.is_occupied_by(pos) {
+            if can_promote(piece.piecetype) {
+                self.place(
+                    pos,
+                    Piece {
+                        promoted: !piece.promoted,
+                        ..piece
+                    },
+                );
+            }
+        }
+    }
+
+    /// give the piece on a square to the other player
+    pub fn switch_owner(&mut self, pos: Position) {
+        if let Some(mut piece) = self.board.is_occupied_by(pos) {
+            piece.color.invert();
+            self.place(pos, piece);
+        }
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.board.get_color()
+    }
+
+    pub fn set_side_to_move(&mut self, color: Color) {
+        self.board.turn = color;
+    }
+
+    /// remove everything, kings included
+    pub fn clear(&mut self) {
+        let turn = self.board.turn;
+        self.board = Board::empty();
+        self.board.turn = turn;
+    }
+
+    /// the piece picked up, following the mouse
+    pub fn dragged(&self) -> Option<Piece> {
+        self.drag.and_then(|spot| self.piece_at(spot))
+    }
+
+    pub fn apply(&mut self, action: EditAction) {
+        match action {
+            EditAction::Pick(spot) => {
+                self.drag = spot.filter(|&spot| self.piece_at(spot).is_some())
+            }
+            EditAction::Put(spot) => {
+                if let (Some(from), Some(to)) = (self.drag.take(), spot) {
+                    self.move_piece(from, to);
+                }
+            }
+            EditAction::TogglePromotion(pos) => self.toggle_promotion(pos),
+            EditAction::SwitchOwner(pos) => self.switch_owner(pos),
+            EditAction::Place(pos, piece) => {
+                self.place(pos, piece);
+            }
+            EditAction::Remove(spot) => {
+                self.remove(spot);
+            }
+            EditAction::SwitchSide => {
+                let mut color = self.side_to_move();
+                color.invert();
+                self.set_side_to_move(color);
+            }
+            EditAction::Clear => self.clear(),
+            EditAction::Reset => *self = Editor::new(Board::new()),
+            EditAction::Start | EditAction::Cancel => {}
+        }
+    }
+
+    /// check that a game can be played from this position
+    pub fn validate(&self) -> Result<(), EditError> {
+        for &color in &[Color::White, Color::Black] {
+            let kings = self
+                .board
+                .iter()
+                .filter(|p| p.piecetype == PieceType::King && p.color == color)
+                .collect::<Vec<_>>();
+            if kings.is_empty() {
+                return Err(EditError::MissingKing(color));
+            }
+            if kings.len() > 1 {
+                return Err(EditError::TooManyKings(color));
+            }
+            if kings[0].position.is_none() {
+                return Err(EditError::KingInHand(color));
+            }
+        }
+
+        for &piecetype in &[
+            PieceType::Pawn,
+            PieceType::Lance,
+            PieceType::Knight,
+            PieceType::Silver,
+            PieceType::Gold,
+            PieceType::Bishop,
+            PieceType::Rook,
+        ] {
+            if self
+                .board
+                .iter()
+                .filter(|p| p.piecetype == piecetype)
+                .count()
+                > set_count(piecetype)
+            {
+                return Err(EditError::TooManyPieces(piecetype));
+            }
+        }
+
+        for piece in self.board.iter().filter(|p| !p.promoted) {
+            if let Some(pos) = piece.position {
+                if rules::is_stuck(piece.piecetype, piece.color, pos) {
+                    return Err(EditError::ImmovablePiece(pos));
+                }
+                if piece.piecetype == PieceType::Pawn
+                    && self.board.iter().any(|other| {
+                        other != piece
+                            && other.piecetype == PieceType::Pawn
+                            && other.color == piece.color
+                            && !other.promoted
+                            && other.position.is_some_and(|p| p.0 % 9 == pos.0 % 9)
+                    })
+                {
+                    return Err(EditError::Nifu(piece.color, sfen::file_rank(pos).0));
+                }
+            }
+        }
+
+        // the side to move must not be able to take the king right away
+        let opponent = {
+            let mut color = self.board.get_color();
+            color.invert();
+            color
+        };
+        let king = self
+            .board
+            .iter()
+            .find(|p| p.piecetype == PieceType::King && p.color == opponent)
+            .and_then(|p| p.position);
+        if self
+            .board
+            .iter_normal_moves_only(false)
+            .any(|mv| mv.parse::<shogai::movement::Movement>().ok().map(|m| m.end) == king)
+        {
+            return Err(EditError::OpponentInCheck);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(position: &str) -> Editor {
+        Editor::new(sfen::from_sfen(position).unwrap().0)
+    }
+
+    fn square(usi: &str) -> Position {
+        sfen::parse_usi_square(usi).unwrap()
+    }
+
+    fn piece(piecetype: PieceType, color: Color) -> Piece {
+        Piece {
+            piecetype,
+            color,
+            promoted: false,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn positions_a_game_can_start_from() {
+        assert_eq!(editor("startpos").validate(), Ok(()));
+        // gote to move while in check is fine, it just has to answer it
+        assert_eq!(editor("4k4/9/9/9/4R4/9/9/9/K8 w - 1").validate(), Ok(()));
+        // promoted pieces can stand anywhere
+        assert_eq!(editor("+P3k3+N/9/9/9/9/9/9/9/4K4 b - 1").validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejected_positions() {
+        let rejected = [
+            (
+                "4k4/9/9/9/9/9/9/9/9 b - 1",
+                EditError::MissingKing(Color::White),
+            ),
+            (
+                "4k4/9/9/9/9/9/9/9/3KK4 b - 1",
+                EditError::TooManyKings(Color::White),
+            ),
+            (
+                "9/9/9/9/9/9/9/9/4K4 b k 1",
+                EditError::KingInHand(Color::Black),
+            ),
+            (
+                "4k4/9/9/9/9/9/9/9/4K4 b 3R 1",
+                EditError::TooManyPieces(PieceType::Rook),
+            ),
+            (
+                "4k4/9/9/9/9/4P4/4P4/9/4K4 b - 1",
+                EditError::Nifu(Color::White, 5),
+            ),
+            (
+                "P3k4/9/9/9/9/9/9/9/4K4 b - 1",
+                EditError::ImmovablePiece(square("9a")),
+            ),
+            (
+                "4k4/N8/9/9/9/9/9/9/4K4 b - 1",
+                EditError::ImmovablePiece(square("9b")),
+            ),
+            (
+                "4k4/9/9/9/9/9/9/9/4K3l w - 1",
+                EditError::ImmovablePiece(square("1i")),
+            ),
+            ("4k4/9/9/9/4R4/9/9/9/K8 b - 1", EditError::OpponentInCheck),
+        ];
+        for (position, error) in rejected {
+            assert_eq!(editor(position).validate(), Err(error), "{}", position);
+        }
+    }
+
+    #[test]
+    fn editing() {
+        let mut editor = editor("4k4/9/9/9/9/9/9/9/4K4 b - 1");
+        let rook = piece(PieceType::Rook, Color::White);
+        assert!(editor.place(square("5e"), rook));
+        assert!(editor.place(square("4e"), rook));
+        // a set has two rooks only
+        assert!(!editor.place(square("3e"), rook));
+
+        editor.toggle_promotion(square("5e"));
+        assert!(
+            editor
+                .piece_at(Spot::Square(square("5e")))
+                .unwrap()
+                .promoted
+        );
+        // kings and golds don't promote
+        editor.toggle_promotion(square("5i"));
+        assert!(
+            !editor
+                .piece_at(Spot::Square(square("5i")))
+                .unwrap()
+                .promoted
+        );
+
+        // taken into a hand, the dragon turns back into a rook of the hand's owner
+        editor.apply(EditAction::Pick(Some(Spot::Square(square("5e")))));
+        editor.apply(EditAction::Put(Some(Spot::Hand(
+            Color::Black,
+            PieceType::Rook,
+        ))));
+        let taken = editor
+            .piece_at(Spot::Hand(Color::Black, PieceType::Rook))
+            .unwrap();
+        assert!(!taken.promoted);
+        assert_eq!(editor.piece_at(Spot::Square(square("5e"))), None);
+
+        // and back on the board from there
+        assert!(editor.move_piece(
+            Spot::Hand(Color::Black, PieceType::Rook),
+            Spot::Square(square("5e"))
+        ));
+        assert_eq!(
+            editor.piece_at(Spot::Square(square("5e"))).unwrap().color,
+            Color::Black
+        );
+        editor.switch_owner(square("5e"));
+        assert_eq!(
+            editor.piece_at(Spot::Square(square("5e"))).unwrap().color,
+            Color::White
+        );
+
+        // kings stay on the board
+        let king = Spot::Square(square("5i"));
+        assert!(!editor.move_piece(king, Spot::Hand(Color::Black, PieceType::King)));
+        assert!(editor.piece_at(king).is_some());
+
+        editor.apply(EditAction::SwitchSide);
+        assert_eq!(editor.side_to_move(), Color::Black);
+        editor.apply(EditAction::Clear);
+        assert_eq!(editor.validate(), Err(EditError::MissingKing(Color::White)));
+        editor.apply(EditAction::Reset);
+        assert_eq!(editor.validate(), Ok(()));
+    }
+}
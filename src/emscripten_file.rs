@@ -1,9 +1,9 @@
 // taken from https://github.com/Gigoteur/PX8/blob/master/src/px8/emscripten.rs
 
-// #[cfg(target_os = "emscripten")]
+#[cfg(target_os = "emscripten")]
 pub mod emscripten_mod {
     use std::cell::RefCell;
-    use std::os::raw::{c_float, c_int, c_void};
+    use std::os::raw::{c_float, c_int, c_uint, c_void};
     use std::ptr::null_mut;
 
     #[allow(non_camel_case_types)]
@@ -17,6 +17,8 @@ pub mod emscripten_mod {
         );
         pub fn emscripten_cancel_main_loop();
         pub fn emscripten_get_now() -> c_float;
+        /// gives the hand back to the browser for a while (needs a build with ASYNCIFY)
+        pub fn emscripten_sleep(ms: c_uint);
     }
 
     thread_local!(static MAIN_LOOP_CALLBACK: RefCell<*mut c_void> = RefCell::new(null_mut()));
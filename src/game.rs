@@ -0,0 +1,673 @@
+// The game being played in the window, without the window: the board and its record, the clock,
+// the result, and the piece the player picked up. The clicks come from the input handler and the
+// renderer draws what's here, so all of it can be played (and tested) without SDL. So is the
+// computer's side, thinking on a worker thread.
+
+use crate::clock::Clock;
+use crate::impasse;
+use crate::opponent::Opponent;
+use crate::record::GameRecord;
+use crate::repetition::Repetitions;
+use crate::result::GameResult;
+use crate::rules;
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+#[cfg(not(target_os = "emscripten"))]
+use std::thread;
+
+/// what a click landed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Square(Position),
+    /// the hand of the side to move
+    Hand(PieceType),
+    Outside,
+}
+
+pub struct GameState {
+    pub board: Board,
+    /// the side played with the mouse
+    pub human: Color,
+    pub record: GameRecord,
+    pub clock: Clock,
+    pub rule: impasse::Rule,
+    pub result: Option<GameResult>,
+    /// impasse points, once a king entered the enemy camp
+    pub points: Option<String>,
+    /// tsume: every move of the player has to be a check
+    pub checks_only: bool,
+    /// positions seen so far, for sennichite
+    repetitions: Repetitions,
+    /// a move was played since the end of the game was last looked for
+    played: bool,
+    /// the piece picked up and its square (None for a piece in hand)
+    selected: Option<PieceType>,
+    selected_at: Option<Position>,
+    /// the piece not drawn where it stands, it follows the mouse
+    hidden: Option<Piece>,
+}
+
+impl GameState {
+    pub fn new(
+        board: Board,
+        human: Color,
+        record: GameRecord,
+        clock: Clock,
+        rule: impasse::Rule,
+    ) -> Self {
+        let repetitions = Repetitions::new(&record.start);
+        GameState {
+            board,
+            human,
+            record,
+            clock,
+            rule,
+            result: None,
+            points: None,
+            checks_only: false,
+            repetitions,
+            played: false,
+            selected: None,
+            selected_at: None,
+            hidden: None,
+        }
+    }
+
+    /// the player can move
+    pub fn human_turn(&self) -> bool {
+        self.result.is_none() && self.board.get_color() == self.human
+    }
+
+    /// a piece was picked up, the next click puts it down
+    pub fn holding(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    /// the square clicked last
+    pub fn clicked(&self) -> Option<Position> {
+        self.selected_at
+    }
+
+    /// the square of the piece picked up, to highlight
+    pub fn selected_square(&self) -> Option<Position> {
+        self.selected_at.filter(|&pos| {
+            self.board
+                .is_occupied_by(pos)
+                .is_some_and(|piece| piece.color == self.board.get_color())
+        })
+    }
+
+    /// the piece not drawn on its square (or in its hand)
+    pub fn hidden(&self) -> Option<Piece> {
+        self.hidden
+    }
+
+    /// the piece following the mouse
+    pub fn floating(&self) -> Option<Piece> {
+        let piecetype = self.selected?;
+        match self.selected_at {
+            Some(_) => self
+                .selected_square()
+                .and_then(|pos| self.board.is_occupied_by(pos)),
+            None => Some(Piece {
+                color: self.board.get_color(),
+                piecetype,
+                position: None,
+                promoted: false,
+            }),
+        }
+    }
+
+    /// put down whatever was picked up
+    pub fn clear_selection(&mut self) {
+        self.selected = None;
+        self.selected_at = None;
+        self.hidden = None;
+    }
+
+    /// a click of the player: the first picks a piece up, the second plays it where it lands.
+    /// `promote` is asked when the move can be played both promoted and not. Returns the move
+    /// played, or why it can't be.
+    pub fn click(
+        &mut self,
+        target: Target,
+        promote: impl FnOnce() -> bool,
+    ) -> Result<Option<String>, String> {
+        let (picked, start) = (self.selected, self.selected_at);
+        let side = self.board.get_color();
+        match target {
+            Target::Square(pos) => match self.board.is_occupied_by(pos) {
+                Some(piece) if piece.color == side => {
+                    self.selected = Some(piece.piecetype);
+                    self.hidden = Some(piece);
+                }
+                Some(_) => self.selected = None,
+                None => {
+                    self.selected = None;
+                    self.hidden = None;
+                }
+            },
+            //drag n drop from reserve (drop move)
+            Target::Hand(piecetype)
+                if self.board.iter().any(|p| {
+                    p.color == side && p.piecetype == piecetype && p.position.is_none()
+                }) =>
+            {
+                self.selected = Some(piecetype);
+                self.hidden = Some(Piece {
+                    color: side,
+                    piecetype,
+                    position: None,
+                    promoted: false,
+                });
+            }
+            _ => {
+                self.selected = None;
+                self.hidden = None;
+            }
+        }
+        self.selected_at = match target {
+            Target::Square(pos) => Some(pos),
+            _ => None,
+        };
+
+        let (piecetype, end) = match (picked, self.selected_at) {
+            (Some(piecetype), Some(end)) => (piecetype, end),
+            _ => return Ok(None),
+        };
+        let plain = Movement {
+            piecetype,
+            start,
+            end,
+            promotion: false,
+            force_capture: false,
+            withdraw: false,
+            restart: false,
+        };
+        let promoted = Movement {
+            promotion: true,
+            ..plain
+        };
+        // the rules module also knows the positions shogai refuses, like tsume problems without
+        // the attacker's king
+        let board = self.board.clone();
+        let legal = |mv: &str| {
+            board.check_move(mv).is_ok() || rules::is_legal(&board, &rules::normalize(&board, mv))
+        };
+        let can_stay = legal(&plain.to_string());
+        //to_string 'ing drops with promotion deletes the (impossible) promotion, and there is
+        //no need to buzz the player if the piece is already promoted
+        let can_promote = legal(&promoted.to_string())
+            && promoted.to_string() != plain.to_string()
+            && !start
+                .and_then(|pos| self.board.is_occupied_by(pos))
+                .is_some_and(|piece| piece.promoted);
+        self.clear_selection();
+
+        let mv = match (can_stay, can_promote) {
+            (true, true) if promote() => promoted,
+            (false, true) => promoted,
+            _ => plain,
+        }
+        .to_string();
+        if !legal(&mv) {
+            return Ok(None);
+        }
+        if self.checks_only && !rules::gives_check(&self.board, &mv) {
+            return Err(String::from("Every move of the attacker has to be a check"));
+        }
+        self.play(&mv);
+        Ok(Some(mv))
+    }
+
+    /// play a move known to be legal, the player's or the opponent's
+    pub fn play(&mut self, mv: &str) {
+        self.board = self.board.play_move_unchecked(mv);
+        self.record.push(mv);
+        self.played = true;
+    }
+
+    /// start again from a position, the moves before it forgotten
+    pub fn set_position(&mut self, board: Board) {
+        self.record =
+            GameRecord::from_position(board.clone(), &self.record.sente, &self.record.gote);
+        self.board = board;
+        self.clear_selection();
+        // the position may already be lost for the side to move
+        self.played = true;
+    }
+
+    /// whether a move was played since the last call
+    pub fn take_played(&mut self) -> bool {
+        std::mem::replace(&mut self.played, false)
+    }
+
+    /// checkmate or sennichite after the last move. Looking for it is *very* expensive, only
+    /// do it once a move was played.
+    pub fn judge(&mut self) -> Option<GameResult> {
+        self.repetitions.update(&self.record);
+        if rules::legal_moves(&self.board).is_empty() {
+            // no way out of check (or no move at all, which loses too)
+            Some(GameResult::Checkmate {
+                winner: rules::opponent(self.board.get_color()),
+            })
+        } else {
+            self.repetitions.result()
+        }
+    }
+
+    /// the game is over, nothing moves anymore
+    pub fn end(&mut self, result: GameResult) {
+        self.result = Some(result);
+        self.record.result = Some(result);
+        self.clock.stop();
+        self.clear_selection();
+    }
+}
+
+/// what the opponent's worker thread sends back: the opponent itself and its move
+type OpponentReply = (Box<dyn Opponent>, Option<String>);
+
+/// let the opponent choose its move on a worker thread, so the window stays responsive (in the
+/// browser, without threads, right away)
+fn think(mut ai: Box<dyn Opponent>, board: Board) -> Receiver<OpponentReply> {
+    let (sender, receiver) = channel();
+    #[cfg(not(target_os = "emscripten"))]
+    thread::spawn(move || {
+        let mv = ai.choose_move(&board);
+        let _ = sender.send((ai, mv));
+    });
+    #[cfg(target_os = "emscripten")]
+    {
+        let mv = ai.choose_move(&board);
+        let _ = sender.send((ai, mv));
+    }
+    receiver
+}
+
+/// run a search for a hint on a worker thread, like the opponent's moves (in the browser, right
+/// away)
+fn search<F>(hint: F) -> Receiver<Option<Movement>>
+where
+    F: FnOnce() -> Option<Movement> + Send + 'static,
+{
+    let (sender, receiver) = channel();
+    #[cfg(not(target_os = "emscripten"))]
+    thread::spawn(move || {
+        let _ = sender.send(hint());
+    });
+    #[cfg(target_os = "emscripten")]
+    {
+        let _ = sender.send(hint());
+    }
+    receiver
+}
+
+/// the computer's side of a game: its opponent, set thinking on its turn
+pub struct Driver {
+    opponent: Option<Box<dyn Opponent>>,
+    /// the opponent is moved to a worker thread while it thinks
+    thinking: Option<Receiver<OpponentReply>>,
+    /// the hint asked for, searched on a worker thread too
+    hinting: Option<Receiver<Option<Movement>>>,
+    name: String,
+    /// what went wrong with the computer, shown once the game is over
+    pub status: String,
+}
+
+impl Driver {
+    /// None for games without a computer (network games, CSA servers)
+    pub fn new(opponent: Option<Box<dyn Opponent>>) -> Self {
+        Driver {
+            name: opponent.as_ref().map_or_else(String::new, |o| o.name()),
+            opponent,
+            thinking: None,
+            hinting: None,
+            status: String::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn thinking(&self) -> bool {
+        self.thinking.is_some()
+    }
+
+    /// play with another opponent from now on (the defender of another tsume problem), the
+    /// move the last one may be thinking about forgotten
+    pub fn replace(&mut self, opponent: Box<dyn Opponent>) {
+        self.name = opponent.name();
+        self.opponent = Some(opponent);
+        self.thinking = None;
+        self.hinting = None;
+    }
+
+    /// the game is over, a move the opponent may still be thinking about doesn't matter anymore
+    pub fn stop(&mut self) {
+        self.thinking = None;
+        self.hinting = None;
+    }
+
+    /// look for a hint away from the window; `hint` gives it once found
+    pub fn search_hint<F>(&mut self, hint: F)
+    where
+        F: FnOnce() -> Option<Movement> + Send + 'static,
+    {
+        self.hinting = Some(search(hint));
+    }
+
+    /// a move was played, a hint still searched for would be for the position before
+    pub fn forget_hint(&mut self) {
+        self.hinting = None;
+    }
+
+    /// the hint searched for, once the search is over: Some(None) when it found nothing
+    pub fn hint(&mut self) -> Option<Option<Movement>> {
+        let found = match self.hinting.as_ref()?.try_recv() {
+            Ok(found) => found,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => None,
+        };
+        self.hinting = None;
+        Some(found)
+    }
+
+    /// on the computer's turn, set it thinking, then play the move it chose. Returns the result
+    /// when the computer ends the game instead: resigning, declaring, playing an illegal move...
+    pub fn update(&mut self, state: &mut GameState) -> Option<GameResult> {
+        if state.result.is_some() || state.board.get_color() == state.human {
+            return None;
+        }
+        if let Some(ai) = self.opponent.take() {
+            self.thinking = Some(think(ai, state.board.clone()));
+            return None;
+        }
+        let (ai, mv) = match self.thinking.as_ref()?.try_recv() {
+            Ok(reply) => reply,
+            Err(TryRecvError::Empty) => return None,
+            // the worker thread died (the engine panicked), its move is never coming
+            Err(TryRecvError::Disconnected) => {
+                self.thinking = None;
+                self.status = format!("{} stopped without playing", self.name);
+                return Some(GameResult::Abort);
+            }
+        };
+        self.opponent = Some(ai);
+        self.thinking = None;
+        let winner = state.human;
+        let mv = match mv {
+            Some(mv) => mv,
+            // a USI engine's "bestmove resign"
+            None => return Some(GameResult::Resignation { winner }),
+        };
+        if mv == impasse::DECLARE {
+            // the computer only declares winning positions, but check it like any move
+            return Some(
+                impasse::declaration(&state.board, state.rule)
+                    .unwrap_or(GameResult::IllegalMove { winner }),
+            );
+        }
+        // the rules module allows the few legal drops shogai refuses (tsume defenders make them)
+        if !rules::is_legal(&state.board, &rules::normalize(&state.board, &mv)) {
+            self.status = format!("{} played an illegal move {}", self.name, mv);
+            return Some(GameResult::IllegalMove { winner });
+        }
+        state.play(&mv);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TimeControl;
+    use crate::handicap::Handicap;
+    use crate::sfen;
+
+    fn state(position: &str) -> GameState {
+        let (board, _) = sfen::from_sfen(position).unwrap();
+        let record = GameRecord::from_position(board.clone(), "You", "Computer");
+        let human = board.get_color();
+        let clock = Clock::new(TimeControl::default());
+        GameState::new(board, human, record, clock, impasse::Rule::default())
+    }
+
+    fn square(usi: &str) -> Target {
+        Target::Square(sfen::parse_usi_square(usi).unwrap())
+    }
+
+    fn never() -> bool {
+        panic!("the promotion was asked")
+    }
+
+    #[test]
+    fn two_clicks_play_a_move() {
+        let mut state = state(&Handicap::Even.sfen());
+        assert_eq!(state.click(square("7g"), never), Ok(None));
+        assert!(state.holding());
+        assert_eq!(state.selected_square(), sfen::parse_usi_square("7g").ok());
+        assert_eq!(state.hidden().map(|p| p.piecetype), Some(PieceType::Pawn));
+        assert_eq!(state.floating(), state.hidden());
+
+        let played = state.click(square("7f"), never).unwrap().unwrap();
+        assert_eq!(sfen::to_usi(&played).unwrap(), "7g7f");
+        assert_eq!(state.record.moves, vec![played]);
+        assert!(!state.holding());
+        assert_eq!(state.hidden(), None);
+        assert!(!state.human_turn());
+        assert!(state.take_played());
+        assert!(!state.take_played());
+    }
+
+    #[test]
+    fn only_pieces_of_the_side_to_move_are_picked_up() {
+        let mut state = state(&Handicap::Even.sfen());
+        assert_eq!(state.click(square("3c"), never), Ok(None));
+        assert!(!state.holding());
+        assert_eq!(state.click(square("5e"), never), Ok(None));
+        assert!(!state.holding());
+        assert_eq!(state.click(Target::Outside, never), Ok(None));
+        assert!(state.record.moves.is_empty());
+    }
+
+    #[test]
+    fn illegal_moves_put_the_piece_back() {
+        let mut state = state(&Handicap::Even.sfen());
+        state.click(square("7g"), never).unwrap();
+        assert_eq!(state.click(square("7d"), never), Ok(None));
+        assert!(!state.holding());
+        assert_eq!(state.hidden(), None);
+        assert!(state.record.moves.is_empty());
+    }
+
+    #[test]
+    fn the_player_chooses_to_promote() {
+        let position = "4k4/9/9/9/9/9/9/1R7/4K4 b - 1";
+        for choice in [true, false] {
+            let mut state = state(position);
+            state.click(square("8h"), never).unwrap();
+            let mut asked = false;
+            let played = state
+                .click(square("8b"), || {
+                    asked = true;
+                    choice
+                })
+                .unwrap()
+                .unwrap();
+            assert!(asked);
+            assert_eq!(
+                sfen::to_usi(&played).unwrap(),
+                if choice { "8h8b+" } else { "8h8b" }
+            );
+        }
+    }
+
+    #[test]
+    fn pieces_are_dropped_from_the_hand() {
+        let mut state = state("4k4/9/9/9/9/9/9/9/4K4 b G 1");
+        state.click(Target::Hand(PieceType::Pawn), never).unwrap();
+        assert!(!state.holding());
+        state.click(Target::Hand(PieceType::Gold), never).unwrap();
+        assert!(state.holding());
+        assert_eq!(state.floating().map(|p| p.piecetype), Some(PieceType::Gold));
+        let played = state.click(square("5b"), never).unwrap().unwrap();
+        assert_eq!(sfen::to_usi(&played).unwrap(), "G*5b");
+    }
+
+    #[test]
+    fn tsume_moves_have_to_check() {
+        let mut state = state("4k4/9/9/9/9/9/9/9/4K4 b G 1");
+        state.checks_only = true;
+        state.click(Target::Hand(PieceType::Gold), never).unwrap();
+        assert!(state.click(square("1e"), never).is_err());
+        assert!(state.record.moves.is_empty());
+        assert!(!state.take_played());
+    }
+
+    #[test]
+    fn problems_without_the_attackers_king() {
+        // shogai refuses every move of a side without its king
+        let mut state = state("4k4/9/4P4/9/9/9/9/9/9 b G 1");
+        state.checks_only = true;
+        state.click(Target::Hand(PieceType::Gold), never).unwrap();
+        let played = state.click(square("5b"), never).unwrap().unwrap();
+        assert_eq!(sfen::to_usi(&played).unwrap(), "G*5b");
+    }
+
+    #[test]
+    fn checkmate_is_judged_after_the_move() {
+        let mut state = state("4k4/9/4P4/9/9/9/9/9/4K4 b G 1");
+        state.click(Target::Hand(PieceType::Gold), never).unwrap();
+        state.click(square("5b"), never).unwrap().unwrap();
+        assert!(state.take_played());
+        let result = state.judge();
+        assert_eq!(
+            result,
+            Some(GameResult::Checkmate {
+                winner: state.human
+            })
+        );
+        state.end(result.unwrap());
+        assert!(!state.human_turn());
+        assert_eq!(state.record.result, result);
+    }
+
+    /// plays the given moves (USI) one after the other, then resigns
+    struct Scripted(Vec<&'static str>);
+
+    impl Opponent for Scripted {
+        fn name(&self) -> String {
+            String::from("scripted")
+        }
+
+        fn choose_move(&mut self, board: &Board) -> Option<String> {
+            if self.0.is_empty() {
+                return None;
+            }
+            match self.0.remove(0) {
+                "panic" => panic!("the engine crashed"),
+                "declare" => Some(String::from(impasse::DECLARE)),
+                usi => Some(sfen::from_usi(usi, board).unwrap()),
+            }
+        }
+    }
+
+    /// the computer's result, once it has moved or ended the game
+    fn reply(driver: &mut Driver, state: &mut GameState) -> Option<GameResult> {
+        for _ in 0..1000 {
+            let result = driver.update(state);
+            if result.is_some() || state.board.get_color() == state.human {
+                return result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        panic!("the computer never answered")
+    }
+
+    fn computer(moves: Vec<&'static str>) -> (Driver, GameState) {
+        let mut state = state(&Handicap::Even.sfen());
+        state.human = Color::Black;
+        (Driver::new(Some(Box::new(Scripted(moves)))), state)
+    }
+
+    #[test]
+    fn the_computer_moves_on_its_turn() {
+        let (mut driver, mut state) = computer(vec!["7g7f", "2g2f"]);
+        assert_eq!(driver.name(), "scripted");
+        assert_eq!(reply(&mut driver, &mut state), None);
+        assert_eq!(state.record.moves.len(), 1);
+        assert!(!driver.thinking());
+        // nothing happens on the player's turn
+        assert_eq!(driver.update(&mut state), None);
+        assert!(!driver.thinking());
+
+        state.click(square("3c"), never).unwrap();
+        state.click(square("3d"), never).unwrap().unwrap();
+        assert_eq!(reply(&mut driver, &mut state), None);
+        assert_eq!(
+            sfen::to_usi(state.record.moves.last().unwrap()).unwrap(),
+            "2g2f"
+        );
+    }
+
+    #[test]
+    fn the_computer_ends_the_game() {
+        let resigned = Some(GameResult::Resignation {
+            winner: Color::Black,
+        });
+        let (mut driver, mut state) = computer(vec![]);
+        assert_eq!(reply(&mut driver, &mut state), resigned);
+
+        let (mut driver, mut state) = computer(vec!["7g7d"]);
+        assert_eq!(
+            reply(&mut driver, &mut state),
+            Some(GameResult::IllegalMove {
+                winner: Color::Black
+            })
+        );
+        assert!(state.record.moves.is_empty());
+        assert!(driver.status.contains("illegal move"));
+
+        let (mut driver, mut state) = computer(vec!["declare"]);
+        assert_eq!(
+            reply(&mut driver, &mut state),
+            Some(GameResult::IllegalMove {
+                winner: Color::Black
+            })
+        );
+
+        // a crash of the engine aborts the game rather than waiting for its move forever
+        let (mut driver, mut state) = computer(vec!["panic"]);
+        assert_eq!(reply(&mut driver, &mut state), Some(GameResult::Abort));
+        assert!(!driver.thinking());
+        assert!(!driver.status.is_empty());
+    }
+
+    #[test]
+    fn hints_are_searched_on_the_worker_thread() {
+        let mut driver = Driver::new(None);
+        assert_eq!(driver.hint(), None);
+        driver.search_hint(|| sfen::parse_movement("7g7f").ok());
+        let hint = (0..1000)
+            .find_map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(2));
+                driver.hint()
+            })
+            .expect("the hint never came");
+        assert_eq!(hint, sfen::parse_movement("7g7f").ok());
+        assert_eq!(driver.hint(), None);
+
+        // the hint of a position played from is dropped
+        driver.search_hint(|| None);
+        driver.forget_hint();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(driver.hint(), None);
+    }
+}
@@ -0,0 +1,288 @@
+// Animated GIFs, written by hand rather than with one more dependency. Every frame gets its own
+// palette, the 256 colours it uses most (a board has few colours, the others are the edges of the
+// pieces), and is compressed with LZW as the format wants.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// an animated GIF being written, looping forever
+pub struct Gif {
+    width: u16,
+    height: u16,
+    bytes: Vec<u8>,
+}
+
+impl Gif {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        // no global palette (each frame has its own), 8 bits per colour
+        bytes.extend_from_slice(&[0x70, 0, 0]);
+        // NETSCAPE2.0 extension: loop forever
+        bytes.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        bytes.extend_from_slice(b"NETSCAPE2.0");
+        bytes.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+        Gif {
+            width,
+            height,
+            bytes,
+        }
+    }
+
+    /// a frame, its pixels row by row, shown for `delay` (in hundredths of a second)
+    pub fn frame(&mut self, pixels: &[[u8; 3]], delay: Duration) {
+        assert_eq!(pixels.len(), self.width as usize * self.height as usize);
+        let (palette, indices) = quantize(pixels);
+        // the palette has 2^(bits) colours, bits from 1 to 8
+        let bits = (1..=8)
+            .find(|&bits| palette.len() <= 1 << bits)
+            .unwrap_or(8);
+
+        let delay = (delay.as_millis() / 10).min(u16::MAX as u128) as u16;
+        self.bytes.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        self.bytes.extend_from_slice(&delay.to_le_bytes());
+        self.bytes.extend_from_slice(&[0x00, 0x00]);
+
+        self.bytes.push(0x2C);
+        self.bytes.extend_from_slice(&[0, 0, 0, 0]);
+        self.bytes.extend_from_slice(&self.width.to_le_bytes());
+        self.bytes.extend_from_slice(&self.height.to_le_bytes());
+        // a palette of its own
+        self.bytes.push(0x80 | (bits - 1));
+        for i in 0..1 << bits {
+            self.bytes
+                .extend_from_slice(&palette.get(i).copied().unwrap_or_default());
+        }
+
+        let min_size = bits.max(2);
+        self.bytes.push(min_size);
+        for block in lzw(min_size, &indices).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend_from_slice(block);
+        }
+        self.bytes.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3B);
+        self.bytes
+    }
+}
+
+/// the colours used most, and the nearest of them for every pixel
+fn quantize(pixels: &[[u8; 3]]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut counts: HashMap<[u8; 3], usize> = HashMap::new();
+    for &pixel in pixels {
+        *counts.entry(pixel).or_default() += 1;
+    }
+    let mut colours: Vec<([u8; 3], usize)> = counts.into_iter().collect();
+    // ties by colour, so that the same pixels always give the same file
+    colours.sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+    let palette: Vec<[u8; 3]> = colours.iter().take(256).map(|&(c, _)| c).collect();
+
+    let distance = |a: [u8; 3], b: [u8; 3]| -> u32 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x as i32 - y as i32).pow(2) as u32)
+            .sum()
+    };
+    let index: HashMap<[u8; 3], u8> = colours
+        .iter()
+        .map(|&(colour, _)| {
+            let nearest = (0..palette.len())
+                .min_by_key(|&i| distance(palette[i], colour))
+                .unwrap_or(0);
+            (colour, nearest as u8)
+        })
+        .collect();
+    (palette, pixels.iter().map(|pixel| index[pixel]).collect())
+}
+
+/// GIF's LZW: codes growing from `min_size + 1` to 12 bits, the table cleared once full
+fn lzw(min_size: u8, indices: &[u8]) -> Vec<u8> {
+    let clear: u16 = 1 << min_size;
+    let end = clear + 1;
+    let mut out = Bits::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = min_size + 1;
+    let mut next = end + 1;
+
+    out.push(clear, size);
+    let mut prefix: Option<u16> = None;
+    for &k in indices {
+        let p = match prefix {
+            None => {
+                prefix = Some(k as u16);
+                continue;
+            }
+            Some(p) => p,
+        };
+        if let Some(&code) = table.get(&(p, k)) {
+            prefix = Some(code);
+            continue;
+        }
+        out.push(p, size);
+        if next < 4096 {
+            table.insert((p, k), next);
+            next += 1;
+            // the decoder, one code behind, widens them when its table is this full
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            out.push(clear, size);
+            table.clear();
+            size = min_size + 1;
+            next = end + 1;
+        }
+        prefix = Some(k as u16);
+    }
+    if let Some(p) = prefix {
+        out.push(p, size);
+    }
+    out.push(end, size);
+    out.finish()
+}
+
+/// codes packed least significant bit first
+#[derive(Default)]
+struct Bits {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the decoder of the GIF specification
+    fn unlzw(min_size: u8, bytes: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min_size;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear).map(|i| vec![i as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+        let mut size = min_size + 1;
+        let (mut position, mut out, mut previous) = (0usize, Vec::new(), None::<usize>);
+        loop {
+            let mut code = 0;
+            for bit in 0..size as usize {
+                let byte = bytes[(position + bit) / 8];
+                code |= ((byte >> ((position + bit) % 8)) as usize & 1) << bit;
+            }
+            position += size as usize;
+            if code == clear {
+                reset(&mut table);
+                size = min_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match previous {
+                None => table[code].clone(),
+                Some(p) => {
+                    let entry = if code < table.len() {
+                        table[code].clone()
+                    } else {
+                        let mut entry = table[p].clone();
+                        entry.push(table[p][0]);
+                        entry
+                    };
+                    let mut added = table[p].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                    entry
+                }
+            };
+            out.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // repetitive, then noisy enough to fill the table more than once
+        let mut data: Vec<u8> = (0..5000).map(|i| (i / 300 % 4) as u8).collect();
+        let mut seed = 12345u32;
+        data.extend((0..40000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }));
+        for &min_size in &[2, 8] {
+            let data: Vec<u8> = data
+                .iter()
+                .map(|&x| (x as u16 % (1 << min_size)) as u8)
+                .collect();
+            assert_eq!(unlzw(min_size, &lzw(min_size, &data)), data);
+        }
+        assert!(lzw(2, &[0; 10000]).len() < 200);
+    }
+
+    #[test]
+    fn frames_have_the_colours_used_most() {
+        let mut pixels = vec![[200, 150, 100]; 300];
+        pixels.extend(vec![[0, 0, 0]; 100]);
+        // a colour too many, shown as the nearest one
+        pixels.extend((0..=255).map(|i| [i as u8, 1, 2]));
+        let (palette, indices) = quantize(&pixels);
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0], [200, 150, 100]);
+        assert_eq!(palette[1], [0, 0, 0]);
+        assert_eq!(indices[0], 0);
+        assert_eq!(indices[300], 1);
+        let unused = pixels[400..]
+            .iter()
+            .position(|c| !palette.contains(c))
+            .unwrap();
+        let near = palette[indices[400 + unused] as usize];
+        assert!((near[0] as i32 - pixels[400 + unused][0] as i32).abs() <= 1);
+    }
+
+    #[test]
+    fn gifs_are_framed() {
+        let mut gif = Gif::new(2, 2);
+        gif.frame(
+            &[[0, 0, 0], [255, 255, 255], [255, 255, 255], [0, 0, 0]],
+            Duration::from_millis(500),
+        );
+        let bytes = gif.finish();
+        assert!(bytes.starts_with(b"GIF89a\x02\x00\x02\x00"));
+        assert_eq!(bytes.last(), Some(&0x3B));
+        // graphic control: 50 hundredths of a second
+        let control = bytes
+            .windows(4)
+            .position(|w| w == [0x21, 0xF9, 0x04, 0x00])
+            .unwrap();
+        assert_eq!(&bytes[control + 4..control + 6], &[50, 0]);
+    }
+}
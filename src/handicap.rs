@@ -0,0 +1,162 @@
+// Handicap games (komaochi).
+//
+// The stronger player (uwate) plays without some of their pieces and moves first. uwate is
+// gote in standard notation, i.e. shogai's Black, the side starting at the bottom of the window.
+
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+// variants are ordered by the size of the handicap, which `removed_squares` relies on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Handicap {
+    Even,
+    Lance,
+    RightLance,
+    Bishop,
+    Rook,
+    RookLance,
+    TwoPiece,
+    FourPiece,
+    SixPiece,
+    EightPiece,
+    TenPiece,
+}
+
+impl Handicap {
+    pub fn all() -> [Handicap; 11] {
+        [
+            Handicap::Even,
+            Handicap::Lance,
+            Handicap::RightLance,
+            Handicap::Bishop,
+            Handicap::Rook,
+            Handicap::RookLance,
+            Handicap::TwoPiece,
+            Handicap::FourPiece,
+            Handicap::SixPiece,
+            Handicap::EightPiece,
+            Handicap::TenPiece,
+        ]
+    }
+
+    /// name used in the "手合割" header of KIF records
+    pub fn kif_name(self) -> &'static str {
+        match self {
+            Handicap::Even => "平手",
+            Handicap::Lance => "香落ち",
+            Handicap::RightLance => "右香落ち",
+            Handicap::Bishop => "角落ち",
+            Handicap::Rook => "飛車落ち",
+            Handicap::RookLance => "飛香落ち",
+            Handicap::TwoPiece => "二枚落ち",
+            Handicap::FourPiece => "四枚落ち",
+            Handicap::SixPiece => "六枚落ち",
+            Handicap::EightPiece => "八枚落ち",
+            Handicap::TenPiece => "十枚落ち",
+        }
+    }
+
+    pub fn from_kif_name(name: &str) -> Option<Handicap> {
+        Handicap::all()
+            .iter()
+            .find(|h| h.kif_name() == name.trim())
+            .copied()
+    }
+
+    /// standard squares (file, rank) of the pieces uwate removes
+    fn removed_squares(self) -> Vec<(u8, u8)> {
+        const LANCE: (u8, u8) = (1, 1);
+        const RIGHT_LANCE: (u8, u8) = (9, 1);
+        const KNIGHTS: [(u8, u8); 2] = [(2, 1), (8, 1)];
+        const SILVERS: [(u8, u8); 2] = [(3, 1), (7, 1)];
+        const GOLDS: [(u8, u8); 2] = [(4, 1), (6, 1)];
+        const ROOK: (u8, u8) = (8, 2);
+        const BISHOP: (u8, u8) = (2, 2);
+
+        let mut squares = match self {
+            Handicap::Even => vec![],
+            Handicap::Lance => vec![LANCE],
+            Handicap::RightLance => vec![RIGHT_LANCE],
+            Handicap::Bishop => vec![BISHOP],
+            Handicap::Rook => vec![ROOK],
+            Handicap::RookLance => vec![ROOK, LANCE],
+            _ => vec![ROOK, BISHOP],
+        };
+        if self >= Handicap::FourPiece {
+            squares.extend(&[LANCE, RIGHT_LANCE]);
+        }
+        if self >= Handicap::SixPiece {
+            squares.extend(&KNIGHTS);
+        }
+        if self >= Handicap::EightPiece {
+            squares.extend(&SILVERS);
+        }
+        if self >= Handicap::TenPiece {
+            squares.extend(&GOLDS);
+        }
+        squares
+    }
+
+    /// the starting board of this handicap, with the right side to move
+    pub fn board(self) -> Board {
+        if self == Handicap::Even {
+            return Board::new();
+        }
+        let removed: Vec<Position> = self
+            .removed_squares()
+            .into_iter()
+            .map(|(file, rank)| sfen::position(file, rank))
+            .collect();
+        let mut board = Board::empty();
+        for piece in Board::new().iter() {
+            if !(piece.color == Color::Black
+                && piece.position.is_some_and(|p| removed.contains(&p)))
+            {
+                board.add_piece(*piece);
+            }
+        }
+        board.turn = Color::Black;
+        board
+    }
+
+    /// SFEN of the starting position
+    pub fn sfen(self) -> String {
+        sfen::to_sfen(&self.board(), 1)
+    }
+}
+
+impl fmt::Display for Handicap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Handicap::Even => "even",
+            Handicap::Lance => "lance",
+            Handicap::RightLance => "right-lance",
+            Handicap::Bishop => "bishop",
+            Handicap::Rook => "rook",
+            Handicap::RookLance => "rook-lance",
+            Handicap::TwoPiece => "2-piece",
+            Handicap::FourPiece => "4-piece",
+            Handicap::SixPiece => "6-piece",
+            Handicap::EightPiece => "8-piece",
+            Handicap::TenPiece => "10-piece",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Handicap {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Handicap::all()
+            .iter()
+            .find(|h| h.to_string() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown handicap '{}'", s))
+    }
+}
@@ -0,0 +1,238 @@
+// Drawing without a window: the renderer on an SDL software surface, in memory, saved as PNG.
+// No video driver or GPU is needed, which is what the golden image tests, the board diagrams of
+// --export-png and the games exported move by move (--export-gif, --export-frames) want.
+
+use sdl2::image::{InitFlag, SaveSurface};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, SurfaceCanvas};
+use sdl2::surface::{Surface, SurfaceRef};
+use sdl2::ttf::Font;
+
+use crate::cli::Args;
+use crate::clock::{Clock, TimeControl};
+use crate::diagram;
+use crate::game::GameState;
+use crate::gif::Gif;
+use crate::impasse;
+use crate::kif;
+use crate::layout::{SCR_HEIGHT, SCR_WIDTH};
+use crate::record::GameRecord;
+use crate::render::{Overlays, Renderer};
+use crate::settings::Settings;
+use crate::sfen;
+use crate::ui::{self, Style};
+
+use shogai::piece::Color;
+use shogai::position::Position;
+
+use std::fs;
+use std::path::Path;
+
+/// the format of the surfaces drawn on
+pub const FORMAT: PixelFormatEnum = PixelFormatEnum::RGBA8888;
+
+/// height of the line under the board where the moves are written
+const CAPTION: u32 = 32;
+
+/// a canvas the size of the window (without the panel), drawing in memory
+pub fn canvas() -> Result<SurfaceCanvas<'static>, String> {
+    let surface = Surface::new(SCR_WIDTH, SCR_HEIGHT, FORMAT)?;
+    Canvas::from_surface(surface)
+}
+
+/// the game as the window would show it with its marks, mouse at (0, 0)
+pub fn render(state: &GameState, settings: &Settings) -> Result<Surface<'static>, String> {
+    let mut canvas = canvas()?;
+    let texture_creator = canvas.texture_creator();
+    let renderer = Renderer::new(&texture_creator)?;
+    let overlays = Overlays {
+        marks: state.record.marks(state.record.moves.len()),
+        ..Overlays::default()
+    };
+    renderer.draw_game(&mut canvas, state, settings, overlays, (0, 0))?;
+    Ok(canvas.into_surface())
+}
+
+pub fn save_png<P: AsRef<Path>>(surface: &Surface, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    surface
+        .save(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// --export-png: a diagram of the --position given (or of the start of the game), drawn with the
+/// theme of the settings
+pub fn export_png(args: &Args, path: &Path) -> Result<(), String> {
+    let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    let board = diagram::board_of(args)?;
+    let record = GameRecord::from_position(board.clone(), "", "");
+    let clock = Clock::new(TimeControl::default());
+    let state = GameState::new(board, args.human, record, clock, args.impasse);
+    save_png(&render(&state, &Settings::load())?, path)?;
+    println!("diagram saved to {}", path.display());
+    Ok(())
+}
+
+/// every position of a recorded game from its start, as the settings draw it (the last move
+/// highlighted if they want it) with its marks, the move written under the board when there's a
+/// font
+pub fn game_frames<F>(
+    record: &GameRecord,
+    settings: &Settings,
+    font: Option<&Font>,
+    mut frame: F,
+) -> Result<(), String>
+where
+    F: FnMut(usize, &Surface) -> Result<(), String>,
+{
+    let mut canvas = canvas()?;
+    let texture_creator = canvas.texture_creator();
+    let renderer = Renderer::new(&texture_creator)?;
+    let start = record.start.clone();
+    let replayed = GameRecord::from_position(start.clone(), &record.sente, &record.gote);
+    let clock = Clock::new(TimeControl::default());
+    let mut state = GameState::new(
+        start,
+        Color::White,
+        replayed,
+        clock,
+        impasse::Rule::default(),
+    );
+    let height = SCR_HEIGHT + font.map_or(0, |_| CAPTION);
+    let mut previous = None;
+
+    for ply in 0..=record.moves.len() {
+        let mut caption = match ply {
+            0 if !record.sente.is_empty() => format!("{} - {}", record.sente, record.gote),
+            0 => String::new(),
+            _ => {
+                let mv = &record.moves[ply - 1];
+                let text = move_caption(&state, mv, previous, font)?;
+                previous = sfen::parse_movement(mv).ok().map(|movement| movement.end);
+                state.play(mv);
+                format!("{}. {}", ply, text)
+            }
+        };
+        if ply == record.moves.len() {
+            if let Some(result) = record.result {
+                state.end(result);
+                caption = format!("{}   {}", caption, result).trim().to_string();
+            }
+        }
+        let overlays = Overlays {
+            marks: record.marks(ply),
+            ..Overlays::default()
+        };
+        renderer.draw_game(&mut canvas, &state, settings, overlays, (0, 0))?;
+
+        let mut surface = Surface::new(SCR_WIDTH, height, FORMAT)?;
+        canvas.surface_mut().set_blend_mode(BlendMode::None)?;
+        canvas.surface().blit(None, &mut surface, None)?;
+        if let Some(font) = font {
+            let style = Style::default();
+            let band = Rect::new(0, SCR_HEIGHT as i32, SCR_WIDTH, CAPTION);
+            surface.fill_rect(band, style.panel)?;
+            if !caption.is_empty() {
+                let text = font
+                    .render(&caption)
+                    .blended(style.text)
+                    .map_err(|e| e.to_string())?;
+                let x = (SCR_WIDTH as i32 - text.width() as i32) / 2;
+                let y = band.y() + (CAPTION as i32 - text.height() as i32) / 2;
+                text.blit(
+                    None,
+                    &mut surface,
+                    Rect::new(x, y, text.width(), text.height()),
+                )?;
+            }
+        }
+        frame(ply, &surface)?;
+    }
+    Ok(())
+}
+
+/// "12. ☗７六歩", or "12. sente 7g7f" with a font without kanji
+fn move_caption(
+    state: &GameState,
+    mv: &str,
+    previous: Option<Position>,
+    font: Option<&Font>,
+) -> Result<String, String> {
+    let color = state.board.get_color();
+    if font.is_some_and(|font| font.find_glyph('歩').is_some()) {
+        let notation = kif::move_notation(&state.board, mv, previous)?;
+        let mark = if color == Color::White { "☗" } else { "☖" };
+        // (the origin square is only for the record)
+        let notation = notation.split('(').next().unwrap_or_default();
+        Ok(format!("{}{}", mark, notation))
+    } else {
+        Ok(format!("{} {}", sfen::side_name(color), sfen::to_usi(mv)?))
+    }
+}
+
+/// the pixels of a surface of FORMAT, row by row
+pub fn pixels(surface: &SurfaceRef) -> Vec<[u8; 3]> {
+    let (width, height, pitch) = (surface.width(), surface.height(), surface.pitch());
+    surface.with_lock(|bytes| {
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let at = (y * pitch + x * 4) as usize;
+                let rgba =
+                    u32::from_ne_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+                pixels.push([(rgba >> 24) as u8, (rgba >> 16) as u8, (rgba >> 8) as u8]);
+            }
+        }
+        pixels
+    })
+}
+
+/// --export-gif and --export-frames: the game of --kif, move by move. The last position stays
+/// three times as long before the GIF starts again.
+pub fn export_game(args: &Args) -> Result<(), String> {
+    let path = args
+        .kif
+        .as_ref()
+        .ok_or("--export-gif and --export-frames need the --kif record of a game")?;
+    let record = kif::read_file(path)?;
+    let settings = Settings::load();
+    let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    let ttf = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let font = if args.captions {
+        ui::load_font(&ttf, settings.font.as_deref())
+    } else {
+        None
+    };
+    if let Some(dir) = &args.export_frames {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+
+    let last = record.moves.len();
+    let mut gif: Option<Gif> = None;
+    game_frames(&record, &settings, font.as_ref(), |ply, surface| {
+        if let Some(dir) = &args.export_frames {
+            save_png(surface, dir.join(format!("{:03}.png", ply)))?;
+        }
+        if args.export_gif.is_some() {
+            let gif = gif
+                .get_or_insert_with(|| Gif::new(surface.width() as u16, surface.height() as u16));
+            let delay = if ply == last {
+                args.delay * 3
+            } else {
+                args.delay
+            };
+            gif.frame(&pixels(surface), delay);
+        }
+        Ok(())
+    })?;
+
+    if let (Some(path), Some(gif)) = (&args.export_gif, gif) {
+        fs::write(path, gif.finish()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("{} positions saved to {}", last + 1, path.display());
+    }
+    if let Some(dir) = &args.export_frames {
+        println!("{} positions saved in {}", last + 1, dir.display());
+    }
+    Ok(())
+}
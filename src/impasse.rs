@@ -0,0 +1,232 @@
+// Entering king (nyugyoku) declarations, the way out of games where both kings are out of reach.
+//
+// The side to move may declare when its king stands in the enemy camp (the three farthest ranks),
+// not in check, with at least 10 of its other pieces there too. The rook and bishop (promoted or
+// not) count 5 points, every other piece 1, for the pieces in the enemy camp and in hand:
+//
+// - 27-point rule (CSA, most engine tournaments): sente needs 28 points, gote 27, to win
+// - 24-point rule (Japan Shogi Association): 31 points win, 24 to 30 make a draw (jishogi)
+//
+// Engines declare with the move `DECLARE`, "win" as in USI's "bestmove win".
+
+use crate::result::GameResult;
+use crate::rules;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// the move of a player declaring, as in USI
+pub const DECLARE: &str = "win";
+
+/// pieces (king aside) needed in the enemy camp
+const CAMP_PIECES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rule {
+    TwentyFour,
+    #[default]
+    TwentySeven,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::TwentyFour => write!(f, "24"),
+            Rule::TwentySeven => write!(f, "27"),
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "24" => Ok(Rule::TwentyFour),
+            "27" => Ok(Rule::TwentySeven),
+            _ => Err(format!("unknown impasse rule '{}' (24 or 27)", s)),
+        }
+    }
+}
+
+fn in_enemy_camp(color: Color, pos: Position) -> bool {
+    match color {
+        Color::White => pos.0 / 9 >= 6,
+        Color::Black => pos.0 / 9 <= 2,
+    }
+}
+
+fn points_of(piecetype: PieceType) -> usize {
+    match piecetype {
+        PieceType::Rook | PieceType::Bishop => 5,
+        _ => 1,
+    }
+}
+
+pub fn king_entered(board: &Board, color: Color) -> bool {
+    rules::king_position(board, color).is_some_and(|king| in_enemy_camp(color, king))
+}
+
+/// pieces of the player in the enemy camp, the king aside
+fn camp_pieces(board: &Board, color: Color) -> impl Iterator<Item = &Piece> {
+    board.iter().filter(move |p| {
+        p.color == color
+            && p.piecetype != PieceType::King
+            && p.position.is_some_and(|pos| in_enemy_camp(color, pos))
+    })
+}
+
+/// points of the player: pieces in the enemy camp and in hand
+pub fn points(board: &Board, color: Color) -> usize {
+    let in_hand = board
+        .iter()
+        .filter(|p| p.color == color && p.position.is_none());
+    camp_pieces(board, color)
+        .chain(in_hand)
+        .map(|p| points_of(p.piecetype))
+        .sum()
+}
+
+/// what a declaration of the side to move would give: the win (or a draw under the 24-point
+/// rule), or why it can't be made
+pub fn declaration(board: &Board, rule: Rule) -> Result<GameResult, String> {
+    let color = board.get_color();
+    if !king_entered(board, color) {
+        return Err(String::from("the king has not entered the enemy camp"));
+    }
+    if rules::in_check(board, color) {
+        return Err(String::from("the king is in check"));
+    }
+    let count = camp_pieces(board, color).count();
+    if count < CAMP_PIECES {
+        return Err(format!(
+            "{} pieces in the enemy camp, {} are needed",
+            count, CAMP_PIECES
+        ));
+    }
+    let points = points(board, color);
+    let win = GameResult::Impasse {
+        winner: Some(color),
+    };
+    match rule {
+        Rule::TwentySeven => {
+            let needed = match color {
+                Color::White => 28,
+                Color::Black => 27,
+            };
+            if points >= needed {
+                Ok(win)
+            } else {
+                Err(format!("{} points, {} are needed", points, needed))
+            }
+        }
+        Rule::TwentyFour if points >= 31 => Ok(win),
+        Rule::TwentyFour if points >= 24 => Ok(GameResult::Impasse { winner: None }),
+        Rule::TwentyFour => Err(format!(
+            "{} points, 24 are needed for a draw and 31 to win",
+            points
+        )),
+    }
+}
+
+/// whether the side to move wins by declaring, whatever the rule
+pub fn can_declare_win(board: &Board) -> bool {
+    [Rule::TwentyFour, Rule::TwentySeven]
+        .iter()
+        .all(|&rule| declaration(board, rule).is_ok_and(|result| result.winner().is_some()))
+}
+
+/// "impasse points: sente 25 (entered), gote 18", once a king entered the enemy camp
+pub fn summary(board: &Board) -> Option<String> {
+    let sides = [Color::White, Color::Black];
+    if !sides.iter().any(|&color| king_entered(board, color)) {
+        return None;
+    }
+    let side = |color: Color| {
+        let entered = if king_entered(board, color) {
+            " (entered)"
+        } else {
+            ""
+        };
+        format!(
+            "{} {}{}",
+            sfen::side_name(color),
+            points(board, color),
+            entered
+        )
+    };
+    Some(format!(
+        "impasse points: {}, {}",
+        side(Color::White),
+        side(Color::Black)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(sfen: &str) -> Board {
+        sfen::from_sfen(sfen).unwrap().0
+    }
+
+    /// sente's king on 5b with nine tokins, a dragon and a horse: 19 points on the board
+    fn entered(hand: &str) -> Board {
+        board(&format!(
+            "+P+P+P+P+P+P+P+P+P/+R+B2K4/9/9/9/8k/9/9/9 b {} 1",
+            hand
+        ))
+    }
+
+    #[test]
+    fn points_and_rules() {
+        let board = entered("2G4S");
+        assert_eq!(points(&board, Color::White), 25);
+        assert!(declaration(&board, Rule::TwentySeven).is_err());
+        assert_eq!(
+            declaration(&board, Rule::TwentyFour),
+            Ok(GameResult::Impasse { winner: None })
+        );
+
+        let board = entered("2G4S3P");
+        let win = Ok(GameResult::Impasse {
+            winner: Some(Color::White),
+        });
+        assert_eq!(declaration(&board, Rule::TwentySeven), win);
+        assert_eq!(
+            declaration(&board, Rule::TwentyFour),
+            Ok(GameResult::Impasse { winner: None })
+        );
+        assert!(!can_declare_win(&board));
+
+        let board = entered("2G4S2L4N3P");
+        assert_eq!(declaration(&board, Rule::TwentyFour), win);
+        assert!(can_declare_win(&board));
+        assert_eq!(
+            summary(&board).as_deref(),
+            Some("impasse points: sente 34 (entered), gote 0")
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        // the king outside the camp
+        let board = board("+P+P+P+P+P+P+P+P+P/+R+B7/9/4K4/9/8k/9/9/9 b 2G4S2L4N3P 1");
+        assert!(declaration(&board, Rule::TwentyFour).is_err());
+        assert_eq!(summary(&board), None);
+        // in check from the lance on 5a
+        let board = self::board("+P+P+P+Pl+P+P+P+P/+R+B2K4/9/9/9/8k/9/9/9 b 2G4S2L4N3P 1");
+        assert_eq!(
+            declaration(&board, Rule::TwentyFour),
+            Err(String::from("the king is in check"))
+        );
+        // not enough pieces in the camp
+        let board = self::board("+P+P+P+P5/+R+B2K4/9/9/9/8k/9/9/9 b 2G4S2L4N8P 1");
+        assert!(declaration(&board, Rule::TwentySeven).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+// The SDL events turned into what they mean for the game: the keys into commands, the mouse into
+// clicks on squares and hands (as the input mode of the settings wants them) and the right button
+// into marks, the chat line typed in network games, and everything done to a position being
+// edited.
+
+use crate::editor::{EditAction, Spot};
+use crate::game::Target;
+use crate::layout;
+use crate::marks::{Mark, MarkColour};
+use crate::render::Panel;
+use crate::settings::InputMode;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+
+use shogai::piece::*;
+use shogai::position::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// the window closed, or Escape
+    Quit,
+    /// Ctrl+S
+    Save,
+    /// Ctrl+O
+    Settings,
+    /// F10
+    Menu,
+    /// Ctrl+R
+    Resign,
+    /// Ctrl+D
+    OfferDraw,
+    /// Ctrl+K, entering king declaration
+    Declare,
+    /// N: a new game once it's over, or the next problem
+    Next,
+    /// P: the previous problem
+    Previous,
+    /// R: a rematch once the game is over, or the problem again
+    Again,
+    /// H: a hint for the problem
+    Hint,
+    /// E: set up a position
+    OpenEditor,
+    /// Ctrl+P, a diagram of the position
+    ExportDiagram,
+    /// B and F
+    TogglePanel(Panel),
+    /// a line typed after Return, network games only
+    Chat(String),
+    /// a square or hand clicked (or where a piece was picked up or put down)
+    Click(Target),
+    /// an arrow dragged with the right button, or a square right clicked
+    Mark(Mark),
+    /// while a position is being edited
+    Edit(EditAction),
+}
+
+/// what the input means depends on what's going on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub input: InputMode,
+    pub flip: bool,
+    /// the side to move, its hand can be clicked
+    pub side: Color,
+    /// a piece is picked up, and the square clicked last
+    pub holding: bool,
+    pub clicked: Option<Position>,
+    pub editing: bool,
+    /// Return starts a chat line
+    pub chat: bool,
+    /// the modifier keys held, choosing the colour of the marks
+    pub keymod: Mod,
+}
+
+#[derive(Debug, Default)]
+pub struct InputHandler {
+    mouse: (i32, i32),
+    /// chat line being typed
+    typing: Option<String>,
+    /// where the right button went down, the start of an arrow
+    marking: Option<Position>,
+}
+
+impl InputHandler {
+    pub fn new() -> Self {
+        InputHandler::default()
+    }
+
+    /// where the mouse was last seen
+    pub fn mouse(&self) -> (i32, i32) {
+        self.mouse
+    }
+
+    /// the chat line being typed, if one is
+    pub fn typing(&self) -> Option<&str> {
+        self.typing.as_deref()
+    }
+
+    /// the mark the right button is drawing, were it released now with `keymod` held
+    pub fn marking(&self, flip: bool, keymod: Mod) -> Option<Mark> {
+        let (x, y) = self.mouse;
+        let square = layout::square_at(x, y, flip)?;
+        Some(mark(self.marking?, square, keymod))
+    }
+
+    pub fn handle(&mut self, event: &Event, context: &Context) -> Option<Command> {
+        match *event {
+            Event::Quit { .. } => Some(Command::Quit),
+            Event::MouseMotion { x, y, .. } => {
+                self.mouse = (x, y);
+                None
+            }
+            Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            } => {
+                self.mouse = (x, y);
+                self.button(mouse_btn, true, context)
+            }
+            Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            } => {
+                self.mouse = (x, y);
+                self.button(mouse_btn, false, context)
+            }
+            Event::TextInput { ref text, .. } => {
+                if let Some(line) = self.typing.as_mut() {
+                    line.push_str(text);
+                }
+                None
+            }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                keymod,
+                ..
+            } => self.key(keycode, keymod, context),
+            _ => None,
+        }
+    }
+
+    fn button(&mut self, button: MouseButton, down: bool, context: &Context) -> Option<Command> {
+        let (x, y) = self.mouse;
+        if context.editing {
+            let spot = layout::spot_at(x, y, context.flip);
+            let square = match spot {
+                Some(Spot::Square(pos)) => Some(pos),
+                _ => None,
+            };
+            let action = match (button, down) {
+                (MouseButton::Left, true) => EditAction::Pick(spot),
+                (MouseButton::Left, false) => EditAction::Put(spot),
+                (MouseButton::Right, false) => EditAction::TogglePromotion(square?),
+                (MouseButton::Middle, false) => EditAction::SwitchOwner(square?),
+                _ => return None,
+            };
+            return Some(Command::Edit(action));
+        }
+
+        if button == MouseButton::Right {
+            let square = layout::square_at(x, y, context.flip);
+            if down {
+                self.marking = square;
+                return None;
+            }
+            let from = self.marking.take()?;
+            return Some(Command::Mark(mark(from, square?, context.keymod)));
+        }
+
+        let clicked = match context.input {
+            InputMode::Click => !down,
+            // pick the piece up when the button goes down, put it down where it goes up
+            InputMode::Drag if !context.holding => down,
+            InputMode::Drag => !down && layout::square_at(x, y, context.flip) != context.clicked,
+        };
+        if !clicked {
+            return None;
+        }
+        let target = if let Some(pos) = layout::square_at(x, y, context.flip) {
+            Target::Square(pos)
+        } else if let Some(piecetype) = layout::hand_at(x, y, context.side, context.flip) {
+            Target::Hand(piecetype)
+        } else {
+            Target::Outside
+        };
+        Some(Command::Click(target))
+    }
+
+    fn key(&mut self, keycode: Keycode, keymod: Mod, context: &Context) -> Option<Command> {
+        if let Some(line) = self.typing.as_mut() {
+            match keycode {
+                Keycode::Return | Keycode::KpEnter => {
+                    let line = self.typing.take().unwrap_or_default();
+                    if !line.trim().is_empty() {
+                        return Some(Command::Chat(line));
+                    }
+                }
+                Keycode::Escape => self.typing = None,
+                Keycode::Backspace => {
+                    line.pop();
+                }
+                _ => {}
+            }
+            return None;
+        }
+        if context.editing {
+            return self.edit_key(keycode, keymod, context).map(Command::Edit);
+        }
+
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        match keycode {
+            Keycode::Return if context.chat => {
+                self.typing = Some(String::new());
+                None
+            }
+            Keycode::Escape => Some(Command::Quit),
+            Keycode::F10 => Some(Command::Menu),
+            Keycode::S if ctrl => Some(Command::Save),
+            Keycode::O if ctrl => Some(Command::Settings),
+            Keycode::R if ctrl => Some(Command::Resign),
+            Keycode::D if ctrl => Some(Command::OfferDraw),
+            Keycode::K if ctrl => Some(Command::Declare),
+            Keycode::P if ctrl => Some(Command::ExportDiagram),
+            Keycode::N => Some(Command::Next),
+            Keycode::P => Some(Command::Previous),
+            Keycode::R => Some(Command::Again),
+            Keycode::H => Some(Command::Hint),
+            Keycode::E => Some(Command::OpenEditor),
+            Keycode::B => Some(Command::TogglePanel(Panel::Book)),
+            Keycode::F => Some(Command::TogglePanel(Panel::Database)),
+            _ => None,
+        }
+    }
+
+    /// Keys of the position editor:
+    /// - P, L, N, S, G, B, R, K put a gote piece on the square under the mouse (sente with
+    ///   Shift), Delete or Backspace removes it
+    /// - T switches the side to move, C clears the board, I restores the even starting position
+    /// - Return starts playing from the position, Escape leaves the editor
+    ///
+    /// (with the mouse: drag pieces between squares and hands with the left button, right click
+    /// to promote/unpromote, middle click to give a piece to the other side)
+    fn edit_key(&self, keycode: Keycode, keymod: Mod, context: &Context) -> Option<EditAction> {
+        let (x, y) = self.mouse;
+        let spot = layout::spot_at(x, y, context.flip);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        let piecetype = match keycode {
+            Keycode::P => Some(PieceType::Pawn),
+            Keycode::L => Some(PieceType::Lance),
+            Keycode::N => Some(PieceType::Knight),
+            Keycode::S => Some(PieceType::Silver),
+            Keycode::G => Some(PieceType::Gold),
+            Keycode::B => Some(PieceType::Bishop),
+            Keycode::R => Some(PieceType::Rook),
+            Keycode::K => Some(PieceType::King),
+            _ => None,
+        };
+        match (keycode, piecetype, spot) {
+            (_, Some(piecetype), Some(Spot::Square(pos))) => {
+                // same convention as SFEN: uppercase for sente (shogai's White)
+                let color = if shift { Color::White } else { Color::Black };
+                Some(EditAction::Place(
+                    pos,
+                    Piece {
+                        color,
+                        piecetype,
+                        promoted: false,
+                        position: Some(pos),
+                    },
+                ))
+            }
+            (Keycode::Delete, _, Some(spot)) | (Keycode::Backspace, _, Some(spot)) => {
+                Some(EditAction::Remove(spot))
+            }
+            (Keycode::T, _, _) => Some(EditAction::SwitchSide),
+            (Keycode::C, _, _) => Some(EditAction::Clear),
+            (Keycode::I, _, _) => Some(EditAction::Reset),
+            (Keycode::Return, _, _) => Some(EditAction::Start),
+            (Keycode::Escape, _, _) => Some(EditAction::Cancel),
+            _ => None,
+        }
+    }
+}
+
+/// from a square to another, an arrow; on the same one, a circle: green, red with Shift, blue
+/// with Ctrl, yellow with Alt
+fn mark(from: Position, to: Position, keymod: Mod) -> Mark {
+    let colour = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        MarkColour::Red
+    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        MarkColour::Blue
+    } else if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+        MarkColour::Yellow
+    } else {
+        MarkColour::Green
+    };
+    if from == to {
+        Mark::Circle(to, colour)
+    } else {
+        Mark::Arrow(from, to, colour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{square_origin, SQR_SIZE};
+    use crate::sfen;
+
+    fn context(input: InputMode) -> Context {
+        Context {
+            input,
+            flip: false,
+            side: Color::White,
+            holding: false,
+            clicked: None,
+            editing: false,
+            chat: false,
+            keymod: Mod::NOMOD,
+        }
+    }
+
+    fn button(down: bool, at: (i32, i32)) -> Event {
+        pressed(MouseButton::Left, down, at)
+    }
+
+    fn pressed(mouse_btn: MouseButton, down: bool, (x, y): (i32, i32)) -> Event {
+        if down {
+            Event::MouseButtonDown {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn,
+                clicks: 1,
+                x,
+                y,
+            }
+        } else {
+            Event::MouseButtonUp {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn,
+                clicks: 1,
+                x,
+                y,
+            }
+        }
+    }
+
+    fn key(keycode: Keycode, keymod: Mod) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod,
+            repeat: false,
+        }
+    }
+
+    fn motion((x, y): (i32, i32)) -> Event {
+        Event::MouseMotion {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            mousestate: sdl2::mouse::MouseState::from_sdl_state(0),
+            x,
+            y,
+            xrel: 0,
+            yrel: 0,
+        }
+    }
+
+    /// the middle of a square
+    fn at(usi: &str) -> (i32, i32) {
+        let (x, y) = square_origin(sfen::parse_usi_square(usi).unwrap(), false);
+        (x + SQR_SIZE as i32 / 2, y + SQR_SIZE as i32 / 2)
+    }
+
+    fn click(usi: &str) -> Option<Command> {
+        Some(Command::Click(Target::Square(
+            sfen::parse_usi_square(usi).unwrap(),
+        )))
+    }
+
+    #[test]
+    fn clicks_happen_when_the_button_goes_up() {
+        let mut input = InputHandler::new();
+        let context = context(InputMode::Click);
+        assert_eq!(input.handle(&button(true, at("7g")), &context), None);
+        assert_eq!(
+            input.handle(&button(false, at("7g")), &context),
+            click("7g")
+        );
+        assert_eq!(input.mouse(), at("7g"));
+    }
+
+    #[test]
+    fn drags_pick_up_on_press_and_put_down_elsewhere() {
+        let mut input = InputHandler::new();
+        let mut context = context(InputMode::Drag);
+        assert_eq!(input.handle(&button(true, at("7g")), &context), click("7g"));
+        context.holding = true;
+        context.clicked = sfen::parse_usi_square("7g").ok();
+        // let go where it was picked up: still held
+        assert_eq!(input.handle(&button(false, at("7g")), &context), None);
+        assert_eq!(
+            input.handle(&button(false, at("7f")), &context),
+            click("7f")
+        );
+    }
+
+    #[test]
+    fn the_right_button_draws_marks() {
+        let mut input = InputHandler::new();
+        let mut context = context(InputMode::Click);
+        let square = |usi| sfen::parse_usi_square(usi).unwrap();
+        let right = |down, usi| pressed(MouseButton::Right, down, at(usi));
+        assert_eq!(input.handle(&right(true, "2h"), &context), None);
+        input.handle(&motion(at("2d")), &context);
+        let arrow = Mark::Arrow(square("2h"), square("2d"), MarkColour::Green);
+        assert_eq!(input.marking(false, Mod::NOMOD), Some(arrow));
+        assert_eq!(
+            input.handle(&right(false, "2d"), &context),
+            Some(Command::Mark(arrow))
+        );
+        assert_eq!(input.marking(false, Mod::NOMOD), None);
+
+        // Shift for red, a click for a circle
+        context.keymod = Mod::LSHIFTMOD;
+        input.handle(&right(true, "5e"), &context);
+        assert_eq!(
+            input.handle(&right(false, "5e"), &context),
+            Some(Command::Mark(Mark::Circle(square("5e"), MarkColour::Red)))
+        );
+        // off the board, nothing
+        input.handle(&right(true, "5e"), &context);
+        assert_eq!(
+            input.handle(&pressed(MouseButton::Right, false, (10, 10)), &context),
+            None
+        );
+    }
+
+    #[test]
+    fn keys_are_commands() {
+        let mut input = InputHandler::new();
+        let context = context(InputMode::Click);
+        assert_eq!(
+            input.handle(&key(Keycode::R, Mod::LCTRLMOD), &context),
+            Some(Command::Resign)
+        );
+        assert_eq!(
+            input.handle(&key(Keycode::R, Mod::NOMOD), &context),
+            Some(Command::Again)
+        );
+        assert_eq!(
+            input.handle(&key(Keycode::Escape, Mod::NOMOD), &context),
+            Some(Command::Quit)
+        );
+    }
+
+    #[test]
+    fn chat_lines_are_typed_after_return() {
+        let mut input = InputHandler::new();
+        let context = Context {
+            chat: true,
+            ..context(InputMode::Click)
+        };
+        assert_eq!(
+            input.handle(&key(Keycode::Return, Mod::NOMOD), &context),
+            None
+        );
+        assert_eq!(input.typing(), Some(""));
+        let text = Event::TextInput {
+            timestamp: 0,
+            window_id: 0,
+            text: String::from("hi!"),
+        };
+        input.handle(&text, &context);
+        // keys go to the line, not to the game
+        assert_eq!(input.handle(&key(Keycode::R, Mod::NOMOD), &context), None);
+        input.handle(&key(Keycode::Backspace, Mod::NOMOD), &context);
+        assert_eq!(
+            input.handle(&key(Keycode::Return, Mod::NOMOD), &context),
+            Some(Command::Chat(String::from("hi")))
+        );
+        assert_eq!(input.typing(), None);
+    }
+
+    #[test]
+    fn the_editor_gets_everything() {
+        let mut input = InputHandler::new();
+        let context = Context {
+            editing: true,
+            ..context(InputMode::Click)
+        };
+        let square = sfen::parse_usi_square("5e").unwrap();
+        input.handle(&button(true, at("5e")), &context);
+        assert_eq!(
+            input.handle(&key(Keycode::G, Mod::LSHIFTMOD), &context),
+            Some(Command::Edit(EditAction::Place(
+                square,
+                Piece {
+                    color: Color::White,
+                    piecetype: PieceType::Gold,
+                    promoted: false,
+                    position: Some(square),
+                }
+            )))
+        );
+        assert_eq!(
+            input.handle(&key(Keycode::Escape, Mod::NOMOD), &context),
+            Some(Command::Edit(EditAction::Cancel))
+        );
+    }
+}
@@ -0,0 +1,556 @@
+// KIF, the most common format for shogi game records. The marks drawn on the positions are kept
+// in comments (see marks.rs).
+
+use crate::handicap::Handicap;
+use crate::marks;
+use crate::record::GameRecord;
+use crate::result::GameResult;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::fs;
+use std::path::Path;
+
+const FILES: [&str; 9] = ["１", "２", "３", "４", "５", "６", "７", "８", "９"];
+pub const RANKS: [&str; 9] = ["一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+/// kanji name of a piece
+pub fn piece_name(piecetype: PieceType, promoted: bool) -> &'static str {
+    match (piecetype, promoted) {
+        (PieceType::Pawn, false) => "歩",
+        (PieceType::Pawn, true) => "と",
+        (PieceType::Lance, false) => "香",
+        (PieceType::Lance, true) => "成香",
+        (PieceType::Knight, false) => "桂",
+        (PieceType::Knight, true) => "成桂",
+        (PieceType::Silver, false) => "銀",
+        (PieceType::Silver, true) => "成銀",
+        (PieceType::Gold, _) => "金",
+        (PieceType::Bishop, false) => "角",
+        (PieceType::Bishop, true) => "馬",
+        (PieceType::Rook, false) => "飛",
+        (PieceType::Rook, true) => "龍",
+        (PieceType::King, _) => "玉",
+    }
+}
+
+fn square(pos: Position) -> String {
+    let (file, rank) = sfen::file_rank(pos);
+    format!("{}{}", FILES[file as usize - 1], RANKS[rank as usize - 1])
+}
+
+/// KIF notation of a move, e.g. "７六歩(77)"; `previous` is the destination of the previous
+/// move, used for the "同" (same square) notation
+pub fn move_notation(
+    board: &Board,
+    mv: &str,
+    previous: Option<Position>,
+) -> Result<String, String> {
+    let movement = sfen::parse_movement(mv)?;
+    let destination = if previous == Some(movement.end) {
+        String::from("同　")
+    } else {
+        square(movement.end)
+    };
+    Ok(match movement.start {
+        None => format!("{}{}打", destination, piece_name(movement.piecetype, false)),
+        Some(start) => {
+            let promoted = board.is_occupied_by(start).is_some_and(|p| p.promoted);
+            let (file, rank) = sfen::file_rank(start);
+            format!(
+                "{}{}{}({}{})",
+                destination,
+                piece_name(movement.piecetype, promoted),
+                if movement.promotion && !promoted {
+                    "成"
+                } else {
+                    ""
+                },
+                file,
+                rank
+            )
+        }
+    })
+}
+
+const NUMBERS: [&str; 19] = [
+    "", "一", "二", "三", "四", "五", "六", "七", "八", "九", "十", "十一", "十二", "十三", "十四",
+    "十五", "十六", "十七", "十八",
+];
+
+/// single character names used in board diagrams
+pub fn diagram_name(piece: &Piece) -> &'static str {
+    match (piece.piecetype, piece.promoted) {
+        (PieceType::Lance, true) => "杏",
+        (PieceType::Knight, true) => "圭",
+        (PieceType::Silver, true) => "全",
+        (piecetype, promoted) => piece_name(piecetype, promoted),
+    }
+}
+
+/// the pieces in hand of a side, as diagrams write them: "飛　歩三", or "なし"
+pub fn hand(board: &Board, color: Color) -> String {
+    let pieces: Vec<String> = [
+        PieceType::Rook,
+        PieceType::Bishop,
+        PieceType::Gold,
+        PieceType::Silver,
+        PieceType::Knight,
+        PieceType::Lance,
+        PieceType::Pawn,
+    ]
+    .iter()
+    .filter_map(
+        |&piecetype| match sfen::hand_count(board, color, piecetype) {
+            0 => None,
+            1 => Some(piece_name(piecetype, false).to_string()),
+            n => Some(format!("{}{}", piece_name(piecetype, false), NUMBERS[n])),
+        },
+    )
+    .collect();
+    if pieces.is_empty() {
+        String::from("なし")
+    } else {
+        pieces.join("　")
+    }
+}
+
+/// board diagram (BOD) of a position, used by KIF for positions that are not a standard start
+pub fn diagram(board: &Board) -> String {
+    let mut bod = format!("後手の持駒：{}\n", hand(board, Color::Black));
+    bod.push_str("  ９ ８ ７ ６ ５ ４ ３ ２ １\n");
+    bod.push_str("+---------------------------+\n");
+    for rank in 1..=9 {
+        bod.push('|');
+        for file in (1..=9).rev() {
+            match board.is_occupied_by(sfen::position(file, rank)) {
+                Some(piece) => {
+                    bod.push(if piece.color == Color::White {
+                        ' '
+                    } else {
+                        'v'
+                    });
+                    bod.push_str(diagram_name(&piece));
+                }
+                None => bod.push_str(" ・"),
+            }
+        }
+        bod.push_str(&format!("|{}\n", RANKS[rank as usize - 1]));
+    }
+    bod.push_str("+---------------------------+\n");
+    bod.push_str(&format!("先手の持駒：{}\n", hand(board, Color::White)));
+    if board.get_color() == Color::Black {
+        bod.push_str("後手番\n");
+    }
+    bod
+}
+
+/// the full KIF record of a game
+pub fn write(record: &GameRecord) -> String {
+    let mut kif = String::from("# KIF形式棋譜ファイル Generated by shogui\n");
+    if let Some(date) = &record.date {
+        kif.push_str(&format!("開始日時：{}\n", date));
+    }
+    if record.is_standard_start() {
+        kif.push_str(&format!("手合割：{}\n", record.handicap.kif_name()));
+    } else {
+        kif.push_str(&diagram(&record.start));
+    }
+    if record.handicap == Handicap::Even {
+        kif.push_str(&format!("先手：{}\n", record.sente));
+        kif.push_str(&format!("後手：{}\n", record.gote));
+    } else {
+        kif.push_str(&format!("下手：{}\n", record.sente));
+        kif.push_str(&format!("上手：{}\n", record.gote));
+    }
+    if let Some(opening) = &record.opening {
+        kif.push_str(&format!("戦型：{}\n", opening));
+    }
+    if !record.marks(0).is_empty() {
+        kif.push_str(&format!("{}\n", marks::kif_comment(record.marks(0))));
+    }
+    kif.push_str("手数----指手---------消費時間--\n");
+
+    let mut board = record.start.clone();
+    let mut previous = None;
+    for (i, mv) in record.moves.iter().enumerate() {
+        let notation = match move_notation(&board, mv, previous) {
+            Ok(notation) => notation,
+            Err(_) => break,
+        };
+        kif.push_str(&format!("{:>4} {}\n", i + 1, notation));
+        if !record.marks(i + 1).is_empty() {
+            kif.push_str(&format!("{}\n", marks::kif_comment(record.marks(i + 1))));
+        }
+        previous = sfen::parse_movement(mv).ok().map(|m| m.end);
+        board = board.play_move_unchecked(mv);
+    }
+    if let Some(result) = record.result {
+        let plies = record.moves.len();
+        kif.push_str(&format!("{:>4} {}\n", plies + 1, result.kif_move()));
+        if let GameResult::Sennichite { winner: Some(_) } = result {
+            // the checking side lost, the winner is read back from the last line
+            kif.push_str("*連続王手の千日手\n");
+        }
+        let winner = match (result.winner(), record.handicap) {
+            (Some(Color::White), Handicap::Even) => "先手の勝ち",
+            (Some(Color::Black), Handicap::Even) => "後手の勝ち",
+            (Some(Color::White), _) => "下手の勝ち",
+            (Some(Color::Black), _) => "上手の勝ち",
+            (None, _) => result.kif_move(),
+        };
+        kif.push_str(&format!("まで{}手で{}\n", plies, winner));
+    }
+    kif
+}
+
+/// the winner the last line of a record names, None for a draw
+fn kif_winner(line: &str) -> Option<Color> {
+    if line.ends_with("先手の勝ち") || line.ends_with("下手の勝ち") {
+        Some(Color::White)
+    } else if line.ends_with("後手の勝ち") || line.ends_with("上手の勝ち") {
+        Some(Color::Black)
+    } else {
+        None
+    }
+}
+
+fn kanji_number(s: &str) -> Option<usize> {
+    if s.is_empty() {
+        return Some(1);
+    }
+    NUMBERS.iter().position(|&n| n == s)
+}
+
+fn piece_from_name(name: &str) -> Option<(PieceType, bool)> {
+    let piece = match name {
+        "歩" => (PieceType::Pawn, false),
+        "と" => (PieceType::Pawn, true),
+        "香" => (PieceType::Lance, false),
+        "成香" | "杏" => (PieceType::Lance, true),
+        "桂" => (PieceType::Knight, false),
+        "成桂" | "圭" => (PieceType::Knight, true),
+        "銀" => (PieceType::Silver, false),
+        "成銀" | "全" => (PieceType::Silver, true),
+        "金" => (PieceType::Gold, false),
+        "角" => (PieceType::Bishop, false),
+        "馬" => (PieceType::Bishop, true),
+        "飛" => (PieceType::Rook, false),
+        "龍" | "竜" => (PieceType::Rook, true),
+        "玉" | "王" => (PieceType::King, false),
+        _ => return None,
+    };
+    Some(piece)
+}
+
+fn digit(c: char) -> Option<u8> {
+    if let Some(d) = c.to_digit(10) {
+        return Some(d as u8);
+    }
+    FILES
+        .iter()
+        .position(|f| f.starts_with(c))
+        .or_else(|| RANKS.iter().position(|r| r.starts_with(c)))
+        .map(|i| i as u8 + 1)
+}
+
+fn read_hand(board: &mut Board, color: Color, hand: &str) -> Result<(), String> {
+    for item in hand.split(['　', ' ']) {
+        let item = item.trim();
+        // "残り全部" (all the rest) is left to the caller, as in tsume problems
+        if item.is_empty() || item == "なし" || item == "残り全部" {
+            continue;
+        }
+        let name: String = item.chars().take(1).collect();
+        let count: String = item.chars().skip(1).collect();
+        let (piecetype, _) = piece_from_name(&name).ok_or(format!("unknown piece '{}'", name))?;
+        let count = kanji_number(&count).ok_or(format!("invalid count in '{}'", item))?;
+        for _ in 0..count {
+            board.add_piece(Piece {
+                color,
+                piecetype,
+                promoted: false,
+                position: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// one row of a board diagram, e.g. "|v香v桂 ・ ・v玉 ・ ・v桂v香|一"
+fn read_diagram_row(board: &mut Board, rank: u8, row: &str) -> Result<(), String> {
+    let cells: Vec<char> = row.trim_start_matches('|').chars().take(18).collect();
+    if cells.len() != 18 {
+        return Err(format!("invalid board row '{}'", row));
+    }
+    for (i, cell) in cells.chunks(2).enumerate() {
+        let file = 9 - i as u8;
+        if cell[1] == '・' {
+            continue;
+        }
+        let (piecetype, promoted) =
+            piece_from_name(&cell[1].to_string()).ok_or(format!("unknown piece in '{}'", row))?;
+        board.add_piece(Piece {
+            color: if cell[0] == 'v' {
+                Color::Black
+            } else {
+                Color::White
+            },
+            piecetype,
+            promoted,
+            position: Some(sfen::position(file, rank)),
+        });
+    }
+    Ok(())
+}
+
+/// parse a move line such as "   1 ７六歩(77)   ( 0:01/00:00:01)", None at the end of the game
+fn read_move(
+    board: &Board,
+    line: &str,
+    previous: Option<Position>,
+) -> Result<Option<String>, String> {
+    // only ASCII spaces separate the fields, "同　歩" has a full-width one
+    let text = match line.split(' ').filter(|field| !field.is_empty()).nth(1) {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    let mut chars = text.chars();
+    let end = if text.starts_with('同') {
+        chars.next();
+        previous.ok_or(format!("'同' without previous move in '{}'", line))?
+    } else {
+        match (chars.next().and_then(digit), chars.next().and_then(digit)) {
+            (Some(file), Some(rank)) => sfen::position(file, rank),
+            // 投了, 詰み, 中断...
+            _ => return Ok(None),
+        }
+    };
+    let rest: String = chars.collect();
+    let rest = rest.trim_start_matches('　');
+    let (piece, start) = match rest.find('(') {
+        Some(i) => (&rest[..i], Some(&rest[i..])),
+        None => (rest, None),
+    };
+
+    let movement = match start {
+        None => {
+            let name = piece.trim_end_matches('打');
+            let (piecetype, _) =
+                piece_from_name(name).ok_or(format!("unknown piece in '{}'", line))?;
+            Movement {
+                piecetype,
+                start: None,
+                end,
+                promotion: false,
+                force_capture: false,
+                withdraw: false,
+                restart: false,
+            }
+        }
+        Some(start) => {
+            let digits: Vec<u8> = start
+                .chars()
+                .filter_map(|c| c.to_digit(10).map(|d| d as u8))
+                .collect();
+            if digits.len() != 2 {
+                return Err(format!("invalid origin in '{}'", line));
+            }
+            let start = sfen::position(digits[0], digits[1]);
+            let moved = board
+                .is_occupied_by(start)
+                .ok_or(format!("no piece to move in '{}'", line))?;
+            Movement {
+                piecetype: moved.piecetype,
+                start: Some(start),
+                end,
+                promotion: piece.ends_with('成') && !piece.ends_with("不成"),
+                force_capture: false,
+                withdraw: false,
+                restart: false,
+            }
+        }
+    };
+    Ok(Some(movement.to_string()))
+}
+
+/// parse a KIF record: headers, handicap or board diagram, and the main line of moves
+pub fn read(text: &str) -> Result<GameRecord, String> {
+    let mut record = GameRecord::new(Handicap::Even, "", "");
+    let mut diagram = Board::empty();
+    let mut has_diagram = false;
+    let mut rank = 0;
+    let mut black_to_move = false;
+    let mut moves = Vec::new();
+
+    let lines: Vec<&str> = text.lines().map(|l| l.trim_end()).collect();
+    let mut i = 0;
+    // headers and diagram
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.starts_with("手数") {
+            break;
+        }
+        if let Some(marks) = marks::from_kif_comment(line) {
+            record.marks.insert(0, marks?);
+            continue;
+        }
+        if line.starts_with('#') || line.starts_with('*') {
+            continue;
+        }
+        if line.starts_with('|') {
+            if rank == 9 {
+                return Err(String::from("the board diagram has more than 9 rows"));
+            }
+            rank += 1;
+            has_diagram = true;
+            read_diagram_row(&mut diagram, rank, line)?;
+            continue;
+        }
+        if line.starts_with("後手番") || line.starts_with("上手番") {
+            black_to_move = true;
+            continue;
+        }
+        if let Some(colon) = line.find('：') {
+            let key = &line[..colon];
+            let value = line[colon + '：'.len_utf8()..].trim();
+            match key {
+                "手合割" => {
+                    record.handicap = Handicap::from_kif_name(value).unwrap_or(Handicap::Even)
+                }
+                "先手" | "下手" => record.sente = value.to_string(),
+                "後手" | "上手" => record.gote = value.to_string(),
+                "開始日時" => record.date = Some(value.to_string()),
+                "戦型" => record.opening = Some(value.to_string()),
+                "先手の持駒" | "下手の持駒" => {
+                    read_hand(&mut diagram, Color::White, value)?
+                }
+                "後手の持駒" | "上手の持駒" => {
+                    read_hand(&mut diagram, Color::Black, value)?
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if has_diagram && rank < 9 {
+        return Err(format!("the board diagram has {} rows, not 9", rank));
+    }
+    record.start = if has_diagram {
+        if black_to_move {
+            diagram.turn = Color::Black;
+        }
+        diagram
+    } else {
+        record.handicap.board()
+    };
+
+    // main line
+    let mut board = record.start.clone();
+    let mut previous = None;
+    for (n, line) in lines.iter().enumerate().skip(i) {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("変化") || trimmed.starts_with("まで") {
+            break;
+        }
+        // the marks of the position the move before reached
+        if let Some(marks) = marks::from_kif_comment(trimmed) {
+            record.marks.insert(moves.len(), marks?);
+            continue;
+        }
+        if !trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        match read_move(&board, trimmed, previous)? {
+            Some(mv) => {
+                previous = sfen::parse_movement(&mv).ok().map(|m| m.end);
+                board = board.play_move_unchecked(&mv);
+                moves.push(mv);
+            }
+            None => {
+                // 投了, 詰み... the line has no move but may say how the game ended
+                let special = trimmed.split(' ').filter(|field| !field.is_empty()).nth(1);
+                record.result = special
+                    .and_then(|special| GameResult::from_kif_move(special, board.get_color()));
+                if record.result == Some(GameResult::Sennichite { winner: None }) {
+                    // a loss by perpetual check: "まで12手で後手の勝ち"
+                    let last = lines[n + 1..]
+                        .iter()
+                        .find(|l| l.trim_start().starts_with("まで"));
+                    if let Some(winner) = last.and_then(|last| kif_winner(last)) {
+                        record.result = Some(GameResult::Sennichite {
+                            winner: Some(winner),
+                        });
+                    }
+                }
+                break;
+            }
+        }
+    }
+    record.times = vec![None; moves.len()];
+    record.moves = moves;
+    Ok(record)
+}
+
+/// read a KIF file, in UTF-8 (.kifu) or Shift_JIS (.kif)
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<GameRecord, String> {
+    let bytes = fs::read(path.as_ref()).map_err(|e| e.to_string())?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => encoding_rs::SHIFT_JIS.decode(e.as_bytes()).0.into_owned(),
+    };
+    read(&text).map_err(|e| format!("{}: {}", path.as_ref().display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSUME: &str = "\
+後手の持駒：残り全部
+  ９ ８ ７ ６ ５ ４ ３ ２ １
++---------------------------+
+| ・ ・ ・ ・ ・ ・ ・ ・v玉|一
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|二
+| ・ ・ ・ ・ ・ ・ ・ ・ 歩|三
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|四
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|五
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|六
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|七
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|八
+| ・ ・ ・ ・ ・ ・ ・ ・ ・|九
++---------------------------+
+先手の持駒：金
+";
+
+    #[test]
+    fn diagrams() {
+        let record = read(TSUME).unwrap();
+        assert_eq!(sfen::to_sfen(&record.start, 1), "8k/9/8P/9/9/9/9/9/9 b G 1");
+        assert!(!record.is_standard_start());
+
+        let (board, _) = sfen::from_sfen(
+            "ln1g3+Rl/2s1k4/p1pp1p2p/4p4/1p7/2P6/PPNPPPP1P/1+b2K4/L2G1GSNL w BG2Pn2p 1",
+        )
+        .unwrap();
+        let record = read(&diagram(&board)).unwrap();
+        assert_eq!(sfen::to_sfen(&record.start, 1), sfen::to_sfen(&board, 1));
+    }
+
+    #[test]
+    fn diagrams_of_nine_rows() {
+        let row = "| ・ ・ ・ ・ ・ ・ ・ ・ 歩|十\n";
+        let ten = TSUME.replace(
+            "+---------------------------+\n先手",
+            &format!("{}+---------------------------+\n先手", row),
+        );
+        assert!(read(&ten).is_err());
+        let eight = TSUME.replace("| ・ ・ ・ ・ ・ ・ ・ ・ ・|九\n", "");
+        assert!(read(&eight).is_err());
+    }
+}
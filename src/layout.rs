@@ -0,0 +1,188 @@
+// Where things are in the window: the board between the two hands, and the panel right of it.
+// Shared by the drawing and the mouse, nothing of SDL here.
+
+use crate::editor::Spot;
+use crate::rules;
+
+use shogai::piece::*;
+use shogai::position::*;
+
+use std::convert::TryFrom;
+
+pub const SRC_RESERVE_HEIGTH: u32 = 100;
+pub const SCR_WIDTH: u32 = 603;
+pub const SCR_HEIGHT: u32 = 603 + 2 * SRC_RESERVE_HEIGTH;
+
+pub const SQR_SIZE: u32 = SCR_WIDTH / 9;
+
+/// the side panel (opening book or game database), right of the board
+pub const PANEL_WIDTH: u32 = 240;
+pub const PANEL_ROW: u32 = 48;
+
+/// the square drawn at `p`, and the other way round: the board turned around when flipped
+pub fn view(p: Position, flip: bool) -> Position {
+    if flip {
+        Position(80 - p.0)
+    } else {
+        p
+    }
+}
+
+/// the side whose hand is drawn at the top
+pub fn top_side(flip: bool) -> Color {
+    if flip {
+        Color::Black
+    } else {
+        Color::White
+    }
+}
+
+/// top left corner of a square
+pub fn square_origin(p: Position, flip: bool) -> (i32, i32) {
+    let p = view(p, flip);
+    let x = (8 - p.0 % 9) * SQR_SIZE as u16;
+    let y = p.0 / 9 * SQR_SIZE as u16 + SRC_RESERVE_HEIGTH as u16;
+    (x as i32, y as i32)
+}
+
+/// top left corner of a piece in hand, the `count`th of its kind
+pub fn reserve_origin(piece: &Piece, count: usize, flip: bool) -> (i32, i32) {
+    let mut x = 2 * match piece.piecetype {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 3,
+        PieceType::Lance => 4,
+        PieceType::Rook => 5,
+        PieceType::Bishop => 6,
+        PieceType::Gold => 7,
+        PieceType::Silver => 8,
+        PieceType::King => panic!("King was found in reserve, what kind of shit is this?"),
+    };
+    let spacing_multiplier = 10; //pixels per identical pieces
+    let y = if piece.color == top_side(flip) {
+        (count % 4) * spacing_multiplier
+    } else {
+        SCR_HEIGHT as usize - SQR_SIZE as usize - (count % 4) * spacing_multiplier
+    };
+    if x == 0 {
+        //only for pawns as there can be many pawns
+        x += count / 4;
+    }
+    (x as i32 * SCR_WIDTH as i32 / 9 / 2, y as i32)
+}
+
+/// the square at a point of the window
+pub fn square_at(x: i32, y: i32, flip: bool) -> Option<Position> {
+    if y >= SRC_RESERVE_HEIGTH as i32
+        && y < SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32
+        && (0..SCR_WIDTH as i32).contains(&x)
+    {
+        let p = Position(
+            (9 - (x / SQR_SIZE as i32) as u16)
+                + ((y - SRC_RESERVE_HEIGTH as i32) / SQR_SIZE as i32) as u16 * 9
+                - 1,
+        );
+        Some(view(p, flip))
+    } else {
+        None
+    }
+}
+
+/// whose hand is at a height of the window
+pub fn side_at(y: i32, flip: bool) -> Option<Color> {
+    if y <= SRC_RESERVE_HEIGTH as i32 {
+        return Some(top_side(flip));
+    }
+    if y >= SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32 {
+        return Some(rules::opponent(top_side(flip)));
+    }
+    None
+}
+
+/// piece type of the reserve column at the given x coordinate
+pub fn reserve_piecetype(x: i32) -> Option<PieceType> {
+    match x * 9 / SCR_WIDTH as i32 {
+        0..=2 => Some(PieceType::Pawn),
+        3 => Some(PieceType::Knight),
+        4 => Some(PieceType::Lance),
+        5 => Some(PieceType::Rook),
+        6 => Some(PieceType::Bishop),
+        7 => Some(PieceType::Gold),
+        8 => Some(PieceType::Silver),
+        _ => None,
+    }
+}
+
+/// the piece type of `side`'s hand at a point of the window
+pub fn hand_at(x: i32, y: i32, side: Color, flip: bool) -> Option<PieceType> {
+    if side_at(y, flip) == Some(side) && (0..SCR_WIDTH as i32).contains(&x) {
+        reserve_piecetype(x)
+    } else {
+        None
+    }
+}
+
+/// the square or hand at a point of the window, for the position editor
+pub fn spot_at(x: i32, y: i32, flip: bool) -> Option<Spot> {
+    if let Some(color) = side_at(y, flip) {
+        reserve_piecetype(x).map(|piecetype| Spot::Hand(color, piecetype))
+    } else if (0..SCR_WIDTH as i32).contains(&x) {
+        let p = Position(
+            (8 - x / SQR_SIZE as i32) as u16
+                + ((y - SRC_RESERVE_HEIGTH as i32) / SQR_SIZE as i32) as u16 * 9,
+        );
+        Some(Spot::Square(view(p, flip)))
+    } else {
+        None
+    }
+}
+
+/// the row of the panel at a point of the window
+pub fn panel_row_at(x: i32, y: i32, rows: usize) -> Option<usize> {
+    if x < SCR_WIDTH as i32 {
+        return None;
+    }
+    let row = (y - SRC_RESERVE_HEIGTH as i32) / PANEL_ROW as i32;
+    usize::try_from(row).ok().filter(|&row| row < rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squares_are_found_where_they_are_drawn() {
+        for flip in [false, true] {
+            for i in 0..81 {
+                let (x, y) = square_origin(Position(i), flip);
+                let middle = SQR_SIZE as i32 / 2;
+                assert_eq!(square_at(x + middle, y + middle, flip), Some(Position(i)));
+                assert_eq!(
+                    spot_at(x + middle, y + middle, flip),
+                    Some(Spot::Square(Position(i)))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hands_are_above_and_below_the_board() {
+        // sente (shogai's White) is at the top unless flipped
+        assert_eq!(hand_at(10, 10, Color::White, false), Some(PieceType::Pawn));
+        assert_eq!(hand_at(10, 10, Color::Black, false), None);
+        let bottom = SCR_HEIGHT as i32 - 10;
+        assert_eq!(
+            hand_at(SCR_WIDTH as i32 - 10, bottom, Color::White, true),
+            Some(PieceType::Silver)
+        );
+        assert_eq!(square_at(10, 10, false), None);
+        assert_eq!(square_at(SCR_WIDTH as i32 + 10, 300, false), None);
+    }
+
+    #[test]
+    fn panel_rows_are_right_of_the_board() {
+        let y = SRC_RESERVE_HEIGTH as i32 + PANEL_ROW as i32 + 1;
+        assert_eq!(panel_row_at(SCR_WIDTH as i32 + 5, y, 3), Some(1));
+        assert_eq!(panel_row_at(SCR_WIDTH as i32 + 5, y, 1), None);
+        assert_eq!(panel_row_at(5, y, 3), None);
+    }
+}
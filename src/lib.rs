@@ -0,0 +1,40 @@
+// The game as a library: the model and its controller, the input and the drawing of the window,
+// the engines, the protocols and the file formats. The shogui executable is a thin layer over
+// it, tests and other programs can use the same pieces.
+
+pub mod animation;
+pub mod assets;
+pub mod book;
+pub mod cli;
+pub mod clock;
+pub mod controller;
+pub mod csa;
+pub mod database;
+pub mod diagram;
+pub mod editor;
+pub mod emscripten_file;
+pub mod game;
+pub mod gif;
+pub mod handicap;
+pub mod headless;
+pub mod impasse;
+pub mod input;
+pub mod kif;
+pub mod layout;
+pub mod marks;
+pub mod net;
+pub mod opponent;
+pub mod record;
+pub mod render;
+pub mod repetition;
+pub mod replay;
+pub mod result;
+pub mod rules;
+pub mod settings;
+pub mod sfen;
+pub mod shogiban;
+pub mod sound;
+pub mod tournament;
+pub mod tsume;
+pub mod ui;
+pub mod usi;
@@ -1,9 +1,61 @@
-pub mod emscripten_file;
-pub mod shogiban;
+use shogui::cli::{Args, USAGE};
+use shogui::{book, csa, database, diagram, headless, shogiban, tournament, usi};
 
 fn main() -> Result<(), String> {
+    let args = Args::parse(std::env::args().skip(1))?;
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    if let Some(path) = &args.save_book {
+        let source = args
+            .book
+            .as_ref()
+            .ok_or("--save-book needs a --book to save")?;
+        let book = book::Book::load(source)?;
+        std::fs::write(path, book.write()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        println!("{} positions saved to {}", book.len(), path.display());
+        return Ok(());
+    }
+
+    if let Some(path) = &args.add_engine {
+        return usi::register(path);
+    }
+
+    // diagrams are drawn without a window
+    if args.export_png.is_some() || args.export_svg.is_some() {
+        if let Some(path) = &args.export_svg {
+            diagram::export_svg(&args, path)?;
+        }
+        if let Some(path) = &args.export_png {
+            headless::export_png(&args, path)?;
+        }
+        return Ok(());
+    }
+
+    // and so are whole games
+    if args.export_gif.is_some() || args.export_frames.is_some() {
+        return headless::export_game(&args);
+    }
+
+    // database queries are answered on the terminal
+    if args.query {
+        return database::run(&args);
+    }
+
+    // an engine on a CSA server needs no window
+    if args.csa.is_some() && args.csa_engine.is_some() {
+        return csa::run(&args);
+    }
+
+    // neither does a match between engines
+    if !args.engines.is_empty() {
+        return tournament::run(&args);
+    }
+
     // let's do this!
-    shogiban::init()?;
+    shogiban::init(&args)?;
 
     Ok(())
 }
@@ -0,0 +1,368 @@
+// Arrows and circles drawn on the board with the right button, as coaches do: drag from a square
+// to another for an arrow, click a square to circle it, Shift, Ctrl or Alt for another colour.
+// Every position of a game has its own, kept in its record and written in its KIF as a comment:
+//
+//   *marks: 7g7f 2h2d:red 5e 3c:blue
+//
+// (USI squares, green unless said otherwise). Where the marks are in the window is worked out
+// here too, as spans of pixel rows for the renderer to fill.
+
+use crate::layout::{square_origin, SQR_SIZE};
+use crate::settings::Rgb;
+use crate::sfen;
+
+use shogai::position::*;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// what starts the comment of the marks in KIF records
+pub const KIF_PREFIX: &str = "*marks:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkColour {
+    Green,
+    Red,
+    Blue,
+    Yellow,
+}
+
+impl MarkColour {
+    pub fn all() -> [MarkColour; 4] {
+        [
+            MarkColour::Green,
+            MarkColour::Red,
+            MarkColour::Blue,
+            MarkColour::Yellow,
+        ]
+    }
+
+    pub fn rgb(self) -> Rgb {
+        match self {
+            MarkColour::Green => (0x15, 0x78, 0x1B),
+            MarkColour::Red => (0xB0, 0x20, 0x20),
+            MarkColour::Blue => (0x20, 0x50, 0xB0),
+            MarkColour::Yellow => (0xD0, 0xA0, 0x10),
+        }
+    }
+}
+
+impl fmt::Display for MarkColour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MarkColour::Green => write!(f, "green"),
+            MarkColour::Red => write!(f, "red"),
+            MarkColour::Blue => write!(f, "blue"),
+            MarkColour::Yellow => write!(f, "yellow"),
+        }
+    }
+}
+
+impl FromStr for MarkColour {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        MarkColour::all()
+            .iter()
+            .find(|colour| colour.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown colour '{}' (green, red, blue or yellow)", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mark {
+    Arrow(Position, Position, MarkColour),
+    Circle(Position, MarkColour),
+}
+
+impl Mark {
+    pub fn colour(&self) -> MarkColour {
+        match *self {
+            Mark::Arrow(_, _, colour) | Mark::Circle(_, colour) => colour,
+        }
+    }
+
+    /// the same arrow or circle, whatever its colour
+    fn same_place(&self, other: &Mark) -> bool {
+        match (*self, *other) {
+            (Mark::Arrow(a, b, _), Mark::Arrow(c, d, _)) => a == c && b == d,
+            (Mark::Circle(a, _), Mark::Circle(b, _)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// the rows of pixels it covers in the window: y, first x and last x
+    pub fn spans(&self, flip: bool) -> Vec<(i32, i32, i32)> {
+        match *self {
+            Mark::Arrow(from, to, _) => polygon_spans(&arrow(centre(from, flip), centre(to, flip))),
+            Mark::Circle(at, _) => {
+                let outer = SQR_SIZE as f32 / 2.0 - 2.0;
+                ring_spans(centre(at, flip), outer, outer - 5.0)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let square = |pos: Position| sfen::usi_square(pos);
+        match *self {
+            Mark::Arrow(from, to, _) => write!(f, "{}{}", square(from), square(to))?,
+            Mark::Circle(at, _) => write!(f, "{}", square(at))?,
+        }
+        match self.colour() {
+            MarkColour::Green => Ok(()),
+            colour => write!(f, ":{}", colour),
+        }
+    }
+}
+
+impl FromStr for Mark {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (squares, colour) = match s.split_once(':') {
+            Some((squares, colour)) => (squares, colour.parse()?),
+            None => (s, MarkColour::Green),
+        };
+        let square =
+            |usi: &str| sfen::parse_usi_square(usi).map_err(|_| format!("'{}' isn't a mark", s));
+        match squares.len() {
+            2 => Ok(Mark::Circle(square(squares)?, colour)),
+            4 if squares.is_char_boundary(2) => Ok(Mark::Arrow(
+                square(&squares[..2])?,
+                square(&squares[2..])?,
+                colour,
+            )),
+            _ => Err(format!("'{}' isn't a mark", s)),
+        }
+    }
+}
+
+/// a mark drawn again: the same one goes away, one of another colour is painted over
+pub fn toggle(marks: &mut Vec<Mark>, mark: Mark) {
+    match marks.iter().position(|m| m.same_place(&mark)) {
+        Some(i) if marks[i] == mark => {
+            marks.remove(i);
+        }
+        Some(i) => marks[i] = mark,
+        None => marks.push(mark),
+    }
+}
+
+/// the KIF comment of the marks of a position
+pub fn kif_comment(marks: &[Mark]) -> String {
+    let marks: Vec<String> = marks.iter().map(|mark| mark.to_string()).collect();
+    format!("{} {}", KIF_PREFIX, marks.join(" "))
+}
+
+/// the marks of a KIF comment, None if it's another comment
+pub fn from_kif_comment(line: &str) -> Option<Result<Vec<Mark>, String>> {
+    let marks = line.trim().strip_prefix(KIF_PREFIX)?;
+    Some(marks.split_whitespace().map(|mark| mark.parse()).collect())
+}
+
+fn centre(pos: Position, flip: bool) -> (f32, f32) {
+    let (x, y) = square_origin(pos, flip);
+    let half = SQR_SIZE as f32 / 2.0;
+    (x as f32 + half, y as f32 + half)
+}
+
+/// the outline of an arrow from a centre of square to another: its shaft, then its head
+fn arrow((x0, y0): (f32, f32), (x1, y1): (f32, f32)) -> Vec<(f32, f32)> {
+    const SHAFT: f32 = 10.0;
+    const HEAD_WIDTH: f32 = 30.0;
+    const HEAD_LENGTH: f32 = 24.0;
+    let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt().max(1.0);
+    // along the arrow, and across it
+    let (dx, dy) = ((x1 - x0) / length, (y1 - y0) / length);
+    let (nx, ny) = (-dy, dx);
+    // it starts off the centre, not to hide the piece it comes from
+    let start = (SQR_SIZE as f32 / 4.0).min(length / 2.0);
+    let base = (length - HEAD_LENGTH).max(start);
+    let at =
+        |along: f32, across: f32| (x0 + dx * along + nx * across, y0 + dy * along + ny * across);
+    vec![
+        at(start, SHAFT / 2.0),
+        at(base, SHAFT / 2.0),
+        at(base, HEAD_WIDTH / 2.0),
+        at(length, 0.0),
+        at(base, -HEAD_WIDTH / 2.0),
+        at(base, -SHAFT / 2.0),
+        at(start, -SHAFT / 2.0),
+    ]
+}
+
+/// the rows of pixels inside a polygon (their centres, as for the even-odd rule)
+fn polygon_spans(points: &[(f32, f32)]) -> Vec<(i32, i32, i32)> {
+    let top = points.iter().map(|p| p.1).fold(f32::MAX, f32::min).floor() as i32;
+    let bottom = points.iter().map(|p| p.1).fold(f32::MIN, f32::max).ceil() as i32;
+    let mut spans = Vec::new();
+    for y in top..bottom {
+        let middle = y as f32 + 0.5;
+        let mut crossings: Vec<f32> = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .filter(|((_, ya), (_, yb))| (*ya <= middle) != (*yb <= middle))
+            .map(|((xa, ya), (xb, yb))| xa + (middle - ya) / (yb - ya) * (xb - xa))
+            .collect();
+        crossings.sort_by(|a, b| a.total_cmp(b));
+        for pair in crossings.chunks(2) {
+            if let [left, right] = *pair {
+                let (left, right) = ((left - 0.5).ceil() as i32, (right - 0.5).floor() as i32);
+                if left <= right {
+                    spans.push((y, left, right));
+                }
+            }
+        }
+    }
+    spans
+}
+
+/// the rows of pixels between two circles
+fn ring_spans((cx, cy): (f32, f32), outer: f32, inner: f32) -> Vec<(i32, i32, i32)> {
+    let half_width = |radius: f32, dy: f32| {
+        if dy.abs() < radius {
+            Some((radius * radius - dy * dy).sqrt())
+        } else {
+            None
+        }
+    };
+    let mut spans = Vec::new();
+    for y in (cy - outer).floor() as i32..(cy + outer).ceil() as i32 {
+        let dy = y as f32 + 0.5 - cy;
+        let outer = match half_width(outer, dy) {
+            Some(w) => w,
+            None => continue,
+        };
+        let (left, right) = ((cx - outer).round() as i32, (cx + outer).round() as i32 - 1);
+        match half_width(inner, dy) {
+            // the sides of the ring
+            Some(inner) => {
+                let (hole_left, hole_right) =
+                    ((cx - inner).round() as i32, (cx + inner).round() as i32 - 1);
+                spans.push((y, left, hole_left - 1));
+                spans.push((y, hole_right + 1, right));
+            }
+            // its top and bottom
+            None => spans.push((y, left, right)),
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+    use crate::kif;
+    use crate::record::GameRecord;
+
+    fn square(usi: &str) -> Position {
+        sfen::parse_usi_square(usi).unwrap()
+    }
+
+    #[test]
+    fn read_and_write() {
+        let marks = from_kif_comment("*marks: 7g7f 2h2d:red 5e 3c:blue")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            marks,
+            vec![
+                Mark::Arrow(square("7g"), square("7f"), MarkColour::Green),
+                Mark::Arrow(square("2h"), square("2d"), MarkColour::Red),
+                Mark::Circle(square("5e"), MarkColour::Green),
+                Mark::Circle(square("3c"), MarkColour::Blue),
+            ]
+        );
+        assert_eq!(kif_comment(&marks), "*marks: 7g7f 2h2d:red 5e 3c:blue");
+        assert!(from_kif_comment("*a comment").is_none());
+        assert!(from_kif_comment("*marks: 7g7z").unwrap().is_err());
+        assert!(from_kif_comment("*marks: 5e:pink").unwrap().is_err());
+    }
+
+    #[test]
+    fn kif_records_keep_the_marks_of_each_position() {
+        let mut record = GameRecord::new(Handicap::Even, "sente", "gote");
+        let circle = Mark::Circle(square("5e"), MarkColour::Yellow);
+        record.toggle_mark(circle);
+        let mut board = record.start.clone();
+        for usi in ["7g7f", "3c3d"] {
+            let mv = sfen::from_usi(usi, &board).unwrap();
+            board = board.play_move_unchecked(&mv);
+            record.push(&mv);
+        }
+        let arrow = Mark::Arrow(square("8h"), square("2b"), MarkColour::Green);
+        record.toggle_mark(arrow);
+        let kif = kif::write(&record);
+        assert!(kif.contains("*marks: 8h2b\n"));
+
+        let read = kif::read(&kif).unwrap();
+        assert_eq!(read.moves, record.moves);
+        assert_eq!(read.marks(0), &[circle]);
+        assert!(read.marks(1).is_empty());
+        assert_eq!(read.marks(2), &[arrow]);
+    }
+
+    #[test]
+    fn marks_drawn_again_go_away() {
+        let mut marks = Vec::new();
+        let arrow = Mark::Arrow(square("7g"), square("7f"), MarkColour::Green);
+        toggle(&mut marks, arrow);
+        toggle(&mut marks, Mark::Circle(square("7g"), MarkColour::Green));
+        assert_eq!(marks.len(), 2);
+        // another colour replaces it
+        let red = Mark::Arrow(square("7g"), square("7f"), MarkColour::Red);
+        toggle(&mut marks, red);
+        assert_eq!(marks[0], red);
+        toggle(&mut marks, red);
+        assert_eq!(marks, vec![Mark::Circle(square("7g"), MarkColour::Green)]);
+        // the other way is another arrow
+        toggle(
+            &mut marks,
+            Mark::Arrow(square("7f"), square("7g"), MarkColour::Red),
+        );
+        assert_eq!(marks.len(), 2);
+    }
+
+    #[test]
+    fn arrows_go_from_square_to_square() {
+        let (from, to) = (square("5g"), square("5c"));
+        let spans = Mark::Arrow(from, to, MarkColour::Green).spans(false);
+        let ((x, y), start) = (centre(to, false), centre(from, false).1);
+        // vertical (downwards, sente is at the top): the head is wider than the shaft, and its tip
+        // is at the centre of the square
+        let width = |row: f32| {
+            spans
+                .iter()
+                .filter(|(y, ..)| *y == row as i32)
+                .map(|(_, left, right)| right - left + 1)
+                .sum::<i32>()
+        };
+        let (shaft, head) = (width((start + y) / 2.0), width(y - 20.0));
+        assert!((9..=11).contains(&shaft), "{}", shaft);
+        assert!(head > 2 * shaft);
+        let ys: Vec<i32> = spans.iter().map(|(y, ..)| *y).collect();
+        assert_eq!(ys.iter().max(), Some(&(y as i32 - 1)));
+        assert!(ys.iter().min() > Some(&(start as i32)));
+        assert!(spans.iter().all(|(_, left, right)| left <= right
+            && (*left as f32) > x - 16.0
+            && (*right as f32) < x + 16.0));
+    }
+
+    #[test]
+    fn circles_are_rings() {
+        let spans = Mark::Circle(square("5e"), MarkColour::Red).spans(true);
+        let (x, y) = centre(square("5e"), true);
+        // across the middle, two short spans
+        let middle: Vec<_> = spans.iter().filter(|s| s.0 == y as i32).collect();
+        assert_eq!(middle.len(), 2);
+        assert!(middle.iter().all(|(_, left, right)| right - left < 8));
+        assert!(spans.iter().all(
+            |(_, left, right)| (*left as f32) >= x - SQR_SIZE as f32 / 2.0
+                && (*right as f32) < x + SQR_SIZE as f32 / 2.0
+        ));
+    }
+}
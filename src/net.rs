@@ -0,0 +1,688 @@
+// Network play between two shogui instances over TCP.
+//
+// One instance hosts (listens on a port), the other joins. They then exchange UTF-8 lines, one
+// message per line, the keyword first:
+//
+//   HELLO <version> <name>          both sides, first thing after connecting (guest first)
+//   GAME <side> <time> <rule> <sfen>
+//                                   host to guest: the side the guest plays (sente or gote), the
+//                                   time control as "<main>+<byoyomi>" in seconds, the impasse
+//                                   rule (24 or 27 points) and the start position
+//   MOVE <usi>                      a move of the sender, e.g. "MOVE 7g7f" or "MOVE P*5e"
+//   CLOCK <sente ms> <gote ms>      main time left on both clocks, sent after each move
+//   RESIGN                          the sender resigns
+//   DECLARE                         the sender declares an entering king win (see impasse.rs)
+//   DRAW_OFFER                      the sender offers a draw...
+//   DRAW_ACCEPT / DRAW_DECLINE      ...and the answer to it
+//   CHAT <text>                     a chat message, to the end of the line
+//   TIMEOUT                         the sender ran out of time
+//   ERROR <text>                    the sender received something it can't accept (an illegal
+//                                   move or declaration...), the game is over
+//   REMATCH                         after the game, the sender wants another one: once both sides
+//                                   sent it, the same game starts again, sides swapped in even
+//                                   games
+//   BYE                             the sender leaves
+//
+// Both ends check every move with `Board::check_move`, and every declaration with
+// `impasse::declaration`, before playing it.
+
+use crate::clock::TimeControl;
+use crate::game::GameState;
+use crate::handicap::Handicap;
+use crate::impasse;
+use crate::result::GameResult;
+use crate::rules;
+use crate::sfen;
+
+use shogai::board::Board;
+use shogai::piece::Color;
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+pub const VERSION: u32 = 2;
+pub const DEFAULT_PORT: u16 = 4080;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Hello {
+        version: u32,
+        name: String,
+    },
+    Game {
+        /// shogai color of the guest
+        guest: Color,
+        control: TimeControl,
+        rule: impasse::Rule,
+        sfen: String,
+    },
+    Move(String),
+    /// main time left for sente and gote
+    Clock(Duration, Duration),
+    Resign,
+    Declare,
+    DrawOffer,
+    DrawAccept,
+    DrawDecline,
+    Chat(String),
+    Timeout,
+    Error(String),
+    Rematch,
+    Bye,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::Hello { version, name } => write!(f, "HELLO {} {}", version, name),
+            Message::Game {
+                guest,
+                control,
+                rule,
+                sfen,
+            } => write!(
+                f,
+                "GAME {} {} {} {}",
+                sfen::side_name(*guest),
+                control,
+                rule,
+                sfen
+            ),
+            Message::Move(usi) => write!(f, "MOVE {}", usi),
+            Message::Clock(sente, gote) => {
+                write!(f, "CLOCK {} {}", sente.as_millis(), gote.as_millis())
+            }
+            Message::Resign => write!(f, "RESIGN"),
+            Message::Declare => write!(f, "DECLARE"),
+            Message::DrawOffer => write!(f, "DRAW_OFFER"),
+            Message::DrawAccept => write!(f, "DRAW_ACCEPT"),
+            Message::DrawDecline => write!(f, "DRAW_DECLINE"),
+            Message::Chat(text) => write!(f, "CHAT {}", text),
+            Message::Timeout => write!(f, "TIMEOUT"),
+            Message::Error(text) => write!(f, "ERROR {}", text),
+            Message::Rematch => write!(f, "REMATCH"),
+            Message::Bye => write!(f, "BYE"),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (keyword, rest) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+        let invalid = || format!("invalid message '{}'", line);
+        let millis = |s: Option<&str>| -> Result<Duration, String> {
+            s.and_then(|s| s.parse().ok())
+                .map(Duration::from_millis)
+                .ok_or_else(invalid)
+        };
+        let message = match keyword {
+            "HELLO" => {
+                let mut fields = rest.splitn(2, ' ');
+                let version = fields
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(invalid)?;
+                let name = fields.next().unwrap_or("").to_string();
+                Message::Hello { version, name }
+            }
+            "GAME" => {
+                let mut fields = rest.splitn(4, ' ');
+                let guest = match fields.next() {
+                    Some("sente") => Color::White,
+                    Some("gote") => Color::Black,
+                    _ => return Err(invalid()),
+                };
+                let control = fields.next().ok_or_else(invalid)?.parse()?;
+                let rule = fields.next().ok_or_else(invalid)?.parse()?;
+                let sfen = fields.next().ok_or_else(invalid)?.to_string();
+                Message::Game {
+                    guest,
+                    control,
+                    rule,
+                    sfen,
+                }
+            }
+            "MOVE" if !rest.is_empty() => Message::Move(rest.to_string()),
+            "CLOCK" => {
+                let mut fields = rest.split(' ');
+                Message::Clock(millis(fields.next())?, millis(fields.next())?)
+            }
+            "RESIGN" => Message::Resign,
+            "DECLARE" => Message::Declare,
+            "DRAW_OFFER" => Message::DrawOffer,
+            "DRAW_ACCEPT" => Message::DrawAccept,
+            "DRAW_DECLINE" => Message::DrawDecline,
+            "CHAT" => Message::Chat(rest.to_string()),
+            "TIMEOUT" => Message::Timeout,
+            "ERROR" => Message::Error(rest.to_string()),
+            "REMATCH" => Message::Rematch,
+            "BYE" => Message::Bye,
+            _ => return Err(invalid()),
+        };
+        Ok(message)
+    }
+}
+
+/// a line based connection, to the other instance (or to a CSA server, see csa.rs). Lines are
+/// read and parsed by a background thread, so polling never blocks the window.
+pub struct Connection<M = Message> {
+    writer: TcpStream,
+    incoming: Receiver<Result<M, String>>,
+    closed: bool,
+}
+
+impl<M> Connection<M>
+where
+    M: FromStr + fmt::Display + Send + 'static,
+    M::Err: ToString,
+{
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let message = match line {
+                    Ok(line) => line.parse().map_err(|e: M::Err| e.to_string()),
+                    Err(_) => break,
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Connection {
+            writer: stream,
+            incoming,
+            closed: false,
+        })
+    }
+
+    pub fn send(&mut self, message: &M) -> Result<(), String> {
+        // chat text can't be allowed to start a new message
+        let line = message.to_string().replace(['\r', '\n'], " ");
+        writeln!(self.writer, "{}", line).map_err(|e| format!("connection lost: {}", e))
+    }
+
+    /// the messages received so far
+    pub fn poll(&mut self) -> Vec<Result<M, String>> {
+        let mut messages = Vec::new();
+        loop {
+            match self.incoming.try_recv() {
+                Ok(message) => messages.push(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        messages
+    }
+
+    /// wait for the next message (at most `timeout`)
+    pub fn receive(&mut self, timeout: Duration) -> Option<Result<M, String>> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                self.closed |= e == std::sync::mpsc::RecvTimeoutError::Disconnected;
+                None
+            }
+        }
+    }
+
+    /// whether the other side closed the connection
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+impl<M> Drop for Connection<M> {
+    fn drop(&mut self) {
+        // the reading thread holds a clone of the socket, which would keep it open
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+/// a network game, once both sides agreed on it
+pub struct NetGame {
+    pub connection: Connection,
+    /// the other player's name
+    pub peer: String,
+    pub human: Color,
+    pub handicap: Handicap,
+    pub start: Board,
+    pub control: TimeControl,
+    /// entering king declaration rule, chosen by the host
+    pub rule: impasse::Rule,
+    /// last chat line or notice, shown in the title
+    pub status: String,
+    /// why the connection can't be used anymore
+    pub error: Option<String>,
+    /// a rematch was asked for, by this side and by the other one
+    pub rematch_sent: bool,
+    pub rematch_received: bool,
+}
+
+impl NetGame {
+    pub fn send(&mut self, message: &Message) {
+        if let Err(e) = self.connection.send(message) {
+            self.error = Some(e);
+        }
+    }
+
+    /// play what the other side sent since the last frame, `accept_draw` asks the player about
+    /// its draw offers. Returns the result when the game ends (the clock of the player falling
+    /// too, or the connection).
+    pub fn poll(
+        &mut self,
+        state: &mut GameState,
+        mut accept_draw: impl FnMut(&str) -> Result<bool, String>,
+    ) -> Result<Option<GameResult>, String> {
+        let mut ended = None;
+        for message in self.connection.poll() {
+            ended = self.receive(message, state, &mut accept_draw)?;
+            if ended.is_some() {
+                break;
+            }
+        }
+        let human = state.human;
+        if state.result.is_none() && ended.is_none() {
+            if state.clock.flagged(human) {
+                self.send(&Message::Timeout);
+                ended = Some(GameResult::TimeLoss {
+                    winner: rules::opponent(human),
+                });
+            } else if self.connection.is_closed() {
+                self.status = format!("{} left the game", self.peer);
+                ended = Some(GameResult::Abort);
+            } else if let Some(e) = self.error.clone() {
+                self.status = e;
+                ended = Some(GameResult::Abort);
+            }
+        }
+        Ok(ended)
+    }
+
+    /// one message of the other side
+    fn receive(
+        &mut self,
+        message: Result<Message, String>,
+        state: &mut GameState,
+        accept_draw: &mut impl FnMut(&str) -> Result<bool, String>,
+    ) -> Result<Option<GameResult>, String> {
+        let human = state.human;
+        let other = rules::opponent(human);
+        let playing = state.result.is_none();
+        let their_turn = playing && state.board.get_color() != human;
+        let ended = match message {
+            Ok(Message::Move(usi)) if their_turn => {
+                let game = &state.board;
+                let checked = sfen::from_usi(&usi, game).and_then(|mv| {
+                    game.check_move(&mv)
+                        .map(|_| mv.clone())
+                        .map_err(|e| e.to_string())
+                });
+                match checked {
+                    Ok(mv) => {
+                        state.play(&mv);
+                        state.clock.switch();
+                        None
+                    }
+                    Err(e) => {
+                        self.send(&Message::Error(format!("illegal move {}", usi)));
+                        self.status = format!("{} played an illegal move: {}", self.peer, e);
+                        Some(GameResult::IllegalMove { winner: human })
+                    }
+                }
+            }
+            Ok(Message::Clock(sente, gote)) => {
+                let main = match other {
+                    Color::White => sente,
+                    Color::Black => gote,
+                };
+                state.clock.set_main_time(other, main);
+                None
+            }
+            Ok(Message::Resign) => Some(GameResult::Resignation { winner: human }),
+            Ok(Message::Declare) if their_turn => {
+                match impasse::declaration(&state.board, self.rule) {
+                    Ok(declared) => Some(declared),
+                    Err(e) => {
+                        self.send(&Message::Error(format!("illegal declaration: {}", e)));
+                        self.status = format!("{} declared illegally: {}", self.peer, e);
+                        Some(GameResult::IllegalMove { winner: human })
+                    }
+                }
+            }
+            Ok(Message::DrawOffer) if playing => {
+                if accept_draw(&format!("{} offers a draw", self.peer))? {
+                    self.send(&Message::DrawAccept);
+                    Some(GameResult::DrawAgreed)
+                } else {
+                    self.send(&Message::DrawDecline);
+                    None
+                }
+            }
+            Ok(Message::DrawAccept) => Some(GameResult::DrawAgreed),
+            Ok(Message::DrawDecline) => {
+                self.status = format!("{} declined the draw", self.peer);
+                None
+            }
+            Ok(Message::Chat(text)) => {
+                self.status = format!("{}: {}", self.peer, text);
+                None
+            }
+            Ok(Message::Timeout) => Some(GameResult::TimeLoss { winner: human }),
+            Ok(Message::Error(text)) => {
+                self.status = format!("{} stopped the game: {}", self.peer, text);
+                Some(if text.starts_with("illegal") {
+                    GameResult::IllegalMove { winner: other }
+                } else {
+                    GameResult::Abort
+                })
+            }
+            Ok(Message::Rematch) if !playing => {
+                self.rematch_received = true;
+                self.status = format!("{} wants a rematch (R to accept)", self.peer);
+                None
+            }
+            Ok(Message::Bye) => {
+                self.status = format!("{} left the game", self.peer);
+                Some(GameResult::Abort)
+            }
+            // moves crossing the end of the game (both flagged at once...)
+            Ok(Message::Move(_)) | Ok(Message::DrawOffer) | Ok(Message::Declare) if !playing => {
+                None
+            }
+            Ok(unexpected) => {
+                self.send(&Message::Error(format!("unexpected {}", unexpected)));
+                self.status = format!("{} sent an unexpected {}", self.peer, unexpected);
+                Some(GameResult::Abort)
+            }
+            Err(e) => {
+                self.send(&Message::Error(e.clone()));
+                self.status = e;
+                Some(GameResult::Abort)
+            }
+        };
+        Ok(ended)
+    }
+
+    /// whether both sides asked for a rematch, which then starts: both sides swap in even games,
+    /// the handicap stays with its giver
+    pub fn rematch(&mut self) -> bool {
+        if !(self.rematch_sent && self.rematch_received) {
+            return false;
+        }
+        if self.handicap == Handicap::Even {
+            self.human = rules::opponent(self.human);
+        }
+        self.rematch_sent = false;
+        self.rematch_received = false;
+        self.status = String::new();
+        true
+    }
+
+    /// the title of the window: the clocks or the result, and the last notice (or the chat line
+    /// being typed)
+    pub fn title(&self, game_title: &str, state: &GameState, typing: Option<&str>) -> String {
+        let status = match (typing, state.result) {
+            (Some(line), _) => format!("say: {}_", line),
+            (None, Some(result)) => format!("{} | R: rematch, Esc: quit | {}", result, self.status),
+            (None, None) => match &state.points {
+                Some(points) => format!("{} | {} | {}", state.clock.summary(), points, self.status),
+                None => format!("{} | {}", state.clock.summary(), self.status),
+            },
+        };
+        format!("{} | {}", game_title, status)
+    }
+}
+
+/// a listening socket waiting for the guest
+pub struct Host {
+    listener: TcpListener,
+}
+
+impl Host {
+    pub fn listen(port: u16) -> Result<Self, String> {
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .map_err(|e| format!("can't listen on port {}: {}", port, e))?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(Host { listener })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map(|a| a.port()).unwrap_or(0)
+    }
+
+    /// the guest's connection if one arrived, without waiting
+    pub fn accept(&self) -> Result<Option<Connection>, String> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+                Connection::new(stream).map(Some).map_err(|e| e.to_string())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// connect to a host, "address:port" or just "address" for the default port
+pub fn join(address: &str) -> Result<Connection, String> {
+    connect(address, DEFAULT_PORT)
+}
+
+/// open a line based connection to "address:port", or "address" and the given port
+pub fn connect<M>(address: &str, default_port: u16) -> Result<Connection<M>, String>
+where
+    M: FromStr + fmt::Display + Send + 'static,
+    M::Err: ToString,
+{
+    let address = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, default_port)
+    };
+    let addr = address
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", address, e))?
+        .next()
+        .ok_or(format!("{}: no such address", address))?;
+    let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(10))
+        .map_err(|e| format!("can't connect to {}: {}", address, e))?;
+    Connection::new(stream).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::record::GameRecord;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = vec![
+            Message::Hello {
+                version: VERSION,
+                name: String::from("Hanako Yamada"),
+            },
+            Message::Game {
+                guest: Color::Black,
+                control: "600+30".parse().unwrap(),
+                rule: impasse::Rule::TwentyFour,
+                sfen: String::from(crate::sfen::STARTPOS),
+            },
+            Message::Move(String::from("7g7f")),
+            Message::Move(String::from("P*5e")),
+            Message::Clock(
+                Duration::from_millis(59_500),
+                Duration::from_millis(600_000),
+            ),
+            Message::Resign,
+            Message::Declare,
+            Message::DrawOffer,
+            Message::DrawAccept,
+            Message::DrawDecline,
+            Message::Chat(String::from("よろしくお願いします")),
+            Message::Timeout,
+            Message::Error(String::from("illegal move 5e5d")),
+            Message::Rematch,
+            Message::Bye,
+        ];
+        for message in messages {
+            assert_eq!(message.to_string().parse::<Message>(), Ok(message));
+        }
+        assert!("MOVE".parse::<Message>().is_err());
+        assert!("HELLO x".parse::<Message>().is_err());
+    }
+
+    /// the guest's and the host's ends of a connection on localhost
+    fn pair() -> (Connection, Connection) {
+        let host = Host::listen(0).unwrap();
+        let guest = join(&format!("127.0.0.1:{}", host.port())).unwrap();
+        loop {
+            if let Some(connection) = host.accept().unwrap() {
+                return (guest, connection);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn host_and_join_on_localhost() {
+        let (mut guest, mut server) = pair();
+
+        guest.send(&Message::Move(String::from("3c3d"))).unwrap();
+        guest
+            .send(&Message::Chat(String::from("hi\nMOVE 1a1b")))
+            .unwrap();
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            server.receive(timeout),
+            Some(Ok(Message::Move(String::from("3c3d"))))
+        );
+        assert_eq!(
+            server.receive(timeout),
+            Some(Ok(Message::Chat(String::from("hi MOVE 1a1b"))))
+        );
+
+        drop(guest);
+        assert_eq!(server.receive(timeout), None);
+        assert!(server.is_closed());
+    }
+
+    fn game(connection: Connection, human: Color) -> (NetGame, GameState) {
+        let start = Board::new();
+        let record = GameRecord::from_position(start.clone(), "sente", "gote");
+        let control = TimeControl::default();
+        let state = GameState::new(
+            start.clone(),
+            human,
+            record,
+            Clock::new(control),
+            impasse::Rule::default(),
+        );
+        let net = NetGame {
+            connection,
+            peer: String::from("Hanako"),
+            human,
+            handicap: Handicap::Even,
+            start,
+            control,
+            rule: impasse::Rule::default(),
+            status: String::new(),
+            error: None,
+            rematch_sent: false,
+            rematch_received: false,
+        };
+        (net, state)
+    }
+
+    /// poll until the messages sent have arrived and `done` says so
+    fn poll_until(
+        net: &mut NetGame,
+        state: &mut GameState,
+        done: impl Fn(&NetGame, &GameState, Option<GameResult>) -> bool,
+    ) -> Option<GameResult> {
+        for _ in 0..500 {
+            let ended = net.poll(state, |_| Ok(true)).unwrap();
+            if done(net, state, ended) {
+                return ended;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the messages never arrived")
+    }
+
+    #[test]
+    fn games_over_the_network() {
+        let (mut peer, connection) = pair();
+        let (mut net, mut state) = game(connection, Color::Black);
+        let timeout = Duration::from_secs(5);
+
+        peer.send(&Message::Move(String::from("7g7f"))).unwrap();
+        peer.send(&Message::Chat(String::from("hi"))).unwrap();
+        poll_until(&mut net, &mut state, |net, _, _| !net.status.is_empty());
+        assert_eq!(state.record.moves.len(), 1);
+        assert_eq!(net.status, "Hanako: hi");
+        assert!(net
+            .title("Shogi - vs Hanako", &state, None)
+            .ends_with("| Hanako: hi"));
+
+        // a move on the player's turn stops the game, and so does an illegal one
+        peer.send(&Message::Move(String::from("7f7d"))).unwrap();
+        let ended = poll_until(&mut net, &mut state, |_, _, ended| ended.is_some());
+        assert_eq!(ended, Some(GameResult::Abort));
+        state.play(&sfen::from_usi("3c3d", &state.board).unwrap());
+        peer.send(&Message::Move(String::from("7f7d"))).unwrap();
+        let ended = poll_until(&mut net, &mut state, |_, _, ended| ended.is_some());
+        assert_eq!(
+            ended,
+            Some(GameResult::IllegalMove {
+                winner: Color::Black
+            })
+        );
+        assert_eq!(
+            peer.receive(timeout),
+            Some(Ok(Message::Error(String::from("unexpected MOVE 7f7d"))))
+        );
+        assert_eq!(
+            peer.receive(timeout),
+            Some(Ok(Message::Error(String::from("illegal move 7f7d"))))
+        );
+        assert_eq!(state.record.moves.len(), 2);
+
+        // the player accepts the draw
+        peer.send(&Message::DrawOffer).unwrap();
+        let ended = poll_until(&mut net, &mut state, |_, _, ended| ended.is_some());
+        assert_eq!(ended, Some(GameResult::DrawAgreed));
+        assert_eq!(peer.receive(timeout), Some(Ok(Message::DrawAccept)));
+
+        // both sides want another game, with the sides swapped
+        state.end(GameResult::DrawAgreed);
+        net.rematch_sent = true;
+        assert!(!net.rematch());
+        peer.send(&Message::Rematch).unwrap();
+        poll_until(&mut net, &mut state, |net, _, _| net.rematch_received);
+        assert!(net.rematch());
+        assert_eq!(net.human, Color::White);
+
+        drop(peer);
+        let ended = poll_until(&mut net, &mut state, |net, _, _| net.connection.is_closed());
+        // the game was over already
+        assert_eq!(ended, None);
+    }
+}
@@ -0,0 +1,424 @@
+// Built-in computer opponents.
+//
+// Every opponent implements the `Opponent` trait, so the GUI (and anything else driving a game)
+// does not need to know whether it talks to a random mover, the greedy bot from shogai or the
+// alpha-beta search defined here.
+
+use crate::impasse;
+
+use rand::seq::IteratorRandom;
+use rand::thread_rng;
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Something able to pick a move for the side to move.
+pub trait Opponent: Send {
+    /// human readable name, used in window titles and records
+    fn name(&self) -> String;
+
+    /// return the chosen move (in shogai notation, or `impasse::DECLARE` to declare an entering
+    /// king win), or None if there is no legal move
+    fn choose_move(&mut self, board: &Board) -> Option<String>;
+
+    /// time left on the clock for the next move, None without a clock; opponents that don't
+    /// think long anyway ignore it
+    fn set_time_budget(&mut self, _budget: Option<Duration>) {}
+}
+
+/// Search limits. A limit set to None is not enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+/// The built-in strength levels, from the weakest to the strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Random,
+    Greedy,
+    Normal,
+    Hard,
+    Expert,
+}
+
+impl Level {
+    pub fn all() -> [Level; 5] {
+        [
+            Level::Random,
+            Level::Greedy,
+            Level::Normal,
+            Level::Hard,
+            Level::Expert,
+        ]
+    }
+
+    /// limits used when none are given on the command line
+    pub fn default_limits(self) -> Limits {
+        match self {
+            Level::Random | Level::Greedy => Limits::default(),
+            Level::Normal => Limits {
+                movetime: Some(Duration::from_secs(3)),
+                nodes: Some(20_000),
+            },
+            Level::Hard => Limits {
+                movetime: Some(Duration::from_secs(10)),
+                nodes: Some(200_000),
+            },
+            Level::Expert => Limits {
+                movetime: Some(Duration::from_secs(15)),
+                nodes: None,
+            },
+        }
+    }
+
+    pub fn build(self, limits: Limits) -> Box<dyn Opponent> {
+        match self {
+            Level::Random => Box::new(RandomMover),
+            Level::Greedy => Box::new(Greedy),
+            Level::Normal => Box::new(AlphaBeta::new(self, 2, limits)),
+            Level::Hard => Box::new(AlphaBeta::new(self, 3, limits)),
+            Level::Expert => Box::new(AlphaBeta::new(self, 5, limits)),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Random => "random",
+            Level::Greedy => "greedy",
+            Level::Normal => "normal",
+            Level::Hard => "hard",
+            Level::Expert => "expert",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Level::all()
+            .iter()
+            .find(|level| level.to_string() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown level '{}'", s))
+    }
+}
+
+/// plays any legal move
+pub struct RandomMover;
+
+impl Opponent for RandomMover {
+    fn name(&self) -> String {
+        Level::Random.to_string()
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        board.iter_moves().choose(&mut thread_rng())
+    }
+}
+
+/// the one-ply bot shipped with shogai
+pub struct Greedy;
+
+impl Opponent for Greedy {
+    fn name(&self) -> String {
+        Level::Greedy.to_string()
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        // shogai::ai::greedy unwraps, so make sure there is something to choose from
+        board.iter_moves().next()?;
+        Some(shogai::ai::greedy(board))
+    }
+}
+
+const MATE: i32 = 1_000_000;
+const INFINITY: i32 = 2 * MATE;
+
+/// depth-limited negamax with alpha-beta pruning and iterative deepening
+pub struct AlphaBeta {
+    level: Level,
+    max_depth: u8,
+    limits: Limits,
+    /// time allowed by the clock, on top of the limits
+    budget: Option<Duration>,
+    nodes: u64,
+    deadline: Option<Instant>,
+}
+
+impl AlphaBeta {
+    pub fn new(level: Level, max_depth: u8, limits: Limits) -> Self {
+        AlphaBeta {
+            level,
+            max_depth,
+            limits,
+            budget: None,
+            nodes: 0,
+            deadline: None,
+        }
+    }
+
+    fn out_of_budget(&self) -> bool {
+        if let Some(max_nodes) = self.limits.nodes {
+            if self.nodes >= max_nodes {
+                return true;
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// score of `board` for the side to move, None if the search ran out of budget
+    fn negamax(&mut self, board: &Board, depth: u8, mut alpha: i32, beta: i32) -> Option<i32> {
+        self.nodes += 1;
+        if !board.contains(PieceType::King, board.get_color()) {
+            // our king was taken on the previous ply: the position before was illegal or mate
+            return Some(-MATE);
+        }
+        if depth == 0 {
+            return Some(evaluate(board));
+        }
+        if self.out_of_budget() {
+            return None;
+        }
+
+        let mut best = -INFINITY;
+        for mv in ordered_moves(board, false) {
+            let score = -self.negamax(&board.play_move_unchecked(&mv), depth - 1, -beta, -alpha)?;
+            if score > best {
+                best = score;
+            }
+            if best > alpha {
+                alpha = best;
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+impl Opponent for AlphaBeta {
+    fn name(&self) -> String {
+        self.level.to_string()
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        if impasse::can_declare_win(board) {
+            return Some(String::from(impasse::DECLARE));
+        }
+        self.nodes = 0;
+        let movetime = match (self.limits.movetime, self.budget) {
+            (Some(movetime), Some(budget)) => Some(movetime.min(budget)),
+            (movetime, budget) => movetime.or(budget),
+        };
+        self.deadline = movetime.map(|t| Instant::now() + t);
+
+        let root_moves = ordered_moves(board, true);
+        let mut best_move = root_moves.first()?.clone();
+
+        'deepening: for depth in 1..=self.max_depth {
+            let mut alpha = -INFINITY;
+            let mut best_at_depth = None;
+            for mv in &root_moves {
+                let score = match self.negamax(
+                    &board.play_move_unchecked(mv),
+                    depth - 1,
+                    -INFINITY,
+                    -alpha,
+                ) {
+                    Some(score) => -score,
+                    // only trust fully searched depths
+                    None => break 'deepening,
+                };
+                if score > alpha || best_at_depth.is_none() {
+                    alpha = score;
+                    best_at_depth = Some(mv.clone());
+                }
+            }
+            if let Some(mv) = best_at_depth {
+                best_move = mv;
+            }
+            if alpha >= MATE / 2 {
+                // found a forced win, no need to look further
+                break;
+            }
+        }
+        Some(best_move)
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.budget = budget;
+    }
+}
+
+/// moves of the side to move, captures and promotions first to help pruning
+fn ordered_moves(board: &Board, complete_check: bool) -> Vec<String> {
+    let mut moves: Vec<String> = if complete_check {
+        board.iter_moves().collect()
+    } else {
+        board.iter_moves_partial_check().collect()
+    };
+    moves.sort_by_key(|mv| {
+        let movement: Movement = mv.parse().unwrap();
+        let captured = board
+            .is_occupied_by(movement.end)
+            .map(|piece| piece.value())
+            .unwrap_or(0);
+        -(captured + if movement.promotion { 300 } else { 0 })
+    });
+    moves
+}
+
+/// rows from the piece's own back rank (0) to the opponent's back rank (8)
+fn advancement(piece: &Piece) -> i32 {
+    let row = piece.position.map(|p| (p.0 / 9) as i32).unwrap_or(0);
+    match piece.color {
+        Color::White => row,
+        Color::Black => 8 - row,
+    }
+}
+
+/// material plus a few positional terms, from the point of view of the side to move
+pub fn evaluate(board: &Board) -> i32 {
+    let mut score = board.value();
+    for piece in board.iter().filter(|p| p.position.is_some()) {
+        let bonus = match piece.piecetype {
+            // walking pieces are worth more when they take part in the attack
+            PieceType::Pawn | PieceType::Silver | PieceType::Knight if !piece.promoted => {
+                4 * advancement(piece)
+            }
+            PieceType::Gold | PieceType::Silver => king_guard_bonus(board, piece),
+            // a king wandering up the board is usually a king in danger
+            PieceType::King => -15 * advancement(piece),
+            _ => 0,
+        };
+        if piece.color == board.get_color() {
+            score += bonus;
+        } else {
+            score -= bonus;
+        }
+    }
+    score
+}
+
+/// whether the computer takes the draw the player offers: unless it thinks it's winning
+pub fn accepts_draw(board: &Board, human: Color) -> bool {
+    let score = evaluate(board);
+    // from the computer's point of view
+    let score = if board.get_color() == human {
+        -score
+    } else {
+        score
+    };
+    score <= 0
+}
+
+/// generals standing next to their own king
+fn king_guard_bonus(board: &Board, piece: &Piece) -> i32 {
+    let king = board
+        .iter()
+        .find(|p| p.piecetype == PieceType::King && p.color == piece.color);
+    match (king.and_then(|k| k.position), piece.position) {
+        (Some(k), Some(p)) => {
+            let dx = (k.0 % 9) as i32 - (p.0 % 9) as i32;
+            let dy = (k.0 / 9) as i32 - (p.0 / 9) as i32;
+            if dx.abs() <= 1 && dy.abs() <= 1 {
+                30
+            } else {
+                0
+            }
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    fn board(position: &str) -> Board {
+        sfen::from_sfen(position).unwrap().0
+    }
+
+    #[test]
+    fn levels() {
+        for level in Level::all() {
+            assert_eq!(level.to_string().parse::<Level>(), Ok(level));
+        }
+        assert_eq!("Hard".parse::<Level>(), Ok(Level::Hard));
+        assert!("grandmaster".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn evaluation_is_for_the_side_to_move() {
+        let positions = [
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B7/LNSGKGSNL",
+            "ln1gk2nl/1r1s2g2/p1pppp1pp/6p2/1p7/2P6/PPSPPPPPP/2G4R1/LN2KGSNL",
+            "4k4/9/9/9/4r4/9/9/9/B7K",
+        ];
+        for position in positions {
+            let sente = board(&format!("{} b - 1", position));
+            let gote = board(&format!("{} w - 1", position));
+            assert_eq!(evaluate(&sente), -evaluate(&gote), "{}", position);
+        }
+    }
+
+    #[test]
+    fn search() {
+        // a mate in one with a gold drop (the pawn spares gote running out of moves elsewhere)
+        let mate = board("8k/9/p7P/9/9/9/9/9/4K4 b G 1");
+        let mut ai = AlphaBeta::new(Level::Hard, 3, Limits::default());
+        let gold = sfen::from_usi("G*1b", &mate).unwrap();
+        assert_eq!(ai.choose_move(&mate), Some(gold));
+
+        // a rook left hanging
+        let hanging = board("4k4/9/9/9/4r4/9/9/9/B7K b - 1");
+        let mv = ai.choose_move(&hanging).unwrap();
+        let after = sfen::to_sfen(&hanging.play_move_unchecked(&mv), 2);
+        assert!(after.starts_with("4k4/9/9/9/4B4/9/9/9/8K w R"), "{}", after);
+    }
+
+    #[test]
+    fn node_limit() {
+        let limits = Limits {
+            movetime: None,
+            nodes: Some(1_000),
+        };
+        let mut ai = AlphaBeta::new(Level::Expert, 5, limits);
+        let start = board("startpos");
+        let mv = ai.choose_move(&start).unwrap();
+        assert!(start.check_move(&mv).is_ok());
+        // the leaves under the last node searched are still counted
+        assert!(ai.nodes < 2_000, "{} nodes", ai.nodes);
+    }
+
+    #[test]
+    fn draw_offers() {
+        // sente (the player) without its rook, then gote (the computer) without its own
+        let rook_up = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B7/LNSGKGSNL";
+        let rook_down = "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL";
+        for side in ["b", "w"] {
+            let up = board(&format!("{} {} - 1", rook_up, side));
+            assert!(!accepts_draw(&up, Color::White));
+            let down = board(&format!("{} {} - 1", rook_down, side));
+            assert!(accepts_draw(&down, Color::White));
+        }
+        assert!(accepts_draw(&board("startpos"), Color::White));
+    }
+}
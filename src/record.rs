@@ -0,0 +1,151 @@
+// The record of a game: how it started and which moves were played.
+
+use crate::csa;
+use crate::handicap::Handicap;
+use crate::kif;
+use crate::marks::{self, Mark};
+use crate::result::GameResult;
+use crate::sfen;
+
+use shogai::board::*;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    /// name of the first player (sente, or shitate in handicap games)
+    pub sente: String,
+    /// name of the second player (gote, or uwate in handicap games)
+    pub gote: String,
+    pub handicap: Handicap,
+    pub start: Board,
+    /// moves in shogai notation
+    pub moves: Vec<String>,
+    /// time spent on each move, when known
+    pub times: Vec<Option<Duration>>,
+    /// how the game ended, None while it goes on
+    pub result: Option<GameResult>,
+    /// when the game started, as written in the record ("2024/03/09 10:00:00")
+    pub date: Option<String>,
+    /// name of the opening (戦型), when the record gives it
+    pub opening: Option<String>,
+    /// the arrows and circles drawn on positions, by the number of moves played before them
+    pub marks: BTreeMap<usize, Vec<Mark>>,
+}
+
+impl GameRecord {
+    pub fn new(handicap: Handicap, sente: &str, gote: &str) -> Self {
+        GameRecord {
+            sente: sente.to_string(),
+            gote: gote.to_string(),
+            handicap,
+            start: handicap.board(),
+            moves: Vec::new(),
+            times: Vec::new(),
+            result: None,
+            date: None,
+            opening: None,
+            marks: BTreeMap::new(),
+        }
+    }
+
+    /// a game starting from an arbitrary position (set up in the editor, a problem...)
+    pub fn from_position(start: Board, sente: &str, gote: &str) -> Self {
+        GameRecord {
+            sente: sente.to_string(),
+            gote: gote.to_string(),
+            handicap: Handicap::Even,
+            start,
+            moves: Vec::new(),
+            times: Vec::new(),
+            result: None,
+            date: None,
+            opening: None,
+            marks: BTreeMap::new(),
+        }
+    }
+
+    /// whether the game started from the position of its handicap (or the even position)
+    pub fn is_standard_start(&self) -> bool {
+        self.start_sfen() == self.handicap.sfen()
+    }
+
+    pub fn push(&mut self, mv: &str) {
+        self.moves.push(mv.to_string());
+        self.times.push(None);
+        self.marks.remove(&self.moves.len());
+    }
+
+    pub fn push_timed(&mut self, mv: &str, time: Duration) {
+        self.moves.push(mv.to_string());
+        self.times.push(Some(time));
+        self.marks.remove(&self.moves.len());
+    }
+
+    /// the marks of the position after `ply` moves
+    pub fn marks(&self, ply: usize) -> &[Mark] {
+        self.marks.get(&ply).map_or(&[], |marks| marks.as_slice())
+    }
+
+    /// a mark drawn on the current position, or taken away if it was there
+    pub fn toggle_mark(&mut self, mark: Mark) {
+        let marks = self.marks.entry(self.moves.len()).or_default();
+        marks::toggle(marks, mark);
+        if marks.is_empty() {
+            self.marks.remove(&self.moves.len());
+        }
+    }
+
+    /// the time of the last move, once known
+    pub fn set_last_time(&mut self, time: Duration) {
+        if let Some(last) = self.times.last_mut() {
+            *last = Some(time);
+        }
+    }
+
+    pub fn time(&self, ply: usize) -> Option<Duration> {
+        self.times.get(ply).copied().flatten()
+    }
+
+    /// the board after all the recorded moves
+    pub fn current(&self) -> Board {
+        self.moves.iter().fold(self.start.clone(), |board, mv| {
+            board.play_move_unchecked(mv)
+        })
+    }
+
+    /// SFEN of the starting position
+    pub fn start_sfen(&self) -> String {
+        sfen::to_sfen(&self.start, 1)
+    }
+
+    /// the USI "position" command reaching the current position, as sent to engines
+    pub fn usi_position(&self) -> String {
+        let start = if self.start_sfen() == sfen::STARTPOS {
+            String::from("startpos")
+        } else {
+            format!("sfen {}", self.start_sfen())
+        };
+        if self.moves.is_empty() {
+            return format!("position {}", start);
+        }
+        let moves: Vec<String> = self
+            .moves
+            .iter()
+            .filter_map(|mv| sfen::to_usi(mv).ok())
+            .collect();
+        format!("position {} moves {}", start, moves.join(" "))
+    }
+
+    pub fn save_kif<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, kif::write(self))
+    }
+
+    pub fn save_csa<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, csa::write(self))
+    }
+}
@@ -0,0 +1,387 @@
+// Drawing the game: the board and its hands, the pieces, the highlights, the panel of book or
+// database moves and the end of the game. The renderer owns the pieces' textures, everything else
+// it draws comes from the game state and the settings. It draws on any SDL render target: the
+// window, or a surface in memory (see headless.rs).
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+use crate::animation::Animation;
+use crate::assets;
+use crate::editor::Editor;
+use crate::game::GameState;
+use crate::layout::*;
+use crate::marks::Mark;
+use crate::result::GameResult;
+use crate::rules;
+use crate::settings::{Palette, Rgb, Settings};
+use crate::sfen;
+
+use std::marker::PhantomData;
+
+/// what's drawn over a game besides the game itself
+#[derive(Clone, Copy, Default)]
+pub struct Overlays<'a> {
+    /// the move suggested
+    pub hint: Option<&'a Movement>,
+    /// the rows of the open panel
+    pub panel: Option<&'a [PanelRow]>,
+    /// the pieces of the last move on their way
+    pub animation: Option<&'a Animation>,
+    /// arrows and circles, over the pieces
+    pub marks: &'a [Mark],
+}
+
+/// what the panel right of the board shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Panel {
+    /// the book moves of the position
+    Book,
+    /// the moves played from the position in the games of the database
+    Database,
+}
+
+/// a move of the panel with its bar, split by result for the database
+pub struct PanelRow {
+    pub mv: String,
+    pub bar: Vec<(u32, Color)>,
+}
+
+/// the sprites of each piece type, unpromoted and promoted (kings and golds don't promote)
+const SPRITES: [(PieceType, &str, &str); 8] = [
+    (PieceType::Pawn, "p", "pp"),
+    (PieceType::Lance, "l", "lp"),
+    (PieceType::Knight, "n", "np"),
+    (PieceType::Silver, "s", "sp"),
+    (PieceType::Gold, "g", "g"),
+    (PieceType::Bishop, "b", "bp"),
+    (PieceType::Rook, "r", "rp"),
+    (PieceType::King, "k", "k"),
+];
+
+/// the pieces' textures, from src/sprites/white and src/sprites/black (or built in)
+/// credits for src/sprites: Wikimedia Commons
+pub struct Renderer<'t, T: RenderTarget> {
+    /// unpromoted and promoted, in the order of `SPRITES`
+    white: Vec<Texture<'t>>,
+    black: Vec<Texture<'t>>,
+    /// (textures can only be drawn on the target they were made for)
+    target: PhantomData<T>,
+}
+
+impl<'t, T: RenderTarget> Renderer<'t, T> {
+    pub fn new(texture_creator: &'t TextureCreator<T::Context>) -> Result<Self, String> {
+        let load = |color: &str| {
+            SPRITES
+                .iter()
+                .flat_map(|(_, piece, promoted)| vec![piece, promoted])
+                .map(|name| assets::load_sprite(texture_creator, &format!("{}/{}", color, name)))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Renderer {
+            white: load("white")?,
+            black: load("black")?,
+            target: PhantomData,
+        })
+    }
+
+    pub fn texture(&self, piece: &Piece) -> &Texture<'t> {
+        let textures = match piece.color {
+            shogai::piece::Color::White => &self.white,
+            shogai::piece::Color::Black => &self.black,
+        };
+        let i = SPRITES
+            .iter()
+            .position(|(piecetype, ..)| *piecetype == piece.piecetype)
+            .unwrap_or(0);
+        &textures[2 * i + piece.promoted as usize]
+    }
+
+    /// the whole window during a game: the last move under everything else, the piece picked up
+    /// following the mouse, the overlays there are
+    pub fn draw_game(
+        &self,
+        canvas: &mut Canvas<T>,
+        state: &GameState,
+        settings: &Settings,
+        overlays: Overlays,
+        mouse: (i32, i32),
+    ) -> Result<(), String> {
+        let palette = settings.palette();
+        draw_board(canvas, &palette);
+
+        if let Some(movement) = state
+            .record
+            .moves
+            .last()
+            .filter(|_| settings.highlight_last_move)
+            .and_then(|mv| sfen::parse_movement(mv).ok())
+        {
+            for p in movement.start.into_iter().chain(Some(movement.end)) {
+                draw_square(canvas, p, palette.last_move, settings.flip);
+            }
+        }
+        //select in green movable pieces on the board
+        if let Some(pos) = state
+            .selected_square()
+            .filter(|_| settings.highlight_select && state.human_turn())
+        {
+            draw_square(canvas, pos, palette.select, settings.flip);
+        }
+        if let Some(mv) = overlays.hint {
+            for p in mv.start.into_iter().chain(Some(mv.end)) {
+                draw_square(canvas, p, palette.hint, settings.flip);
+            }
+        }
+        if let Some(rows) = overlays.panel {
+            // the hints of the panel go under the pieces
+            self.draw_panel(canvas, rows, &state.board, settings, mouse)?;
+        }
+        let mut hidden: Vec<Piece> = state.hidden().into_iter().collect();
+        if let Some(animation) = overlays.animation {
+            hidden.extend(animation.hidden());
+            self.draw_pieces(canvas, &state.board, &hidden, settings.flip);
+            for (piece, origin) in animation.pieces(settings.flip) {
+                self.draw_piece(canvas, &piece, origin, settings.flip);
+            }
+        } else {
+            self.draw_pieces(canvas, &state.board, &hidden, settings.flip);
+        }
+        draw_marks(canvas, overlays.marks, settings.flip);
+        if let Some(piece) = state.floating() {
+            self.draw_floating(canvas, &piece, mouse);
+        }
+        if let Some(result) = state.result {
+            draw_result(canvas, &state.board, result, settings.flip);
+        }
+        Ok(())
+    }
+
+    /// the window while a position is set up
+    pub fn draw_editor(
+        &self,
+        canvas: &mut Canvas<T>,
+        editor: &Editor,
+        settings: &Settings,
+        mouse: (i32, i32),
+    ) {
+        draw_board(canvas, &settings.palette());
+        let dragged = editor.dragged();
+        let hidden: Vec<Piece> = dragged.into_iter().collect();
+        self.draw_pieces(canvas, editor.board(), &hidden, settings.flip);
+        if let Some(piece) = dragged {
+            self.draw_floating(canvas, &piece, mouse);
+        }
+    }
+
+    /// This will parse and draw all pieces currently on the game to the window.
+    /// Pieces of `hidden` are left out, only once: of the identical pieces of a hand, the last.
+    fn draw_pieces(&self, canvas: &mut Canvas<T>, game: &Board, hidden: &[Piece], flip: bool) {
+        let pieces: Vec<&Piece> = game.iter().collect();
+        let left_out: Vec<usize> = hidden
+            .iter()
+            .filter_map(|hidden| pieces.iter().rposition(|p| *p == hidden))
+            .collect();
+        for (j, piece) in pieces.iter().enumerate() {
+            if left_out.contains(&j) {
+                continue;
+            }
+            let origin = match piece.position {
+                Some(i) => square_origin(i, flip),
+                None => {
+                    let count = pieces[..j].iter().filter(|p| p == &piece).count();
+                    reserve_origin(piece, count, flip)
+                }
+            };
+            self.draw_piece(canvas, piece, origin, flip);
+        }
+    }
+
+    /// a piece, its top left corner at `(x, y)`, turned around with the board
+    fn draw_piece(&self, canvas: &mut Canvas<T>, piece: &Piece, (x, y): (i32, i32), flip: bool) {
+        let _ = canvas.copy_ex(
+            self.texture(piece),
+            None,
+            Rect::new(x, y, SQR_SIZE, SQR_SIZE),
+            0.0,
+            None,
+            flip,
+            flip,
+        );
+    }
+
+    /// a piece under the mouse
+    fn draw_floating(&self, canvas: &mut Canvas<T>, piece: &Piece, (x, y): (i32, i32)) {
+        let _ = canvas.copy(
+            self.texture(piece),
+            None,
+            Rect::new(
+                x - SQR_SIZE as i32 / 2,
+                y - SQR_SIZE as i32 / 2,
+                SQR_SIZE,
+                SQR_SIZE,
+            ),
+        );
+    }
+
+    /// one row per move: the piece moving and how often it's played, the move under the mouse
+    /// (or the most played one) shown on the board
+    fn draw_panel(
+        &self,
+        canvas: &mut Canvas<T>,
+        rows: &[PanelRow],
+        game: &Board,
+        settings: &Settings,
+        (x, y): (i32, i32),
+    ) -> Result<(), String> {
+        canvas.set_draw_color(Color::RGB(0x30, 0x30, 0x30));
+        canvas.fill_rect(Rect::new(SCR_WIDTH as i32, 0, PANEL_WIDTH, SCR_HEIGHT))?;
+        let total: u32 = rows
+            .iter()
+            .flat_map(|row| row.bar.iter().map(|(count, _)| count))
+            .sum();
+        let hovered = panel_row_at(x, y, rows.len());
+        let shown = (SCR_HEIGHT - SRC_RESERVE_HEIGTH) / PANEL_ROW;
+        for (row, PanelRow { mv, bar }) in rows.iter().take(shown as usize).enumerate() {
+            let movement = match sfen::parse_movement(mv) {
+                Ok(movement) => movement,
+                Err(_) => continue,
+            };
+            let y = (SRC_RESERVE_HEIGTH + row as u32 * PANEL_ROW) as i32;
+            if hovered == Some(row) || (hovered.is_none() && row == 0) {
+                canvas.set_draw_color(Color::RGB(0x50, 0x50, 0x50));
+                canvas.fill_rect(Rect::new(SCR_WIDTH as i32, y, PANEL_WIDTH, PANEL_ROW))?;
+                for p in movement.start.into_iter().chain(Some(movement.end)) {
+                    draw_square(canvas, p, settings.palette().hint, settings.flip);
+                }
+            }
+            let piece = match movement.start.and_then(|start| game.is_occupied_by(start)) {
+                Some(piece) => Piece {
+                    promoted: piece.promoted || movement.promotion,
+                    ..piece
+                },
+                None => Piece {
+                    color: game.get_color(),
+                    piecetype: movement.piecetype,
+                    promoted: false,
+                    position: None,
+                },
+            };
+            canvas.copy(
+                self.texture(&piece),
+                None,
+                Rect::new(SCR_WIDTH as i32 + 4, y + 2, PANEL_ROW - 4, PANEL_ROW - 4),
+            )?;
+            let mut x = (SCR_WIDTH + PANEL_ROW + 4) as i32;
+            for &(count, color) in bar {
+                let width = (PANEL_WIDTH - PANEL_ROW - 12) * count / total.max(1);
+                if width == 0 {
+                    continue;
+                }
+                canvas.set_draw_color(color);
+                canvas.fill_rect(Rect::new(x, y + PANEL_ROW as i32 / 2 - 10, width, 20))?;
+                x += width as i32;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the squares and the hands, nothing on them
+fn draw_board<T: RenderTarget>(canvas: &mut Canvas<T>, palette: &Palette) {
+    canvas.set_draw_color(rgb(palette.board));
+    canvas.clear();
+    draw_grid(canvas, palette);
+
+    canvas.set_draw_color(rgb(palette.reserve));
+    let _ = canvas.fill_rect(Rect::new(0, 0, SCR_WIDTH, SRC_RESERVE_HEIGTH));
+    let _ = canvas.fill_rect(Rect::new(
+        0,
+        SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32,
+        SCR_WIDTH,
+        SRC_RESERVE_HEIGTH,
+    ));
+}
+
+// from: https://www.libsdl.org/tmp/SDL/test/testdrawchessboard.c
+// adapted for shogi
+fn draw_grid<T: RenderTarget>(canvas: &mut Canvas<T>, palette: &Palette) {
+    canvas.set_draw_color(rgb(palette.squares));
+    for row in 0..9 {
+        let mut x = row % 2;
+        for _ in (row % 2)..(5 + (row % 2)) {
+            let rect = Rect::new(
+                x * SQR_SIZE as i32,
+                row * SQR_SIZE as i32 + SRC_RESERVE_HEIGTH as i32,
+                SQR_SIZE,
+                SQR_SIZE,
+            );
+            x += 2;
+            let _ = canvas.fill_rect(rect);
+        }
+    }
+}
+
+/// dim the board once the game is over, and frame the kings: gold for the winner, red for the
+/// loser, grey for both after a draw
+fn draw_result<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    game: &Board,
+    result: GameResult,
+    flip: bool,
+) {
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 0x50));
+    let _ = canvas.fill_rect(Rect::new(0, 0, SCR_WIDTH, SCR_HEIGHT));
+    canvas.set_blend_mode(BlendMode::None);
+
+    for color in [shogai::piece::Color::White, shogai::piece::Color::Black] {
+        let frame = match result.winner() {
+            Some(winner) if winner == color => Color::RGB(0xE8, 0xB8, 0x20),
+            Some(_) => Color::RGB(0xC0, 0x20, 0x20),
+            None => Color::RGB(0x90, 0x90, 0x90),
+        };
+        if let Some(p) = rules::king_position(game, color) {
+            let (x, y) = square_origin(p, flip);
+            canvas.set_draw_color(frame);
+            for width in 0..4 {
+                let _ = canvas.draw_rect(Rect::new(
+                    x + width,
+                    y + width,
+                    SQR_SIZE - 2 * width as u32,
+                    SQR_SIZE - 2 * width as u32,
+                ));
+            }
+        }
+    }
+}
+
+/// arrows and circles, see-through
+fn draw_marks<T: RenderTarget>(canvas: &mut Canvas<T>, marks: &[Mark], flip: bool) {
+    canvas.set_blend_mode(BlendMode::Blend);
+    for mark in marks {
+        let (r, g, b) = mark.colour().rgb();
+        canvas.set_draw_color(Color::RGBA(r, g, b, 0xC0));
+        for (y, left, right) in mark.spans(flip) {
+            let _ = canvas.draw_line((left, y), (right, y));
+        }
+    }
+    canvas.set_blend_mode(BlendMode::None);
+}
+
+/// fill a square of the board
+fn draw_square<T: RenderTarget>(canvas: &mut Canvas<T>, p: Position, color: Rgb, flip: bool) {
+    canvas.set_draw_color(rgb(color));
+    let (x, y) = square_origin(p, flip);
+    let _ = canvas.fill_rect(Rect::new(x, y, SQR_SIZE, SQR_SIZE));
+}
+
+pub fn rgb((r, g, b): Rgb) -> Color {
+    Color::RGB(r, g, b)
+}
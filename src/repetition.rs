@@ -0,0 +1,253 @@
+// Sennichite: when the same position (board, hands and side to move) appears for the fourth time
+// the game is a draw, unless one side gave check with every one of its moves since the first time
+// it appeared (perpetual check), and then that side loses.
+//
+// Positions are compared by their Zobrist hash: a random key for every (square, piece) pair and
+// every (color, piece, count) in hand, xored together with one more key when gote is to move.
+
+use crate::record::GameRecord;
+use crate::result::GameResult;
+use crate::rules;
+
+use shogai::board::*;
+use shogai::piece::*;
+
+/// how many times a position has to appear for sennichite
+const OCCURRENCES: usize = 4;
+
+/// most pieces of one kind a hand can hold (pawns)
+const MAX_HAND: usize = 18;
+
+struct Keys {
+    /// by square, then piece (color, type and promotion, see `piece_index`)
+    squares: [[u64; 32]; 81],
+    /// by color, piece type and count
+    hands: [[[u64; MAX_HAND + 1]; 8]; 2],
+    side: u64,
+}
+
+/// splitmix64, good enough to spread the keys and usable at compile time
+const fn next_key(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn keys() -> Keys {
+    let mut keys = Keys {
+        squares: [[0; 32]; 81],
+        hands: [[[0; MAX_HAND + 1]; 8]; 2],
+        side: 0,
+    };
+    let mut state = 0x5348_4F47_4955_u64;
+    let mut square = 0;
+    while square < 81 {
+        let mut piece = 0;
+        while piece < 32 {
+            keys.squares[square][piece] = next_key(&mut state);
+            piece += 1;
+        }
+        square += 1;
+    }
+    let mut color = 0;
+    while color < 2 {
+        let mut piece = 0;
+        while piece < 8 {
+            // no key for an empty hand, so that it doesn't change the hash
+            let mut count = 1;
+            while count <= MAX_HAND {
+                keys.hands[color][piece][count] = next_key(&mut state);
+                count += 1;
+            }
+            piece += 1;
+        }
+        color += 1;
+    }
+    keys.side = next_key(&mut state);
+    keys
+}
+
+static KEYS: Keys = keys();
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+fn type_index(piecetype: PieceType) -> usize {
+    match piecetype {
+        PieceType::Pawn => 0,
+        PieceType::Lance => 1,
+        PieceType::Knight => 2,
+        PieceType::Silver => 3,
+        PieceType::Gold => 4,
+        PieceType::Bishop => 5,
+        PieceType::Rook => 6,
+        PieceType::King => 7,
+    }
+}
+
+fn piece_index(piece: &Piece) -> usize {
+    color_index(piece.color) * 16 + type_index(piece.piecetype) * 2 + piece.promoted as usize
+}
+
+/// Zobrist hash of the position: equal for equal positions, whatever the order of the pieces
+pub fn hash(board: &Board) -> u64 {
+    let mut hash = 0;
+    let mut hands = [[0usize; 8]; 2];
+    for piece in board.iter() {
+        match piece.position {
+            Some(position) => hash ^= KEYS.squares[position.0 as usize][piece_index(piece)],
+            None => hands[color_index(piece.color)][type_index(piece.piecetype)] += 1,
+        }
+    }
+    for (color, hand) in hands.iter().enumerate() {
+        for (piece, &count) in hand.iter().enumerate() {
+            hash ^= KEYS.hands[color][piece][count.min(MAX_HAND)];
+        }
+    }
+    if board.get_color() == Color::Black {
+        hash ^= KEYS.side;
+    }
+    hash
+}
+
+/// the positions of a game so far, one per ply
+#[derive(Debug, Clone)]
+pub struct Repetitions {
+    board: Board,
+    /// hash of every position since the start, and whether the side to move was in check there
+    positions: Vec<(u64, bool)>,
+}
+
+impl Repetitions {
+    pub fn new(start: &Board) -> Self {
+        Repetitions {
+            board: start.clone(),
+            positions: vec![(hash(start), rules::in_check(start, start.get_color()))],
+        }
+    }
+
+    /// number of moves played
+    pub fn plies(&self) -> usize {
+        self.positions.len() - 1
+    }
+
+    /// the position after a move
+    pub fn push(&mut self, mv: &str) {
+        self.board = self.board.play_move_unchecked(mv);
+        let color = self.board.get_color();
+        self.positions
+            .push((hash(&self.board), rules::in_check(&self.board, color)));
+    }
+
+    /// catch up with the moves of a record, starting again if it's another game (or the same
+    /// one started again from a set up position)
+    pub fn update(&mut self, record: &GameRecord) {
+        if self.plies() > record.moves.len() || self.positions[0].0 != hash(&record.start) {
+            *self = Repetitions::new(&record.start);
+        }
+        for mv in &record.moves[self.plies()..] {
+            self.push(mv);
+        }
+    }
+
+    /// sennichite, if the last position appeared for the fourth time
+    pub fn result(&self) -> Option<GameResult> {
+        let (last, _) = *self.positions.last()?;
+        let occurrences: Vec<usize> = (0..self.positions.len())
+            .filter(|&i| self.positions[i].0 == last)
+            .collect();
+        if occurrences.len() < OCCURRENCES {
+            return None;
+        }
+
+        // the moves since the first time the position appeared: a position where the side to
+        // move is in check means the move leading to it was a check
+        let first = occurrences[occurrences.len() - OCCURRENCES];
+        let mover_to_last = rules::opponent(self.board.get_color());
+        let checking = |mover: Color| {
+            (first + 1..self.positions.len())
+                .filter(|i| {
+                    // the mover of the move leading to the last position, then alternating
+                    let from_last = self.positions.len() - 1 - i;
+                    from_last.is_multiple_of(2) == (mover == mover_to_last)
+                })
+                .all(|i| self.positions[i].1)
+        };
+        let winner = [Color::White, Color::Black]
+            .iter()
+            .find(|&&color| checking(color))
+            .map(|&color| rules::opponent(color));
+        Some(GameResult::Sennichite { winner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    /// play the USI moves `times` times from the SFEN position
+    fn repeat(position: &str, moves: &[&str], times: usize) -> Repetitions {
+        let (board, _) = sfen::from_sfen(position).unwrap();
+        let mut repetitions = Repetitions::new(&board);
+        let mut board = board;
+        for _ in 0..times {
+            for usi in moves {
+                assert_eq!(repetitions.result(), None);
+                let mv = sfen::from_usi(usi, &board).unwrap();
+                board = board.play_move_unchecked(&mv);
+                repetitions.push(&mv);
+            }
+        }
+        repetitions
+    }
+
+    #[test]
+    fn hash_ignores_piece_order() {
+        let board = Board::new();
+        let (read, _) = sfen::from_sfen(&sfen::to_sfen(&board, 1)).unwrap();
+        assert_eq!(hash(&board), hash(&read));
+
+        let moved = board.play_move_unchecked("P3c-3d");
+        assert_ne!(hash(&board), hash(&moved));
+        // same pieces, other side to move
+        let mut turned = board.clone();
+        turned.turn = Color::Black;
+        assert_ne!(hash(&board), hash(&turned));
+    }
+
+    #[test]
+    fn fourfold_repetition_is_a_draw() {
+        let repetitions = repeat(
+            "8k/9/9/9/9/9/9/9/K8 b - 1",
+            &["9i9h", "1a1b", "9h9i", "1b1a"],
+            3,
+        );
+        assert_eq!(
+            repetitions.result(),
+            Some(GameResult::Sennichite { winner: None })
+        );
+    }
+
+    #[test]
+    fn perpetual_check_loses() {
+        // gote to move, in check from the rook on 1e
+        let repetitions = repeat(
+            "8k/9/9/9/8R/9/9/9/4K4 w - 1",
+            &["1a2a", "1e2e", "2a1a", "2e1e"],
+            3,
+        );
+        assert_eq!(
+            repetitions.result(),
+            Some(GameResult::Sennichite {
+                winner: Some(Color::Black)
+            })
+        );
+    }
+}
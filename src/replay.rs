@@ -0,0 +1,671 @@
+// Input recorded and replayed: the mouse buttons and keys of a game, the answers to the promotion
+// dialog and the opponent's moves, one per line. --record-input writes them as the game is
+// played; replaying them goes through the same InputHandler and GameState as the window, without
+// one, so the clicks, drags and promotions can be tested from a script.
+//
+//     position <sfen>          start again from this position (the even one at first)
+//     human <sente|gote>       the side played with the mouse (gote at first)
+//     input <click|drag>
+//     flip <on|off>
+//     down <button> <point>    a mouse button pressed (left, middle or right), and released:
+//     up <button> <point>
+//     key <key> [ctrl] [shift] as SDL names them: R, Return, Escape, F10...
+//     text <text>              typed in the chat line
+//     quit                     the window closed, nothing happens after it
+//     promote <yes|no>         the answer to the question of the click before
+//     opponent <usi move>
+//     expect position <sfen>   what the game is at then (the ply number aside),
+//     expect moves <usi...>    and the moves played since the start
+//
+// A point is x and y in the window, or a square ("7g", its middle), or a piece in hand ("R*"
+// for sente's rooks, "r*" for gote's, SFEN letters). Blank lines and lines starting with # are
+// left out, and so is the indentation. The mouse moving isn't recorded: the buttons carry their position.
+
+use crate::clock::{Clock, TimeControl};
+use crate::game::GameState;
+use crate::handicap::Handicap;
+use crate::impasse;
+use crate::input::{Command, Context, InputHandler};
+use crate::layout::{self, SQR_SIZE};
+use crate::record::GameRecord;
+use crate::settings::{InputMode, Settings};
+use crate::sfen;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+
+use shogai::piece::*;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// a line of a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Position(String),
+    Human(Color),
+    Input(InputMode),
+    Flip(bool),
+    Event(Event),
+    Promote(bool),
+    /// in USI notation
+    Opponent(String),
+    ExpectPosition(String),
+    ExpectMoves(Vec<String>),
+}
+
+impl Step {
+    /// a line of a script, its points found on the board as `flip` has it
+    pub fn parse(line: &str, flip: bool) -> Result<Step, String> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        Ok(match (word, words.as_slice()) {
+            ("position", _) => Step::Position(rest.trim().to_string()),
+            ("human", ["sente"]) => Step::Human(Color::White),
+            ("human", ["gote"]) => Step::Human(Color::Black),
+            ("input", [mode]) => Step::Input(mode.parse()?),
+            ("flip", [on]) => Step::Flip(yes_no(on, "on", "off")?),
+            ("down", [button, point @ ..]) | ("up", [button, point @ ..]) => {
+                let mouse_btn = mouse_button(button)?;
+                let (x, y) = parse_point(point, flip)?;
+                if word == "down" {
+                    Step::Event(Event::MouseButtonDown {
+                        timestamp: 0,
+                        window_id: 0,
+                        which: 0,
+                        mouse_btn,
+                        clicks: 1,
+                        x,
+                        y,
+                    })
+                } else {
+                    Step::Event(Event::MouseButtonUp {
+                        timestamp: 0,
+                        window_id: 0,
+                        which: 0,
+                        mouse_btn,
+                        clicks: 1,
+                        x,
+                        y,
+                    })
+                }
+            }
+            ("key", [key, modifiers @ ..]) => {
+                let keycode = keycode(key).ok_or_else(|| format!("unknown key '{}'", key))?;
+                let mut keymod = Mod::NOMOD;
+                for modifier in modifiers {
+                    keymod |= match *modifier {
+                        "ctrl" => Mod::LCTRLMOD,
+                        "shift" => Mod::LSHIFTMOD,
+                        other => return Err(format!("unknown modifier '{}'", other)),
+                    };
+                }
+                Step::Event(Event::KeyDown {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode: Some(keycode),
+                    scancode: None,
+                    keymod,
+                    repeat: false,
+                })
+            }
+            ("text", _) => Step::Event(Event::TextInput {
+                timestamp: 0,
+                window_id: 0,
+                text: rest.to_string(),
+            }),
+            ("quit", []) => Step::Event(Event::Quit { timestamp: 0 }),
+            ("promote", [yes]) => Step::Promote(yes_no(yes, "yes", "no")?),
+            ("opponent", [mv]) => Step::Opponent(mv.to_string()),
+            ("expect", ["position", ..]) => {
+                let sfen = rest.trim()["position".len()..].trim();
+                Step::ExpectPosition(sfen.to_string())
+            }
+            ("expect", ["moves", moves @ ..]) => {
+                Step::ExpectMoves(moves.iter().map(|mv| mv.to_string()).collect())
+            }
+            _ => return Err(format!("can't make sense of '{}'", line)),
+        })
+    }
+
+    /// the step of an event, None for the ones the game doesn't listen to
+    pub fn of_event(event: &Event) -> Option<Step> {
+        match event {
+            Event::Quit { .. } | Event::TextInput { .. } => Some(Step::Event(event.clone())),
+            Event::MouseButtonDown { .. } | Event::MouseButtonUp { .. } => {
+                Some(Step::Event(event.clone()))
+            }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } if KEYS.contains(keycode) => Some(Step::Event(event.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Position(sfen) => write!(f, "position {}", sfen),
+            Step::Human(color) => write!(f, "human {}", sfen::side_name(*color)),
+            Step::Input(mode) => write!(f, "input {}", mode),
+            Step::Flip(flip) => write!(f, "flip {}", if *flip { "on" } else { "off" }),
+            Step::Event(Event::MouseButtonDown {
+                mouse_btn, x, y, ..
+            }) => write!(f, "down {} {} {}", button_name(*mouse_btn), x, y),
+            Step::Event(Event::MouseButtonUp {
+                mouse_btn, x, y, ..
+            }) => write!(f, "up {} {} {}", button_name(*mouse_btn), x, y),
+            Step::Event(Event::KeyDown {
+                keycode: Some(keycode),
+                keymod,
+                ..
+            }) => {
+                write!(f, "key {:?}", keycode)?;
+                if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+                    write!(f, " ctrl")?;
+                }
+                if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    write!(f, " shift")?;
+                }
+                Ok(())
+            }
+            Step::Event(Event::TextInput { text, .. }) => write!(f, "text {}", text),
+            Step::Event(Event::Quit { .. }) => write!(f, "quit"),
+            Step::Event(event) => write!(f, "# {:?}", event),
+            Step::Promote(yes) => write!(f, "promote {}", if *yes { "yes" } else { "no" }),
+            Step::Opponent(mv) => write!(f, "opponent {}", mv),
+            Step::ExpectPosition(sfen) => write!(f, "expect position {}", sfen),
+            Step::ExpectMoves(moves) => write!(f, "expect moves {}", moves.join(" ")),
+        }
+    }
+}
+
+/// the steps of a script, with their line numbers
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    pub steps: Vec<(usize, Step)>,
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        let mut flip = false;
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim_start();
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = Step::parse(line, flip).map_err(|e| format!("line {}: {}", n + 1, e))?;
+            if let Step::Flip(on) = step {
+                flip = on;
+            }
+            steps.push((n + 1, step));
+        }
+        Ok(Script { steps })
+    }
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Script, String> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// the game played again, step by step; fails at the first step that can't be played or
+    /// expects something else
+    pub fn replay(&self) -> Result<GameState, String> {
+        let mut state = start(Handicap::Even.board(), Color::Black);
+        let mut input = InputHandler::new();
+        let mut mode = InputMode::Click;
+        let mut flip = false;
+        let mut steps = self.steps.iter().peekable();
+        while let Some((n, step)) = steps.next() {
+            let at = |e: String| format!("line {}: {}", n, e);
+            match step {
+                Step::Position(position) => {
+                    let human = state.human;
+                    state = start(sfen::from_sfen(position).map_err(at)?.0, human);
+                }
+                Step::Human(color) => state.human = *color,
+                Step::Input(input_mode) => mode = *input_mode,
+                Step::Flip(on) => flip = *on,
+                Step::Event(event) => {
+                    let context = Context {
+                        input: mode,
+                        flip,
+                        side: state.board.get_color(),
+                        holding: state.holding(),
+                        clicked: state.clicked(),
+                        editing: false,
+                        chat: false,
+                        keymod: Mod::NOMOD,
+                    };
+                    match input.handle(event, &context) {
+                        Some(Command::Quit) => break,
+                        Some(Command::Click(target)) if state.human_turn() => {
+                            let answer = match steps.peek() {
+                                Some((_, Step::Promote(yes))) => Some(*yes),
+                                _ => None,
+                            };
+                            let mut asked = false;
+                            // a move that isn't a check in tsume is only refused
+                            let _ = state.click(target, || {
+                                asked = true;
+                                answer.unwrap_or(true)
+                            });
+                            match (asked, answer) {
+                                (true, Some(_)) => {
+                                    steps.next();
+                                }
+                                (true, None) => {
+                                    return Err(at(String::from("promotion asked, no answer")))
+                                }
+                                _ => {}
+                            }
+                        }
+                        Some(Command::Mark(mark)) => state.record.toggle_mark(mark),
+                        // the dialogs, panels and editor aren't replayed
+                        _ => {}
+                    }
+                }
+                Step::Promote(_) => return Err(at(String::from("no promotion was asked"))),
+                Step::Opponent(usi) => {
+                    if state.human_turn() {
+                        return Err(at(format!("the opponent plays {} on your turn", usi)));
+                    }
+                    let mv = sfen::from_usi(usi, &state.board).map_err(at)?;
+                    state
+                        .board
+                        .check_move(&mv)
+                        .map_err(|e| at(format!("illegal move {}: {}", usi, e)))?;
+                    state.play(&mv);
+                }
+                Step::ExpectPosition(expected) => {
+                    let position = sfen::to_sfen(&state.board, 1);
+                    if without_ply(&position) != without_ply(expected) {
+                        return Err(at(format!("position {}, not {}", position, expected)));
+                    }
+                }
+                Step::ExpectMoves(expected) => {
+                    let moves = usi_moves(&state)?;
+                    if &moves != expected {
+                        return Err(at(format!(
+                            "moves {}, not {}",
+                            moves.join(" "),
+                            expected.join(" ")
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// writes the steps of the game being played, for --record-input
+pub struct Recorder {
+    path: PathBuf,
+    out: Option<LineWriter<File>>,
+    /// what was written already: the start of the game and the moves since, the settings
+    start: String,
+    moves: usize,
+    input: Option<InputMode>,
+    flip: Option<bool>,
+    /// nothing is written once the game is over
+    over: bool,
+}
+
+impl Recorder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Recorder {
+            path: path.as_ref().to_path_buf(),
+            out: None,
+            start: String::new(),
+            moves: 0,
+            input: None,
+            flip: None,
+            over: false,
+        }
+    }
+
+    /// a new game: the file starts again, with its position
+    pub fn start(&mut self, state: &GameState, settings: &Settings) -> Result<(), String> {
+        let file =
+            File::create(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        self.out = Some(LineWriter::new(file));
+        self.start = sfen::to_sfen(&state.record.start, 1);
+        self.moves = 0;
+        self.input = None;
+        self.flip = None;
+        self.over = false;
+        self.write(&Step::Position(self.start.clone()))?;
+        self.write(&Step::Human(state.human))?;
+        self.sync(state, settings, false)
+    }
+
+    /// an event, as it comes
+    pub fn event(&mut self, event: &Event) -> Result<(), String> {
+        match Step::of_event(event) {
+            Some(step) if !self.over => self.write(&step),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn promotion(&mut self, promote: bool) -> Result<(), String> {
+        self.write(&Step::Promote(promote))
+    }
+
+    /// what changed since the last call: the settings, the position set up again, the moves
+    /// played (by the player after an event, by the opponent otherwise)
+    pub fn sync(
+        &mut self,
+        state: &GameState,
+        settings: &Settings,
+        player: bool,
+    ) -> Result<(), String> {
+        if self.out.is_none() || self.over {
+            return Ok(());
+        }
+        if self.input != Some(settings.input) {
+            self.input = Some(settings.input);
+            self.write(&Step::Input(settings.input))?;
+        }
+        if self.flip != Some(settings.flip) {
+            self.flip = Some(settings.flip);
+            self.write(&Step::Flip(settings.flip))?;
+        }
+        let start = sfen::to_sfen(&state.record.start, 1);
+        if start != self.start || state.record.moves.len() < self.moves {
+            self.write(&Step::Position(start.clone()))?;
+            self.start = start;
+            self.moves = 0;
+        }
+        if player {
+            self.moves = state.record.moves.len();
+        }
+        while self.moves < state.record.moves.len() {
+            let usi = sfen::to_usi(&state.record.moves[self.moves])?;
+            self.write(&Step::Opponent(usi))?;
+            self.moves += 1;
+        }
+        self.over = state.result.is_some();
+        Ok(())
+    }
+
+    /// the end of the game, or of the recording: what the replay is to find
+    pub fn finish(&mut self, state: &GameState) -> Result<(), String> {
+        if self.out.is_none() {
+            return Ok(());
+        }
+        self.write(&Step::ExpectPosition(sfen::to_sfen(&state.board, 1)))?;
+        self.write(&Step::ExpectMoves(usi_moves(state)?))?;
+        self.out = None;
+        Ok(())
+    }
+
+    fn write(&mut self, step: &Step) -> Result<(), String> {
+        match self.out.as_mut() {
+            Some(out) => {
+                writeln!(out, "{}", step).map_err(|e| format!("{}: {}", self.path.display(), e))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn start(board: shogai::board::Board, human: Color) -> GameState {
+    let record = GameRecord::from_position(board.clone(), "", "");
+    let clock = Clock::new(TimeControl::default());
+    GameState::new(board, human, record, clock, impasse::Rule::default())
+}
+
+fn usi_moves(state: &GameState) -> Result<Vec<String>, String> {
+    state
+        .record
+        .moves
+        .iter()
+        .map(|mv| sfen::to_usi(mv))
+        .collect()
+}
+
+/// the board, side to move and hands of a SFEN
+fn without_ply(sfen: &str) -> Vec<&str> {
+    sfen.split_whitespace().take(3).collect()
+}
+
+fn yes_no(word: &str, yes: &str, no: &str) -> Result<bool, String> {
+    match word {
+        w if w == yes => Ok(true),
+        w if w == no => Ok(false),
+        _ => Err(format!("'{}' is neither {} nor {}", word, yes, no)),
+    }
+}
+
+fn mouse_button(name: &str) -> Result<MouseButton, String> {
+    match name {
+        "left" => Ok(MouseButton::Left),
+        "middle" => Ok(MouseButton::Middle),
+        "right" => Ok(MouseButton::Right),
+        _ => Err(format!("unknown mouse button '{}'", name)),
+    }
+}
+
+fn button_name(button: MouseButton) -> &'static str {
+    match button {
+        MouseButton::Left => "left",
+        MouseButton::Middle => "middle",
+        MouseButton::Right => "right",
+        _ => "other",
+    }
+}
+
+/// the keys a script can press: the ones the game and the editor listen to
+const KEYS: &[Keycode] = &[
+    Keycode::A,
+    Keycode::B,
+    Keycode::C,
+    Keycode::D,
+    Keycode::E,
+    Keycode::F,
+    Keycode::G,
+    Keycode::H,
+    Keycode::I,
+    Keycode::J,
+    Keycode::K,
+    Keycode::L,
+    Keycode::M,
+    Keycode::N,
+    Keycode::O,
+    Keycode::P,
+    Keycode::Q,
+    Keycode::R,
+    Keycode::S,
+    Keycode::T,
+    Keycode::U,
+    Keycode::V,
+    Keycode::W,
+    Keycode::X,
+    Keycode::Y,
+    Keycode::Z,
+    Keycode::Return,
+    Keycode::KpEnter,
+    Keycode::Escape,
+    Keycode::Backspace,
+    Keycode::Delete,
+    Keycode::Space,
+    Keycode::Tab,
+    Keycode::Up,
+    Keycode::Down,
+    Keycode::Left,
+    Keycode::Right,
+    Keycode::F10,
+];
+
+/// a key by the name of its Keycode (SDL's own names would need SDL running)
+fn keycode(name: &str) -> Option<Keycode> {
+    KEYS.iter()
+        .copied()
+        .find(|keycode| format!("{:?}", keycode) == name)
+}
+
+/// a point of the window: "x y", a square or a hand
+fn parse_point(words: &[&str], flip: bool) -> Result<(i32, i32), String> {
+    let middle = SQR_SIZE as i32 / 2;
+    match words {
+        [x, y] => {
+            let number = |n: &str| n.parse().map_err(|_| format!("invalid coordinate '{}'", n));
+            Ok((number(x)?, number(y)?))
+        }
+        [hand] if hand.len() == 2 && hand.ends_with('*') => {
+            let letter = hand.chars().next().unwrap_or_default();
+            let piecetype = sfen::piece_from_letter(letter)
+                .filter(|&piecetype| piecetype != PieceType::King)
+                .ok_or_else(|| format!("no piece in hand '{}'", hand))?;
+            let piece = Piece {
+                color: if letter.is_ascii_uppercase() {
+                    Color::White
+                } else {
+                    Color::Black
+                },
+                piecetype,
+                promoted: false,
+                position: None,
+            };
+            let (x, y) = layout::reserve_origin(&piece, 0, flip);
+            Ok((x + middle, y + middle))
+        }
+        [square] => {
+            let (x, y) = layout::square_origin(sfen::parse_usi_square(square)?, flip);
+            Ok((x + middle, y + middle))
+        }
+        _ => Err(format!("invalid point '{}'", words.join(" "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(script: &str) -> Result<GameState, String> {
+        script.parse::<Script>()?.replay()
+    }
+
+    fn failure(script: &str) -> String {
+        match replay(script) {
+            Ok(_) => panic!("replayed without a fault: {}", script),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn steps_are_written_as_they_are_read() {
+        for line in &[
+            "position lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "human sente",
+            "input drag",
+            "flip on",
+            "down left 120 340",
+            "up right 5 6",
+            "key R ctrl",
+            "key Return",
+            "key F10 shift",
+            "text hi there",
+            "quit",
+            "promote no",
+            "opponent 3c3d",
+            "expect moves 7g7f 3c3d",
+        ] {
+            let step = Step::parse(line, false).unwrap();
+            assert_eq!(step.to_string(), *line);
+        }
+        assert!(Step::parse("key Nothing", false).is_err());
+        assert!(Step::parse("down left 7j", false).is_err());
+    }
+
+    #[test]
+    fn points_are_found_where_the_board_is_drawn() {
+        let (x, y) = parse_point(&["7g"], true).unwrap();
+        assert_eq!(
+            layout::square_at(x, y, true),
+            sfen::parse_usi_square("7g").ok()
+        );
+        let (x, y) = parse_point(&["r*"], false).unwrap();
+        assert_eq!(
+            layout::hand_at(x, y, Color::Black, false),
+            Some(PieceType::Rook)
+        );
+    }
+
+    #[test]
+    fn clicks_and_drags_play_moves() {
+        let state = replay(
+            "human sente
+            down left 7g
+            up left 7g
+            down left 7f
+            up left 7f
+            opponent 3c3d
+            input drag
+            down left 2g
+            # let go where it was picked up, it's still held
+            up left 2g
+            up left 2f
+            expect moves 7g7f 3c3d 2g2f",
+        )
+        .unwrap();
+        assert_eq!(state.record.moves.len(), 3);
+        assert!(!state.holding());
+    }
+
+    #[test]
+    fn promotions_are_answered() {
+        let script = "position 4k4/9/9/9/9/9/9/1R7/4K4 b - 1
+            human sente
+            down left 8h
+            up left 8h
+            down left 8b
+            up left 8b
+            promote no
+            expect moves 8h8b";
+        assert!(replay(script).is_ok());
+        assert!(replay(&script.replace("promote no", "promote yes")).is_err());
+        assert!(failure(&script.replace("promote no", "")).contains("no answer"));
+    }
+
+    #[test]
+    fn drops_come_from_the_hand() {
+        let state = replay(
+            "position 4k4/9/9/9/9/9/9/9/4K4 b G 1
+            human sente
+            down left G*
+            up left G*
+            down left 5b
+            up left 5b
+            expect position 4k4/4G4/9/9/9/9/9/9/4K4 w - 2",
+        )
+        .unwrap();
+        assert_eq!(state.record.moves.len(), 1);
+    }
+
+    #[test]
+    fn what_isnt_expected_fails() {
+        assert!(failure("opponent 7g7f\nexpect moves 3c3d").starts_with("line 2:"));
+        // the player is gote at first
+        assert!(replay("opponent 7g7f\nopponent 3c3d").is_err());
+        // clicks out of turn do nothing, and nothing happens once the window is closed
+        assert!(replay("down left 3c\nup left 3c\ndown left 3d\nup left 3d\nexpect moves").is_ok());
+        assert!(replay("quit\nexpect moves 7g7f").is_ok());
+    }
+}
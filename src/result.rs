@@ -0,0 +1,240 @@
+// How a game ended: who won (if anyone) and why.
+
+use crate::csa::Outcome;
+use crate::rules;
+
+use shogai::piece::Color;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    /// the loser's king was mated (or the loser had no legal move)
+    Checkmate {
+        winner: Color,
+    },
+    Resignation {
+        winner: Color,
+    },
+    TimeLoss {
+        winner: Color,
+    },
+    /// fourfold repetition: a draw, unless one side gave check all along (it loses)
+    Sennichite {
+        winner: Option<Color>,
+    },
+    /// impasse (jishogi): won by a declaration, or drawn
+    Impasse {
+        winner: Option<Color>,
+    },
+    /// the loser played an illegal move
+    IllegalMove {
+        winner: Color,
+    },
+    /// a draw offer was accepted
+    DrawAgreed,
+    /// a draw once the game reached the most moves allowed (engine matches, CSA servers)
+    MaxMoves,
+    /// the game stopped without a result (the other side left, the connection was lost...)
+    Abort,
+}
+
+impl GameResult {
+    pub fn winner(&self) -> Option<Color> {
+        match *self {
+            GameResult::Checkmate { winner }
+            | GameResult::Resignation { winner }
+            | GameResult::TimeLoss { winner }
+            | GameResult::IllegalMove { winner } => Some(winner),
+            GameResult::Sennichite { winner } | GameResult::Impasse { winner } => winner,
+            GameResult::DrawAgreed | GameResult::MaxMoves | GameResult::Abort => None,
+        }
+    }
+
+    /// "by resignation", "by sennichite"...
+    fn reason(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate { .. } => "checkmate",
+            GameResult::Resignation { .. } => "resignation",
+            GameResult::TimeLoss { .. } => "time",
+            GameResult::Sennichite { winner: Some(_) } => "perpetual check",
+            GameResult::Sennichite { winner: None } => "sennichite",
+            GameResult::Impasse { .. } => "impasse",
+            GameResult::IllegalMove { .. } => "illegal move",
+            GameResult::DrawAgreed => "agreement",
+            GameResult::MaxMoves => "move limit",
+            GameResult::Abort => "abort",
+        }
+    }
+
+    /// the special move closing a KIF record
+    pub fn kif_move(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate { .. } => "詰み",
+            GameResult::Resignation { .. } => "投了",
+            GameResult::TimeLoss { .. } => "切れ負け",
+            GameResult::Sennichite { .. } => "千日手",
+            GameResult::Impasse { winner: Some(_) } => "入玉勝ち",
+            GameResult::Impasse { winner: None } => "持将棋",
+            GameResult::IllegalMove { .. } => "反則負け",
+            GameResult::MaxMoves => "最大手数",
+            GameResult::DrawAgreed | GameResult::Abort => "中断",
+        }
+    }
+
+    /// read the special move of a KIF record, `to_move` being the side that "played" it
+    pub fn from_kif_move(special: &str, to_move: Color) -> Option<GameResult> {
+        let other = rules::opponent(to_move);
+        let result = match special {
+            "詰み" => GameResult::Checkmate { winner: other },
+            "投了" => GameResult::Resignation { winner: other },
+            "切れ負け" => GameResult::TimeLoss { winner: other },
+            "千日手" => GameResult::Sennichite { winner: None },
+            "入玉勝ち" => GameResult::Impasse {
+                winner: Some(to_move),
+            },
+            "持将棋" => GameResult::Impasse { winner: None },
+            "反則負け" => GameResult::IllegalMove { winner: other },
+            "最大手数" => GameResult::MaxMoves,
+            "中断" => GameResult::Abort,
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// the special move closing a CSA record
+    pub fn csa_move(&self) -> &'static str {
+        match self {
+            GameResult::Checkmate { .. } => "%TSUMI",
+            GameResult::Resignation { .. } => "%TORYO",
+            GameResult::TimeLoss { .. } => "%TIME_UP",
+            GameResult::Sennichite { winner: Some(_) } => "%OUTE_SENNICHITE",
+            GameResult::Sennichite { winner: None } => "%SENNICHITE",
+            GameResult::Impasse { winner: Some(_) } => "%KACHI",
+            GameResult::Impasse { winner: None } => "%JISHOGI",
+            GameResult::IllegalMove { .. } => "%ILLEGAL_MOVE",
+            GameResult::DrawAgreed => "%HIKIWAKE",
+            GameResult::MaxMoves => "%MAX_MOVES",
+            GameResult::Abort => "%CHUDAN",
+        }
+    }
+
+    /// the result announced by a CSA server to the player of `color`, with the reason line
+    /// before it ("#RESIGN"...)
+    pub fn from_csa(outcome: Outcome, reason: Option<&str>, color: Color) -> GameResult {
+        let winner = match outcome {
+            Outcome::Win => Some(color),
+            Outcome::Lose => Some(rules::opponent(color)),
+            Outcome::Draw => None,
+            Outcome::Interrupted => return GameResult::Abort,
+        };
+        match (reason, winner) {
+            (Some("#SENNICHITE"), _) | (Some("#OUTE_SENNICHITE"), _) => {
+                GameResult::Sennichite { winner }
+            }
+            (Some("#JISHOGI"), _) => GameResult::Impasse { winner },
+            (Some("#MAX_MOVES"), _) => GameResult::MaxMoves,
+            (Some("#TIME_UP"), Some(winner)) => GameResult::TimeLoss { winner },
+            (Some("#ILLEGAL_MOVE"), Some(winner)) => GameResult::IllegalMove { winner },
+            (Some("#CHECKMATE"), Some(winner)) => GameResult::Checkmate { winner },
+            (_, Some(winner)) => GameResult::Resignation { winner },
+            (_, None) => GameResult::DrawAgreed,
+        }
+    }
+}
+
+/// "Gote wins by resignation", "Draw by sennichite", "Game aborted"
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == GameResult::Abort {
+            return write!(f, "Game aborted");
+        }
+        match self.winner() {
+            Some(Color::White) => write!(f, "Sente wins by {}", self.reason()),
+            Some(Color::Black) => write!(f, "Gote wins by {}", self.reason()),
+            None => write!(f, "Draw by {}", self.reason()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+    use crate::kif;
+    use crate::record::GameRecord;
+
+    #[test]
+    fn descriptions() {
+        let resigned = GameResult::Resignation {
+            winner: Color::Black,
+        };
+        assert_eq!(resigned.to_string(), "Gote wins by resignation");
+        assert_eq!(
+            GameResult::Sennichite { winner: None }.to_string(),
+            "Draw by sennichite"
+        );
+        assert_eq!(GameResult::Abort.to_string(), "Game aborted");
+        assert_eq!(
+            GameResult::from_csa(Outcome::Lose, Some("#TIME_UP"), Color::White),
+            GameResult::TimeLoss {
+                winner: Color::Black
+            }
+        );
+        assert_eq!(
+            GameResult::from_csa(Outcome::Draw, Some("#SENNICHITE"), Color::White),
+            GameResult::Sennichite { winner: None }
+        );
+    }
+
+    #[test]
+    fn kif_round_trip() {
+        let mut record = GameRecord::new(Handicap::Even, "Taro", "Hanako");
+        record.push("P3c-3d");
+        record.result = Some(GameResult::Resignation {
+            winner: Color::White,
+        });
+        let text = kif::write(&record);
+        assert!(text.ends_with("   2 投了\nまで1手で先手の勝ち\n"));
+        assert_eq!(kif::read(&text).unwrap().result, record.result);
+    }
+
+    #[test]
+    fn move_limit() {
+        let limit = GameResult::MaxMoves;
+        assert_eq!(limit.to_string(), "Draw by move limit");
+        assert_eq!(limit.csa_move(), "%MAX_MOVES");
+        assert_eq!(
+            GameResult::from_csa(Outcome::Draw, Some("#MAX_MOVES"), Color::White),
+            limit
+        );
+        let mut record = GameRecord::new(Handicap::Even, "Taro", "Hanako");
+        record.push("P7g-7f");
+        record.result = Some(limit);
+        let text = kif::write(&record);
+        assert!(text.ends_with("   2 最大手数\nまで1手で最大手数\n"));
+        assert_eq!(kif::read(&text).unwrap().result, Some(limit));
+    }
+
+    #[test]
+    fn perpetual_check() {
+        let loss = GameResult::Sennichite {
+            winner: Some(Color::Black),
+        };
+        assert_eq!(loss.csa_move(), "%OUTE_SENNICHITE");
+        assert_eq!(
+            GameResult::from_csa(Outcome::Win, Some("#OUTE_SENNICHITE"), Color::Black),
+            loss
+        );
+        let mut record = GameRecord::new(Handicap::Even, "Taro", "Hanako");
+        record.push("P7g-7f");
+        record.result = Some(loss);
+        let text = kif::write(&record);
+        assert!(text.ends_with("   2 千日手\n*連続王手の千日手\nまで1手で後手の勝ち\n"));
+        assert_eq!(kif::read(&text).unwrap().result, Some(loss));
+        // a plain sennichite stays a draw
+        record.result = Some(GameResult::Sennichite { winner: None });
+        let text = kif::write(&record);
+        assert_eq!(kif::read(&text).unwrap().result, record.result);
+    }
+}
@@ -0,0 +1,426 @@
+// Rule helpers shogai doesn't provide, or provides too slowly for searching: check detection and
+// a complete legal move generator.
+//
+// shogai's own generator never drops on its last square (its "9i", standard 1a), shuffles the
+// moves of every piece it looks at and verifies king safety by trying every reply, which is fine
+// for a single move but not inside a solver.
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+pub fn opponent(color: Color) -> Color {
+    let mut other = color;
+    other.invert();
+    other
+}
+
+pub fn king_position(board: &Board, color: Color) -> Option<Position> {
+    board
+        .iter_pieces(color)
+        .find(|p| p.piecetype == PieceType::King)
+        .and_then(|p| p.position)
+}
+
+/// step of the piece's owner towards the far end of the board
+fn forward(color: Color) -> i16 {
+    match color {
+        Color::White => 1,
+        Color::Black => -1,
+    }
+}
+
+const GOLD: [(i16, i16); 6] = [(0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0), (0, -1)];
+const SILVER: [(i16, i16); 5] = [(0, 1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+const KING: [(i16, i16); 8] = [
+    (0, 1),
+    (1, 1),
+    (-1, 1),
+    (1, 0),
+    (-1, 0),
+    (0, -1),
+    (1, -1),
+    (-1, -1),
+];
+const ORTHOGONAL: [(i16, i16); 4] = [(0, 1), (1, 0), (-1, 0), (0, -1)];
+const DIAGONAL: [(i16, i16); 4] = [(1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+/// relative moves, for White (Black's are mirrored)
+type Directions = &'static [(i16, i16)];
+
+/// single steps and sliding directions of a piece
+fn movement(piece: &Piece) -> (Directions, Directions) {
+    match (piece.piecetype, piece.promoted) {
+        (PieceType::King, _) => (&KING, &[]),
+        (PieceType::Rook, false) => (&[], &ORTHOGONAL),
+        (PieceType::Rook, true) => (&DIAGONAL, &ORTHOGONAL),
+        (PieceType::Bishop, false) => (&[], &DIAGONAL),
+        (PieceType::Bishop, true) => (&ORTHOGONAL, &DIAGONAL),
+        (PieceType::Gold, _) | (_, true) => (&GOLD, &[]),
+        (PieceType::Silver, false) => (&SILVER, &[]),
+        (PieceType::Knight, false) => (&[(1, 2), (-1, 2)], &[]),
+        (PieceType::Lance, false) => (&[], &[(0, 1)]),
+        (PieceType::Pawn, false) => (&[(0, 1)], &[]),
+    }
+}
+
+fn square(column: i16, row: i16) -> Option<Position> {
+    if (0..9).contains(&column) && (0..9).contains(&row) {
+        Some(Position((column + 9 * row) as u16))
+    } else {
+        None
+    }
+}
+
+/// owner of the piece on each square
+fn occupancy(board: &Board) -> [Option<Color>; 81] {
+    let mut squares = [None; 81];
+    for piece in board.iter() {
+        if let Some(pos) = piece.position {
+            squares[pos.0 as usize] = Some(piece.color);
+        }
+    }
+    squares
+}
+
+/// squares a piece on the board can move to (or capture on), whoever stands there
+fn targets(piece: &Piece, occupied: &[Option<Color>; 81]) -> Vec<Position> {
+    let start = match piece.position {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+    let (column, row) = ((start.0 % 9) as i16, (start.0 / 9) as i16);
+    let forward = forward(piece.color);
+    let (steps, slides) = movement(piece);
+    let mut targets = Vec::new();
+    for &(dx, dy) in steps {
+        targets.extend(square(column + dx, row + dy * forward));
+    }
+    for &(dx, dy) in slides {
+        let (mut x, mut y) = (column + dx, row + dy * forward);
+        while let Some(pos) = square(x, y) {
+            targets.push(pos);
+            if occupied[pos.0 as usize].is_some() {
+                break;
+            }
+            x += dx;
+            y += dy * forward;
+        }
+    }
+    targets
+}
+
+/// whether `piece` attacks `target` (blocking pieces taken into account)
+pub fn attacks(board: &Board, piece: &Piece, target: Position) -> bool {
+    targets(piece, &occupancy(board)).contains(&target)
+}
+
+/// rows between the square and the far end of the board for `color` (0 on the last row)
+pub(crate) fn rows_left(color: Color, pos: Position) -> u16 {
+    match color {
+        Color::White => 8 - pos.0 / 9,
+        Color::Black => pos.0 / 9,
+    }
+}
+
+/// whether a piece without promotion could never move again from the square
+pub(crate) fn is_stuck(piecetype: PieceType, color: Color, pos: Position) -> bool {
+    match piecetype {
+        PieceType::Pawn | PieceType::Lance => rows_left(color, pos) == 0,
+        PieceType::Knight => rows_left(color, pos) < 2,
+        _ => false,
+    }
+}
+
+fn can_promote(piece: &Piece, start: Position, end: Position) -> bool {
+    !piece.promoted
+        && piece.piecetype != PieceType::Gold
+        && piece.piecetype != PieceType::King
+        && (rows_left(piece.color, start) < 3 || rows_left(piece.color, end) < 3)
+}
+
+/// the squares a piece dropped to block the checks on the king could go to, None when not
+/// in check. Adjacent checks and double checks can't be blocked.
+fn blocking_squares(
+    board: &Board,
+    color: Color,
+    occupied: &[Option<Color>; 81],
+) -> Option<Vec<u16>> {
+    let king = king_position(board, color)?;
+    let checkers: Vec<&Piece> = board
+        .iter()
+        .filter(|p| p.color != color && targets(p, occupied).contains(&king))
+        .collect();
+    match checkers.as_slice() {
+        [] => None,
+        [checker] => {
+            let start = checker.position.unwrap_or(king);
+            let dx = (king.0 % 9) as i16 - (start.0 % 9) as i16;
+            let dy = (king.0 / 9) as i16 - (start.0 / 9) as i16;
+            let mut squares = Vec::new();
+            if dx == 0 || dy == 0 || dx.abs() == dy.abs() {
+                let (sx, sy) = (dx.signum(), dy.signum());
+                let (mut x, mut y) = ((start.0 % 9) as i16 + sx, (start.0 / 9) as i16 + sy);
+                while (x, y) != ((king.0 % 9) as i16, (king.0 / 9) as i16) {
+                    squares.push((x + 9 * y) as u16);
+                    x += sx;
+                    y += sy;
+                }
+            }
+            Some(squares)
+        }
+        _ => Some(Vec::new()),
+    }
+}
+
+/// whether the king of `color` is attacked
+pub fn in_check(board: &Board, color: Color) -> bool {
+    let king = match king_position(board, color) {
+        Some(king) => king,
+        None => return false,
+    };
+    let occupied = occupancy(board);
+    board
+        .iter()
+        .filter(|p| p.color != color)
+        .any(|p| targets(p, &occupied).contains(&king))
+}
+
+/// whether the move (legal or not) puts the opponent of the side to move in check
+pub fn gives_check(board: &Board, mv: &str) -> bool {
+    let after = board.play_move_unchecked(mv);
+    in_check(&after, after.get_color())
+}
+
+/// all the legal moves of the side to move
+pub fn legal_moves(board: &Board) -> Vec<String> {
+    let mut moves = pseudo_legal_moves(board);
+    moves.retain(|mv| is_legal_pseudo(board, mv, true));
+    moves
+}
+
+/// the legal moves of the side to move that give check
+pub fn checking_moves(board: &Board) -> Vec<String> {
+    let mut moves = pseudo_legal_moves(board);
+    moves.retain(|mv| gives_check(board, mv) && is_legal_pseudo(board, mv, true));
+    moves
+}
+
+/// moves following the piece movement, drop and promotion rules, which may leave the king in
+/// check or mate with a pawn drop
+fn pseudo_legal_moves(board: &Board) -> Vec<String> {
+    let color = board.get_color();
+    let occupied = occupancy(board);
+    let mut moves = Vec::new();
+
+    for piece in board.iter_pieces(color).chain(board.iter_pawns(color)) {
+        let start = match piece.position {
+            Some(start) => start,
+            None => continue,
+        };
+        for end in targets(piece, &occupied) {
+            if occupied[end.0 as usize] == Some(color) {
+                continue;
+            }
+            let mv = Movement {
+                piecetype: piece.piecetype,
+                start: Some(start),
+                end,
+                promotion: false,
+                force_capture: false,
+                withdraw: false,
+                restart: false,
+            };
+            if can_promote(piece, start, end) {
+                moves.push(
+                    Movement {
+                        promotion: true,
+                        ..mv
+                    }
+                    .to_string(),
+                );
+            }
+            if piece.promoted || !is_stuck(piece.piecetype, color, end) {
+                moves.push(mv.to_string());
+            }
+        }
+    }
+
+    let mut in_hand: Vec<PieceType> = board
+        .iter()
+        .filter(|p| p.color == color && p.position.is_none())
+        .map(|p| p.piecetype)
+        .collect();
+    in_hand.sort_by_key(|&piecetype| piecetype as u8);
+    in_hand.dedup();
+    // when in check, only drops between the king and its attacker can be legal
+    let squares: Vec<u16> =
+        blocking_squares(board, color, &occupied).unwrap_or_else(|| (0..81).collect());
+    for piecetype in in_hand {
+        for &square in &squares {
+            let end = Position(square);
+            if occupied[square as usize].is_some()
+                || is_stuck(piecetype, color, end)
+                || (piecetype == PieceType::Pawn && nifu(board, color, square % 9))
+            {
+                continue;
+            }
+            moves.push(
+                Movement {
+                    piecetype,
+                    start: None,
+                    end,
+                    promotion: false,
+                    force_capture: false,
+                    withdraw: false,
+                    restart: false,
+                }
+                .to_string(),
+            );
+        }
+    }
+    moves
+}
+
+/// whether `color` has an unpromoted pawn on the column (shogai's check_nifu also counts
+/// promoted ones)
+fn nifu(board: &Board, color: Color, column: u16) -> bool {
+    board
+        .iter_pawns(color)
+        .any(|p| !p.promoted && p.position.is_some_and(|pos| pos.0 % 9 == column))
+}
+
+/// legality of a move known to be pseudo-legal
+fn is_legal_pseudo(board: &Board, mv: &str, check_pawn_drop_mate: bool) -> bool {
+    let after = board.play_move_unchecked(mv);
+    if in_check(&after, board.get_color()) {
+        return false;
+    }
+    if check_pawn_drop_mate && mv.starts_with("P*") && in_check(&after, after.get_color()) {
+        // uchifuzume: the drop is illegal if the opponent has no way out
+        let escapes = pseudo_legal_moves(&after)
+            .iter()
+            .any(|reply| is_legal_pseudo(&after, reply, false));
+        if !escapes {
+            return false;
+        }
+    }
+    true
+}
+
+/// whether a move is legal, more thorough (drops on every square) and faster than
+/// `Board::check_move`. Moves must be written as shogai writes them, without "x" for captures.
+pub fn is_legal(board: &Board, mv: &str) -> bool {
+    pseudo_legal_moves(board).iter().any(|m| m == mv) && is_legal_pseudo(board, mv, true)
+}
+
+/// the move as `is_legal` expects it: shogai marks the moves of pieces already promoted as
+/// promotions ("B2b-3b+" for a horse), drop that mark
+pub fn normalize(board: &Board, mv: &str) -> String {
+    let promoted = mv
+        .parse::<Movement>()
+        .ok()
+        .and_then(|movement| movement.start)
+        .and_then(|start| board.is_occupied_by(start))
+        .is_some_and(|piece| piece.promoted);
+    match mv.strip_suffix('+') {
+        Some(stripped) if promoted => stripped.to_string(),
+        _ => mv.to_string(),
+    }
+}
+
+/// whether the side to move is checkmated
+pub fn is_checkmate(board: &Board) -> bool {
+    in_check(board, board.get_color())
+        && !pseudo_legal_moves(board)
+            .iter()
+            .any(|mv| is_legal_pseudo(board, mv, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    fn board(sfen: &str) -> Board {
+        sfen::from_sfen(sfen).unwrap().0
+    }
+
+    fn legal(board: &Board, usi: &str) -> bool {
+        is_legal(board, &sfen::from_usi(usi, board).unwrap())
+    }
+
+    #[test]
+    fn moves_of_the_start() {
+        let board = Board::new();
+        assert_eq!(legal_moves(&board).len(), 30);
+        assert!(checking_moves(&board).is_empty());
+        assert!(legal(&board, "7g7f"));
+        assert!(!legal(&board, "7g7e"));
+        assert!(!is_checkmate(&board));
+    }
+
+    #[test]
+    fn drops() {
+        // nowhere to go from the last ranks
+        let board = board("4k4/9/9/9/9/9/9/9/4K4 b PLN 1");
+        assert!(!legal(&board, "P*3a"));
+        assert!(!legal(&board, "L*3a"));
+        assert!(!legal(&board, "N*3b"));
+        assert!(legal(&board, "P*3b"));
+        assert!(legal(&board, "L*3b"));
+        assert!(legal(&board, "N*3c"));
+        // including the last square, which shogai leaves out
+        assert!(legal(&board, "L*1b"));
+        let board = self::board("4k4/9/9/9/9/9/9/9/4K4 w pln 1");
+        assert!(!legal(&board, "P*1i"));
+        assert!(!legal(&board, "N*1h"));
+        assert!(legal(&board, "N*1g"));
+
+        // nifu, which a tokin doesn't count for
+        let board = self::board("4k4/9/9/9/9/9/2P6/9/4K+P3 b P 1");
+        assert!(!legal(&board, "P*7d"));
+        assert!(legal(&board, "P*4d"));
+        assert!(legal(&board, "P*6d"));
+    }
+
+    #[test]
+    fn uchifuzume() {
+        // the pawn on 1b mates: the knight on 2a takes the king's way out
+        let board = board("7nk/9/7G1/9/9/9/9/9/4K4 b P 1");
+        let drop = sfen::from_usi("P*1b", &board).unwrap();
+        assert!(!is_legal(&board, &drop));
+        assert!(!legal_moves(&board).contains(&drop));
+        assert!(legal(&board, "P*1c"));
+        // without the knight the king gets away by 2a
+        let board = self::board("8k/9/7G1/9/9/9/9/9/4K4 b P 1");
+        assert!(legal(&board, "P*1b"));
+        // mating by pushing a pawn is fine
+        let board = self::board("7nk/9/7GP/9/9/9/9/9/4K4 b - 1");
+        let mv = sfen::from_usi("1c1b", &board).unwrap();
+        assert!(is_legal(&board, &mv));
+        assert!(is_checkmate(&board.play_move_unchecked(&mv)));
+    }
+
+    #[test]
+    fn checks() {
+        // the head gold
+        let board = board("8k/9/8P/9/9/9/9/9/4K4 b G 1");
+        let mv = sfen::from_usi("G*1b", &board).unwrap();
+        assert!(gives_check(&board, &mv));
+        assert!(checking_moves(&board).contains(&mv));
+        let after = board.play_move_unchecked(&mv);
+        assert!(in_check(&after, Color::Black));
+        assert!(is_checkmate(&after));
+        assert!(legal_moves(&after).is_empty());
+
+        // in check, only the moves out of it are legal
+        let board = self::board("4k4/9/9/9/4r4/9/9/9/4K4 b G 1");
+        assert!(in_check(&board, Color::White));
+        assert!(legal(&board, "G*5f"));
+        assert!(!legal(&board, "G*4f"));
+        assert!(legal(&board, "5i4h"));
+        assert!(!legal(&board, "5i5h"));
+    }
+}
@@ -0,0 +1,501 @@
+// User settings, kept between sessions in $XDG_CONFIG_HOME/shogui/settings.conf (or
+// ~/.config/shogui/settings.conf), one "key = value" per line:
+//
+//   theme = wood
+//   flip = no
+//   highlight_select = yes
+//   highlight_last_move = yes
+//   input = click
+//   sounds = yes
+//   volume = 80
+//   sound_set = synth
+//   animation = 200
+//   reduce_motion = no
+//   time = 600+30
+//   level = greedy
+//   engines = random,greedy,normal,hard,expert
+//   window = 100,80
+//   font = /usr/share/fonts/truetype/dejavu/DejaVuSans.ttf
+//
+// followed by the USI engines of the registry, each with its options and presets:
+//
+//   engine.YaneuraOu.path = /opt/yaneuraou/YaneuraOu
+//   engine.YaneuraOu.dir = /opt/yaneuraou
+//   engine.YaneuraOu.option.USI_Hash = 1024
+//   engine.YaneuraOu.preset.analysis.Threads = 8
+//
+// Missing keys keep their default, so an empty (or missing) file is the default settings. The
+// command line wins over the file: `--level` and `--time` replace `level` and `time`, the clock
+// of the network games we host (games against the computer are untimed). Portable builds keep
+// the file next to the executable instead. The animation of the moves lasts `animation`
+// milliseconds, 0 (or reduce_motion) for none. `sounds = no` mutes them all, the volume goes
+// from 0 to 100 and the sound set is one of sounds/ (see sound.rs).
+
+use crate::clock::TimeControl;
+use crate::opponent::Level;
+use crate::sound;
+use crate::usi::{EngineConfig, Preset};
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// red, green, blue
+pub type Rgb = (u8, u8, u8);
+
+/// the colours of a theme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// the dark squares, and what's behind everything
+    pub board: Rgb,
+    /// the light squares
+    pub squares: Rgb,
+    /// both hands
+    pub reserve: Rgb,
+    /// the selected piece
+    pub select: Rgb,
+    /// hints and moves shown from the book or the database
+    pub hint: Rgb,
+    /// the squares of the last move
+    pub last_move: Rgb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Wood,
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn all() -> [Theme; 3] {
+        [Theme::Wood, Theme::Dark, Theme::Light]
+    }
+
+    pub fn palette(self) -> Palette {
+        match self {
+            Theme::Wood => Palette {
+                board: (0xD1, 0x8B, 0x47),
+                squares: (0xFF, 0xCE, 0x9E),
+                reserve: (0x75, 0x48, 0x3B),
+                select: (5, 150, 5),
+                hint: (60, 110, 200),
+                last_move: (0xE8, 0xB8, 0x50),
+            },
+            Theme::Dark => Palette {
+                board: (0x3A, 0x3F, 0x4A),
+                squares: (0x5A, 0x61, 0x70),
+                reserve: (0x20, 0x23, 0x2A),
+                select: (0x3C, 0xA0, 0x5A),
+                hint: (0x50, 0x8C, 0xE6),
+                last_move: (0x8A, 0x7A, 0x3A),
+            },
+            Theme::Light => Palette {
+                board: (0xE8, 0xD9, 0xB5),
+                squares: (0xF7, 0xEE, 0xD8),
+                reserve: (0xC9, 0xB2, 0x8A),
+                select: (0x7C, 0xC0, 0x7C),
+                hint: (0x80, 0xA8, 0xE8),
+                last_move: (0xF0, 0xE0, 0x80),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Theme::Wood => "wood",
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Theme::all()
+            .iter()
+            .find(|theme| theme.to_string() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown theme '{}' (wood, dark or light)", s))
+    }
+}
+
+/// how pieces are moved with the mouse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// click the piece, then the square it goes to
+    Click,
+    /// press on the piece and release on the square it goes to
+    Drag,
+}
+
+impl fmt::Display for InputMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputMode::Click => write!(f, "click"),
+            InputMode::Drag => write!(f, "drag"),
+        }
+    }
+}
+
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "click" => Ok(InputMode::Click),
+            "drag" => Ok(InputMode::Drag),
+            _ => Err(format!("unknown input mode '{}' (click or drag)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub theme: Theme,
+    /// gote's side of the board at the bottom
+    pub flip: bool,
+    pub highlight_select: bool,
+    pub highlight_last_move: bool,
+    pub input: InputMode,
+    /// the sounds of the moves and the clock, no to mute them
+    pub sounds: bool,
+    /// from 0 to 100
+    pub volume: u8,
+    pub sound_set: String,
+    /// how long a piece takes to slide where it's moved
+    pub animation: Duration,
+    /// pieces jump where they're moved, whatever `animation` says
+    pub reduce_motion: bool,
+    /// clock of the network games we host
+    pub time: TimeControl,
+    /// the opponent when none is chosen
+    pub level: Level,
+    /// the opponents offered when a game starts
+    pub engines: Vec<Level>,
+    /// position of the window, None to center it
+    pub window: Option<(i32, i32)>,
+    /// the font of the dialogs and menus, None for the first usual one found
+    pub font: Option<PathBuf>,
+    /// the registered USI engines
+    pub usi_engines: Vec<EngineConfig>,
+    /// where the settings are saved, None not to save them
+    path: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            theme: Theme::Wood,
+            flip: false,
+            highlight_select: true,
+            highlight_last_move: true,
+            input: InputMode::Click,
+            sounds: true,
+            volume: 80,
+            sound_set: String::from(sound::BUILT_IN),
+            animation: Duration::from_millis(200),
+            reduce_motion: false,
+            time: TimeControl::default(),
+            level: Level::Greedy,
+            engines: Level::all().to_vec(),
+            window: None,
+            font: None,
+            usi_engines: Vec::new(),
+            path: None,
+        }
+    }
+}
+
+/// where configuration files go: $XDG_CONFIG_HOME/shogui, or ~/.config/shogui (next to the
+/// executable in portable builds)
+pub fn config_dir() -> Option<PathBuf> {
+    if cfg!(feature = "portable") {
+        return portable_dir();
+    }
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("shogui"))
+}
+
+/// the directory of the executable, where portable builds keep their files
+pub fn portable_dir() -> Option<PathBuf> {
+    Some(env::current_exe().ok()?.parent()?.to_path_buf())
+}
+
+fn yes_no(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "yes" | "on" | "true" => Ok(true),
+        "no" | "off" | "false" => Ok(false),
+        _ => Err(format!("{}: expected yes or no, not '{}'", key, value)),
+    }
+}
+
+fn yes_no_name(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+impl Settings {
+    /// the settings saved in the config directory, the default ones if there are none (or they
+    /// can't be read)
+    pub fn load() -> Self {
+        let path = match config_dir() {
+            Some(dir) => dir.join("settings.conf"),
+            None => return Settings::default(),
+        };
+        let settings = match fs::read_to_string(&path) {
+            Ok(text) => Settings::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}, using the default settings", path.display(), e);
+                Settings::default()
+            }),
+            Err(_) => Settings::default(),
+        };
+        Settings {
+            path: Some(path),
+            ..settings
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected <key> = <value>", i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            settings
+                .set(key, value)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(settings)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "theme" => self.theme = value.parse()?,
+            "flip" => self.flip = yes_no(key, value)?,
+            "highlight_select" => self.highlight_select = yes_no(key, value)?,
+            "highlight_last_move" => self.highlight_last_move = yes_no(key, value)?,
+            "input" => self.input = value.parse()?,
+            "sounds" => self.sounds = yes_no(key, value)?,
+            "animation" => {
+                let ms = value
+                    .parse()
+                    .map_err(|_| format!("animation: expected milliseconds, not '{}'", value))?;
+                self.animation = Duration::from_millis(ms);
+            }
+            "reduce_motion" => self.reduce_motion = yes_no(key, value)?,
+            "volume" => {
+                self.volume = value
+                    .parse()
+                    .ok()
+                    .filter(|volume| *volume <= 100)
+                    .ok_or_else(|| format!("volume: expected 0 to 100, not '{}'", value))?
+            }
+            "sound_set" => self.sound_set = value.to_string(),
+            "time" => self.time = value.parse()?,
+            "level" => self.level = value.parse()?,
+            "engines" => {
+                self.engines = value
+                    .split(',')
+                    .map(|level| level.trim().parse())
+                    .collect::<Result<_, _>>()?
+            }
+            "window" if value == "centered" => self.window = None,
+            "window" => {
+                let invalid = || format!("window: expected <x>,<y> or centered, not '{}'", value);
+                let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+                let x = x.trim().parse().map_err(|_| invalid())?;
+                let y = y.trim().parse().map_err(|_| invalid())?;
+                self.window = Some((x, y));
+            }
+            "font" if value.is_empty() => self.font = None,
+            "font" => self.font = Some(PathBuf::from(value)),
+            _ => {
+                if let Some(engine) = key.strip_prefix("engine.") {
+                    self.set_engine(engine, value)?;
+                }
+                // (other keys are settings of a newer version)
+            }
+        }
+        Ok(())
+    }
+
+    /// "<name>.path", "<name>.dir", "<name>.option.<option>" or
+    /// "<name>.preset.<preset>.<option>"
+    fn set_engine(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let (name, field) = key
+            .split_once('.')
+            .ok_or_else(|| format!("engine.{}: expected engine.<name>.<field>", key))?;
+        let engine = match self.usi_engines.iter().position(|e| e.name == name) {
+            Some(i) => &mut self.usi_engines[i],
+            None => {
+                self.usi_engines.push(EngineConfig {
+                    name: name.to_string(),
+                    ..EngineConfig::default()
+                });
+                self.usi_engines.last_mut().unwrap()
+            }
+        };
+        let value = value.to_string();
+        if field == "path" {
+            engine.path = PathBuf::from(value);
+        } else if field == "dir" {
+            engine.dir = Some(PathBuf::from(value));
+        } else if let Some(option) = field.strip_prefix("option.") {
+            engine.set_option(option, Some(value));
+        } else if let Some((preset, option)) = field
+            .strip_prefix("preset.")
+            .and_then(|preset| preset.split_once('.'))
+        {
+            match engine.presets.iter_mut().find(|p| p.name == preset) {
+                Some(preset) => preset.options.push((option.to_string(), value)),
+                None => engine.presets.push(Preset {
+                    name: preset.to_string(),
+                    options: vec![(option.to_string(), value)],
+                }),
+            }
+        }
+        Ok(())
+    }
+
+    /// the settings file
+    pub fn write(&self) -> String {
+        let engines: Vec<String> = self.engines.iter().map(|level| level.to_string()).collect();
+        let window = match self.window {
+            Some((x, y)) => format!("{},{}", x, y),
+            None => String::from("centered"),
+        };
+        let lines = [
+            format!("theme = {}", self.theme),
+            format!("flip = {}", yes_no_name(self.flip)),
+            format!("highlight_select = {}", yes_no_name(self.highlight_select)),
+            format!(
+                "highlight_last_move = {}",
+                yes_no_name(self.highlight_last_move)
+            ),
+            format!("input = {}", self.input),
+            format!("sounds = {}", yes_no_name(self.sounds)),
+            format!("volume = {}", self.volume),
+            format!("sound_set = {}", self.sound_set),
+            format!("animation = {}", self.animation.as_millis()),
+            format!("reduce_motion = {}", yes_no_name(self.reduce_motion)),
+            format!("time = {}", self.time),
+            format!("level = {}", self.level),
+            format!("engines = {}", engines.join(",")),
+            format!("window = {}", window),
+        ];
+        let mut text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        if let Some(font) = &self.font {
+            text.push_str(&format!("font = {}\n", font.display()));
+        }
+        for engine in &self.usi_engines {
+            let key = format!("engine.{}", engine.name);
+            text.push_str(&format!("{}.path = {}\n", key, engine.path.display()));
+            if let Some(dir) = &engine.dir {
+                text.push_str(&format!("{}.dir = {}\n", key, dir.display()));
+            }
+            for (option, value) in &engine.options {
+                text.push_str(&format!("{}.option.{} = {}\n", key, option, value));
+            }
+            for preset in &engine.presets {
+                for (option, value) in &preset.options {
+                    text.push_str(&format!(
+                        "{}.preset.{}.{} = {}\n",
+                        key, preset.name, option, value
+                    ));
+                }
+            }
+        }
+        text
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, self.write()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// the settings file, None when the settings aren't saved
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// how long a move is animated, None when pieces just jump
+    pub fn animation(&self) -> Option<Duration> {
+        Some(self.animation).filter(|d| !self.reduce_motion && *d > Duration::from_millis(0))
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.theme.palette()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_write() {
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+
+        let text = "# mine\ntheme = dark\nflip = yes\ninput = drag\ntime = 600+30\n\
+                    engines = normal, hard\nwindow = 10,-20\nunknown = 1\n\
+                    font = /fonts/Sans.ttf\nanimation = 400\n\
+                    volume = 35\nsound_set = wood\n";
+        let settings = Settings::parse(text).unwrap();
+        assert_eq!(settings.theme, Theme::Dark);
+        assert!(settings.flip && settings.highlight_select);
+        assert_eq!(settings.input, InputMode::Drag);
+        assert_eq!(settings.time.to_string(), "600+30");
+        assert_eq!(settings.engines, vec![Level::Normal, Level::Hard]);
+        assert_eq!(settings.window, Some((10, -20)));
+        assert_eq!(settings.font, Some(PathBuf::from("/fonts/Sans.ttf")));
+        assert_eq!(settings.animation(), Some(Duration::from_millis(400)));
+        assert_eq!((settings.volume, settings.sound_set.as_str()), (35, "wood"));
+        assert_eq!(Settings::parse(&settings.write()).unwrap(), settings);
+
+        let text = "engine.Yane.path = /opt/yane/YaneuraOu\nengine.Yane.dir = /opt/yane\n\
+                    engine.Yane.option.USI_Hash = 1024\nengine.Yane.option.Eval.Dir = eval\n\
+                    engine.Yane.preset.fast.Threads = 1\n";
+        let settings = Settings::parse(text).unwrap();
+        let engine = &settings.usi_engines[0];
+        assert_eq!(engine.path, PathBuf::from("/opt/yane/YaneuraOu"));
+        assert_eq!(engine.option("Eval.Dir"), Some("eval"));
+        assert_eq!(engine.presets[0].name, "fast");
+        assert_eq!(Settings::parse(&settings.write()).unwrap(), settings);
+
+        assert!(Settings::parse("theme = pink").is_err());
+        assert!(Settings::parse("sounds").is_err());
+        assert!(Settings::parse("animation = fast").is_err());
+        assert!(Settings::parse("volume = 101").is_err());
+        assert_eq!(
+            Settings::parse("reduce_motion = yes").unwrap().animation(),
+            None
+        );
+        assert_eq!(Settings::parse("animation = 0").unwrap().animation(), None);
+    }
+}
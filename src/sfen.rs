@@ -0,0 +1,415 @@
+// Conversions between shogai boards/moves and the standard notations (SFEN, USI moves).
+//
+// shogai and the standard notations don't look at the board from the same side: the first
+// player (shogai's White, sente) starts on rows a-c in shogai but on ranks 7-9 in standard
+// notation. Standard coordinates are therefore shogai's rotated by 180 degrees, and shogai's
+// White is sente ("b" in SFEN, uppercase pieces) while shogai's Black is gote.
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+use shogai::position::*;
+
+/// SFEN of the even starting position
+pub const STARTPOS: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+/// order in which hands are written in SFEN
+const HAND_ORDER: [PieceType; 7] = [
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Gold,
+    PieceType::Silver,
+    PieceType::Knight,
+    PieceType::Lance,
+    PieceType::Pawn,
+];
+
+/// standard name of the side playing with the given shogai color
+pub fn side_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "sente",
+        Color::Black => "gote",
+    }
+}
+
+/// shogai position of a standard square given as file (1-9) and rank (1-9)
+pub fn position(file: u8, rank: u8) -> Position {
+    Position(80 - ((file as u16 - 1) + (rank as u16 - 1) * 9))
+}
+
+/// standard (file, rank) of a shogai position, both in 1..=9
+pub fn file_rank(pos: Position) -> (u8, u8) {
+    let standard = 80 - pos.0;
+    ((standard % 9) as u8 + 1, (standard / 9) as u8 + 1)
+}
+
+/// USI square, such as "7g"
+pub fn usi_square(pos: Position) -> String {
+    let (file, rank) = file_rank(pos);
+    format!("{}{}", file, (b'a' + rank - 1) as char)
+}
+
+pub fn parse_usi_square(s: &str) -> Result<Position, String> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 2 || !(b'1'..=b'9').contains(&bytes[0]) || !(b'a'..=b'i').contains(&bytes[1])
+    {
+        return Err(format!("invalid square '{}'", s));
+    }
+    Ok(position(bytes[0] - b'0', bytes[1] - b'a' + 1))
+}
+
+/// SFEN letter of a piece type, uppercase
+pub fn piece_letter(piecetype: PieceType) -> char {
+    match piecetype {
+        PieceType::Pawn => 'P',
+        PieceType::Lance => 'L',
+        PieceType::Knight => 'N',
+        PieceType::Silver => 'S',
+        PieceType::Gold => 'G',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::King => 'K',
+    }
+}
+
+pub fn piece_from_letter(letter: char) -> Option<PieceType> {
+    match letter.to_ascii_uppercase() {
+        'P' => Some(PieceType::Pawn),
+        'L' => Some(PieceType::Lance),
+        'N' => Some(PieceType::Knight),
+        'S' => Some(PieceType::Silver),
+        'G' => Some(PieceType::Gold),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+/// SFEN representation of the board, `ply` being the number of the next move
+pub fn to_sfen(board: &Board, ply: u32) -> String {
+    let mut rows = Vec::new();
+    for rank in 1..=9 {
+        let mut row = String::new();
+        let mut empty = 0;
+        for file in (1..=9).rev() {
+            match board.is_occupied_by(position(file, rank)) {
+                Some(piece) => {
+                    if empty > 0 {
+                        row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    if piece.promoted {
+                        row.push('+');
+                    }
+                    let letter = piece_letter(piece.piecetype);
+                    row.push(match piece.color {
+                        Color::White => letter,
+                        Color::Black => letter.to_ascii_lowercase(),
+                    });
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        rows.push(row);
+    }
+
+    let mut hands = String::new();
+    for &color in &[Color::White, Color::Black] {
+        for &piecetype in HAND_ORDER.iter() {
+            let count = hand_count(board, color, piecetype);
+            if count > 1 {
+                hands.push_str(&count.to_string());
+            }
+            if count > 0 {
+                let letter = piece_letter(piecetype);
+                hands.push(match color {
+                    Color::White => letter,
+                    Color::Black => letter.to_ascii_lowercase(),
+                });
+            }
+        }
+    }
+    if hands.is_empty() {
+        hands.push('-');
+    }
+
+    let side = match board.get_color() {
+        Color::White => 'b',
+        Color::Black => 'w',
+    };
+    format!("{} {} {} {}", rows.join("/"), side, hands, ply)
+}
+
+/// number of pieces of the given type in the hand of `color`
+pub fn hand_count(board: &Board, color: Color, piecetype: PieceType) -> usize {
+    board
+        .iter()
+        .filter(|p| p.position.is_none() && p.color == color && p.piecetype == piecetype)
+        .count()
+}
+
+/// parse a SFEN string (the "sfen" keyword and "startpos" are accepted) into a board and the
+/// number of the next move
+pub fn from_sfen(sfen: &str) -> Result<(Board, u32), String> {
+    let sfen = sfen.trim();
+    let sfen = sfen.strip_prefix("sfen ").unwrap_or(sfen).trim();
+    if sfen == "startpos" {
+        return from_sfen(STARTPOS);
+    }
+    let fields: Vec<&str> = sfen.split_whitespace().collect();
+    if fields.len() < 3 {
+        return Err(format!("incomplete SFEN '{}'", sfen));
+    }
+
+    let mut board = Board::empty();
+    let rows: Vec<&str> = fields[0].split('/').collect();
+    if rows.len() != 9 {
+        return Err(format!("SFEN board must have 9 ranks, got {}", rows.len()));
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let rank = i as u8 + 1;
+        let mut file = 10u8;
+        let mut promoted = false;
+        for c in row.chars() {
+            if c == '+' {
+                promoted = true;
+                continue;
+            }
+            if let Some(n) = c.to_digit(10) {
+                file = file.saturating_sub(n as u8);
+                continue;
+            }
+            file = file.saturating_sub(1);
+            if file == 0 {
+                return Err(format!("too many squares on rank {}", rank));
+            }
+            let piecetype = piece_from_letter(c).ok_or(format!("unknown piece '{}'", c))?;
+            board.add_piece(Piece {
+                color: if c.is_ascii_uppercase() {
+                    Color::White
+                } else {
+                    Color::Black
+                },
+                piecetype,
+                promoted,
+                position: Some(position(file, rank)),
+            });
+            promoted = false;
+        }
+        if file != 1 {
+            return Err(format!("rank {} does not have 9 squares", rank));
+        }
+    }
+
+    board.turn = match fields[1] {
+        "b" => Color::White,
+        "w" => Color::Black,
+        other => return Err(format!("invalid side to move '{}'", other)),
+    };
+
+    if fields[2] != "-" {
+        let mut count = 0;
+        for c in fields[2].chars() {
+            if let Some(n) = c.to_digit(10) {
+                count = count * 10 + n;
+                continue;
+            }
+            let piecetype = piece_from_letter(c).ok_or(format!("unknown piece '{}'", c))?;
+            for _ in 0..count.max(1) {
+                board.add_piece(Piece {
+                    color: if c.is_ascii_uppercase() {
+                        Color::White
+                    } else {
+                        Color::Black
+                    },
+                    piecetype,
+                    promoted: false,
+                    position: None,
+                });
+            }
+            count = 0;
+        }
+    }
+
+    let ply = match fields.get(3) {
+        Some(n) => n
+            .parse()
+            .map_err(|_| format!("invalid move number '{}'", n))?,
+        None => 1,
+    };
+    Ok((board, ply))
+}
+
+/// USI notation ("7g7f", "2b8h+", "P*5e") of a move in shogai notation
+pub fn to_usi(mv: &str) -> Result<String, String> {
+    let movement: Movement = parse_movement(mv)?;
+    Ok(match movement.start {
+        None => format!(
+            "{}*{}",
+            piece_letter(movement.piecetype),
+            usi_square(movement.end)
+        ),
+        Some(start) => format!(
+            "{}{}{}",
+            usi_square(start),
+            usi_square(movement.end),
+            if movement.promotion { "+" } else { "" }
+        ),
+    })
+}
+
+/// shogai notation of a move given in USI notation; the board is needed to know which piece
+/// moves and whether it captures
+pub fn from_usi(usi: &str, board: &Board) -> Result<String, String> {
+    let usi = usi.trim();
+    // the slices below are of bytes
+    if usi.len() < 4 || !usi.is_ascii() {
+        return Err(format!("invalid USI move '{}'", usi));
+    }
+    let movement = if &usi[1..2] == "*" {
+        let piecetype = piece_from_letter(usi.chars().next().unwrap())
+            .ok_or(format!("invalid drop '{}'", usi))?;
+        Movement {
+            piecetype,
+            start: None,
+            end: parse_usi_square(&usi[2..4])?,
+            promotion: false,
+            force_capture: false,
+            withdraw: false,
+            restart: false,
+        }
+    } else {
+        let start = parse_usi_square(&usi[0..2])?;
+        let end = parse_usi_square(&usi[2..4])?;
+        let piece = board.is_occupied_by(start).ok_or(format!(
+            "no piece on {} for move '{}'",
+            &usi[0..2],
+            usi
+        ))?;
+        Movement {
+            piecetype: piece.piecetype,
+            start: Some(start),
+            end,
+            promotion: usi.ends_with('+'),
+            force_capture: false,
+            withdraw: false,
+            restart: false,
+        }
+    };
+    Ok(movement.to_string())
+}
+
+/// Movement::from_str panics on malformed input, check the syntax first
+pub fn parse_movement(mv: &str) -> Result<Movement, String> {
+    shogai::invalidmoveerror::check_syntax(mv)
+        .and_then(shogai::invalidmoveerror::check_in_board)
+        .map_err(|e| e.to_string())?;
+    mv.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handicap::Handicap;
+
+    #[test]
+    fn sfen_round_trips() {
+        let (board, ply) = from_sfen("startpos").unwrap();
+        assert_eq!(ply, 1);
+        assert_eq!(to_sfen(&board, 1), STARTPOS);
+        assert_eq!(to_sfen(&Board::new(), 1), STARTPOS);
+
+        // promoted pieces, hands with counts, gote to move
+        let sfen = "ln1g3+Rl/2s1k4/p1pp1p2p/4p4/1p7/2P6/PPNPPPP1P/1+b2K4/L2G1GSNL w BG2Pn2p 42";
+        let (board, ply) = from_sfen(&format!("sfen {}", sfen)).unwrap();
+        assert_eq!(ply, 42);
+        assert_eq!(board.get_color(), Color::Black);
+        assert_eq!(hand_count(&board, Color::White, PieceType::Pawn), 2);
+        assert_eq!(hand_count(&board, Color::Black, PieceType::Pawn), 2);
+        assert_eq!(to_sfen(&board, ply), sfen);
+
+        assert!(from_sfen("9/9/9/9/9/9/9/9 b - 1").is_err());
+        assert!(
+            from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNLL b - 1").is_err()
+        );
+        assert!(
+            from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL x - 1").is_err()
+        );
+    }
+
+    #[test]
+    fn handicap_sfens() {
+        let sfens = [
+            (Handicap::Even, STARTPOS),
+            (
+                Handicap::Lance,
+                "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::RightLance,
+                "1nsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::Bishop,
+                "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::Rook,
+                "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::RookLance,
+                "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::TwoPiece,
+                "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::FourPiece,
+                "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::SixPiece,
+                "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::EightPiece,
+                "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+            (
+                Handicap::TenPiece,
+                "4k4/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1",
+            ),
+        ];
+        for (handicap, sfen) in sfens.iter() {
+            assert_eq!(handicap.sfen(), *sfen, "{}", handicap);
+            let (board, _) = from_sfen(sfen).unwrap();
+            assert_eq!(to_sfen(&board, 1), *sfen);
+        }
+    }
+
+    #[test]
+    fn usi_moves() {
+        let mut board = Board::new();
+        for usi in ["7g7f", "3c3d", "8h2b+"].iter() {
+            let mv = from_usi(usi, &board).unwrap();
+            assert_eq!(to_usi(&mv).unwrap(), *usi);
+            board = board.play_move_unchecked(&mv);
+        }
+        let (board, _) = from_sfen("4k4/9/9/9/9/9/9/9/4K4 b P 1").unwrap();
+        let mv = from_usi("P*5e", &board).unwrap();
+        assert_eq!(to_usi(&mv).unwrap(), "P*5e");
+
+        assert!(from_usi("5e5d", &board).is_err());
+        assert!(from_usi("7g", &board).is_err());
+        assert!(from_usi("５五歩打", &board).is_err());
+        assert!(from_usi("7ｇ7f", &board).is_err());
+        assert_eq!(parse_usi_square("5e"), Ok(position(5, 5)));
+        assert_eq!(usi_square(position(7, 7)), "7g");
+    }
+}
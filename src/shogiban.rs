@@ -1,42 +1,135 @@
 use sdl2::event::Event;
-use sdl2::image::{InitFlag, LoadTexture};
-use sdl2::keyboard::Keycode;
+use sdl2::image::InitFlag;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::messagebox::ClickedButton;
 use sdl2::messagebox::*;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture};
+use sdl2::render::Canvas;
 use sdl2::video::Window;
+use sdl2::EventPump;
+use sdl2::VideoSubsystem;
 
 // use shakmaty::{Board, Chess, File, Move, Position, Rank, Role, Setup, Square};
 // use shogai::ai::*;
 use shogai::board::*;
 use shogai::movement::*;
-use shogai::piece::*;
-use shogai::position::*;
 
-use std::collections::HashSet;
-use std::path::Path;
+use crate::animation::Animation;
+use crate::book::{Book, BookPlayer};
+use crate::cli::{Args, Network};
+use crate::clock::{Clock, TimeControl};
+use crate::controller::{Controller, Dialogs, Reply, Restart};
+use crate::csa::{self, Client, CsaGame};
+use crate::database::{self, Database, Filter};
+use crate::diagram::Diagram;
+use crate::editor::Editor;
+#[cfg(target_os = "emscripten")]
+use crate::emscripten_file::emscripten_mod;
+use crate::game::{Driver, GameState};
+use crate::handicap::Handicap;
+use crate::headless;
+use crate::input::{Context, InputHandler};
+use crate::kif;
+use crate::layout::{PANEL_WIDTH, SCR_HEIGHT, SCR_WIDTH};
+use crate::marks::Mark;
+use crate::net::{self, Host, Message, NetGame};
+use crate::opponent::{Level, Limits, Opponent};
+use crate::record::GameRecord;
+use crate::render::{rgb, Overlays, Panel, PanelRow, Renderer};
+use crate::replay::Recorder;
+use crate::result::GameResult;
+use crate::rules;
+use crate::settings::{InputMode, Palette, Settings, Theme};
+use crate::sfen;
+use crate::sound::{self, Effect, Sounds};
+use crate::tsume::{self, Trainer};
+use crate::ui::{self, columns, split, ListState, Menu, Rows, Scroll, Ui, PADDING, ROW};
+use crate::usi::{self, EngineConfig, EngineInfo, OptionKind, UsiEngine, UsiOption};
 
-const SRC_RESERVE_HEIGTH: u32 = 100;
-const SCR_WIDTH: u32 = 603;
-const SCR_HEIGHT: u32 = 603 + 2 * SRC_RESERVE_HEIGTH;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-const SQR_SIZE: u32 = SCR_WIDTH / 9;
+/// an item of the menus: its text, the key doing the same and whether Ctrl goes with it
+type MenuItem = (&'static str, Keycode, bool);
+
+/// the menus of F10
+const MENUS: &[(&str, &[MenuItem])] = &[
+    (
+        "Game",
+        &[
+            ("Save (Ctrl+S)", Keycode::S, true),
+            ("Resign (Ctrl+R)", Keycode::R, true),
+            ("Offer a draw (Ctrl+D)", Keycode::D, true),
+            ("Declare (Ctrl+K)", Keycode::K, true),
+            ("New game (N)", Keycode::N, false),
+            ("Rematch (R)", Keycode::R, false),
+            ("Quit (Esc)", Keycode::Escape, false),
+        ],
+    ),
+    (
+        "Study",
+        &[
+            ("Opening book (B)", Keycode::B, false),
+            ("Game database (F)", Keycode::F, false),
+            ("Set up a position (E)", Keycode::E, false),
+            ("Export a diagram (Ctrl+P)", Keycode::P, true),
+        ],
+    ),
+    ("Options", &[("Settings (Ctrl+O)", Keycode::O, true)]),
+];
+
+/// the computer side of a local game
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Computer {
+    Level(Level),
+    /// a USI engine of the registry, by name
+    Usi(String),
+}
+
+impl fmt::Display for Computer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Computer::Level(level) => write!(f, "{}", level),
+            Computer::Usi(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// what was chosen in the new game dialogs
+#[derive(Debug, Clone, Copy)]
+struct NewGame {
+    handicap: Handicap,
+    human: shogai::piece::Color,
+    /// start by setting up a position in the editor
+    edit: bool,
+}
+
+pub fn init(args: &Args) -> Result<(), String> {
+    // the command line wins over the saved settings
+    let settings = Settings::load();
+    let args = Args {
+        time: args.time.or(Some(settings.time)),
+        ..args.clone()
+    };
+    let args = &args;
 
-pub fn init() -> Result<(), String> {
     // sdl things
     let context = sdl2::init().unwrap();
     let video = context.video().unwrap();
 
     let _image_context = sdl2::image::init(InitFlag::PNG)?;
+    let ttf = sdl2::ttf::init().map_err(|e| e.to_string())?;
 
-    let window = match video
-        .window("Shogi", SCR_WIDTH, SCR_HEIGHT)
-        .position_centered()
-        .opengl()
-        .build()
-    {
+    let mut builder = video.window("Shogi", SCR_WIDTH, SCR_HEIGHT);
+    match settings.window {
+        Some((x, y)) => builder.position(x, y),
+        None => builder.position_centered(),
+    };
+    let window = match builder.opengl().build() {
         Ok(window) => window,
         Err(err) => panic!("failed to create window: {}", err),
     };
@@ -49,557 +142,1681 @@ pub fn init() -> Result<(), String> {
 
     let mut events = context.event_pump()?;
 
-    canvas.set_draw_color(Color::RGB(0xD1, 0x8B, 0x47));
-    canvas.clear();
+    // the dialogs and menus
+    let ui = Ui::new(ui::load_font(&ttf, settings.font.as_deref()));
 
-    let texture_creator = canvas.texture_creator();
+    // the sounds of the moves and the clock, None without a sound device
+    let sounds = context
+        .audio()
+        .ok()
+        .and_then(|audio| Sounds::open(&audio).ok());
 
-    // define standard board
-    let mut game = Board::new();
-
-    // completely transparent texture
-    let nothing = texture_creator.load_texture(Path::new("src/sprites/nothing.png"))?;
-
-    // load white pieces' src/sprites.
-    // credits for src/sprites: Wikimedia Commons
-    let w_k = texture_creator.load_texture(Path::new("src/sprites/white/k.png"))?;
-    let w_r = texture_creator.load_texture(Path::new("src/sprites/white/r.png"))?;
-    let w_b = texture_creator.load_texture(Path::new("src/sprites/white/b.png"))?;
-    let w_p = texture_creator.load_texture(Path::new("src/sprites/white/p.png"))?;
-    let w_n = texture_creator.load_texture(Path::new("src/sprites/white/n.png"))?;
-    let w_l = texture_creator.load_texture(Path::new("src/sprites/white/l.png"))?;
-    let w_g = texture_creator.load_texture(Path::new("src/sprites/white/g.png"))?;
-    let w_s = texture_creator.load_texture(Path::new("src/sprites/white/s.png"))?;
-
-    let w_bp = texture_creator.load_texture(Path::new("src/sprites/white/bp.png"))?;
-    let w_rp = texture_creator.load_texture(Path::new("src/sprites/white/rp.png"))?;
-    let w_pp = texture_creator.load_texture(Path::new("src/sprites/white/pp.png"))?;
-    let w_lp = texture_creator.load_texture(Path::new("src/sprites/white/lp.png"))?;
-    let w_np = texture_creator.load_texture(Path::new("src/sprites/white/np.png"))?;
-    let w_sp = texture_creator.load_texture(Path::new("src/sprites/white/sp.png"))?;
-
-    // load black pieces' src/sprites.
-    let b_k = texture_creator.load_texture(Path::new("src/sprites/black/k.png"))?;
-    let b_r = texture_creator.load_texture(Path::new("src/sprites/black/r.png"))?;
-    let b_b = texture_creator.load_texture(Path::new("src/sprites/black/b.png"))?;
-    let b_p = texture_creator.load_texture(Path::new("src/sprites/black/p.png"))?;
-    let b_n = texture_creator.load_texture(Path::new("src/sprites/black/n.png"))?;
-    let b_l = texture_creator.load_texture(Path::new("src/sprites/black/l.png"))?;
-    let b_g = texture_creator.load_texture(Path::new("src/sprites/black/g.png"))?;
-    let b_s = texture_creator.load_texture(Path::new("src/sprites/black/s.png"))?;
-
-    let b_bp = texture_creator.load_texture(Path::new("src/sprites/black/bp.png"))?;
-    let b_rp = texture_creator.load_texture(Path::new("src/sprites/black/rp.png"))?;
-    let b_pp = texture_creator.load_texture(Path::new("src/sprites/black/pp.png"))?;
-    let b_lp = texture_creator.load_texture(Path::new("src/sprites/black/lp.png"))?;
-    let b_np = texture_creator.load_texture(Path::new("src/sprites/black/np.png"))?;
-    let b_sp = texture_creator.load_texture(Path::new("src/sprites/black/sp.png"))?;
-
-    let piece_to_texture = |piece: &Piece| {
-        if piece.promoted {
-            match piece.color {
-                shogai::piece::Color::White => match piece.piecetype {
-                    PieceType::Pawn => &w_pp,
-                    PieceType::Bishop => &w_bp,
-                    PieceType::Rook => &w_rp,
-                    PieceType::Knight => &w_np,
-                    PieceType::King => &w_k,
-                    PieceType::Gold => &w_g,
-                    PieceType::Lance => &w_lp,
-                    PieceType::Silver => &w_sp,
-                },
-                shogai::piece::Color::Black => match piece.piecetype {
-                    PieceType::Pawn => &b_pp,
-                    PieceType::Bishop => &b_bp,
-                    PieceType::Rook => &b_rp,
-                    PieceType::Knight => &b_np,
-                    PieceType::King => &b_k,
-                    PieceType::Gold => &b_g,
-                    PieceType::Lance => &b_lp,
-                    PieceType::Silver => &b_sp,
-                },
-            }
-        } else {
-            match piece.color {
-                shogai::piece::Color::White => match piece.piecetype {
-                    PieceType::Pawn => &w_p,
-                    PieceType::Bishop => &w_b,
-                    PieceType::Rook => &w_r,
-                    PieceType::Knight => &w_n,
-                    PieceType::King => &w_k,
-                    PieceType::Gold => &w_g,
-                    PieceType::Lance => &w_l,
-                    PieceType::Silver => &w_s,
-                },
-                shogai::piece::Color::Black => match piece.piecetype {
-                    PieceType::Pawn => &b_p,
-                    PieceType::Bishop => &b_b,
-                    PieceType::Rook => &b_r,
-                    PieceType::Knight => &b_n,
-                    PieceType::King => &b_k,
-                    PieceType::Gold => &b_g,
-                    PieceType::Lance => &b_l,
-                    PieceType::Silver => &b_s,
-                },
+    // tsume mode: a series of problems instead of a game
+    let trainer = match &args.tsume {
+        Some(path) => Some(Trainer::new(tsume::load(path)?, tsume::Progress::load())),
+        None => None,
+    };
+
+    // network game: connect first, the host decides the position, the sides and the clock
+    let net = match &args.network {
+        Some(network) => {
+            match connect(network, args, &settings.palette(), &mut canvas, &mut events)? {
+                Some(net) => Some(net),
+                // the window was closed while waiting
+                None => return Ok(()),
             }
         }
+        None => None,
     };
-
-    // This will parse and draw all pieces currently on the game to the window.
-    let draw_pieces = |canvas: &mut Canvas<Window>, game: &Board, hidden: Option<Piece>| {
-        let mut piece_hidden = false;
-        for (j, piece) in game.iter().enumerate() {
-            //TODO filter "only once" to remove only on exemplary of pieces in reserve
-            if !piece_hidden && Some(*piece) == hidden {
-                piece_hidden = true;
-                continue;
-            }
-            if let Some(i) = piece.position {
-                draw_piece(canvas, piece_to_texture(piece), i);
-            } else {
-                let count = game.iter().take(j).filter(|p| p == &piece).count();
-                draw_piece_on_reserve(canvas, piece_to_texture(piece), piece, count);
-                //TODO manage drawing multiple identical pieces
+    // game on a CSA server, the server decides everything
+    let csa = match &args.csa {
+        Some(server) => {
+            match connect_csa(server, args, &settings.palette(), &mut canvas, &mut events)? {
+                Some(csa) => Some(csa),
+                None => return Ok(()),
             }
         }
+        None => None,
     };
 
-    let get_mouse_position: fn(sdl2::mouse::MouseState) -> Option<Position> = |mouse_state| {
-        if mouse_state.y() >= SRC_RESERVE_HEIGTH as i32
-            && mouse_state.y() <= SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32
-        {
-            return Some(Position(
-                (9 - (mouse_state.x() / SQR_SIZE as i32) as u16)
-                    + ((mouse_state.y() - SRC_RESERVE_HEIGTH as i32) / SQR_SIZE as i32) as u16 * 9
-                    - 1,
-            ));
-        } else {
-            //manage get from reserve
-            return None;
-        }
+    canvas.set_draw_color(rgb(settings.palette().board));
+    canvas.clear();
+
+    let texture_creator = canvas.texture_creator();
+
+    let renderer = Renderer::new(&texture_creator)?;
+
+    // opening book of the computer, also shown in the book panel
+    let book = match &args.book {
+        Some(path) => Some(Arc::new(Book::load(path)?)),
+        None => None,
     };
+    // games browsed in the database panel
+    let database = match &args.database {
+        Some(dir) => Some(Database::load(dir)?),
+        None => None,
+    };
+    let filter = database::filter_of(args);
 
-    // We need to set this before the render loop to avoid undefined behaviour,
-    // so we just set an arbritary texture to this by now.
-    let mut curr_texture: &Texture = &nothing;
-    let mut hidden = None;
+    let gui = Gui {
+        args: args.clone(),
+        settings,
+        context,
+        video,
+        canvas,
+        events,
+        pending: None,
+        ui,
+        sounds,
+        renderer,
+        input: InputHandler::new(),
+        recorder: args.record_input.as_ref().map(Recorder::new),
+        // the first problem is set up on the first frame
+        problem_changed: trainer.is_some(),
+        trainer,
+        hint: None,
+        net,
+        csa,
+        restart: Restart::First,
+        previous: None,
+        book,
+        database,
+        filter,
+        panel: None,
+        panel_rows: Vec::new(),
+        panel_line: String::new(),
+        panel_position: String::new(),
+        round: None,
+    };
+    run(gui)
+}
 
-    // arbitrary to avoid undefined behaviour
-    let mut prev_click_pos: Option<Position> = None;
+/// the frames one after the other, until the window is closed
+#[cfg(not(target_os = "emscripten"))]
+fn run(mut gui: Gui) -> Result<(), String> {
+    while gui.step()? {
+        // sleep until something happens, or until the next frame: at 60 Hz while pieces slide,
+        // often enough for the clocks and the opponent's replies otherwise
+        let timeout = if gui.animating() { 16 } else { 50 };
+        gui.pending = gui.events.wait_event_timeout(timeout);
+    }
+    gui.close()
+}
 
-    let mut prev_role_click: Option<PieceType> = None;
-    let mut curr_role_click: Option<PieceType> = None;
+/// the browser can't be kept waiting in a loop, it calls `step` on every animation frame
+#[cfg(target_os = "emscripten")]
+fn run(mut gui: Gui) -> Result<(), String> {
+    emscripten_mod::set_main_loop_callback(move || {
+        let done = match gui.step() {
+            Ok(true) => return,
+            Ok(false) => gui.close(),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = done {
+            eprintln!("{}", e);
+        }
+        unsafe { emscripten_mod::emscripten_cancel_main_loop() };
+    });
+    Ok(())
+}
 
-    let mut curr_click_pos: Option<Position> = None;
-    let mut prev_mouse_buttons = HashSet::new();
+/// how a frame ended
+enum Frame {
+    Continue,
+    /// the window was closed
+    Quit,
+    /// another game is wanted
+    Next(Restart),
+}
 
-    let mut has_played = false;
+/// one game, from its start to the next one (or a tsume session, or a position being set up)
+struct Round {
+    computer: Computer,
+    handicap: Handicap,
+    /// the computer, None in network games
+    driver: Driver,
+    opponent_name: String,
+    game_title: String,
+    /// the board, its record and clock, the piece picked up...
+    state: GameState,
+    /// position editor, Some while editing
+    editor: Option<Editor>,
+    /// a declaration of the computer, ending the game on the next frame
+    declared: Option<GameResult>,
+    heard: Heard,
+}
 
-    //main loop start ####################################
-    //####################################################
-    //###################################################
+/// what was last heard and drawn of a game: every new move sounds like what it did, and its
+/// pieces slide
+struct Heard {
+    /// moves already heard, and whether the end of the game was, and the last second of the
+    /// clock counted
+    moves: usize,
+    result: bool,
+    counted: Option<u32>,
+    /// the board as last drawn, and the pieces of the move since sliding from there
+    drawn: Board,
+    animation: Option<Animation>,
+}
 
-    'main_loop: loop {
-        for event in events.poll_iter() {
-            // if esc is pressed, exit main loop
-            // (consequently ending the program)
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'main_loop,
-                _ => false,
-            };
+impl Heard {
+    fn new(state: &GameState) -> Heard {
+        Heard {
+            moves: state.record.moves.len(),
+            result: false,
+            counted: None,
+            drawn: state.board.clone(),
+            animation: None,
         }
+    }
 
-        if has_played {
-            //game_over check is *very* expensive, don't do it everytime or UI lag
-            //also may be used for future multithreading
-            has_played = false;
-            if game.game_over() {
-                let who;
-                if game.get_turn() {
-                    who = "second player";
-                } else {
-                    who = "first player";
+    /// the sounds and the slide of what happened since the last frame
+    fn update(&mut self, state: &GameState, settings: &Settings, mut sounds: Option<&mut Sounds>) {
+        if state.record.moves.len() != self.moves {
+            // one move more since the board was drawn: its pieces slide there, unless the player
+            // just dragged them, and it sounds like what it did
+            let drawn = &self.drawn;
+            let moved = state.record.moves.last().filter(|mv| {
+                state.record.moves.len() == self.moves + 1
+                    && sfen::to_sfen(&drawn.play_move_unchecked(mv), 1)
+                        == sfen::to_sfen(&state.board, 1)
+            });
+            let dragged = settings.input == InputMode::Drag && drawn.get_color() == state.human;
+            self.animation = match (moved, settings.animation()) {
+                (Some(mv), Some(duration)) if !dragged => {
+                    Animation::of_move(drawn, &state.board, mv, duration).ok()
                 }
-                let message = [who, &"has won the game!"].join(" ");
-                return show_simple_message_box(
-                    MessageBoxFlag::empty(),
-                    &"Game Over",
-                    &message,
-                    canvas.window(),
-                )
-                .map_err(|e| e.to_string());
+                _ => None,
+            };
+            let effects = match moved {
+                Some(mv) => sound::effects(drawn, mv, &state.board),
+                None => vec![Effect::Place],
+            };
+            if let Some(sounds) = sounds.as_deref_mut() {
+                sounds.play(&effects, settings);
+            }
+            self.moves = state.record.moves.len();
+            self.drawn = state.board.clone();
+        }
+        if state.result.is_some() != self.result {
+            self.result = state.result.is_some();
+            if let Some(sounds) = sounds.as_deref_mut().filter(|_| self.result) {
+                sounds.play(&[Effect::GameEnd], settings);
+            }
+        }
+        // the last seconds of the player's clock, counted
+        let countdown = Some(state.board.get_color())
+            .filter(|_| state.human_turn())
+            .and_then(|side| state.clock.countdown(side));
+        if countdown != self.counted {
+            self.counted = countdown;
+            if let (Some(sounds), Some(seconds)) = (sounds, countdown) {
+                sounds.play(&[Effect::Countdown(seconds)], settings);
             }
         }
+        if self.animation.as_ref().is_some_and(Animation::done) {
+            self.animation = None;
+        }
+    }
+}
 
-        let mouse_state = events.mouse_state();
-        let curr_mouse_buttons: HashSet<_> = mouse_state.pressed_mouse_buttons().collect();
-        canvas.set_draw_color(Color::RGB(0xD1, 0x8B, 0x47));
-        canvas.clear();
+/// the window and everything lasting from one game to the next
+struct Gui<'a> {
+    args: Args,
+    settings: Settings,
+    context: sdl2::Sdl,
+    video: VideoSubsystem,
+    canvas: Canvas<Window>,
+    events: EventPump,
+    /// the event the loop woke up for, the first one of the next frame
+    pending: Option<Event>,
+    ui: Ui<'a>,
+    /// the sounds of the moves and the clock, None without a sound device
+    sounds: Option<Sounds>,
+    renderer: Renderer<'a, Window>,
+    /// the mouse, the keys and the chat line being typed
+    input: InputHandler,
+    /// --record-input
+    recorder: Option<Recorder>,
+    /// tsume mode: a series of problems instead of a game
+    trainer: Option<Trainer>,
+    /// set when the problem to solve changes (or is restarted)
+    problem_changed: bool,
+    /// the first move of the mate, shown once the driver found it
+    hint: Option<Movement>,
+    net: Option<NetGame>,
+    csa: Option<CsaGame>,
+    /// what the game after this one is, and what the last one was (for rematches)
+    restart: Restart,
+    previous: Option<(Computer, NewGame, Board)>,
+    /// opening book of the computer, also shown in the book panel
+    book: Option<Arc<Book>>,
+    /// games browsed in the database panel
+    database: Option<Database>,
+    filter: Filter,
+    /// the open panel, its rows and title line, and the position they are for
+    panel: Option<Panel>,
+    panel_rows: Vec<PanelRow>,
+    panel_line: String,
+    panel_position: String,
+    /// the game being played, None when the next one is to be set up
+    round: Option<Round>,
+}
 
-        draw_shogiban(&mut canvas);
-        let mut human_play = |game: &mut Board| {
-            let get_texture = |game: &Board| {
-                if let Some(pos) = get_mouse_position(mouse_state) {
-                    match game.is_occupied_by(pos) {
-                        Some(piece) => piece_to_texture(&piece),
-                        None => &nothing,
-                    }
-                } else {
-                    &nothing
+impl<'a> Gui<'a> {
+    /// pieces are sliding, frames are to be drawn without waiting for events
+    #[cfg(not(target_os = "emscripten"))]
+    fn animating(&self) -> bool {
+        self.round
+            .as_ref()
+            .is_some_and(|round| round.heard.animation.is_some())
+    }
+
+    /// one frame, starting a new game first if needed; false when the window is to be closed
+    fn step(&mut self) -> Result<bool, String> {
+        let mut round = match self.round.take() {
+            Some(round) => round,
+            None => {
+                let round = self.start_round()?;
+                if let Some(recorder) = self.recorder.as_mut() {
+                    recorder.start(&round.state, &self.settings)?;
                 }
-            };
-            //select in green movable pieces on the board
-            if let Some(pos) = prev_click_pos {
-                if let Some(selected_piece) = game.is_occupied_by(pos) {
-                    if selected_piece.color == game.get_color() {
-                        draw_select(pos, &mut canvas);
+                round
+            }
+        };
+        let frame = self.frame(&mut round)?;
+        if let (Frame::Quit, Some(recorder)) | (Frame::Next(_), Some(recorder)) =
+            (&frame, self.recorder.as_mut())
+        {
+            recorder.finish(&round.state)?;
+        }
+        match frame {
+            Frame::Continue => {
+                self.round = Some(round);
+                Ok(true)
+            }
+            Frame::Quit => Ok(false),
+            Frame::Next(next) => {
+                if let Some(old) = self.csa.take() {
+                    let palette = self.settings.palette();
+                    match next_csa_game(old.client, &palette, &mut self.canvas, &mut self.events)? {
+                        Some(game) => self.csa = Some(game),
+                        None => return Ok(false),
                     }
                 }
+                self.previous = Some((
+                    round.computer,
+                    NewGame {
+                        handicap: round.handicap,
+                        human: round.state.human,
+                        edit: false,
+                    },
+                    round.state.record.start,
+                ));
+                self.restart = next;
+                Ok(true)
             }
-            let is_mouse_released = &prev_mouse_buttons - &curr_mouse_buttons;
-            prev_mouse_buttons = curr_mouse_buttons.clone();
-            prev_role_click = curr_role_click;
-            prev_click_pos = curr_click_pos;
+        }
+    }
 
-            if !is_mouse_released.is_empty() {
-                curr_texture = get_texture(&game);
+    /// the window is closing
+    fn close(&mut self) -> Result<(), String> {
+        // open the window where it was left next time
+        self.settings.window = Some(self.canvas.window().position());
+        self.settings.save()
+    }
+
+    /// set up the next game: its opponent, its position, its clock...
+    fn start_round(&mut self) -> Result<Round, String> {
+        let Gui {
+            args,
+            settings,
+            canvas,
+            trainer,
+            net,
+            csa,
+            restart,
+            previous,
+            book,
+            ..
+        } = self;
+        let restart = *restart;
+        let computer = match (restart, previous.as_ref()) {
+            (Restart::Rematch, Some((computer, _, _))) => computer.clone(),
+            (Restart::NewGame, _) if net.is_none() && csa.is_none() => {
+                choose_level(canvas.window(), settings)?
+            }
+            _ => match args.level {
+                Some(level) => Computer::Level(level),
+                // unused, problems come with their own defender and network games have none
+                None if trainer.is_some() || net.is_some() || csa.is_some() => {
+                    Computer::Level(Level::Normal)
+                }
+                None => choose_level(canvas.window(), settings)?,
+            },
+        };
+        let NewGame {
+            handicap,
+            human,
+            edit,
+        } = match (args.handicap, args.level) {
+            // the one giving the handicap gives it again, even games swap sides
+            _ if restart == Restart::Rematch && net.is_none() && csa.is_none() => {
+                match previous.as_ref() {
+                    Some((_, last, _)) if last.handicap == Handicap::Even => NewGame {
+                        human: rules::opponent(last.human),
+                        edit: false,
+                        ..*last
+                    },
+                    Some((_, last, _)) => NewGame {
+                        edit: false,
+                        ..*last
+                    },
+                    None => choose_game(canvas.window())?,
+                }
             }
+            _ if restart == Restart::NewGame && net.is_none() && csa.is_none() => {
+                choose_game(canvas.window())?
+            }
+            _ if net.is_some() => NewGame {
+                handicap: net.as_ref().map_or(Handicap::Even, |n| n.handicap),
+                human: net.as_ref().map_or(args.human, |n| n.human),
+                edit: false,
+            },
+            _ if csa.is_some() => NewGame {
+                handicap: Handicap::Even,
+                human: csa.as_ref().map_or(args.human, |c| c.summary.color),
+                edit: false,
+            },
+            _ if trainer.is_some() => NewGame {
+                handicap: Handicap::Even,
+                human: args.human,
+                edit: false,
+            },
+            (Some(handicap), _) => NewGame {
+                handicap,
+                human: args.human,
+                edit: args.edit,
+            },
+            // the level was given on the command line, don't bother the user with more questions
+            (None, Some(_)) => NewGame {
+                handicap: Handicap::Even,
+                human: args.human,
+                edit: args.edit,
+            },
+            (None, None) => choose_game(canvas.window())?,
+        };
+        let opponent: Option<Box<dyn Opponent>> = if net.is_some() || csa.is_some() {
+            None
+        } else {
+            let engine = build_computer(&computer, settings, args, canvas.window())?;
+            Some(match &book {
+                Some(book) => Box::new(BookPlayer::new(book.clone(), engine)),
+                None => engine,
+            })
+        };
+        let opponent_name = match (&net, &csa) {
+            (Some(net), _) => net.peer.clone(),
+            (_, Some(csa)) => match human {
+                shogai::piece::Color::White => csa.summary.gote.clone(),
+                shogai::piece::Color::Black => csa.summary.sente.clone(),
+            },
+            _ => opponent.as_ref().map_or_else(String::new, |o| o.name()),
+        };
+        let game_title = match handicap {
+            Handicap::Even => format!("Shogi - vs {}", opponent_name),
+            _ => format!("Shogi - vs {} ({} handicap)", opponent_name, handicap),
+        };
+        canvas
+            .window_mut()
+            .set_title(&game_title)
+            .map_err(|e| e.to_string())?;
 
-            if !is_mouse_released.is_empty() {
-                if let Some(pos) = get_mouse_position(mouse_state) {
-                    curr_role_click = match game.is_occupied_by(pos) {
-                        None => None,
-                        Some(piece) => Some(piece.piecetype),
-                    };
-                    if let Some(piece) = game.is_occupied_by(pos) {
-                        if piece.color == game.get_color() {
-                            hidden = game.is_occupied_by(pos);
-                        } else {
-                            curr_role_click = None;
-                        }
-                    } else {
-                        hidden = None;
-                    }
-                } else if let Some(piecetype) = get_in_reserve(mouse_state, &game) {
-                    //drag n drop from reserve (drop move)
-                    if game.iter().any(|p| {
-                        p.color == game.get_color()
-                            && p.piecetype == piecetype
-                            && p.position == None
-                    }) {
-                        curr_role_click = Some(piecetype);
-                        curr_click_pos = None;
-                        hidden = Some(Piece {
-                            color: game.get_color(),
-                            piecetype: piecetype,
-                            position: None,
-                            promoted: false,
-                        });
-                    } else {
-                        curr_role_click = None;
-                        hidden = None;
-                    }
+        // define the starting board, with the pieces given as handicap removed
+        let game = match (&net, &csa, restart, previous.as_ref()) {
+            (Some(net), ..) => net.start.clone(),
+            (_, Some(csa), ..) => csa.summary.record().current(),
+            // a rematch starts from the same position, even one set up in the editor
+            (_, _, Restart::Rematch, Some((_, _, start))) => start.clone(),
+            _ => handicap.board(),
+        };
+        let you = if net.is_some() || csa.is_some() {
+            args.player_name()
+        } else {
+            String::from("You")
+        };
+        let (sente_name, gote_name) = match human {
+            shogai::piece::Color::White => (you, opponent_name.clone()),
+            shogai::piece::Color::Black => (opponent_name.clone(), you),
+        };
+        let record = if let Some(csa) = &csa {
+            csa.summary.record()
+        } else if sfen::to_sfen(&game, 1) == handicap.sfen() {
+            GameRecord::new(handicap, &sente_name, &gote_name)
+        } else {
+            GameRecord::from_position(game.clone(), &sente_name, &gote_name)
+        };
+        let mut clock = match (&net, &csa) {
+            (Some(net), _) => Clock::new(net.control),
+            (_, Some(csa)) => Clock::new(csa.summary.control),
+            // games against the computer and tsume problems are untimed, `--time` is the clock
+            // of the games we host
+            _ => Clock::new(TimeControl::default()),
+        };
+        if net.is_some() || csa.is_some() {
+            clock.start(game.get_color());
+        }
+        // (CSA servers check declarations with their own rule)
+        let rule = net.as_ref().map_or(args.impasse, |net| net.rule);
+
+        // position editor, Some while editing
+        let editor = if edit {
+            Some(Editor::new(game.clone()))
+        } else {
+            None
+        };
+        let mut state = GameState::new(game, human, record, clock, rule);
+        state.checks_only = trainer.is_some();
+        let heard = Heard::new(&state);
+
+        Ok(Round {
+            computer,
+            handicap,
+            driver: Driver::new(opponent),
+            opponent_name,
+            game_title,
+            state,
+            editor,
+            declared: None,
+            heard,
+        })
+    }
+
+    /// one frame of the game: the events, the moves, the drawing
+    fn frame(&mut self, round: &mut Round) -> Result<Frame, String> {
+        let Gui {
+            settings,
+            context,
+            video,
+            canvas,
+            events,
+            pending,
+            ui,
+            sounds,
+            renderer,
+            input,
+            recorder,
+            trainer,
+            problem_changed,
+            hint,
+            net,
+            csa,
+            book,
+            database,
+            filter,
+            panel,
+            panel_rows,
+            panel_line,
+            panel_position,
+            ..
+        } = self;
+        let mut game = Controller {
+            state: &mut round.state,
+            editor: &mut round.editor,
+            driver: &mut round.driver,
+            opponent_name: &round.opponent_name,
+            trainer: trainer.as_mut(),
+            net: net.as_mut(),
+            csa: csa.as_mut(),
+        };
+
+        // set when the game ends during this frame
+        let mut ended: Option<GameResult> = round.declared.take();
+        let mut next: Option<Restart> = None;
+        // the dialogs asked for, opened once the frame is drawn
+        let mut open_settings = false;
+        let mut open_menu = false;
+        for event in pending.take().into_iter().chain(events.poll_iter()) {
+            // the move of the event before was the player's; the editor isn't recorded
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.sync(game.state, settings, true)?;
+                if game.editor.is_none() {
+                    recorder.event(&event)?;
+                }
+            }
+            let context = Context {
+                input: settings.input,
+                flip: settings.flip,
+                side: game.state.board.get_color(),
+                holding: game.state.holding(),
+                clicked: game.state.clicked(),
+                editing: game.editor.is_some(),
+                chat: game.net.is_some(),
+                keymod: context.keyboard().mod_state(),
+            };
+            let command = input.handle(&event, &context);
+            // chat line being typed in a network game
+            if input.typing().is_some() != video.text_input().is_active() {
+                if input.typing().is_some() {
+                    video.text_input().start();
                 } else {
-                    curr_role_click = None;
-                    hidden = None;
+                    video.text_input().stop();
                 }
-                curr_click_pos = get_mouse_position(mouse_state);
-
-                if let Some(piecetype) = prev_role_click {
-                    if let Some(end) = curr_click_pos {
-                        let full_mv = Movement {
-                            piecetype: piecetype,
-                            start: prev_click_pos,
-                            end: end,
-                            promotion: false,
-                            force_capture: false,
-                            withdraw: false,
-                            restart: false,
-                        };
-                        let full_mv_with_promotion = Movement {
-                            promotion: true,
-                            ..full_mv
-                        };
-                        let res1 = game.check_move(&full_mv.to_string()).is_ok();
-                        let mut res2 = game.check_move(&full_mv_with_promotion.to_string()).is_ok()
-                            && (full_mv_with_promotion.to_string() != full_mv.to_string());
-                        if let Some(pos) = prev_click_pos {
-                            if let Some(piece) = game.is_occupied_by(pos) {
-                                if piece.promoted {
-                                    //no need to buzz the player if the piece is already promoted
-                                    res2 = false;
-                                }
-                            }
-                        }
-                        //^ necessary as to_string 'ing drops with promotion delete the
-                        //(impossible) promotion
-
-                        let chosen_move;
-                        if res1 && !res2 {
-                            chosen_move = full_mv;
-                        } else if res2 && !res1 {
-                            chosen_move = full_mv_with_promotion;
-                        } else if res1 && res2 {
-                            //ask wether to promote
-                            let buttons: Vec<_> = vec![
-                                ButtonData {
-                                    flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT,
-                                    button_id: 1,
-                                    text: "Promote",
-                                },
-                                ButtonData {
-                                    flags: MessageBoxButtonFlag::NOTHING,
-                                    button_id: 2,
-                                    text: "Do not promote",
-                                },
-                            ];
-                            let res: ClickedButton = show_message_box(
-                                MessageBoxFlag::empty(),
-                                buttons.as_slice(),
-                                "",
-                                "Do you want to promote the piece ?",
-                                canvas.window(),
-                                None,
-                            )
-                            .unwrap();
-                            chosen_move = match res {
-                                ClickedButton::CloseButton => full_mv_with_promotion,
-                                ClickedButton::CustomButton(buttondata) => {
-                                    match buttondata.button_id {
-                                        1 => full_mv_with_promotion,
-                                        _ => full_mv,
-                                    }
-                                }
-                            };
-                        } else {
-                            chosen_move = full_mv; //to satisfy checker, but is caught later anyway
+            }
+            let command = match command {
+                Some(command) => command,
+                None => continue,
+            };
+            let mut dialogs = WindowDialogs {
+                window: canvas.window(),
+                recorder: recorder.as_mut(),
+            };
+            match game.command(command, &mut dialogs)? {
+                Reply::Done => {}
+                Reply::Ended(result) => ended = Some(result),
+                Reply::Next(restart) => next = Some(restart),
+                Reply::TogglePanel(wanted) => {
+                    let missing = match wanted {
+                        Panel::Book if book.is_none() => {
+                            Some("No opening book loaded, start shogui with --book <path>")
                         }
-
-                        curr_role_click = None;
-                        curr_click_pos = None;
-                        let mv = chosen_move.to_string();
-                        if game.check_move(&mv).is_ok() {
-                            *game = game.play_move_unchecked(&mv);
-                            curr_texture = &nothing;
-                            hidden = None;
-                            has_played = true;
-                        } else {
-                            hidden = None;
+                        Panel::Database if database.is_none() => {
+                            Some("No game database loaded, start shogui with --db <dir>")
                         }
+                        _ => None,
+                    };
+                    if let Some(missing) = missing {
+                        dialogs.tell("Study", missing, false)?;
+                        continue;
                     }
+                    *panel = if *panel == Some(wanted) {
+                        None
+                    } else {
+                        Some(wanted)
+                    };
+                    panel_position.clear();
+                    let width = if panel.is_some() {
+                        SCR_WIDTH + PANEL_WIDTH
+                    } else {
+                        SCR_WIDTH
+                    };
+                    canvas
+                        .window_mut()
+                        .set_size(width, SCR_HEIGHT)
+                        .map_err(|e| e.to_string())?;
                 }
+                Reply::ProblemChanged => *problem_changed = true,
+                Reply::Save => save_record(&game.state.record, canvas.window())?,
+                Reply::ExportDiagram => export_diagram(game.state, settings, canvas.window())?,
+                Reply::Settings => open_settings = true,
+                Reply::Menu => open_menu = true,
+                Reply::Quit => return Ok(Frame::Quit),
             }
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.sync(game.state, settings, true)?;
+        }
 
-            //drag effect
-            if let Some(_) = curr_role_click {
-                if let Some(pos) = curr_click_pos {
-                    //manage drag pieces from reserve
-                    if let Some(piece) = game.is_occupied_by(pos) {
-                        if piece.color == game.get_color() {
-                            let _ = canvas.copy(
-                                curr_texture,
-                                None,
-                                Rect::new(
-                                    mouse_state.x() as i32 - SQR_SIZE as i32 / 2,
-                                    mouse_state.y() as i32 - SQR_SIZE as i32 / 2,
-                                    SQR_SIZE,
-                                    SQR_SIZE,
-                                ),
-                            );
-                        }
-                    }
-                }
+        let mut dialogs = WindowDialogs {
+            window: canvas.window(),
+            recorder: None,
+        };
+        if *problem_changed {
+            *problem_changed = false;
+            *hint = None;
+            game.set_up_problem(&mut dialogs)?;
+        }
+        if let Some(found) = game.driver.hint() {
+            *hint = found;
+            if hint.is_none() {
+                let message = "No mate found from here, press R to start again";
+                dialogs.tell("Hint", message, false)?;
             }
-            if let Some(piecetype) = curr_role_click {
-                if let None = curr_click_pos {
-                    let drag_piece = Piece {
-                        color: game.get_color(),
-                        piecetype: piecetype,
-                        position: None,
-                        promoted: false,
-                    };
-                    curr_texture = piece_to_texture(&drag_piece);
-                    let _ = canvas.copy(
-                        curr_texture,
-                        None,
-                        Rect::new(
-                            mouse_state.x() as i32 - SQR_SIZE as i32 / 2,
-                            mouse_state.y() as i32 - SQR_SIZE as i32 / 2,
-                            SQR_SIZE,
-                            SQR_SIZE,
-                        ),
-                    );
-                }
+        }
+        if let Some(reply) = game.played(&mut dialogs)? {
+            *hint = None;
+            match reply {
+                Reply::Ended(result) => ended = Some(result),
+                Reply::ProblemChanged => *problem_changed = true,
+                _ => {}
             }
-        };
+        }
+        // the other side's moves and answers
+        if let Some(net) = game.net.as_deref_mut() {
+            let accept_draw = |message: &str| {
+                let choices = [String::from("Accept"), String::from("Decline")];
+                Ok(dialogs.ask("Draw offer", message, &choices)? == Some(0))
+            };
+            if let Some(result) = net.poll(game.state, accept_draw)? {
+                ended = Some(result);
+            }
+            if net.rematch() {
+                next = Some(Restart::Rematch);
+            }
+        }
+        if let Some(csa) = game.csa.as_deref_mut() {
+            if let Some(result) = csa.poll(game.state) {
+                ended = Some(result);
+            }
+        }
+        if let (Some(ended), None) = (ended, game.state.result) {
+            game.end(ended);
+        }
 
-        let human_turn = !game.get_turn();
-        if human_turn {
-            human_play(&mut game);
-        } else {
-            let mv = shogai::ai::greedy(&game);
-            game = game.play_move(&mv);
-            has_played = true;
+        if let Some(shown) = panel {
+            let position = sfen::to_sfen(&game.state.board, 1);
+            if position != *panel_position {
+                (*panel_rows, *panel_line) = panel_contents(
+                    *shown,
+                    &game.state.board,
+                    book.as_deref(),
+                    database.as_ref(),
+                    filter,
+                );
+                *panel_position = position;
+            }
+        }
+        let line = panel.map(|_| panel_line.as_str());
+        let title = game.title(&round.game_title, input.typing(), line);
+        if canvas.window().title() != title {
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|e| e.to_string())?;
         }
 
-        // for AI play, don't forget to  maybe multithread and make use of the has_played flag to
-        // reduce game_over checks
-        // ai should look like this
-        // if turn of AI {
-        // mv = ai guess move
-        // game.play(move)
-        // has_played = true
-        //}
+        if let Some(next) = next {
+            return Ok(Frame::Next(next));
+        }
 
-        draw_pieces(&mut canvas, &game, hidden);
-        canvas.present();
+        if let Some(ed) = game.editor.as_ref() {
+            renderer.draw_editor(canvas, ed, settings, input.mouse());
+            canvas.present();
+            return Ok(Frame::Continue);
+        }
 
-        // if you don't do this cpu usage will skyrocket to 100%
-        events.wait_event_timeout(10);
+        // nothing moves once the game is over, the player moves with the clicks
+        if let Some(result) = game.driver.update(game.state) {
+            round.declared = Some(result);
+        }
+        round.heard.update(game.state, settings, sounds.as_mut());
 
-        events.poll_event();
-        //draw_check(&game, &mut canvas);
+        // the opponent's moves of this frame
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.sync(game.state, settings, false)?;
+        }
+        let rows = panel.map(|_| panel_rows.as_slice());
+        // the marks of the position, and the one being drawn
+        let mut marks = game
+            .state
+            .record
+            .marks(game.state.record.moves.len())
+            .to_vec();
+        marks.extend(input.marking(settings.flip, context.keyboard().mod_state()));
+        let overlays = Overlays {
+            hint: hint.as_ref(),
+            panel: rows,
+            animation: round.heard.animation.as_ref(),
+            marks: &marks,
+        };
+        renderer.draw_game(canvas, game.state, settings, overlays, input.mouse())?;
+        if (open_settings || open_menu) && !ui.has_font() {
+            missing_font(settings, canvas.window())?;
+        } else if open_settings {
+            if edit_settings(settings, ui, canvas, events)? {
+                if let Err(e) = settings.save() {
+                    show_simple_message_box(
+                        MessageBoxFlag::WARNING,
+                        "Settings",
+                        &format!("Could not save the settings: {}", e),
+                        canvas.window(),
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+            }
+        } else if open_menu {
+            run_menu(ui, canvas, events, context)?;
+        }
+        canvas.present();
+        Ok(Frame::Continue)
     }
-
-    Ok(())
 }
 
 //-----------------------------------------------------------------------------------
 //
 
-fn get_side(mouse_state: sdl2::mouse::MouseState) -> Option<shogai::piece::Color> {
-    if mouse_state.y() <= SRC_RESERVE_HEIGTH as i32 {
-        return Some(shogai::piece::Color::White);
-    }
-    if mouse_state.y() >= SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32 {
-        return Some(shogai::piece::Color::Black);
+/// the menu bar, opened over the board; the command chosen is run as if its key was pressed
+fn run_menu(
+    ui: &mut Ui,
+    canvas: &mut Canvas<Window>,
+    events: &mut EventPump,
+    context: &sdl2::Sdl,
+) -> Result<(), String> {
+    let menus: Vec<Menu> = MENUS
+        .iter()
+        .map(|(title, items)| Menu {
+            title: title.to_string(),
+            items: items.iter().map(|(item, ..)| item.to_string()).collect(),
+        })
+        .collect();
+    if let Some((menu, item)) = ui.menu(canvas, events, &menus)? {
+        let (_, keycode, ctrl) = MENUS[menu].1[item];
+        context.event()?.push_event(Event::KeyDown {
+            timestamp: 0,
+            window_id: canvas.window().id(),
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: if ctrl { Mod::LCTRLMOD } else { Mod::NOMOD },
+            repeat: false,
+        })?;
     }
-    return None;
+    Ok(())
 }
 
-fn get_in_reserve(mouse_state: sdl2::mouse::MouseState, game: &Board) -> Option<PieceType> {
-    if Some(game.get_color()) != get_side(mouse_state) {
-        return None;
-    } else {
-        // in reserve
-        match mouse_state.x() * 9 / SCR_WIDTH as i32 {
-            0 => Some(PieceType::Pawn),
-            1 => Some(PieceType::Pawn),
-            2 => Some(PieceType::Pawn),
-            3 => Some(PieceType::Knight),
-            4 => Some(PieceType::Lance),
-            5 => Some(PieceType::Rook),
-            6 => Some(PieceType::Bishop),
-            7 => Some(PieceType::Gold),
-            8 => Some(PieceType::Silver),
-            _ => None,
+/// show a message box with one button per choice, return the index of the clicked one (None
+/// if the box was closed)
+fn ask(
+    window: &Window,
+    title: &str,
+    message: &str,
+    choices: &[String],
+) -> Result<Option<usize>, String> {
+    let buttons: Vec<_> = choices
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| ButtonData {
+            flags: if i == 0 {
+                MessageBoxButtonFlag::RETURNKEY_DEFAULT
+            } else {
+                MessageBoxButtonFlag::NOTHING
+            },
+            button_id: i as i32,
+            text: choice,
+        })
+        .collect();
+    let res = show_message_box(
+        MessageBoxFlag::empty(),
+        buttons.as_slice(),
+        title,
+        message,
+        window,
+        None,
+    )
+    .map_err(|e| format!("{:?}", e))?;
+    Ok(match res {
+        ClickedButton::CustomButton(buttondata) => Some(buttondata.button_id as usize),
+        ClickedButton::CloseButton => None,
+    })
+}
+
+/// ask whether to promote the piece moved (closing the box promotes it)
+/// the message boxes of the window, for the controller; the promotions asked are recorded
+struct WindowDialogs<'a> {
+    window: &'a Window,
+    recorder: Option<&'a mut Recorder>,
+}
+
+impl Dialogs for WindowDialogs<'_> {
+    fn ask(
+        &mut self,
+        title: &str,
+        message: &str,
+        choices: &[String],
+    ) -> Result<Option<usize>, String> {
+        ask(self.window, title, message, choices)
+    }
+
+    fn tell(&mut self, title: &str, message: &str, warning: bool) -> Result<(), String> {
+        let flags = if warning {
+            MessageBoxFlag::WARNING
+        } else {
+            MessageBoxFlag::empty()
+        };
+        show_simple_message_box(flags, title, message, self.window).map_err(|e| e.to_string())
+    }
+
+    fn promote(&mut self) -> Result<bool, String> {
+        let promote = ask_promotion(self.window);
+        if let Some(recorder) = self.recorder.as_deref_mut() {
+            recorder.promotion(promote)?;
         }
+        Ok(promote)
     }
 }
 
-fn draw_piece(canvas: &mut Canvas<Window>, texture: &Texture, i: Position) {
-    canvas
-        .copy(
-            texture,
-            None,
-            Rect::new(
-                ((9 - (i.0 as u32 % 9) - 1) * SQR_SIZE) as i32,
-                (i.0 as u32 / 9 * SQR_SIZE) as i32 + SRC_RESERVE_HEIGTH as i32,
-                SQR_SIZE,
-                SQR_SIZE,
-            ),
+fn ask_promotion(window: &Window) -> bool {
+    let buttons = [
+        ButtonData {
+            flags: MessageBoxButtonFlag::RETURNKEY_DEFAULT,
+            button_id: 1,
+            text: "Promote",
+        },
+        ButtonData {
+            flags: MessageBoxButtonFlag::NOTHING,
+            button_id: 2,
+            text: "Do not promote",
+        },
+    ];
+    match show_message_box(
+        MessageBoxFlag::empty(),
+        &buttons,
+        "",
+        "Do you want to promote the piece ?",
+        window,
+        None,
+    ) {
+        Ok(ClickedButton::CustomButton(button)) => button.button_id == 1,
+        _ => true,
+    }
+}
+
+/// whether the user closed the window or pressed Escape
+fn quit_requested(events: &mut EventPump) -> bool {
+    events.poll_iter().any(|event| {
+        matches!(
+            event,
+            Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
         )
-        .unwrap();
+    })
 }
 
-fn draw_piece_on_reserve(
+/// host or join a network game and agree on it with the other side, None if the user gave up
+/// waiting
+fn connect(
+    network: &Network,
+    args: &Args,
+    palette: &Palette,
     canvas: &mut Canvas<Window>,
-    texture: &Texture,
-    piece: &Piece,
-    count: usize,
-) {
-    let mut x = 2 * match piece.piecetype {
-        PieceType::Pawn => 0,
-        PieceType::Knight => 3,
-        PieceType::Lance => 4,
-        PieceType::Rook => 5,
-        PieceType::Bishop => 6,
-        PieceType::Gold => 7,
-        PieceType::Silver => 8,
-        PieceType::King => panic!("King was found in reserve, what kind of shit is this?"),
+    events: &mut EventPump,
+) -> Result<Option<NetGame>, String> {
+    let mut connection = match network {
+        Network::Host(port) => {
+            let host = Host::listen(*port)?;
+            let title = format!("Shogi - waiting for an opponent on port {}", host.port());
+            canvas
+                .window_mut()
+                .set_title(&title)
+                .map_err(|e| e.to_string())?;
+            loop {
+                if quit_requested(events) {
+                    return Ok(None);
+                }
+                if let Some(connection) = host.accept()? {
+                    break connection;
+                }
+                canvas.set_draw_color(rgb(palette.board));
+                canvas.clear();
+                canvas.present();
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        Network::Join(address) => net::join(address)?,
     };
-    let spacing_multiplier = 10; //pixels per identical pieces
-    let y: usize;
-    if piece.color == shogai::piece::Color::White {
-        y = (count % 4) * spacing_multiplier;
-    } else {
-        y = SCR_HEIGHT as usize - SQR_SIZE as usize - (count % 4) * spacing_multiplier;
+
+    let hosting = matches!(network, Network::Host(_));
+    connection.send(&Message::Hello {
+        version: net::VERSION,
+        name: args.player_name(),
+    })?;
+    let mut game = None;
+    if hosting {
+        let human = args.human;
+        let handicap = args.handicap.unwrap_or(Handicap::Even);
+        let control = args.time.unwrap_or_default();
+        connection.send(&Message::Game {
+            guest: rules::opponent(human),
+            control,
+            rule: args.impasse,
+            sfen: handicap.sfen(),
+        })?;
+        game = Some((human, handicap, handicap.board(), control, args.impasse));
     }
-    if x == 0 {
-        //only for pawns as there can be many pawns
-        x += count / 4;
+
+    let mut peer = None;
+    while peer.is_none() || game.is_none() {
+        if quit_requested(events) {
+            let _ = connection.send(&Message::Bye);
+            return Ok(None);
+        }
+        match connection.receive(Duration::from_millis(50)) {
+            Some(Ok(Message::Hello { version, name })) => {
+                if version != net::VERSION {
+                    let _ = connection.send(&Message::Error(String::from("protocol version")));
+                    return Err(format!(
+                        "{} uses version {} of the protocol, not {}",
+                        name,
+                        version,
+                        net::VERSION
+                    ));
+                }
+                peer = Some(name);
+            }
+            Some(Ok(Message::Game {
+                guest,
+                control,
+                rule,
+                sfen,
+            })) if !hosting => {
+                let (start, _) = sfen::from_sfen(&sfen)?;
+                let handicap = Handicap::all()
+                    .iter()
+                    .copied()
+                    .find(|h| h.sfen() == sfen)
+                    .unwrap_or(Handicap::Even);
+                game = Some((guest, handicap, start, control, rule));
+            }
+            Some(Ok(Message::Error(e))) => return Err(format!("the other side refused: {}", e)),
+            Some(Ok(message)) => return Err(format!("unexpected message: {}", message)),
+            Some(Err(e)) => return Err(e),
+            None if connection.is_closed() => return Err(String::from("connection closed")),
+            None => {}
+        }
     }
+
+    let (human, handicap, start, control, rule) = game.unwrap();
+    Ok(Some(NetGame {
+        connection,
+        peer: peer.unwrap_or_default(),
+        human,
+        handicap,
+        start,
+        control,
+        rule,
+        status: String::new(),
+        error: None,
+        rematch_sent: false,
+        rematch_received: false,
+    }))
+}
+
+/// log in to a CSA server and wait for a game there, None if the user gave up waiting
+fn connect_csa(
+    server: &str,
+    args: &Args,
+    palette: &Palette,
+    canvas: &mut Canvas<Window>,
+    events: &mut EventPump,
+) -> Result<Option<CsaGame>, String> {
+    let password = args.password.as_deref().unwrap_or("");
+    let client = Client::login(server, &args.player_name(), password)?;
+    next_csa_game(client, palette, canvas, events)
+}
+
+/// wait for the server to offer a game and agree to it, None if the user gave up waiting
+fn next_csa_game(
+    mut client: Client,
+    palette: &Palette,
+    canvas: &mut Canvas<Window>,
+    events: &mut EventPump,
+) -> Result<Option<CsaGame>, String> {
+    let title = format!("Shogi - {} waiting for a game", client.name());
     canvas
-        .copy(
-            texture,
-            None,
-            Rect::new(
-                x as i32 * SCR_WIDTH as i32 / 9 / 2,
-                y as i32,
-                SQR_SIZE,
-                SQR_SIZE,
-            ),
+        .window_mut()
+        .set_title(&title)
+        .map_err(|e| e.to_string())?;
+
+    let mut summary = None;
+    loop {
+        if quit_requested(events) {
+            let _ = client.logout();
+            return Ok(None);
+        }
+        for event in client.poll()? {
+            match event {
+                csa::Event::Summary(offered) => {
+                    client.agree(&offered)?;
+                    summary = Some(*offered);
+                }
+                csa::Event::Start => {
+                    if let Some(summary) = summary {
+                        return Ok(Some(CsaGame {
+                            client,
+                            summary,
+                            used: [Duration::from_secs(0); 2],
+                            status: String::new(),
+                            error: None,
+                        }));
+                    }
+                }
+                // the other side didn't agree, wait for the next game
+                csa::Event::Reject => summary = None,
+                _ => {}
+            }
+        }
+        canvas.set_draw_color(rgb(palette.board));
+        canvas.clear();
+        canvas.present();
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// ask which opponent to play against, among the built-in ones of the settings and the
+/// registered USI engines
+fn choose_level(window: &Window, settings: &Settings) -> Result<Computer, String> {
+    let levels = if settings.engines.is_empty() {
+        Level::all().to_vec()
+    } else {
+        settings.engines.clone()
+    };
+    let computers: Vec<Computer> = levels
+        .into_iter()
+        .map(Computer::Level)
+        .chain(
+            settings
+                .usi_engines
+                .iter()
+                .map(|engine| Computer::Usi(engine.name.clone())),
         )
-        .unwrap();
+        .collect();
+    let names: Vec<String> = computers.iter().map(|c| c.to_string()).collect();
+    let choice = ask(
+        window,
+        "New game",
+        "Choose the strength of your opponent",
+        &names,
+    )?;
+    Ok(choice.map_or(Computer::Level(settings.level), |i| computers[i].clone()))
+}
+
+/// the opponent of a local game; a USI engine that can't be started is replaced by the default
+/// built-in opponent
+fn build_computer(
+    computer: &Computer,
+    settings: &Settings,
+    args: &Args,
+    window: &Window,
+) -> Result<Box<dyn Opponent>, String> {
+    let name = match computer {
+        Computer::Level(level) => return Ok(level.build(args.limits(*level))),
+        Computer::Usi(name) => name,
+    };
+    let limits = Limits {
+        movetime: args.movetime,
+        nodes: args.nodes,
+    };
+    let started = settings
+        .usi_engines
+        .iter()
+        .find(|engine| &engine.name == name)
+        .ok_or_else(|| String::from("it is not registered anymore"))
+        .and_then(|engine| UsiEngine::start(engine, limits));
+    match started {
+        Ok(engine) => Ok(Box::new(engine)),
+        Err(e) => {
+            show_simple_message_box(
+                MessageBoxFlag::WARNING,
+                "Engine",
+                &format!(
+                    "Could not start {}: {}\n{} plays instead",
+                    name, e, settings.level
+                ),
+                window,
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(settings.level.build(args.limits(settings.level)))
+        }
+    }
 }
 
-fn draw_shogiban(canvas: &mut Canvas<Window>) {
-    draw_grid(canvas);
+/// the dialogs are drawn with a font, say where to give one when none was found
+fn missing_font(settings: &Settings, window: &Window) -> Result<(), String> {
+    let message = format!(
+        "No font found for the dialogs, add the line \"font = <path to a .ttf file>\" to {}",
+        settings
+            .path()
+            .map_or(String::from("the settings file"), |path| path
+                .display()
+                .to_string())
+    );
+    show_simple_message_box(MessageBoxFlag::WARNING, "Settings", &message, window)
+        .map_err(|e| e.to_string())
+}
 
-    canvas.set_draw_color(Color::RGB(0x75, 0x48, 0x3B));
-    let _ = canvas.fill_rect(Rect::new(0, 0, SCR_WIDTH, SRC_RESERVE_HEIGTH));
-    let _ = canvas.fill_rect(Rect::new(
-        0,
-        SCR_HEIGHT as i32 - SRC_RESERVE_HEIGTH as i32,
-        SCR_WIDTH,
-        SRC_RESERVE_HEIGTH,
-    ));
+/// how the settings dialog was left
+enum SettingsOutcome {
+    Save,
+    Cancel,
+    Engines,
+}
+
+/// width of the labels left of the settings
+const LABEL_WIDTH: u32 = 160;
+
+/// the volumes offered, in percent
+const VOLUMES: [u8; 4] = [25, 50, 80, 100];
+
+/// the durations of the animations offered, in milliseconds
+const ANIMATIONS: [(&str, u64); 3] = [("fast", 100), ("normal", 200), ("slow", 400)];
+
+/// the settings screen, changing a copy of the settings which replaces them on Save; true then
+fn edit_settings(
+    settings: &mut Settings,
+    ui: &mut Ui,
+    canvas: &mut Canvas<Window>,
+    events: &mut EventPump,
+) -> Result<bool, String> {
+    let mut draft = settings.clone();
+    let mut time = draft.time.to_string();
+    // (as many as fit in a row)
+    let sets: Vec<String> = sound::sets().into_iter().take(4).collect();
+    loop {
+        let outcome = ui.dialog(
+            canvas,
+            events,
+            "Settings",
+            (480, 566),
+            |ui, canvas, rect| {
+                let mut rows = Rows::new(rect);
+
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Theme")?;
+                let themes = Theme::all();
+                for (theme, cell) in themes.iter().zip(columns(rest, themes.len() as u32)) {
+                    let key = format!("theme.{}", theme);
+                    if ui.choice(
+                        canvas,
+                        &key,
+                        cell,
+                        &theme.to_string(),
+                        draft.theme == *theme,
+                    )? {
+                        draft.theme = *theme;
+                    }
+                }
+                let text = "Board turned around (gote at the bottom)";
+                ui.checkbox(canvas, "flip", rows.row(), text, &mut draft.flip)?;
+                let text = "Highlight the selected piece";
+                ui.checkbox(
+                    canvas,
+                    "select",
+                    rows.row(),
+                    text,
+                    &mut draft.highlight_select,
+                )?;
+                let text = "Highlight the last move";
+                let value = &mut draft.highlight_last_move;
+                ui.checkbox(canvas, "last_move", rows.row(), text, value)?;
+
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Move pieces by")?;
+                let cells = columns(rest, 2);
+                let click = draft.input == InputMode::Click;
+                if ui.choice(canvas, "input.click", cells[0], "clicking", click)? {
+                    draft.input = InputMode::Click;
+                }
+                if ui.choice(canvas, "input.drag", cells[1], "dragging", !click)? {
+                    draft.input = InputMode::Drag;
+                }
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Sounds")?;
+                let cells = columns(rest, VOLUMES.len() as u32 + 1);
+                let mut mute = !draft.sounds;
+                if ui.checkbox(canvas, "mute", cells[0], "mute", &mut mute)? {
+                    draft.sounds = !mute;
+                }
+                for (volume, cell) in VOLUMES.iter().zip(&cells[1..]) {
+                    let key = format!("volume.{}", volume);
+                    let text = format!("{}%", volume);
+                    if ui.choice(canvas, &key, *cell, &text, draft.volume == *volume)? {
+                        draft.volume = *volume;
+                        draft.sounds = true;
+                    }
+                }
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Sound set")?;
+                for (set, cell) in sets.iter().zip(columns(rest, sets.len() as u32)) {
+                    let key = format!("sound_set.{}", set);
+                    if ui.choice(canvas, &key, cell, set, draft.sound_set == *set)? {
+                        draft.sound_set = set.clone();
+                    }
+                }
+
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Pieces slide")?;
+                for ((name, ms), cell) in ANIMATIONS.iter().zip(columns(rest, 3)) {
+                    let key = format!("animation.{}", name);
+                    let chosen = draft.animation == Duration::from_millis(*ms);
+                    if ui.choice(canvas, &key, cell, name, chosen)? {
+                        draft.animation = Duration::from_millis(*ms);
+                    }
+                }
+                let text = "Reduce motion (pieces jump where they go)";
+                let value = &mut draft.reduce_motion;
+                ui.checkbox(canvas, "reduce_motion", rows.row(), text, value)?;
+
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Clock of hosted games")?;
+                let (field, hint) = split(rest, 100);
+                ui.text_field(canvas, "time", field, &mut time)?;
+                let control = time.trim().parse::<TimeControl>();
+                match control {
+                    Ok(_) => ui.label(canvas, hint, "main+byoyomi, in seconds")?,
+                    Err(_) => ui.error(canvas, hint, "expected <main>+<byoyomi>")?,
+                }
+
+                ui.label(canvas, rows.row(), "Opponents offered")?;
+                let levels = Level::all();
+                for (level, cell) in levels.iter().zip(columns(rows.row(), levels.len() as u32)) {
+                    let mut offered = draft.engines.contains(level);
+                    let key = format!("offered.{}", level);
+                    if !ui.checkbox(canvas, &key, cell, &level.to_string(), &mut offered)? {
+                        continue;
+                    }
+                    if offered {
+                        draft.engines = levels
+                            .iter()
+                            .filter(|l| *l == level || draft.engines.contains(l))
+                            .copied()
+                            .collect();
+                    } else if draft.engines.len() > 1 {
+                        // (there has to be someone to play with)
+                        draft.engines.retain(|l| l != level);
+                    }
+                    if !draft.engines.contains(&draft.level) {
+                        draft.level = draft.engines[0];
+                    }
+                }
+                let (label, rest) = split(rows.row(), LABEL_WIDTH);
+                ui.label(canvas, label, "Default opponent")?;
+                let offered = draft.engines.clone();
+                for (level, cell) in offered.iter().zip(columns(rest, offered.len() as u32)) {
+                    let key = format!("level.{}", level);
+                    if ui.choice(
+                        canvas,
+                        &key,
+                        cell,
+                        &level.to_string(),
+                        draft.level == *level,
+                    )? {
+                        draft.level = *level;
+                    }
+                }
+                let engines = match draft.usi_engines.len() {
+                    0 => String::from("No USI engine registered"),
+                    1 => String::from("1 USI engine registered"),
+                    n => format!("{} USI engines registered", n),
+                };
+                ui.label(canvas, rows.row(), &engines)?;
+
+                let bottom = Rect::new(rect.x(), rect.bottom() - ROW as i32, rect.width(), ROW);
+                let cells = columns(bottom, 3);
+                if ui.button(canvas, "engines", cells[0], "Engines...")? {
+                    return Ok(Some(SettingsOutcome::Engines));
+                }
+                if ui.button(canvas, "save", cells[1], "Save")? {
+                    if let Ok(control) = control {
+                        draft.time = control;
+                        return Ok(Some(SettingsOutcome::Save));
+                    }
+                }
+                if ui.button(canvas, "cancel", cells[2], "Cancel")? {
+                    return Ok(Some(SettingsOutcome::Cancel));
+                }
+                Ok(None)
+            },
+        )?;
+        match outcome {
+            Some(SettingsOutcome::Save) => {
+                *settings = draft;
+                return Ok(true);
+            }
+            Some(SettingsOutcome::Engines) => edit_engines(&mut draft, ui, canvas, events)?,
+            Some(SettingsOutcome::Cancel) | None => return Ok(false),
+        }
+    }
 }
 
-// from: https://www.libsdl.org/tmp/SDL/test/testdrawchessboard.c
-// adapted for shogi
-fn draw_grid(canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(0xFF, 0xCE, 0x9E));
-    let mut row = 0;
+/// the registered USI engines: their options and presets, or removing them
+fn edit_engines(
+    settings: &mut Settings,
+    ui: &mut Ui,
+    canvas: &mut Canvas<Window>,
+    events: &mut EventPump,
+) -> Result<(), String> {
+    let mut list = ListState {
+        selected: Some(0).filter(|_| !settings.usi_engines.is_empty()),
+        ..ListState::default()
+    };
+    // the options of the selected engine, asked to it when it is selected
+    let mut info: Option<(usize, Result<EngineInfo, String>)> = None;
+    // what is typed in the text fields of the options
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut scroll = Scroll::default();
+    let mut preset = String::new();
+    ui.dialog(canvas, events, "Engines", (590, 640), |ui, canvas, rect| {
+        let (left, right) = split(rect, 160);
+
+        let mut rows = Rows::new(left);
+        let names: Vec<String> = settings
+            .usi_engines
+            .iter()
+            .map(|engine| engine.name.clone())
+            .collect();
+        let height = left.height().saturating_sub(2 * (ROW + PADDING as u32));
+        ui.list(canvas, "engines", rows.row_of(height), &names, &mut list)?;
+        if ui.button(canvas, "remove", rows.row(), "Remove")? {
+            if let Some(i) = list.selected.take() {
+                settings.usi_engines.remove(i);
+                info = None;
+            }
+        }
+        if ui.button(canvas, "done", rows.row(), "Done")? {
+            return Ok(Some(()));
+        }
 
-    while row < 9 {
-        let mut x = row % 2;
+        match list.selected {
+            Some(i) if info.as_ref().map(|(shown, _)| *shown) != Some(i) => {
+                info = Some((i, usi::discover(&settings.usi_engines[i])));
+                fields.clear();
+                scroll = Scroll::default();
+            }
+            Some(_) => {}
+            None => info = None,
+        }
 
-        for _ in (row % 2)..(5 + (row % 2)) {
-            let rect = Rect::new(
-                x * SQR_SIZE as i32,
-                row * SQR_SIZE as i32 + SRC_RESERVE_HEIGTH as i32,
-                SQR_SIZE,
-                SQR_SIZE,
-            );
-            x += 2;
+        let mut rows = Rows::new(right);
+        let (i, info) = match &info {
+            Some((i, Ok(info))) => (*i, info),
+            Some((_, Err(e))) => {
+                ui.error(canvas, rows.row(), "Could not start the engine:")?;
+                ui.error(canvas, rows.row(), e)?;
+                return Ok(None);
+            }
+            None if names.is_empty() => {
+                ui.label(canvas, rows.row(), "No USI engine registered yet,")?;
+                let text = "add one with shogui --add-engine <path>";
+                ui.label(canvas, rows.row(), text)?;
+                return Ok(None);
+            }
+            None => {
+                ui.label(canvas, rows.row(), "Choose an engine")?;
+                return Ok(None);
+            }
+        };
+        let engine = &mut settings.usi_engines[i];
+        ui.label(canvas, rows.row(), &info.to_string())?;
+        ui.label(canvas, rows.row(), &engine.path.display().to_string())?;
 
-            let _ = canvas.fill_rect(rect);
+        // the options as they are now can be saved under a name, and used again later
+        let (field, save) = split(rows.row(), right.width().saturating_sub(110));
+        ui.text_field(canvas, "preset", field, &mut preset)?;
+        if ui.button(canvas, "preset.save", save, "Save preset")? && !preset.trim().is_empty() {
+            engine.save_preset(preset.trim());
+            preset.clear();
         }
+        let presets: Vec<String> = engine.presets.iter().map(|p| p.name.clone()).collect();
+        if !presets.is_empty() {
+            for (name, cell) in presets
+                .iter()
+                .zip(columns(rows.row(), presets.len() as u32))
+            {
+                if ui.button(canvas, &format!("preset.{}", name), cell, name)? {
+                    engine.load_preset(name);
+                    fields.clear();
+                }
+            }
+        }
+
+        let area = rows.rest();
+        let row_height = ROW as i32 + PADDING;
+        let height = info.options.len() as u32 * row_height as u32;
+        ui.scroll_area(canvas, area, height, &mut scroll, |ui, canvas, top| {
+            for (n, option) in info.options.iter().enumerate() {
+                let y = top + n as i32 * row_height;
+                let row = Rect::new(area.x(), y, area.width().saturating_sub(8), ROW);
+                if row.has_intersection(area) {
+                    edit_option(ui, canvas, row, engine, option, &mut fields)?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(None)
+    })?;
+    Ok(())
+}
 
-        row += 1;
+/// the widget an engine option calls for, its name on its left
+fn edit_option(
+    ui: &mut Ui,
+    canvas: &mut Canvas<Window>,
+    row: Rect,
+    engine: &mut EngineConfig,
+    option: &UsiOption,
+    fields: &mut HashMap<String, String>,
+) -> Result<(), String> {
+    let (label, rest) = split(row, row.width() / 2);
+    ui.label(canvas, label, &option.name)?;
+    let key = format!("option.{}", option.name);
+    let current = engine
+        .option(&option.name)
+        .map(|value| value.to_string())
+        .or_else(|| option.default_value());
+    let value = match &option.kind {
+        OptionKind::Check { .. } => {
+            let mut on = current.as_deref() == Some("true");
+            if !ui.checkbox(canvas, &key, rest, "", &mut on)? {
+                return Ok(());
+            }
+            Some(on.to_string())
+        }
+        OptionKind::Spin { min, max, .. } => {
+            let (field, hint) = split(rest, 100);
+            let text = fields
+                .entry(key.clone())
+                .or_insert_with(|| current.unwrap_or_default());
+            let changed = ui.text_field(canvas, &key, field, text)?;
+            let value = text.trim().parse::<i64>().ok();
+            let value = value.filter(|value| (*min..=*max).contains(value));
+            let range = format!("{} to {}", min, max);
+            match value {
+                Some(value) => {
+                    ui.label(canvas, hint, &range)?;
+                    if !changed {
+                        return Ok(());
+                    }
+                    Some(value.to_string())
+                }
+                None => return ui.error(canvas, hint, &range),
+            }
+        }
+        OptionKind::Combo { vars, .. } => {
+            let shown = current.unwrap_or_default();
+            if !ui.button(canvas, &key, rest, &shown)? {
+                return Ok(());
+            }
+            // the next value
+            let next = vars
+                .iter()
+                .position(|var| *var == shown)
+                .map_or(0, |i| (i + 1) % vars.len());
+            vars.get(next).cloned()
+        }
+        OptionKind::Button => {
+            let mut pressed = engine.option(&option.name).is_some();
+            if !ui.checkbox(canvas, &key, rest, "press at start", &mut pressed)? {
+                return Ok(());
+            }
+            Some(String::new()).filter(|_| pressed)
+        }
+        OptionKind::String { .. } | OptionKind::Filename { .. } => {
+            let text = fields
+                .entry(key.clone())
+                .or_insert_with(|| current.unwrap_or_default());
+            if !ui.text_field(canvas, &key, rest, text)? {
+                return Ok(());
+            }
+            Some(text.clone())
+        }
+    };
+    // the engine's own value doesn't need to be given
+    let value = value.filter(|value| Some(value) != option.default_value().as_ref());
+    engine.set_option(&option.name, value);
+    Ok(())
+}
+
+/// ask for the kind of game: even, handicap (and who gives it) or from a position to set up
+fn choose_game(window: &Window) -> Result<NewGame, String> {
+    let even = NewGame {
+        handicap: Handicap::Even,
+        human: shogai::piece::Color::Black,
+        edit: false,
+    };
+    let kinds = vec![
+        String::from("Even game"),
+        String::from("Handicap game"),
+        String::from("Set up a position"),
+    ];
+    match ask(window, "New game", "Choose the kind of game", &kinds)? {
+        Some(1) => {}
+        Some(2) => return Ok(NewGame { edit: true, ..even }),
+        _ => return Ok(even),
     }
+
+    // too many handicaps for a single row of buttons
+    let small = &Handicap::all()[1..6];
+    let large = &Handicap::all()[6..];
+    let mut names: Vec<String> = small.iter().map(|h| h.to_string()).collect();
+    names.push(String::from("more..."));
+    let handicap = match ask(window, "Handicap", "Choose the pieces removed", &names)? {
+        Some(i) if i < small.len() => small[i],
+        Some(_) => {
+            let names: Vec<String> = large.iter().map(|h| h.to_string()).collect();
+            match ask(window, "Handicap", "Choose the pieces removed", &names)? {
+                Some(i) => large[i],
+                None => return Ok(even),
+            }
+        }
+        None => return Ok(even),
+    };
+
+    let givers = vec![String::from("The computer"), String::from("Me")];
+    let human = match ask(window, "Handicap", "Who gives the handicap?", &givers)? {
+        // the giver (uwate) is shogai's Black
+        Some(1) => shogai::piece::Color::Black,
+        _ => shogai::piece::Color::White,
+    };
+    Ok(NewGame {
+        handicap,
+        human,
+        edit: false,
+    })
 }
 
-//----------------------------------------------------------------
+/// save the game as KIF, CSA and as a USI position line next to them
+fn save_record(record: &GameRecord, window: &Window) -> Result<(), String> {
+    let message = match record
+        .save_kif("game.kif")
+        .and_then(|_| record.save_csa("game.csa"))
+        .and_then(|_| std::fs::write("game.sfen", record.usi_position() + "\n"))
+    {
+        Ok(()) => String::from("Game saved to game.kif, game.csa and game.sfen"),
+        Err(e) => format!("Could not save the game: {}", e),
+    };
+    show_simple_message_box(MessageBoxFlag::empty(), "Save", &message, window)
+        .map_err(|e| e.to_string())
+}
 
-fn draw_select(p: Position, canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGB(5, 150, 5));
-    let x = (8 - p.0 % 9) * SQR_SIZE as u16;
-    let y = p.0 / 9 * SQR_SIZE as u16 + SRC_RESERVE_HEIGTH as u16;
-    let _ = canvas.fill_rect(Rect::new(x as i32, y as i32, SQR_SIZE, SQR_SIZE));
+/// the position as diagram.svg and diagram.png, the last move highlighted
+fn export_diagram(state: &GameState, settings: &Settings, window: &Window) -> Result<(), String> {
+    let mut diagram = Diagram::new(state.board.clone());
+    diagram.flip = settings.flip;
+    if let Some(mv) = state.record.moves.last() {
+        let movement = sfen::parse_movement(mv)?;
+        diagram.highlights = movement
+            .start
+            .into_iter()
+            .chain(Some(movement.end))
+            .collect();
+    }
+    // the marks, without their colours
+    for mark in state.record.marks(state.record.moves.len()) {
+        match *mark {
+            Mark::Arrow(from, to, _) => diagram.arrows.push((from, to)),
+            Mark::Circle(at, _) => diagram.highlights.push(at),
+        }
+    }
+    let record = &state.record;
+    diagram.caption = Some(format!(
+        "☗{} ☖{}, {} moves",
+        record.sente,
+        record.gote,
+        record.moves.len()
+    ));
+    let message = match diagram
+        .save_svg("diagram.svg")
+        .and_then(|_| headless::save_png(&headless::render(state, settings)?, "diagram.png"))
+    {
+        Ok(()) => String::from("Diagram saved to diagram.svg and diagram.png"),
+        Err(e) => format!("Could not save the diagram: {}", e),
+    };
+    show_simple_message_box(MessageBoxFlag::empty(), "Diagram", &message, window)
+        .map_err(|e| e.to_string())
+}
+
+/// the rows of the panel for the position, and its title line: "book: ７六歩 60%, ２六歩 40%",
+/// "database, 12 games: ７六歩 8 games: sente 50%..."
+fn panel_contents(
+    panel: Panel,
+    game: &Board,
+    book: Option<&Book>,
+    database: Option<&Database>,
+    filter: &Filter,
+) -> (Vec<PanelRow>, String) {
+    match panel {
+        Panel::Book => {
+            let moves = book.map_or(Vec::new(), |book| book.moves(game));
+            if moves.is_empty() {
+                return (Vec::new(), String::from("out of book"));
+            }
+            let total: u32 = moves.iter().map(|(_, count)| count).sum();
+            let line: Vec<String> = moves
+                .iter()
+                .map(|(mv, count)| {
+                    let notation =
+                        kif::move_notation(game, mv, None).unwrap_or_else(|_| mv.clone());
+                    // (the origin square is only for the record)
+                    let notation = notation.split('(').next().unwrap_or_default().to_string();
+                    format!("{} {}%", notation, count * 100 / total.max(1))
+                })
+                .collect();
+            let rows = moves
+                .into_iter()
+                .map(|(mv, count)| PanelRow {
+                    mv,
+                    bar: vec![(count, Color::RGB(60, 110, 200))],
+                })
+                .collect();
+            (rows, format!("book: {}", line.join(", ")))
+        }
+        Panel::Database => {
+            let database = match database {
+                Some(database) => database,
+                None => return (Vec::new(), String::new()),
+            };
+            let games = database.search(game, filter).len();
+            let stats = database.next_moves(game, filter);
+            let line: Vec<String> = stats.iter().map(|s| s.describe(game)).collect();
+            let rows = stats
+                .into_iter()
+                .map(|s| {
+                    let unknown = s.games - s.sente_wins - s.gote_wins - s.draws;
+                    PanelRow {
+                        bar: vec![
+                            (s.sente_wins, Color::RGB(0xE0, 0xB0, 0x30)),
+                            (s.draws, Color::RGB(0x90, 0x90, 0x90)),
+                            (s.gote_wins, Color::RGB(0xC0, 0x40, 0x40)),
+                            (unknown, Color::RGB(60, 110, 200)),
+                        ],
+                        mv: s.mv,
+                    }
+                })
+                .collect();
+            let line = format!("database, {} games: {}", games, line.join(" / "));
+            (rows, line)
+        }
+    }
 }
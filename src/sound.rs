@@ -0,0 +1,367 @@
+// The sounds of the game: the pieces put down (the "pachi" of a piece on a board), taken,
+// promoted, a check, the end of the game and the last seconds of the clock counted down.
+//
+// A sound set is a directory of the config directory's `sounds/` (sounds/<set>/place.wav...)
+// with a file per effect: place, capture, check, promotion, game_end, countdown_1 to countdown_10
+// (a voice reading the seconds) and beep (for the seconds without a voice). Effects without a
+// file are the built-in ones, synthesized, which also make the "synth" set. SDL plays WAV files;
+// with the `mixer` feature SDL2_mixer plays OGG files too, and the sounds of a move over each
+// other rather than mixed together first.
+
+use crate::rules;
+use crate::settings::{config_dir, Settings};
+
+#[cfg(not(feature = "mixer"))]
+use sdl2::audio::{AudioCVT, AudioFormat, AudioQueue, AudioSpecDesired, AudioSpecWAV};
+#[cfg(feature = "mixer")]
+use sdl2::mixer::{self, Channel, Chunk, LoaderRWops};
+#[cfg(feature = "mixer")]
+use sdl2::rwops::RWops;
+
+use shogai::board::*;
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::fs;
+use std::path::PathBuf;
+
+/// the set of sounds made here, without files
+pub const BUILT_IN: &str = "synth";
+
+const RATE: u32 = 44_100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Effect {
+    Place,
+    Capture,
+    Check,
+    Promotion,
+    GameEnd,
+    /// seconds left on the clock, from 10
+    Countdown(u32),
+}
+
+impl Effect {
+    /// its file in a sound set, without the extension
+    pub fn name(self) -> String {
+        match self {
+            Effect::Place => String::from("place"),
+            Effect::Capture => String::from("capture"),
+            Effect::Check => String::from("check"),
+            Effect::Promotion => String::from("promotion"),
+            Effect::GameEnd => String::from("game_end"),
+            Effect::Countdown(seconds) => format!("countdown_{}", seconds),
+        }
+    }
+
+    /// the file played when a set has none for it
+    fn fallback(self) -> Option<&'static str> {
+        match self {
+            Effect::Countdown(_) => Some("beep"),
+            _ => None,
+        }
+    }
+}
+
+/// the sounds of a move played from `before`, which gave `after`: the piece put down or taking
+/// another, then the promotion and the check
+pub fn effects(before: &Board, mv: &str, after: &Board) -> Vec<Effect> {
+    let movement = match crate::sfen::parse_movement(mv) {
+        Ok(movement) => movement,
+        Err(_) => return vec![Effect::Place],
+    };
+    let mut effects = vec![match before.is_occupied_by(movement.end) {
+        Some(_) => Effect::Capture,
+        None => Effect::Place,
+    }];
+    let was_promoted = movement
+        .start
+        .and_then(|start| before.is_occupied_by(start))
+        .is_some_and(|piece| piece.promoted);
+    let promoted = after
+        .is_occupied_by(movement.end)
+        .is_some_and(|piece| piece.promoted);
+    if promoted && !was_promoted {
+        effects.push(Effect::Promotion);
+    }
+    if rules::in_check(after, after.get_color()) {
+        effects.push(Effect::Check);
+    }
+    effects
+}
+
+/// the built-in sound set and the directories of sounds/, by name
+pub fn sets() -> Vec<String> {
+    let mut sets = vec![String::from(BUILT_IN)];
+    if let Some(entries) = config_dir().and_then(|dir| fs::read_dir(dir.join("sounds")).ok()) {
+        let mut dirs: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name != BUILT_IN)
+            .collect();
+        dirs.sort();
+        sets.extend(dirs);
+    }
+    sets
+}
+
+/// the file of an effect in a sound set, if it has one
+fn file(set: &str, name: &str) -> Option<PathBuf> {
+    if set == BUILT_IN {
+        return None;
+    }
+    let dir = config_dir()?.join("sounds").join(set);
+    let extensions: &[&str] = if cfg!(feature = "mixer") {
+        &["ogg", "wav"]
+    } else {
+        &["wav"]
+    };
+    extensions
+        .iter()
+        .map(|extension| dir.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file())
+}
+
+/// the built-in sound of an effect, samples from -1 to 1 at `rate` per second
+pub fn synth(effect: Effect, rate: u32) -> Vec<f32> {
+    let rate = rate as f32;
+    // a tone of `frequency` dying out at `decay`, for `length` seconds
+    let tone = |frequency: f32, decay: f32, length: f32| -> Vec<f32> {
+        (0..(rate * length) as usize)
+            .map(|i| {
+                let t = i as f32 / rate;
+                (t * frequency * TAU).sin() * (-t * decay).exp()
+            })
+            .collect()
+    };
+    let then = |mut first: Vec<f32>, second: Vec<f32>| {
+        first.extend(second);
+        first
+    };
+    let sound = match effect {
+        // a short knock of wood
+        Effect::Place => tone(700.0, 90.0, 0.05),
+        // two knocks, the second one lower
+        Effect::Capture => then(tone(700.0, 90.0, 0.05), tone(450.0, 60.0, 0.08)),
+        Effect::Promotion => then(tone(660.0, 20.0, 0.08), tone(990.0, 15.0, 0.12)),
+        Effect::Check => then(tone(880.0, 10.0, 0.1), tone(880.0, 10.0, 0.1)),
+        Effect::GameEnd => [523.0, 659.0, 784.0]
+            .iter()
+            .map(|&frequency| tone(frequency, 6.0, 0.2))
+            .fold(Vec::new(), then),
+        // higher for the very last seconds
+        Effect::Countdown(seconds) if seconds <= 3 => tone(1320.0, 25.0, 0.1),
+        Effect::Countdown(_) => tone(990.0, 25.0, 0.08),
+    };
+    sound.iter().map(|sample| sample * 0.4).collect()
+}
+
+/// mono 16-bit WAV of samples from -1 to 1
+pub fn wav(samples: &[f32], rate: u32) -> Vec<u8> {
+    let data = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    // PCM, one channel, two bytes a sample
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&[1, 0, 1, 0]);
+    bytes.extend_from_slice(&rate.to_le_bytes());
+    bytes.extend_from_slice(&(rate * 2).to_le_bytes());
+    bytes.extend_from_slice(&[2, 0, 16, 0]);
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&to_i16(*sample).to_le_bytes());
+    }
+    bytes
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// the sounds of a set, loaded as they're first played
+pub struct Sounds {
+    #[cfg(not(feature = "mixer"))]
+    queue: AudioQueue<i16>,
+    #[cfg(not(feature = "mixer"))]
+    loaded: HashMap<Effect, Vec<f32>>,
+    #[cfg(feature = "mixer")]
+    _mixer: mixer::Sdl2MixerContext,
+    #[cfg(feature = "mixer")]
+    loaded: HashMap<Effect, Chunk>,
+    set: String,
+}
+
+impl Sounds {
+    /// the sound device, Err without one
+    #[cfg(not(feature = "mixer"))]
+    pub fn open(audio: &sdl2::AudioSubsystem) -> Result<Sounds, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(RATE as i32),
+            channels: Some(1),
+            samples: None,
+        };
+        Ok(Sounds {
+            queue: audio.open_queue::<i16, _>(None, &spec)?,
+            loaded: HashMap::new(),
+            set: String::from(BUILT_IN),
+        })
+    }
+
+    /// the sound device, Err without one (SDL2_mixer opens it itself)
+    #[cfg(feature = "mixer")]
+    pub fn open(_audio: &sdl2::AudioSubsystem) -> Result<Sounds, String> {
+        let context = mixer::init(mixer::InitFlag::OGG)?;
+        mixer::open_audio(RATE as i32, mixer::DEFAULT_FORMAT, 2, 1024)?;
+        // enough for the sounds of a move, and the seconds counted over them
+        mixer::allocate_channels(8);
+        Ok(Sounds {
+            _mixer: context,
+            loaded: HashMap::new(),
+            set: String::from(BUILT_IN),
+        })
+    }
+
+    /// the effects together, with the set and the volume of the settings (nothing when muted)
+    pub fn play(&mut self, effects: &[Effect], settings: &Settings) {
+        if !settings.sounds || settings.volume == 0 || effects.is_empty() {
+            return;
+        }
+        if settings.sound_set != self.set {
+            self.set = settings.sound_set.clone();
+            self.loaded.clear();
+        }
+        let volume = settings.volume.min(100) as f32 / 100.0;
+        self.output(effects, volume);
+    }
+
+    /// the file of the set, or the built-in sound when there's none or it can't be read
+    fn sound_file(&self, effect: Effect) -> Option<PathBuf> {
+        file(&self.set, &effect.name()).or_else(|| file(&self.set, effect.fallback()?))
+    }
+
+    #[cfg(not(feature = "mixer"))]
+    fn output(&mut self, effects: &[Effect], volume: f32) {
+        let mut mixed: Vec<f32> = Vec::new();
+        for &effect in effects {
+            if !self.loaded.contains_key(&effect) {
+                let samples = self
+                    .sound_file(effect)
+                    .and_then(|path| load_wav(&path).ok())
+                    .unwrap_or_else(|| synth(effect, RATE));
+                self.loaded.insert(effect, samples);
+            }
+            let samples = &self.loaded[&effect];
+            if mixed.len() < samples.len() {
+                mixed.resize(samples.len(), 0.0);
+            }
+            for (out, sample) in mixed.iter_mut().zip(samples) {
+                *out += sample;
+            }
+        }
+        let samples: Vec<i16> = mixed.iter().map(|s| to_i16(s * volume)).collect();
+        self.queue.clear();
+        self.queue.queue(&samples);
+        self.queue.resume();
+    }
+
+    #[cfg(feature = "mixer")]
+    fn output(&mut self, effects: &[Effect], volume: f32) {
+        for &effect in effects {
+            if !self.loaded.contains_key(&effect) {
+                let chunk = match self.sound_file(effect).map(Chunk::from_file) {
+                    Some(Ok(chunk)) => chunk,
+                    _ => match RWops::from_bytes(&wav(&synth(effect, RATE), RATE))
+                        .and_then(|bytes| bytes.load_wav())
+                    {
+                        Ok(chunk) => chunk,
+                        Err(_) => continue,
+                    },
+                };
+                self.loaded.insert(effect, chunk);
+            }
+            if let Some(chunk) = self.loaded.get_mut(&effect) {
+                chunk.set_volume((volume * mixer::MAX_VOLUME as f32) as i32);
+                let _ = Channel::all().play(chunk, 0);
+            }
+        }
+    }
+}
+
+/// a WAV file as mono samples at the rate of the queue
+#[cfg(not(feature = "mixer"))]
+fn load_wav(path: &std::path::Path) -> Result<Vec<f32>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let convert = AudioCVT::new(
+        wav.format,
+        wav.channels,
+        wav.freq,
+        AudioFormat::S16LSB,
+        1,
+        RATE as i32,
+    )?;
+    let bytes = convert.convert(wav.buffer().to_vec());
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]) as f32 / i16::MAX as f32)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfen;
+
+    fn effects_of(position: &str, usi: &str) -> Vec<Effect> {
+        let before = sfen::from_sfen(position).unwrap().0;
+        let mv = sfen::from_usi(usi, &before).unwrap();
+        let after = before.play_move_unchecked(&mv);
+        effects(&before, &mv, &after)
+    }
+
+    #[test]
+    fn moves_sound_like_what_they_do() {
+        assert_eq!(effects_of(sfen::STARTPOS, "7g7f"), vec![Effect::Place]);
+        // the rook takes the pawn, promotes and gives check
+        assert_eq!(
+            effects_of("4k4/9/4p4/9/9/9/9/4R4/4K4 b - 1", "5h5c+"),
+            vec![Effect::Capture, Effect::Promotion, Effect::Check]
+        );
+        assert_eq!(
+            effects_of("4k4/9/9/9/9/9/9/9/4K4 b G 1", "G*5b"),
+            vec![Effect::Place, Effect::Check]
+        );
+    }
+
+    #[test]
+    fn built_in_sounds_are_short_and_not_too_loud() {
+        let mut effects = vec![
+            Effect::Place,
+            Effect::Capture,
+            Effect::Check,
+            Effect::Promotion,
+            Effect::GameEnd,
+        ];
+        effects.extend((1..=10).map(Effect::Countdown));
+        for effect in effects {
+            let samples = synth(effect, RATE);
+            assert!(!samples.is_empty() && samples.len() < RATE as usize);
+            let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            assert!(peak > 0.1 && peak <= 0.4, "{:?}: {}", effect, peak);
+        }
+        assert_eq!(Effect::Countdown(3).name(), "countdown_3");
+        assert_eq!(Effect::Countdown(3).fallback(), Some("beep"));
+    }
+
+    #[test]
+    fn wav_files() {
+        let bytes = wav(&[0.0, 1.0, -1.0], 8000);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[24..28], &8000u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[0, 0, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}
@@ -0,0 +1,537 @@
+// Engine matches without a window, for comparing engines (or versions of one) on machines with no
+// display: a gauntlet pits the first engine against each of the others, a tournament plays every
+// pairing.
+//
+// Every pairing plays a number of games with alternating colours, each opening (start position)
+// being played twice in a row so that both engines get its two sides. Games end as in the GUI
+// (checkmate, sennichite, declarations, time) or are adjudicated: a draw after a number of moves,
+// and, when asked for, a win for the side whose evaluation stayed above a threshold for
+// `ADJUDICATION_PLIES` plies. Every record is saved as KIF, and the scores are printed at the end
+// with the Elo differences and their 95% error bars.
+
+use crate::book::{Book, BookPlayer};
+use crate::cli::Args;
+use crate::clock::{Clock, TimeControl};
+use crate::handicap::Handicap;
+use crate::impasse;
+use crate::kif;
+use crate::opponent::{self, Level, Limits, Opponent};
+use crate::record::GameRecord;
+use crate::repetition::Repetitions;
+use crate::result::GameResult;
+use crate::rules;
+use crate::settings::Settings;
+use crate::sfen;
+use crate::tsume;
+use crate::usi::{EngineConfig, UsiEngine};
+
+use shogai::board::*;
+use shogai::piece::*;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// plies the evaluation has to stay past the threshold for a game to be adjudicated
+const ADJUDICATION_PLIES: usize = 10;
+
+/// part of the main time an engine may spend on one move
+const MAIN_TIME_SHARE: u32 = 30;
+
+/// kept on the clock for the time it takes an engine to notice its deadline
+const SAFETY_MARGIN: Duration = Duration::from_millis(50);
+
+/// how the games of a match are played and cut short
+#[derive(Debug, Clone, Copy)]
+pub struct GameRules {
+    pub control: TimeControl,
+    pub impasse: impasse::Rule,
+    /// plies after which the game is a draw
+    pub max_plies: usize,
+    /// evaluation (a pawn is 100) past which the game is won, None to play it out
+    pub adjudicate: Option<i32>,
+}
+
+/// the time an engine may take for its next move
+fn time_budget(clock: &Clock, color: Color) -> Duration {
+    let (main, byoyomi) = clock.remaining(color);
+    (main / MAIN_TIME_SHARE + byoyomi).saturating_sub(SAFETY_MARGIN)
+}
+
+/// play a game between two engines from `start`, the record holding the result
+pub fn play_game(
+    sente: &mut dyn Opponent,
+    gote: &mut dyn Opponent,
+    start: &Board,
+    game_rules: &GameRules,
+) -> GameRecord {
+    let (sente_name, gote_name) = (sente.name(), gote.name());
+    let mut record = if sfen::to_sfen(start, 1) == Handicap::Even.sfen() {
+        GameRecord::new(Handicap::Even, &sente_name, &gote_name)
+    } else {
+        GameRecord::from_position(start.clone(), &sente_name, &gote_name)
+    };
+    let mut board = start.clone();
+    let mut repetitions = Repetitions::new(start);
+    let mut clock = Clock::new(game_rules.control);
+    clock.start(board.get_color());
+    // plies in a row the evaluation was past the threshold, and for whom
+    let mut lopsided: Option<(Color, usize)> = None;
+
+    let result = loop {
+        let color = board.get_color();
+        let other = rules::opponent(color);
+        if rules::legal_moves(&board).is_empty() {
+            break GameResult::Checkmate { winner: other };
+        }
+        if record.moves.len() >= game_rules.max_plies {
+            break GameResult::MaxMoves;
+        }
+        if let Some(threshold) = game_rules.adjudicate {
+            let score = opponent::evaluate(&board);
+            let leader = if score > 0 { color } else { other };
+            lopsided = match lopsided {
+                _ if score.abs() < threshold => None,
+                Some((previous, plies)) if previous == leader => Some((leader, plies + 1)),
+                _ => Some((leader, 1)),
+            };
+            if let Some((leader, plies)) = lopsided {
+                if plies >= ADJUDICATION_PLIES {
+                    break GameResult::Resignation { winner: leader };
+                }
+            }
+        }
+
+        let engine: &mut dyn Opponent = match color {
+            Color::White => &mut *sente,
+            Color::Black => &mut *gote,
+        };
+        if !game_rules.control.is_unlimited() {
+            engine.set_time_budget(Some(time_budget(&clock, color)));
+        }
+        let started = Instant::now();
+        let mv = engine.choose_move(&board);
+        let time = started.elapsed();
+        if clock.flagged(color) {
+            break GameResult::TimeLoss { winner: other };
+        }
+        match mv.map(|mv| rules::normalize(&board, &mv)) {
+            None => break GameResult::Resignation { winner: other },
+            Some(mv) if mv == impasse::DECLARE => {
+                break impasse::declaration(&board, game_rules.impasse)
+                    .unwrap_or(GameResult::IllegalMove { winner: other });
+            }
+            Some(mv) if !rules::is_legal(&board, &mv) => {
+                break GameResult::IllegalMove { winner: other };
+            }
+            Some(mv) => {
+                board = board.play_move_unchecked(&mv);
+                record.push_timed(&mv, time);
+                clock.switch();
+                repetitions.push(&mv);
+                if let Some(sennichite) = repetitions.result() {
+                    break sennichite;
+                }
+            }
+        }
+    };
+    record.result = Some(result);
+    record
+}
+
+/// wins, draws and losses of one side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// points per game, a draw counting half
+    pub fn ratio(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// Elo difference with the opponents and its 95% error bar, None before any game and when
+    /// every game was won or lost (the difference has no bound then)
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let games = self.games() as f64;
+        let ratio = self.ratio();
+        if self.games() == 0 || ratio <= 0.0 || ratio >= 1.0 {
+            return None;
+        }
+        let deviation = |points: f64, count: u32| count as f64 * (points - ratio).powi(2);
+        let variance =
+            (deviation(1.0, self.wins) + deviation(0.5, self.draws) + deviation(0.0, self.losses))
+                / games;
+        let margin = 1.96 * (variance / games).sqrt();
+        let low = elo_difference((ratio - margin).max(0.001));
+        let high = elo_difference((ratio + margin).min(0.999));
+        Some((elo_difference(ratio), (high - low) / 2.0))
+    }
+
+    fn add(&mut self, result: &GameResult, color: Color) {
+        match result.winner() {
+            Some(winner) if winner == color => self.wins += 1,
+            Some(_) => self.losses += 1,
+            None => self.draws += 1,
+        }
+    }
+}
+
+/// "+3 =1 -6 (35.0%), Elo -107 ± 180"
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "+{} ={} -{}", self.wins, self.draws, self.losses)?;
+        if self.games() > 0 {
+            write!(f, " ({:.1}%)", self.ratio() * 100.0)?;
+        }
+        match self.elo() {
+            // (no "-0" for an even score)
+            Some((elo, margin)) => write!(f, ", Elo {:+.0} ± {:.0}", elo.round() + 0.0, margin),
+            None => Ok(()),
+        }
+    }
+}
+
+/// the Elo difference giving the expected score `ratio`
+fn elo_difference(ratio: f64) -> f64 {
+    -400.0 * (1.0 / ratio - 1.0).log10()
+}
+
+/// start positions from a KIF file (the position after its moves) or a file of SFEN lines
+pub fn load_openings(path: &Path) -> Result<Vec<Board>, String> {
+    if tsume::is_kif(path) {
+        return Ok(vec![kif::read_file(path)?.current()]);
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut openings = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (board, _) =
+            sfen::from_sfen(line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+        openings.push(board);
+    }
+    if openings.is_empty() {
+        return Err(format!("{}: no opening found", path.display()));
+    }
+    Ok(openings)
+}
+
+/// a player of the match: a built-in level, or a USI engine of the registry
+#[derive(Debug, Clone, PartialEq)]
+enum Entrant {
+    Level(Level),
+    Usi(EngineConfig),
+}
+
+impl Entrant {
+    /// the level of that name, or else the registered engine
+    fn resolve(name: &str, registry: &[EngineConfig]) -> Result<Entrant, String> {
+        if let Ok(level) = name.parse() {
+            return Ok(Entrant::Level(level));
+        }
+        registry
+            .iter()
+            .find(|engine| engine.name == name)
+            .map(|engine| Entrant::Usi(engine.clone()))
+            .ok_or_else(|| {
+                format!(
+                    "unknown engine '{}': neither a level nor a registered USI engine",
+                    name
+                )
+            })
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Entrant::Level(level) => level.to_string(),
+            Entrant::Usi(engine) => engine.name.clone(),
+        }
+    }
+
+    /// a fresh opponent for the next game
+    fn build(&self, args: &Args) -> Result<Box<dyn Opponent>, String> {
+        match self {
+            Entrant::Level(level) => Ok(level.build(args.limits(*level))),
+            Entrant::Usi(engine) => {
+                let limits = Limits {
+                    movetime: args.movetime,
+                    nodes: args.nodes,
+                };
+                let started = UsiEngine::start(engine, limits)
+                    .map_err(|e| format!("can't start {}: {}", engine.name, e))?;
+                Ok(Box::new(started))
+            }
+        }
+    }
+}
+
+/// engine names, numbered when the same engine plays more than once ("hard", "hard-2")
+fn engine_names(entrants: &[Entrant]) -> Vec<String> {
+    entrants
+        .iter()
+        .enumerate()
+        .map(|(i, entrant)| {
+            let same = entrants[..i].iter().filter(|&e| e == entrant).count();
+            if same == 0 {
+                entrant.name()
+            } else {
+                format!("{}-{}", entrant.name(), same + 1)
+            }
+        })
+        .collect()
+}
+
+/// play the match asked for on the command line and print the results
+pub fn run(args: &Args) -> Result<(), String> {
+    if args.engines.len() < 2 {
+        return Err(String::from("a match needs at least two engines"));
+    }
+    let registry = Settings::load().usi_engines;
+    let entrants = args
+        .engines
+        .iter()
+        .map(|name| Entrant::resolve(name, &registry))
+        .collect::<Result<Vec<_>, _>>()?;
+    let names = engine_names(&entrants);
+    let openings = match &args.openings {
+        Some(path) => load_openings(path)?,
+        None => vec![args.handicap.unwrap_or(Handicap::Even).board()],
+    };
+    let game_rules = GameRules {
+        control: args.time.unwrap_or_default(),
+        impasse: args.impasse,
+        max_plies: args.max_moves,
+        adjudicate: args.adjudicate,
+    };
+    let book = match &args.book {
+        Some(path) => Some(Arc::new(Book::load(path)?)),
+        None => None,
+    };
+    let records = args
+        .records
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("match"));
+    fs::create_dir_all(&records).map_err(|e| format!("{}: {}", records.display(), e))?;
+
+    let count = names.len();
+    let pairings: Vec<(usize, usize)> = if args.gauntlet {
+        (1..count).map(|i| (0, i)).collect()
+    } else {
+        (0..count)
+            .flat_map(|i| (i + 1..count).map(move |j| (i, j)))
+            .collect()
+    };
+    let total = pairings.len() * args.games as usize;
+    // by pairing, from the point of view of its first engine
+    let mut scores = vec![Score::default(); pairings.len()];
+    let mut standings = vec![Score::default(); count];
+    let mut played = 0;
+
+    for (pairing, &(first, second)) in pairings.iter().enumerate() {
+        for game in 0..args.games as usize {
+            let start = &openings[(game / 2) % openings.len()];
+            let (sente, gote) = if game % 2 == 0 {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            let engine = |i: usize| -> Result<Box<dyn Opponent>, String> {
+                let engine = entrants[i].build(args)?;
+                Ok(match &book {
+                    Some(book) => Box::new(BookPlayer::new(book.clone(), engine)),
+                    None => engine,
+                })
+            };
+            let (mut sente_engine, mut gote_engine) = (engine(sente)?, engine(gote)?);
+            let mut record = play_game(
+                sente_engine.as_mut(),
+                gote_engine.as_mut(),
+                start,
+                &game_rules,
+            );
+            record.sente = names[sente].clone();
+            record.gote = names[gote].clone();
+            let result = record.result.unwrap_or(GameResult::Abort);
+
+            let first_color = if sente == first {
+                Color::White
+            } else {
+                Color::Black
+            };
+            scores[pairing].add(&result, first_color);
+            standings[first].add(&result, first_color);
+            standings[second].add(&result, rules::opponent(first_color));
+
+            played += 1;
+            let path = records.join(format!(
+                "{:03}-{}-{}.kif",
+                played, names[sente], names[gote]
+            ));
+            record
+                .save_kif(&path)
+                .map_err(|e| format!("can't save {}: {}", path.display(), e))?;
+            println!(
+                "game {}/{}: {} (sente) vs {} (gote): {} after {} moves",
+                played,
+                total,
+                names[sente],
+                names[gote],
+                result,
+                record.moves.len()
+            );
+        }
+    }
+
+    println!();
+    for (&(first, second), score) in pairings.iter().zip(&scores) {
+        println!("{} vs {}: {}", names[first], names[second], score);
+    }
+    if !args.gauntlet && count > 2 {
+        let mut order: Vec<usize> = (0..count).collect();
+        order.sort_by(|&a, &b| standings[b].ratio().total_cmp(&standings[a].ratio()));
+        println!();
+        println!("{:<4} {:<12} score", "rank", "engine");
+        for (rank, &i) in order.iter().enumerate() {
+            println!("{:<4} {:<12} {}", rank + 1, names[i], standings[i]);
+        }
+    }
+    println!("records saved in {}", records.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::seq::IteratorRandom;
+    use rand::SeedableRng;
+
+    #[test]
+    fn entrants() {
+        let registry = [EngineConfig {
+            name: String::from("yaneuraou"),
+            ..EngineConfig::default()
+        }];
+        let entrants: Vec<Entrant> = ["Hard", "yaneuraou", "hard"]
+            .iter()
+            .map(|name| Entrant::resolve(name, &registry).unwrap())
+            .collect();
+        assert_eq!(entrants[0], Entrant::Level(Level::Hard));
+        assert_eq!(entrants[1], Entrant::Usi(registry[0].clone()));
+        assert_eq!(engine_names(&entrants), ["hard", "yaneuraou", "hard-2"]);
+        assert!(Entrant::resolve("gnushogi", &registry).is_err());
+    }
+
+    #[test]
+    fn elo_and_error_bars() {
+        let even = Score {
+            wins: 10,
+            draws: 0,
+            losses: 10,
+        };
+        let (elo, margin) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        assert!((margin - 160.0).abs() < 10.0, "{}", margin);
+
+        let better = Score {
+            wins: 30,
+            draws: 10,
+            losses: 10,
+        };
+        let (elo, margin) = better.elo().unwrap();
+        // a 70% score
+        assert!((elo - 147.2).abs() < 0.1, "{}", elo);
+        assert!(margin < 120.0 && margin > 60.0, "{}", margin);
+        assert_eq!(
+            better.to_string(),
+            format!("+30 =10 -10 (70.0%), Elo +147 ± {:.0}", margin)
+        );
+
+        let clean = Score {
+            wins: 3,
+            draws: 0,
+            losses: 0,
+        };
+        assert_eq!(clean.elo(), None);
+        assert_eq!(clean.to_string(), "+3 =0 -0 (100.0%)");
+    }
+
+    /// a random mover with its own seed, whose games come out the same every time
+    struct Seeded(StdRng);
+
+    impl Opponent for Seeded {
+        fn name(&self) -> String {
+            String::from("seeded")
+        }
+
+        fn choose_move(&mut self, board: &Board) -> Option<String> {
+            rules::legal_moves(board).into_iter().choose(&mut self.0)
+        }
+    }
+
+    /// takes the most it can, the first legal move otherwise
+    struct Taker;
+
+    impl Opponent for Taker {
+        fn name(&self) -> String {
+            String::from("taker")
+        }
+
+        fn choose_move(&mut self, board: &Board) -> Option<String> {
+            rules::legal_moves(board)
+                .into_iter()
+                .min_by_key(|mv| board.play_move_unchecked(mv).value())
+        }
+    }
+
+    #[test]
+    fn games_end_with_a_result() {
+        let game_rules = GameRules {
+            control: TimeControl::default(),
+            impasse: impasse::Rule::default(),
+            max_plies: 40,
+            adjudicate: None,
+        };
+        let mut sente = Seeded(StdRng::seed_from_u64(1));
+        let mut gote = Seeded(StdRng::seed_from_u64(2));
+        let record = play_game(&mut sente, &mut gote, &Board::new(), &game_rules);
+        // (these two random movers play on to the move limit, a draw of its own)
+        assert_eq!(record.moves.len(), 40);
+        assert_eq!(record.result, Some(GameResult::MaxMoves));
+        // every move of the generator is accepted as legal
+        assert!(!matches!(
+            record.result,
+            None | Some(GameResult::IllegalMove { .. })
+        ));
+        assert_eq!(
+            record.current().get_color() == Color::White,
+            record.moves.len().is_multiple_of(2)
+        );
+
+        // the taker takes everything the random mover leaves hanging, the evaluation soon stays
+        // lopsided
+        let game_rules = GameRules {
+            max_plies: 400,
+            adjudicate: Some(1000),
+            ..game_rules
+        };
+        let record = play_game(&mut Taker, &mut gote, &Board::new(), &game_rules);
+        assert_eq!(
+            record.result.and_then(|result| result.winner()),
+            Some(Color::White)
+        );
+    }
+}
@@ -0,0 +1,685 @@
+// Tsume-shogi: mating problems, a solver for them and the player's progress.
+//
+// The attacker has to check with every move and the defender answers with the longest defense.
+// The solver is a depth-first proof-number search (df-pn) bounded by a number of plies, run with
+// increasing bounds so that the first proof found is also the shortest mate.
+
+use crate::game::GameState;
+use crate::kif;
+use crate::opponent::Opponent;
+use crate::record::GameRecord;
+use crate::rules;
+use crate::sfen;
+
+use shogai::board::*;
+use shogai::movement::*;
+use shogai::piece::*;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// longest mate looked for, in plies
+pub const MAX_PLIES: u8 = 31;
+/// nodes searched before the solver gives up
+const MAX_NODES: u64 = 200_000;
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub name: String,
+    pub board: Board,
+    /// moves of the solution given with the problem, if any
+    pub solution: Vec<String>,
+}
+
+impl Problem {
+    pub fn new(name: &str, mut board: Board, solution: Vec<String>) -> Self {
+        fill_defender_hand(&mut board);
+        Problem {
+            name: name.to_string(),
+            board,
+            solution,
+        }
+    }
+
+    /// key of the problem in the progress file
+    pub fn key(&self) -> String {
+        sfen::to_sfen(&self.board, 1)
+    }
+
+    pub fn attacker(&self) -> Color {
+        self.board.get_color()
+    }
+}
+
+/// in tsume-shogi the defender holds every piece that is not on the board or in the attacker's
+/// hand, which problems usually leave implicit
+fn fill_defender_hand(board: &mut Board) {
+    let defender = rules::opponent(board.get_color());
+    if board
+        .iter()
+        .any(|p| p.color == defender && p.position.is_none())
+    {
+        return;
+    }
+    for &(piecetype, total) in &[
+        (PieceType::Rook, 2),
+        (PieceType::Bishop, 2),
+        (PieceType::Gold, 4),
+        (PieceType::Silver, 4),
+        (PieceType::Knight, 4),
+        (PieceType::Lance, 4),
+        (PieceType::Pawn, 18),
+    ] {
+        let used = board.iter().filter(|p| p.piecetype == piecetype).count();
+        for _ in used..total {
+            board.add_piece(Piece {
+                color: defender,
+                piecetype,
+                promoted: false,
+                position: None,
+            });
+        }
+    }
+}
+
+pub fn is_kif(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "kif" || ext == "kifu")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn load_kif(path: &Path) -> Result<Problem, String> {
+    let record = kif::read_file(path)?;
+    Ok(Problem::new(&file_name(path), record.start, record.moves))
+}
+
+/// one SFEN per line, blank lines and lines starting with '#' are skipped
+fn load_sfen(path: &Path) -> Result<Vec<Problem>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut problems = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (board, _) =
+            sfen::from_sfen(line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))?;
+        let name = format!("{} #{}", file_name(path), problems.len() + 1);
+        problems.push(Problem::new(&name, board, Vec::new()));
+    }
+    Ok(problems)
+}
+
+/// load problems from a KIF file, a file of SFEN lines or a directory of KIF files
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Problem>, String> {
+    let path = path.as_ref();
+    let problems = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_kif(path))
+            .collect();
+        files.sort();
+        files
+            .iter()
+            .map(|file| load_kif(file))
+            .collect::<Result<Vec<_>, _>>()?
+    } else if is_kif(path) {
+        vec![load_kif(path)?]
+    } else {
+        load_sfen(path)?
+    };
+    if problems.is_empty() {
+        return Err(format!("no problem found in {}", path.display()));
+    }
+    Ok(problems)
+}
+
+const INFINITE: u32 = u32::MAX / 2;
+
+/// proof and disproof numbers from the point of view of the side to move:
+/// (phi, delta) = (pn, dn) at attacker nodes, (dn, pn) at defender nodes
+type Numbers = (u32, u32);
+
+pub struct Solver {
+    attacker: Color,
+    nodes: u64,
+    max_nodes: u64,
+    table: HashMap<(Board, u8), Numbers>,
+}
+
+impl Solver {
+    pub fn new(attacker: Color) -> Self {
+        Solver {
+            attacker,
+            nodes: 0,
+            max_nodes: MAX_NODES,
+            table: HashMap::new(),
+        }
+    }
+
+    fn children(&self, board: &Board) -> Vec<String> {
+        if board.get_color() == self.attacker {
+            rules::checking_moves(board)
+        } else {
+            rules::legal_moves(board)
+        }
+    }
+
+    fn lookup(&self, board: &Board, plies: u8) -> Numbers {
+        self.table
+            .get(&(board.clone(), plies))
+            .copied()
+            .unwrap_or((1, 1))
+    }
+
+    /// multiple iterative deepening of df-pn: search until phi >= `phi_max` or delta >= `delta_max`
+    fn mid(&mut self, board: &Board, plies: u8, phi_max: u32, delta_max: u32) -> Numbers {
+        self.nodes += 1;
+        let children: Vec<Board> = if board.get_color() == self.attacker && plies == 0 {
+            // out of plies, the attacker failed
+            Vec::new()
+        } else {
+            self.children(board)
+                .iter()
+                .map(|mv| board.play_move_unchecked(mv))
+                .collect()
+        };
+        if children.is_empty() {
+            // the side to move lost: no check left for the attacker, checkmate for the defender
+            let numbers = (INFINITE, 0);
+            self.table.insert((board.clone(), plies), numbers);
+            return numbers;
+        }
+        let child_plies = plies.saturating_sub(1);
+
+        loop {
+            let numbers: Vec<Numbers> = children
+                .iter()
+                .map(|child| self.lookup(child, child_plies))
+                .collect();
+            let phi = numbers.iter().map(|n| n.1).min().unwrap_or(INFINITE);
+            let delta = numbers
+                .iter()
+                .map(|n| n.0)
+                .fold(0, |sum: u32, n| sum.saturating_add(n).min(INFINITE));
+            if phi >= phi_max || delta >= delta_max || self.nodes >= self.max_nodes {
+                self.table.insert((board.clone(), plies), (phi, delta));
+                return (phi, delta);
+            }
+
+            let mut best = 0;
+            let mut second = INFINITE;
+            for (i, n) in numbers.iter().enumerate().skip(1) {
+                if n.1 < numbers[best].1 {
+                    second = numbers[best].1;
+                    best = i;
+                } else if n.1 < second {
+                    second = n.1;
+                }
+            }
+            let child_phi_max = (delta_max - delta)
+                .saturating_add(numbers[best].0)
+                .min(INFINITE);
+            let child_delta_max = phi_max.min(second.saturating_add(1));
+            self.mid(&children[best], child_plies, child_phi_max, child_delta_max);
+        }
+    }
+
+    /// whether the attacker mates within `plies`, None if the search ran out of nodes
+    fn proves(&mut self, board: &Board, plies: u8) -> Option<bool> {
+        self.nodes = 0;
+        let (phi, delta) = self.mid(board, plies, INFINITE, INFINITE);
+        let attacking = board.get_color() == self.attacker;
+        match (phi, delta) {
+            (0, _) => Some(attacking),
+            (_, 0) => Some(!attacking),
+            _ => None,
+        }
+    }
+
+    /// length in plies of the shortest mate, counted from the side to move
+    pub fn mate_length(&mut self, board: &Board, max_plies: u8) -> Option<u8> {
+        let first = if board.get_color() == self.attacker {
+            1
+        } else {
+            0
+        };
+        for plies in (first..=max_plies).step_by(2) {
+            if plies == 0 {
+                if rules::is_checkmate(board) {
+                    return Some(0);
+                }
+                continue;
+            }
+            match self.proves(board, plies) {
+                Some(true) => return Some(plies),
+                Some(false) => {}
+                None => return None,
+            }
+        }
+        None
+    }
+
+    /// the best move for the side to move: the quickest mate for the attacker, the longest
+    /// defense for the defender (escaping altogether if it can)
+    pub fn best_move(&mut self, board: &Board, max_plies: u8) -> Option<String> {
+        let moves = self.children(board);
+        let attacking = board.get_color() == self.attacker;
+        let mut best: Option<(String, u8)> = None;
+        for mv in moves {
+            let after = board.play_move_unchecked(&mv);
+            let length = self.mate_length(&after, max_plies.saturating_sub(1));
+            let better = match (&best, length, attacking) {
+                (None, _, _) => true,
+                // the attacker wants a mate, as quickly as possible
+                (Some((_, current)), Some(length), true) => length < *current,
+                (Some(_), None, true) => false,
+                // the defender wants no mate at all, or the longest one, interposing a dropped
+                // piece only when it really delays the mate
+                (Some(_), None, false) => true,
+                (Some((_, current)), Some(length), false) => {
+                    length > *current || (length == *current && !mv.contains('*'))
+                }
+            };
+            if better {
+                match (length, attacking) {
+                    (None, true) => {}
+                    (None, false) => return Some(mv),
+                    (Some(length), _) => best = Some((mv, length)),
+                }
+            }
+        }
+        best.map(|(mv, _)| mv)
+    }
+
+    /// a main line from the position: attacker's quickest mate against the longest defense
+    pub fn solve(&mut self, board: &Board, max_plies: u8) -> Option<Vec<String>> {
+        let mut length = self.mate_length(board, max_plies)?;
+        let mut board = board.clone();
+        let mut line = Vec::new();
+        while length > 0 {
+            let mv = self.best_move(&board, length)?;
+            board = board.play_move_unchecked(&mv);
+            line.push(mv);
+            length = self.mate_length(&board, length - 1)?;
+        }
+        Some(line)
+    }
+}
+
+/// check a solution given with a problem: checks all along, ending in checkmate
+pub fn verify_solution(problem: &Problem) -> Result<(), String> {
+    let mut board = problem.board.clone();
+    for (i, mv) in problem.solution.iter().enumerate() {
+        if !rules::is_legal(&board, mv) {
+            return Err(format!("move {} ({}) is illegal", i + 1, mv));
+        }
+        if board.get_color() == problem.attacker() && !rules::gives_check(&board, mv) {
+            return Err(format!("move {} ({}) is not a check", i + 1, mv));
+        }
+        board = board.play_move_unchecked(mv);
+    }
+    if !rules::is_checkmate(&board) {
+        return Err(String::from("the solution does not end in checkmate"));
+    }
+    Ok(())
+}
+
+/// Some(true) once the defender is checkmated, Some(false) when the attacker is to move and has
+/// no check left, None while the problem goes on
+pub fn outcome(board: &Board, attacker: Color) -> Option<bool> {
+    if board.get_color() == attacker {
+        if rules::checking_moves(board).is_empty() {
+            return Some(false);
+        }
+    } else if rules::is_checkmate(board) {
+        return Some(true);
+    }
+    None
+}
+
+/// the defending side of a problem
+pub struct TsumeDefender {
+    attacker: Color,
+}
+
+impl TsumeDefender {
+    pub fn new(problem: &Problem) -> Self {
+        TsumeDefender {
+            attacker: problem.attacker(),
+        }
+    }
+}
+
+impl Opponent for TsumeDefender {
+    fn name(&self) -> String {
+        String::from("Tsume defender")
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        Solver::new(self.attacker).best_move(board, MAX_PLIES)
+    }
+}
+
+/// the player's results, kept between sessions
+#[derive(Debug, Default)]
+pub struct Progress {
+    path: Option<PathBuf>,
+    /// attempts and whether the problem was solved, by problem key
+    results: HashMap<String, (u32, bool)>,
+}
+
+/// where data files go: $XDG_DATA_HOME/shogui, or ~/.local/share/shogui (next to the executable
+/// in portable builds)
+pub fn data_dir() -> Option<PathBuf> {
+    if cfg!(feature = "portable") {
+        return crate::settings::portable_dir();
+    }
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(base.join("shogui"))
+}
+
+impl Progress {
+    /// the progress saved in the data directory, empty if there is none
+    pub fn load() -> Self {
+        match data_dir() {
+            Some(dir) => Progress::load_from(dir.join("tsume.txt")),
+            None => Progress::default(),
+        }
+    }
+
+    /// lines of "<attempts> <solved|unsolved> <sfen>"
+    pub fn load_from(path: PathBuf) -> Self {
+        let mut results = HashMap::new();
+        if let Ok(text) = fs::read_to_string(&path) {
+            for line in text.lines() {
+                let mut fields = line.splitn(3, ' ');
+                if let (Some(attempts), Some(solved), Some(key)) =
+                    (fields.next(), fields.next(), fields.next())
+                {
+                    if let Ok(attempts) = attempts.parse() {
+                        results.insert(key.to_string(), (attempts, solved == "solved"));
+                    }
+                }
+            }
+        }
+        Progress {
+            path: Some(path),
+            results,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let mut lines: Vec<String> = self
+            .results
+            .iter()
+            .map(|(key, (attempts, solved))| {
+                let solved = if *solved { "solved" } else { "unsolved" };
+                format!("{} {} {}\n", attempts, solved, key)
+            })
+            .collect();
+        lines.sort();
+        fs::write(path, lines.concat()).map_err(|e| e.to_string())
+    }
+
+    pub fn is_solved(&self, problem: &Problem) -> bool {
+        self.results
+            .get(&problem.key())
+            .is_some_and(|(_, solved)| *solved)
+    }
+
+    pub fn attempts(&self, problem: &Problem) -> u32 {
+        self.results.get(&problem.key()).map_or(0, |(n, _)| *n)
+    }
+
+    /// count an attempt, solved or not
+    pub fn record(&mut self, problem: &Problem, solved: bool) {
+        let entry = self.results.entry(problem.key()).or_insert((0, false));
+        entry.0 += 1;
+        entry.1 |= solved;
+    }
+
+    /// the first unsolved problem, to resume where the player stopped
+    pub fn first_unsolved(&self, problems: &[Problem]) -> usize {
+        problems
+            .iter()
+            .position(|p| !self.is_solved(p))
+            .unwrap_or(0)
+    }
+}
+
+/// the first move of the quickest mate for the attacker, None without one; a long search, run
+/// away from the window
+pub fn hint(board: &Board, attacker: Color) -> Option<Movement> {
+    Solver::new(attacker)
+        .best_move(board, MAX_PLIES)
+        .and_then(|mv| sfen::parse_movement(&mv).ok())
+}
+
+/// a set of problems played one after the other
+pub struct Trainer {
+    pub problems: Vec<Problem>,
+    pub current: usize,
+    pub progress: Progress,
+}
+
+impl Trainer {
+    /// start at the first problem not solved yet
+    pub fn new(problems: Vec<Problem>, progress: Progress) -> Self {
+        let current = progress.first_unsolved(&problems);
+        Trainer {
+            problems,
+            current,
+            progress,
+        }
+    }
+
+    pub fn problem(&self) -> &Problem {
+        &self.problems[self.current]
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.problems.len();
+    }
+
+    pub fn previous(&mut self) {
+        self.current = (self.current + self.problems.len() - 1) % self.problems.len();
+    }
+
+    /// record the outcome of an attempt at the current problem and save the progress
+    pub fn finish(&mut self, solved: bool) -> Result<(), String> {
+        let problem = self.problems[self.current].clone();
+        self.progress.record(&problem, solved);
+        self.progress.save()
+    }
+
+    /// set the game up for the current problem, from its start; returns its defender
+    pub fn set_up(&self, state: &mut GameState) -> Box<dyn Opponent> {
+        let problem = self.problem();
+        let defender = TsumeDefender::new(problem);
+        state.board = problem.board.clone();
+        state.human = problem.attacker();
+        let (sente_name, gote_name) = match state.human {
+            Color::White => (String::from("You"), defender.name()),
+            Color::Black => (defender.name(), String::from("You")),
+        };
+        state.record = GameRecord::from_position(state.board.clone(), &sente_name, &gote_name);
+        state.clear_selection();
+        Box::new(defender)
+    }
+
+    /// after a move: whether the attempt was a success once it's over, recorded (and on to the
+    /// next problem when solved)
+    pub fn judge(&mut self, state: &GameState) -> Result<Option<bool>, String> {
+        let solved = match outcome(&state.board, state.human) {
+            Some(solved) => solved,
+            None => return Ok(None),
+        };
+        self.finish(solved)?;
+        if solved {
+            self.next();
+        }
+        Ok(Some(solved))
+    }
+
+    pub fn title(&self) -> String {
+        let problem = self.problem();
+        let status = if self.progress.is_solved(problem) {
+            String::from("solved")
+        } else {
+            match self.progress.attempts(problem) {
+                0 => String::from("new"),
+                1 => String::from("1 attempt"),
+                n => format!("{} attempts", n),
+            }
+        };
+        let solved = self
+            .problems
+            .iter()
+            .filter(|p| self.progress.is_solved(p))
+            .count();
+        format!(
+            "Shogi - tsume {}/{}: {} ({}) - {} solved",
+            self.current + 1,
+            self.problems.len(),
+            problem.name,
+            status,
+            solved
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, TimeControl};
+    use crate::impasse::Rule;
+
+    fn problem(sfen: &str) -> Problem {
+        Problem::new("test", sfen::from_sfen(sfen).unwrap().0, Vec::new())
+    }
+
+    /// the solver's main line in USI notation
+    fn solve(problem: &Problem) -> Option<Vec<String>> {
+        let line = Solver::new(problem.attacker()).solve(&problem.board, MAX_PLIES)?;
+        Some(line.iter().map(|mv| sfen::to_usi(mv).unwrap()).collect())
+    }
+
+    fn check(sfen: &str, plies: u8, first: &str) {
+        let mut problem = problem(sfen);
+        let attacker = problem.attacker();
+        assert_eq!(
+            Solver::new(attacker).mate_length(&problem.board, MAX_PLIES),
+            Some(plies),
+            "{}",
+            sfen
+        );
+        let line = solve(&problem).unwrap();
+        assert_eq!(line.len(), plies as usize);
+        assert_eq!(line[0], first);
+        problem.solution = Solver::new(attacker)
+            .solve(&problem.board, MAX_PLIES)
+            .unwrap();
+        assert_eq!(verify_solution(&problem), Ok(()));
+    }
+
+    #[test]
+    fn short_mates() {
+        // the head gold, protected by the pawn
+        check("8k/9/8P/9/9/9/9/9/9 b G 1", 1, "G*1b");
+        // the king runs to 1a or 2a, the second gold follows
+        check("9/8k/9/8P/9/9/9/9/9 b 2G 1", 3, "G*1c");
+        // golds chasing the king along the top
+        check("9/9/8k/9/8P/9/9/9/9 b 3G 1", 5, "G*1d");
+    }
+
+    #[test]
+    fn no_mate() {
+        let problem = problem("8k/9/9/9/9/9/9/9/9 b 2G 1");
+        assert_eq!(solve(&problem), None);
+        // the only mate would be by dropping a pawn
+        let problem = self::problem("7nk/9/7G1/9/9/9/9/9/9 b P 1");
+        assert_eq!(
+            Solver::new(problem.attacker()).mate_length(&problem.board, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn defender_hand_and_solutions() {
+        let mut problem = problem("8k/9/8P/9/9/9/9/9/9 b G 1");
+        // every piece not on the board nor in the attacker's hand
+        assert_eq!(
+            sfen::hand_count(&problem.board, Color::Black, PieceType::Gold),
+            3
+        );
+        assert_eq!(
+            sfen::hand_count(&problem.board, Color::Black, PieceType::Pawn),
+            17
+        );
+        assert_eq!(outcome(&problem.board, problem.attacker()), None);
+
+        problem.solution = vec![sfen::from_usi("G*1c", &problem.board).unwrap()];
+        assert!(verify_solution(&problem).is_err());
+        problem.solution = vec![sfen::from_usi("G*1b", &problem.board).unwrap()];
+        assert_eq!(verify_solution(&problem), Ok(()));
+        let mated = problem.board.play_move_unchecked(&problem.solution[0]);
+        assert_eq!(outcome(&mated, problem.attacker()), Some(true));
+    }
+
+    #[test]
+    fn attempts_at_problems() {
+        let problems = vec![
+            problem("8k/9/8P/9/9/9/9/9/9 b G 1"),
+            // not a problem: the king takes the gold
+            problem("8k/9/9/9/9/9/9/9/9 b G 1"),
+        ];
+        let mut trainer = Trainer::new(problems, Progress::default());
+        let record = GameRecord::from_position(Board::new(), "You", "Computer");
+        let clock = Clock::new(TimeControl::default());
+        let mut state = GameState::new(Board::new(), Color::Black, record, clock, Rule::default());
+
+        let defender = trainer.set_up(&mut state);
+        assert_eq!(defender.name(), "Tsume defender");
+        assert_eq!(state.human, Color::White);
+        assert_eq!(state.record.gote, "Tsume defender");
+        assert_eq!(state.board, trainer.problem().board);
+        let mate = hint(&state.board, state.human).unwrap();
+        assert_eq!(sfen::to_usi(&mate.to_string()).unwrap(), "G*1b");
+        state.play(&mate.to_string());
+        assert_eq!(trainer.judge(&state), Ok(Some(true)));
+        assert_eq!(trainer.current, 1);
+        assert!(trainer.progress.is_solved(&trainer.problems[0]));
+
+        trainer.set_up(&mut state);
+        assert!(state.record.moves.is_empty());
+        assert_eq!(hint(&state.board, state.human), None);
+        let mv = sfen::from_usi("G*1b", &state.board).unwrap();
+        state.play(&mv);
+        assert_eq!(trainer.judge(&state), Ok(None));
+        let mv = sfen::from_usi("1a1b", &state.board).unwrap();
+        state.play(&mv);
+        assert_eq!(trainer.judge(&state), Ok(Some(false)));
+        assert_eq!(trainer.current, 1);
+        assert_eq!(trainer.progress.attempts(trainer.problem()), 1);
+        assert!(trainer.title().starts_with("Shogi - tsume 2/2"));
+    }
+}
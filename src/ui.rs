@@ -0,0 +1,936 @@
+// A small immediate-mode widget toolkit drawn on the board's canvas: labels (with SDL2_ttf),
+// buttons, checkboxes, text fields, lists, scroll areas, a menu bar and modal dialogs.
+//
+// Immediate mode: widgets are functions called on every frame, their state (the text of a field,
+// the selected row of a list...) kept by the caller; they draw themselves and say what the user
+// did with them during the frame. Tab moves the keyboard focus to the next widget (Shift+Tab to
+// the previous one), Space or Return presses the focused button or checkbox, the arrows move in
+// lists and menus, and the focused text field gets what is typed.
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::ttf::{Font, Sdl2TtfContext};
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "emscripten"))]
+use std::thread;
+#[cfg(not(target_os = "emscripten"))]
+use std::time::Duration;
+
+/// fonts tried in order when the settings don't name one
+const FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+const FONT_SIZE: u16 = 14;
+
+/// height of a line of widgets
+pub const ROW: u32 = 26;
+
+/// space between widgets, and around text
+pub const PADDING: i32 = 6;
+
+/// the font named in the settings, or the first of the usual ones found
+pub fn load_font<'ttf>(
+    ttf: &'ttf Sdl2TtfContext,
+    path: Option<&Path>,
+) -> Option<Font<'ttf, 'static>> {
+    path.map(Path::to_path_buf)
+        .into_iter()
+        .chain(FONTS.iter().map(PathBuf::from))
+        .find_map(|path| ttf.load_font(path, FONT_SIZE).ok())
+}
+
+pub struct Style {
+    pub panel: Color,
+    pub widget: Color,
+    pub hover: Color,
+    /// pressed buttons, checked boxes, selected rows
+    pub accent: Color,
+    pub text: Color,
+    pub focus: Color,
+    pub error: Color,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            panel: Color::RGB(0x2B, 0x2B, 0x30),
+            widget: Color::RGB(0x45, 0x45, 0x4D),
+            hover: Color::RGB(0x5A, 0x5A, 0x64),
+            accent: Color::RGB(0x3C, 0x6E, 0xC8),
+            text: Color::RGB(0xEE, 0xEE, 0xEE),
+            focus: Color::RGB(0xE8, 0xB8, 0x50),
+            error: Color::RGB(0xE0, 0x60, 0x60),
+        }
+    }
+}
+
+/// what happened since the last frame
+#[derive(Default)]
+struct Input {
+    mouse: (i32, i32),
+    /// the left button went down, or up
+    pressed: bool,
+    released: bool,
+    /// rows scrolled, up is positive
+    wheel: i32,
+    keys: Vec<Keycode>,
+    text: String,
+    /// Tab (1) or Shift+Tab (-1)
+    tab: i32,
+}
+
+/// how far a list or a scroll area is scrolled, in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Scroll {
+    pub offset: i32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListState {
+    pub selected: Option<usize>,
+    pub scroll: Scroll,
+}
+
+/// the open menu of a menu bar and its highlighted item
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MenuState {
+    pub open: Option<usize>,
+    pub item: Option<usize>,
+}
+
+pub struct Menu {
+    pub title: String,
+    pub items: Vec<String>,
+}
+
+/// rows of widgets going down a rectangle
+pub struct Rows {
+    rect: Rect,
+    y: i32,
+}
+
+impl Rows {
+    pub fn new(rect: Rect) -> Self {
+        Rows { rect, y: rect.y() }
+    }
+
+    /// the next row, `ROW` high
+    pub fn row(&mut self) -> Rect {
+        self.row_of(ROW)
+    }
+
+    pub fn row_of(&mut self, height: u32) -> Rect {
+        let row = Rect::new(self.rect.x(), self.y, self.rect.width(), height);
+        self.y += height as i32 + PADDING;
+        row
+    }
+
+    /// what's left under the rows
+    pub fn rest(&self) -> Rect {
+        let used = (self.y - self.rect.y()) as u32;
+        Rect::new(
+            self.rect.x(),
+            self.y,
+            self.rect.width(),
+            self.rect.height().saturating_sub(used).max(1),
+        )
+    }
+}
+
+/// `rect` cut in `n` columns side by side
+pub fn columns(rect: Rect, n: u32) -> Vec<Rect> {
+    let n = n.max(1);
+    let width = (rect.width() as i32 - PADDING * (n as i32 - 1)) / n as i32;
+    (0..n as i32)
+        .map(|i| {
+            Rect::new(
+                rect.x() + i * (width + PADDING),
+                rect.y(),
+                width.max(1) as u32,
+                rect.height(),
+            )
+        })
+        .collect()
+}
+
+/// `rect` cut in two at `width` pixels from the left
+pub fn split(rect: Rect, width: u32) -> (Rect, Rect) {
+    let width = width.min(rect.width().saturating_sub(1));
+    (
+        Rect::new(rect.x(), rect.y(), width.max(1), rect.height()),
+        Rect::new(
+            rect.x() + width as i32 + PADDING,
+            rect.y(),
+            (rect.width() as i32 - width as i32 - PADDING).max(1) as u32,
+            rect.height(),
+        ),
+    )
+}
+
+/// wait a little before the next frame of a dialog or menu; in the browser the page has to be
+/// given the hand to show it
+fn pause() {
+    #[cfg(not(target_os = "emscripten"))]
+    thread::sleep(Duration::from_millis(15));
+    #[cfg(target_os = "emscripten")]
+    unsafe {
+        crate::emscripten_file::emscripten_mod::emscripten_sleep(15)
+    };
+}
+
+fn id(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub struct Ui<'ttf> {
+    font: Option<Font<'ttf, 'static>>,
+    pub style: Style,
+    input: Input,
+    /// the widget the mouse button went down on
+    active: Option<u64>,
+    /// the widget getting the keyboard
+    focus: Option<u64>,
+    /// focusable widgets of the frame, in the order they were drawn, for Tab
+    order: Vec<u64>,
+    /// whether a widget was under the mouse when the button went down
+    claimed: bool,
+    /// the scroll area being drawn: widgets out of it don't get the mouse
+    clip: Option<Rect>,
+}
+
+impl<'ttf> Ui<'ttf> {
+    pub fn new(font: Option<Font<'ttf, 'static>>) -> Self {
+        Ui {
+            font,
+            style: Style::default(),
+            input: Input::default(),
+            active: None,
+            focus: None,
+            order: Vec::new(),
+            claimed: false,
+            clip: None,
+        }
+    }
+
+    /// without a font there is no text to show
+    pub fn has_font(&self) -> bool {
+        self.font.is_some()
+    }
+
+    /// give an event to the widgets of the next frame
+    pub fn event(&mut self, event: &Event) {
+        let input = &mut self.input;
+        match event {
+            Event::MouseMotion { x, y, .. } => input.mouse = (*x, *y),
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => {
+                input.mouse = (*x, *y);
+                input.pressed = true;
+            }
+            Event::MouseButtonUp {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => {
+                input.mouse = (*x, *y);
+                input.released = true;
+            }
+            Event::MouseWheel { y, .. } => input.wheel += y,
+            Event::KeyDown {
+                keycode: Some(Keycode::Tab),
+                keymod,
+                ..
+            } => {
+                input.tab = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    -1
+                } else {
+                    1
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => input.keys.push(*keycode),
+            Event::TextInput { text, .. } => input.text.push_str(text),
+            _ => {}
+        }
+    }
+
+    /// once every widget of the frame was drawn: move the focus, forget the frame's input
+    pub fn end_frame(&mut self) {
+        if self.input.tab != 0 && !self.order.is_empty() {
+            let len = self.order.len() as i32;
+            let next = match self
+                .focus
+                .and_then(|f| self.order.iter().position(|&o| o == f))
+            {
+                Some(i) => (i as i32 + self.input.tab).rem_euclid(len),
+                None if self.input.tab > 0 => 0,
+                None => len - 1,
+            };
+            self.focus = Some(self.order[next as usize]);
+        }
+        if self.input.pressed && !self.claimed {
+            // a click on nothing
+            self.focus = None;
+        }
+        if self.input.released {
+            self.active = None;
+        }
+        self.order.clear();
+        self.claimed = false;
+        let mouse = self.input.mouse;
+        self.input = Input {
+            mouse,
+            ..Input::default()
+        };
+    }
+
+    /// forget the focus and what was pressed, for a new dialog
+    pub fn reset(&mut self) {
+        self.end_frame();
+        self.focus = None;
+        self.active = None;
+    }
+
+    fn hovered(&self, rect: Rect) -> bool {
+        let (x, y) = self.input.mouse;
+        rect.contains_point((x, y)) && self.clip.is_none_or(|clip| clip.contains_point((x, y)))
+    }
+
+    /// the mouse on a widget: (hovered, clicked); a click is a press and a release on it
+    fn interact(&mut self, id: u64, rect: Rect, focusable: bool) -> (bool, bool) {
+        if focusable {
+            self.order.push(id);
+        }
+        let hovered = self.hovered(rect);
+        if hovered && self.input.pressed {
+            self.active = Some(id);
+            self.claimed = true;
+            if focusable {
+                self.focus = Some(id);
+            }
+        }
+        let clicked = hovered && self.input.released && self.active == Some(id);
+        (hovered, clicked)
+    }
+
+    fn focused(&self, id: u64) -> bool {
+        self.focus == Some(id)
+    }
+
+    /// take a key pressed during the frame, if it was
+    fn take_key(&mut self, keys: &[Keycode]) -> Option<Keycode> {
+        let i = self.input.keys.iter().position(|key| keys.contains(key))?;
+        Some(self.input.keys.remove(i))
+    }
+
+    pub fn text_width(&self, text: &str) -> u32 {
+        match &self.font {
+            Some(font) => font.size_of(text).map_or(0, |(width, _)| width),
+            None => 0,
+        }
+    }
+
+    /// draw a line of text, vertically centered in a row starting at `y`
+    fn text(
+        &self,
+        canvas: &mut Canvas<Window>,
+        x: i32,
+        y: i32,
+        height: u32,
+        text: &str,
+        color: Color,
+    ) -> Result<(), String> {
+        let font = match &self.font {
+            Some(font) if !text.is_empty() => font,
+            _ => return Ok(()),
+        };
+        let surface = font
+            .render(text)
+            .blended(color)
+            .map_err(|e| e.to_string())?;
+        let texture_creator = canvas.texture_creator();
+        let texture = texture_creator
+            .create_texture_from_surface(&surface)
+            .map_err(|e| e.to_string())?;
+        let top = y + (height as i32 - surface.height() as i32) / 2;
+        canvas.copy(
+            &texture,
+            None,
+            Rect::new(x, top, surface.width(), surface.height()),
+        )
+    }
+
+    fn frame(&self, canvas: &mut Canvas<Window>, rect: Rect, id: u64) -> Result<(), String> {
+        if self.focused(id) {
+            canvas.set_draw_color(self.style.focus);
+            canvas.draw_rect(rect)?;
+        }
+        Ok(())
+    }
+
+    pub fn label(&self, canvas: &mut Canvas<Window>, rect: Rect, text: &str) -> Result<(), String> {
+        self.text(
+            canvas,
+            rect.x(),
+            rect.y(),
+            rect.height(),
+            text,
+            self.style.text,
+        )
+    }
+
+    /// a label in the error colour
+    pub fn error(&self, canvas: &mut Canvas<Window>, rect: Rect, text: &str) -> Result<(), String> {
+        self.text(
+            canvas,
+            rect.x(),
+            rect.y(),
+            rect.height(),
+            text,
+            self.style.error,
+        )
+    }
+
+    /// true when clicked (or pressed with the keyboard)
+    pub fn button(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        key: &str,
+        rect: Rect,
+        text: &str,
+    ) -> Result<bool, String> {
+        self.choice(canvas, key, rect, text, false)
+    }
+
+    /// a button shown pressed when `selected`, for one choice among a few
+    pub fn choice(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        key: &str,
+        rect: Rect,
+        text: &str,
+        selected: bool,
+    ) -> Result<bool, String> {
+        let id = id(key);
+        let (hovered, mut clicked) = self.interact(id, rect, true);
+        if self.focused(id) && self.take_key(&[Keycode::Space, Keycode::Return]).is_some() {
+            clicked = true;
+        }
+        let color = if selected || (hovered && self.active == Some(id)) {
+            self.style.accent
+        } else if hovered {
+            self.style.hover
+        } else {
+            self.style.widget
+        };
+        canvas.set_draw_color(color);
+        canvas.fill_rect(rect)?;
+        self.frame(canvas, rect, id)?;
+        let width = self.text_width(text) as i32;
+        let x = rect.x() + (rect.width() as i32 - width).max(0) / 2;
+        self.text(canvas, x, rect.y(), rect.height(), text, self.style.text)?;
+        Ok(clicked)
+    }
+
+    /// true when switched
+    pub fn checkbox(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        key: &str,
+        rect: Rect,
+        text: &str,
+        value: &mut bool,
+    ) -> Result<bool, String> {
+        let id = id(key);
+        let (hovered, mut clicked) = self.interact(id, rect, true);
+        if self.focused(id) && self.take_key(&[Keycode::Space, Keycode::Return]).is_some() {
+            clicked = true;
+        }
+        if clicked {
+            *value = !*value;
+        }
+        let size = rect.height().saturating_sub(8).max(4);
+        let square = Rect::new(rect.x() + 2, rect.y() + 4, size, size);
+        canvas.set_draw_color(if hovered {
+            self.style.hover
+        } else {
+            self.style.widget
+        });
+        canvas.fill_rect(square)?;
+        if *value {
+            canvas.set_draw_color(self.style.accent);
+            canvas.fill_rect(Rect::new(
+                square.x() + 3,
+                square.y() + 3,
+                size.saturating_sub(6).max(1),
+                size.saturating_sub(6).max(1),
+            ))?;
+        }
+        self.frame(canvas, square, id)?;
+        let x = square.right() + PADDING;
+        self.text(canvas, x, rect.y(), rect.height(), text, self.style.text)?;
+        Ok(clicked)
+    }
+
+    /// a line of text to type; true when it changed
+    pub fn text_field(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        key: &str,
+        rect: Rect,
+        value: &mut String,
+    ) -> Result<bool, String> {
+        let id = id(key);
+        let (hovered, _) = self.interact(id, rect, true);
+        let mut changed = false;
+        if self.focused(id) {
+            if !self.input.text.is_empty() {
+                value.push_str(&std::mem::take(&mut self.input.text));
+                changed = true;
+            }
+            while self.take_key(&[Keycode::Backspace]).is_some() {
+                changed |= value.pop().is_some();
+            }
+        }
+        canvas.set_draw_color(if hovered {
+            self.style.hover
+        } else {
+            self.style.widget
+        });
+        canvas.fill_rect(rect)?;
+        self.frame(canvas, rect, id)?;
+        // the end of the text when it doesn't fit
+        let room = (rect.width() as i32 - 2 * PADDING).max(0) as u32;
+        let mut shown = value.as_str();
+        while self.text_width(shown) > room && !shown.is_empty() {
+            let mut chars = shown.chars();
+            chars.next();
+            shown = chars.as_str();
+        }
+        let x = rect.x() + PADDING;
+        self.text(canvas, x, rect.y(), rect.height(), shown, self.style.text)?;
+        if self.focused(id) {
+            let caret = x + self.text_width(shown) as i32 + 1;
+            canvas.set_draw_color(self.style.text);
+            canvas.fill_rect(Rect::new(
+                caret,
+                rect.y() + 4,
+                2,
+                rect.height().saturating_sub(8),
+            ))?;
+        }
+        Ok(changed)
+    }
+
+    /// a rectangle showing part of something taller, scrolled with the mouse wheel; `body` draws
+    /// the content with its top at the given y
+    pub fn scroll_area<F>(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        rect: Rect,
+        content_height: u32,
+        scroll: &mut Scroll,
+        mut body: F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(&mut Self, &mut Canvas<Window>, i32) -> Result<(), String>,
+    {
+        if self.hovered(rect) && self.input.wheel != 0 {
+            scroll.offset -= self.input.wheel * ROW as i32;
+        }
+        let max = (content_height as i32 - rect.height() as i32).max(0);
+        scroll.offset = scroll.offset.clamp(0, max);
+
+        canvas.set_clip_rect(rect);
+        self.clip = Some(rect);
+        let drawn = body(self, canvas, rect.y() - scroll.offset);
+        self.clip = None;
+        canvas.set_clip_rect(None);
+        drawn?;
+
+        if max > 0 {
+            let height = rect.height() * rect.height() / content_height.max(1);
+            let top = scroll.offset * (rect.height() as i32 - height as i32) / max;
+            canvas.set_draw_color(self.style.hover);
+            canvas.fill_rect(Rect::new(
+                rect.right() - 4,
+                rect.y() + top,
+                4,
+                height.max(8),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// rows to choose from; true when the selection changed
+    pub fn list(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        key: &str,
+        rect: Rect,
+        items: &[String],
+        state: &mut ListState,
+    ) -> Result<bool, String> {
+        let id = id(key);
+        self.interact(id, rect, true);
+        let mut changed = false;
+        if self.focused(id) && !items.is_empty() {
+            let last = items.len() - 1;
+            let selected = match self.take_key(&[Keycode::Up, Keycode::Down]) {
+                Some(Keycode::Up) => Some(state.selected.map_or(last, |s| s.saturating_sub(1))),
+                Some(_) => Some(state.selected.map_or(0, |s| (s + 1).min(last))),
+                None => state.selected,
+            };
+            if selected != state.selected {
+                state.selected = selected;
+                changed = true;
+                // keep the selected row in sight
+                if let Some(s) = selected {
+                    let top = s as i32 * ROW as i32;
+                    let bottom = top + ROW as i32 - rect.height() as i32;
+                    state.scroll.offset = state.scroll.offset.clamp(bottom, top);
+                }
+            }
+        }
+
+        canvas.set_draw_color(self.style.widget);
+        canvas.fill_rect(rect)?;
+        let ListState { selected, scroll } = state;
+        let height = items.len() as u32 * ROW;
+        self.scroll_area(canvas, rect, height, scroll, |ui, canvas, top| {
+            for (i, item) in items.iter().enumerate() {
+                let row = Rect::new(rect.x(), top + i as i32 * ROW as i32, rect.width(), ROW);
+                if !row.has_intersection(rect) {
+                    continue;
+                }
+                let hovered = ui.hovered(row);
+                if hovered && ui.input.pressed {
+                    changed |= *selected != Some(i);
+                    *selected = Some(i);
+                }
+                if *selected == Some(i) || hovered {
+                    canvas.set_draw_color(if *selected == Some(i) {
+                        ui.style.accent
+                    } else {
+                        ui.style.hover
+                    });
+                    canvas.fill_rect(row)?;
+                }
+                ui.text(canvas, row.x() + PADDING, row.y(), ROW, item, ui.style.text)?;
+            }
+            Ok(())
+        })?;
+        self.frame(canvas, rect, id)?;
+        Ok(changed)
+    }
+
+    /// a bar of menus across `rect`, their items dropping down under them; the chosen item as
+    /// (menu, item)
+    pub fn menu_bar(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        rect: Rect,
+        menus: &[Menu],
+        state: &mut MenuState,
+    ) -> Result<Option<(usize, usize)>, String> {
+        if menus.is_empty() {
+            return Ok(None);
+        }
+        // the keyboard, while a menu is open
+        if let Some(open) = state.open {
+            let items = menus[open].items.len();
+            match self.take_key(&[
+                Keycode::Left,
+                Keycode::Right,
+                Keycode::Up,
+                Keycode::Down,
+                Keycode::Return,
+                Keycode::Escape,
+            ]) {
+                Some(Keycode::Left) => {
+                    state.open = Some((open + menus.len() - 1) % menus.len());
+                    state.item = None;
+                }
+                Some(Keycode::Right) => {
+                    state.open = Some((open + 1) % menus.len());
+                    state.item = None;
+                }
+                Some(Keycode::Up) if items > 0 => {
+                    state.item = Some(state.item.map_or(items - 1, |i| (i + items - 1) % items))
+                }
+                Some(Keycode::Down) if items > 0 => {
+                    state.item = Some(state.item.map_or(0, |i| (i + 1) % items))
+                }
+                Some(Keycode::Return) => {
+                    if let Some(item) = state.item {
+                        *state = MenuState::default();
+                        return Ok(Some((open, item)));
+                    }
+                }
+                Some(Keycode::Escape) => *state = MenuState::default(),
+                _ => {}
+            }
+        }
+
+        canvas.set_draw_color(self.style.panel);
+        canvas.fill_rect(rect)?;
+        let mut chosen = None;
+        let mut x = rect.x();
+        let mut on_menu = false;
+        for (m, menu) in menus.iter().enumerate() {
+            let width = self.text_width(&menu.title) + 2 * PADDING as u32;
+            let title = Rect::new(x, rect.y(), width, rect.height());
+            x += width as i32;
+            let hovered = self.hovered(title);
+            on_menu |= hovered;
+            if hovered && self.input.pressed {
+                self.claimed = true;
+                state.open = if state.open == Some(m) { None } else { Some(m) };
+                state.item = None;
+            } else if hovered && state.open.is_some() && state.open != Some(m) {
+                // sliding along the bar
+                state.open = Some(m);
+                state.item = None;
+            }
+            if state.open == Some(m) || hovered {
+                canvas.set_draw_color(if state.open == Some(m) {
+                    self.style.accent
+                } else {
+                    self.style.hover
+                });
+                canvas.fill_rect(title)?;
+            }
+            self.text(
+                canvas,
+                title.x() + PADDING,
+                title.y(),
+                title.height(),
+                &menu.title,
+                self.style.text,
+            )?;
+            if state.open != Some(m) {
+                continue;
+            }
+
+            let width = menu
+                .items
+                .iter()
+                .map(|item| self.text_width(item))
+                .max()
+                .unwrap_or(0)
+                + 4 * PADDING as u32;
+            let drop = Rect::new(
+                title.x(),
+                title.bottom(),
+                width.max(title.width()),
+                menu.items.len().max(1) as u32 * ROW,
+            );
+            canvas.set_draw_color(self.style.panel);
+            canvas.fill_rect(drop)?;
+            on_menu |= self.hovered(drop);
+            for (i, item) in menu.items.iter().enumerate() {
+                let row = Rect::new(
+                    drop.x(),
+                    drop.y() + i as i32 * ROW as i32,
+                    drop.width(),
+                    ROW,
+                );
+                if self.hovered(row) {
+                    state.item = Some(i);
+                    if self.input.pressed {
+                        self.claimed = true;
+                    }
+                    if self.input.released {
+                        chosen = Some((m, i));
+                    }
+                }
+                if state.item == Some(i) {
+                    canvas.set_draw_color(self.style.accent);
+                    canvas.fill_rect(row)?;
+                }
+                self.text(
+                    canvas,
+                    row.x() + 2 * PADDING,
+                    row.y(),
+                    ROW,
+                    item,
+                    self.style.text,
+                )?;
+            }
+        }
+        if self.input.pressed && !on_menu {
+            // a click elsewhere closes the menu
+            *state = MenuState::default();
+        }
+        if chosen.is_some() {
+            *state = MenuState::default();
+        }
+        Ok(chosen)
+    }
+
+    /// the window as it is now, to show under dialogs and menus
+    fn snapshot(canvas: &Canvas<Window>) -> Result<(Vec<u8>, u32, u32), String> {
+        let (width, height) = canvas.output_size()?;
+        let pixels = canvas.read_pixels(None, PixelFormatEnum::ARGB8888)?;
+        Ok((pixels, width, height))
+    }
+
+    /// run a modal dialog in the middle of the window until `body` returns something, or the
+    /// dialog is closed (Escape, or closing the window) which gives None; the window is shown
+    /// dimmed under it
+    pub fn dialog<R, F>(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        events: &mut EventPump,
+        title: &str,
+        (width, height): (u32, u32),
+        mut body: F,
+    ) -> Result<Option<R>, String>
+    where
+        F: FnMut(&mut Self, &mut Canvas<Window>, Rect) -> Result<Option<R>, String>,
+    {
+        let (pixels, screen_width, screen_height) = Ui::snapshot(canvas)?;
+        let texture_creator = canvas.texture_creator();
+        let mut backdrop = texture_creator
+            .create_texture_streaming(PixelFormatEnum::ARGB8888, screen_width, screen_height)
+            .map_err(|e| e.to_string())?;
+        backdrop
+            .update(None, &pixels, screen_width as usize * 4)
+            .map_err(|e| e.to_string())?;
+        let (width, height) = (width.min(screen_width), height.min(screen_height));
+        let rect = Rect::new(
+            (screen_width - width) as i32 / 2,
+            (screen_height - height) as i32 / 2,
+            width,
+            height,
+        );
+        let content = Rect::new(
+            rect.x() + PADDING,
+            rect.y() + ROW as i32 + PADDING,
+            width.saturating_sub(2 * PADDING as u32),
+            height.saturating_sub(ROW + 2 * PADDING as u32),
+        );
+
+        self.reset();
+        let text_input = canvas.window().subsystem().text_input();
+        text_input.start();
+        let result = loop {
+            let mut closed = false;
+            for event in events.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => closed = true,
+                    event => self.event(&event),
+                }
+            }
+            if closed {
+                break Ok(None);
+            }
+
+            canvas.copy(&backdrop, None, None)?;
+            canvas.set_blend_mode(BlendMode::Blend);
+            canvas.set_draw_color(Color::RGBA(0, 0, 0, 0x80));
+            canvas.fill_rect(None)?;
+            canvas.set_blend_mode(BlendMode::None);
+            canvas.set_draw_color(self.style.panel);
+            canvas.fill_rect(rect)?;
+            canvas.set_draw_color(self.style.accent);
+            let bar = Rect::new(rect.x(), rect.y(), width, ROW);
+            canvas.fill_rect(bar)?;
+            self.text(
+                canvas,
+                bar.x() + PADDING,
+                bar.y(),
+                ROW,
+                title,
+                self.style.text,
+            )?;
+
+            let result = body(self, canvas, content);
+            self.end_frame();
+            canvas.present();
+            match result {
+                Ok(None) => pause(),
+                done => break done,
+            }
+        };
+        text_input.stop();
+        self.reset();
+        result
+    }
+
+    /// the menu bar over the top of the window, the first menu open, until an item is chosen
+    /// (or the menu closed)
+    pub fn menu(
+        &mut self,
+        canvas: &mut Canvas<Window>,
+        events: &mut EventPump,
+        menus: &[Menu],
+    ) -> Result<Option<(usize, usize)>, String> {
+        let (pixels, screen_width, screen_height) = Ui::snapshot(canvas)?;
+        let texture_creator = canvas.texture_creator();
+        let mut backdrop = texture_creator
+            .create_texture_streaming(PixelFormatEnum::ARGB8888, screen_width, screen_height)
+            .map_err(|e| e.to_string())?;
+        backdrop
+            .update(None, &pixels, screen_width as usize * 4)
+            .map_err(|e| e.to_string())?;
+
+        self.reset();
+        let mut state = MenuState {
+            open: Some(0),
+            item: None,
+        };
+        let bar = Rect::new(0, 0, screen_width, ROW);
+        loop {
+            for event in events.poll_iter() {
+                match event {
+                    Event::Quit { .. }
+                    | Event::KeyDown {
+                        keycode: Some(Keycode::F10),
+                        ..
+                    } => return Ok(None),
+                    event => self.event(&event),
+                }
+            }
+            canvas.copy(&backdrop, None, None)?;
+            let chosen = self.menu_bar(canvas, bar, menus, &mut state);
+            self.end_frame();
+            canvas.present();
+            match chosen? {
+                Some(chosen) => return Ok(Some(chosen)),
+                None if state.open.is_none() => return Ok(None),
+                None => pause(),
+            }
+        }
+    }
+}
@@ -0,0 +1,543 @@
+// USI engines: external programs speaking the Universal Shogi Interface on their standard input
+// and output (YaneuraOu, Apery, ...).
+//
+// Engines are registered in the settings with their path, the directory they run in and the
+// values of their options; `discover` runs `usi` to learn which options an engine has:
+//
+//   option name USI_Hash type spin default 256 min 1 max 33554432
+//   option name EvalDir type string default eval
+//   option name BookFile type combo default standard_book.db var no_book var standard_book.db
+//
+// A registered engine then plays through `UsiEngine`, like the built-in opponents.
+
+use crate::impasse;
+use crate::opponent::{Limits, Opponent};
+use crate::rules;
+use crate::settings::Settings;
+use crate::sfen;
+
+use shogai::board::*;
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// time given to an engine to answer `usi` and `isready` (loading evaluation files takes a while)
+const STARTUP_TIME: Duration = Duration::from_secs(30);
+
+/// thinking time per move when neither the limits nor the clock give one
+const DEFAULT_MOVETIME: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionKind {
+    Check {
+        default: bool,
+    },
+    Spin {
+        default: i64,
+        min: i64,
+        max: i64,
+    },
+    Combo {
+        default: String,
+        vars: Vec<String>,
+    },
+    /// an action, without a value
+    Button,
+    String {
+        default: String,
+    },
+    Filename {
+        default: String,
+    },
+}
+
+/// an option announced by an engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsiOption {
+    pub name: String,
+    pub kind: OptionKind,
+}
+
+impl UsiOption {
+    /// the engine's own value, None for buttons
+    pub fn default_value(&self) -> Option<String> {
+        match &self.kind {
+            OptionKind::Check { default } => Some(default.to_string()),
+            OptionKind::Spin { default, .. } => Some(default.to_string()),
+            OptionKind::Combo { default, .. }
+            | OptionKind::String { default }
+            | OptionKind::Filename { default } => Some(default.clone()),
+            OptionKind::Button => None,
+        }
+    }
+}
+
+/// "option name <id> type <type> [default <x>] [min <x>] [max <x>] [var <x>]..."
+impl FromStr for UsiOption {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid option line '{}'", line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first() != Some(&"option") {
+            return Err(invalid());
+        }
+        // values run until the next keyword, names and defaults may have spaces
+        let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+        for word in &words[1..] {
+            match *word {
+                "name" | "type" | "default" | "min" | "max" | "var" => {
+                    fields.push((word, Vec::new()))
+                }
+                _ => fields.last_mut().ok_or_else(invalid)?.1.push(word),
+            }
+        }
+        let field = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, words)| words.join(" "))
+        };
+        let number = |key: &str| -> Result<i64, String> {
+            field(key)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let name = field("name").filter(|name| !name.is_empty());
+        let default = field("default").unwrap_or_default();
+        let kind = match field("type").as_deref() {
+            Some("check") => OptionKind::Check {
+                default: default == "true",
+            },
+            Some("spin") => OptionKind::Spin {
+                default: number("default")?,
+                min: number("min")?,
+                max: number("max")?,
+            },
+            Some("combo") => OptionKind::Combo {
+                default,
+                vars: fields
+                    .iter()
+                    .filter(|(k, _)| *k == "var")
+                    .map(|(_, words)| words.join(" "))
+                    .collect(),
+            },
+            Some("button") => OptionKind::Button,
+            Some("string") => OptionKind::String { default },
+            Some("filename") => OptionKind::Filename { default },
+            _ => return Err(invalid()),
+        };
+        Ok(UsiOption {
+            name: name.ok_or_else(invalid)?,
+            kind,
+        })
+    }
+}
+
+/// option values saved under a name, to switch between setups quickly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub name: String,
+    pub options: Vec<(String, String)>,
+}
+
+/// an engine of the registry
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EngineConfig {
+    pub name: String,
+    pub path: PathBuf,
+    /// where the engine runs, its evaluation files are usually found from there
+    pub dir: Option<PathBuf>,
+    /// options given to the engine, the others keep the engine's defaults; buttons are pressed
+    /// (with an empty value)
+    pub options: Vec<(String, String)>,
+    pub presets: Vec<Preset>,
+}
+
+impl EngineConfig {
+    /// the value given to the option, None to keep the engine's
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// give a value to the option, None to go back to the engine's
+    pub fn set_option(&mut self, name: &str, value: Option<String>) {
+        self.options.retain(|(option, _)| option != name);
+        if let Some(value) = value {
+            self.options.push((name.to_string(), value));
+        }
+    }
+
+    /// save the current options under the name, replacing a preset with the same name
+    pub fn save_preset(&mut self, name: &str) {
+        self.presets.retain(|preset| preset.name != name);
+        self.presets.push(Preset {
+            name: name.to_string(),
+            options: self.options.clone(),
+        });
+    }
+
+    /// use the options of a preset, false if there is none with that name
+    pub fn load_preset(&mut self, name: &str) -> bool {
+        match self.presets.iter().find(|preset| preset.name == name) {
+            Some(preset) => {
+                self.options = preset.options.clone();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// what an engine says about itself
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EngineInfo {
+    pub name: String,
+    pub author: String,
+    pub options: Vec<UsiOption>,
+}
+
+/// "YaneuraOu by yaneurao, 42 options"
+impl fmt::Display for EngineInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.author.is_empty() {
+            write!(f, " by {}", self.author)?;
+        }
+        write!(f, ", {} options", self.options.len())
+    }
+}
+
+/// a running engine, its output read line by line in a thread
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    name: String,
+}
+
+impl Process {
+    fn start(config: &EngineConfig) -> Result<Self, String> {
+        let failed = |e: std::io::Error| format!("{}: {}", config.path.display(), e);
+        let mut command = Command::new(&config.path);
+        if let Some(dir) = &config.dir {
+            command.current_dir(dir);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(failed)?;
+        let stdin = child.stdin.take().ok_or("no standard input")?;
+        let stdout = child.stdout.take().ok_or("no standard output")?;
+        let (sender, lines) = channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) if sender.send(line.trim_end().to_string()).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Ok(Process {
+            child,
+            stdin,
+            lines,
+            name: config.name.clone(),
+        })
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("{}: {}", self.name, e))
+    }
+
+    /// the lines written until one starting with `token` (included)
+    fn read_until(
+        &mut self,
+        token: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<String>, String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut lines = Vec::new();
+        loop {
+            let line = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    self.lines.recv_timeout(left).map_err(|e| match e {
+                        RecvTimeoutError::Timeout => {
+                            format!("{} did not answer '{}' in time", self.name, token)
+                        }
+                        RecvTimeoutError::Disconnected => format!("{} stopped", self.name),
+                    })?
+                }
+                None => self
+                    .lines
+                    .recv()
+                    .map_err(|_| format!("{} stopped", self.name))?,
+            };
+            let done = line.split_whitespace().next() == Some(token);
+            lines.push(line);
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // give it a moment to leave by itself
+        for _ in 0..10 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// start the engine and ask for its name and options
+pub fn discover(config: &EngineConfig) -> Result<EngineInfo, String> {
+    let mut process = Process::start(config)?;
+    process.send("usi")?;
+    let mut info = EngineInfo::default();
+    for line in process.read_until("usiok", Some(STARTUP_TIME))? {
+        if let Some(name) = line.strip_prefix("id name ") {
+            info.name = name.to_string();
+        } else if let Some(author) = line.strip_prefix("id author ") {
+            info.author = author.to_string();
+        } else if line.starts_with("option ") {
+            info.options.push(line.parse()?);
+        }
+    }
+    Ok(info)
+}
+
+/// add the engine to the registry of the settings (or update its path), named after what it says
+/// about itself
+pub fn register(path: &Path) -> Result<(), String> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut config = EngineConfig {
+        name: path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        dir: path.parent().map(Path::to_path_buf),
+        path,
+        ..EngineConfig::default()
+    };
+    let info = discover(&config)?;
+    // (dots and equal signs would get in the way in the settings file)
+    let name: String = info
+        .name
+        .chars()
+        .map(|c| if c == '.' || c == '=' { '_' } else { c })
+        .collect();
+    if !name.trim().is_empty() {
+        config.name = name.trim().to_string();
+    }
+
+    let mut settings = Settings::load();
+    match settings
+        .usi_engines
+        .iter_mut()
+        .find(|engine| engine.name == config.name)
+    {
+        Some(known) => {
+            known.path = config.path.clone();
+            known.dir = config.dir.clone();
+        }
+        None => settings.usi_engines.push(config.clone()),
+    }
+    settings.save()?;
+    println!("registered {} as {}", info, config.name);
+    Ok(())
+}
+
+/// a registered engine as an opponent
+pub struct UsiEngine {
+    name: String,
+    process: Process,
+    limits: Limits,
+    budget: Option<Duration>,
+}
+
+impl UsiEngine {
+    /// start the engine with its options and wait until it's ready to play
+    pub fn start(config: &EngineConfig, limits: Limits) -> Result<Self, String> {
+        let mut process = Process::start(config)?;
+        process.send("usi")?;
+        process.read_until("usiok", Some(STARTUP_TIME))?;
+        for (name, value) in &config.options {
+            if value.is_empty() {
+                process.send(&format!("setoption name {}", name))?;
+            } else {
+                process.send(&format!("setoption name {} value {}", name, value))?;
+            }
+        }
+        process.send("isready")?;
+        process.read_until("readyok", Some(STARTUP_TIME))?;
+        process.send("usinewgame")?;
+        Ok(UsiEngine {
+            name: config.name.clone(),
+            process,
+            limits,
+            budget: None,
+        })
+    }
+
+    fn go(&mut self, board: &Board) -> Result<Option<String>, String> {
+        self.process
+            .send(&format!("position sfen {}", sfen::to_sfen(board, 1)))?;
+        let go = match (self.limits.nodes, self.limits.movetime, self.budget) {
+            (Some(nodes), None, None) => format!("go nodes {}", nodes),
+            (_, movetime, budget) => {
+                let time = match (movetime, budget) {
+                    (Some(movetime), Some(budget)) => movetime.min(budget),
+                    (movetime, budget) => movetime.or(budget).unwrap_or(DEFAULT_MOVETIME),
+                };
+                format!("go btime 0 wtime 0 byoyomi {}", time.as_millis())
+            }
+        };
+        self.process.send(&go)?;
+        let lines = self.process.read_until("bestmove", None)?;
+        let best = lines
+            .last()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .unwrap_or("resign");
+        match best {
+            // (a resigning engine is treated as having no move)
+            "resign" => Ok(None),
+            "win" => Ok(Some(String::from(impasse::DECLARE))),
+            usi => {
+                let mv = sfen::from_usi(usi, board)?;
+                Ok(Some(rules::normalize(board, &mv)))
+            }
+        }
+    }
+}
+
+impl Opponent for UsiEngine {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn choose_move(&mut self, board: &Board) -> Option<String> {
+        board.iter_moves().next()?;
+        match self.go(board) {
+            Ok(mv) => mv,
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.budget = budget;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn option_lines() {
+        let spin: UsiOption = "option name USI_Hash type spin default 256 min 1 max 33554432"
+            .parse()
+            .unwrap();
+        assert_eq!(spin.name, "USI_Hash");
+        assert_eq!(
+            spin.kind,
+            OptionKind::Spin {
+                default: 256,
+                min: 1,
+                max: 33554432
+            }
+        );
+        let combo: UsiOption =
+            "option name Book File type combo default no book var no book var standard.db"
+                .parse()
+                .unwrap();
+        assert_eq!(combo.name, "Book File");
+        assert_eq!(
+            combo.kind,
+            OptionKind::Combo {
+                default: String::from("no book"),
+                vars: vec![String::from("no book"), String::from("standard.db")]
+            }
+        );
+        let button: UsiOption = "option name Clear Hash type button".parse().unwrap();
+        assert_eq!(button.default_value(), None);
+        assert!("option name Threads type spin default 4"
+            .parse::<UsiOption>()
+            .is_err());
+        assert!("option type check default true"
+            .parse::<UsiOption>()
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mock_engine() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("shogui-usi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("engine.sh");
+        let script = "#!/bin/sh\n\
+            while read line; do\n\
+              case \"$line\" in\n\
+                usi) echo 'id name Mock'; echo 'id author Nobody';\n\
+                     echo 'option name Hash type spin default 16 min 1 max 1024';\n\
+                     echo 'option name Ponder type check default false'; echo usiok ;;\n\
+                isready) echo readyok ;;\n\
+                go*) echo 'info depth 1'; echo 'bestmove 7g7f' ;;\n\
+                quit) exit 0 ;;\n\
+              esac\n\
+            done\n";
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = EngineConfig {
+            name: String::from("mock"),
+            path,
+            dir: Some(dir.clone()),
+            ..EngineConfig::default()
+        };
+        let info = discover(&config).unwrap();
+        assert_eq!(info.to_string(), "Mock by Nobody, 2 options");
+
+        config.set_option("Hash", Some(String::from("64")));
+        config.save_preset("small");
+        config.set_option("Hash", None);
+        assert!(config.load_preset("small"));
+        assert_eq!(config.option("Hash"), Some("64"));
+
+        let mut engine = UsiEngine::start(&config, Limits::default()).unwrap();
+        assert_eq!(
+            engine.choose_move(&Board::new()),
+            Some(String::from("P3c-3d"))
+        );
+        drop(engine);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,128 @@
+// Golden image tests: positions drawn by the headless renderer, compared with the PNGs of
+// tests/golden. A missing image is written instead (all of them with SHOGUI_BLESS=1), to be looked
+// at and committed; on a mismatch, the picture drawn is saved in the temporary directory.
+
+use sdl2::image::LoadSurface;
+use sdl2::render::BlendMode;
+use sdl2::surface::Surface;
+
+use shogui::clock::{Clock, TimeControl};
+use shogui::game::{GameState, Target};
+use shogui::handicap::Handicap;
+use shogui::headless;
+use shogui::impasse;
+use shogui::record::GameRecord;
+use shogui::settings::Settings;
+use shogui::sfen;
+
+use std::env;
+use std::path::Path;
+
+/// largest difference allowed on a colour channel, for blending done a bit differently
+const TOLERANCE: u8 = 2;
+
+fn state(position: &str) -> GameState {
+    let (board, _) = sfen::from_sfen(position).unwrap();
+    let record = GameRecord::from_position(board.clone(), "sente", "gote");
+    let human = board.get_color();
+    let clock = Clock::new(TimeControl::default());
+    GameState::new(board, human, record, clock, impasse::Rule::default())
+}
+
+fn square(usi: &str) -> Target {
+    Target::Square(sfen::parse_usi_square(usi).unwrap())
+}
+
+fn check(name: &str, state: &GameState, settings: &Settings) {
+    let drawn = headless::render(state, settings).unwrap();
+    let path = Path::new("tests/golden").join(format!("{}.png", name));
+    if env::var_os("SHOGUI_BLESS").is_some() || !path.exists() {
+        headless::save_png(&drawn, &path).unwrap();
+        eprintln!(
+            "{} written, look at it before committing it",
+            path.display()
+        );
+        return;
+    }
+
+    let mut golden = Surface::from_file(&path).unwrap();
+    assert_eq!(
+        (golden.width(), golden.height()),
+        (drawn.width(), drawn.height()),
+        "{} has another size",
+        path.display()
+    );
+    // the golden image in the format drawn
+    let mut expected = Surface::new(drawn.width(), drawn.height(), headless::FORMAT).unwrap();
+    golden.set_blend_mode(BlendMode::None).unwrap();
+    golden.blit(None, &mut expected, None).unwrap();
+    let different = drawn.with_lock(|drawn| {
+        expected.with_lock(|expected| {
+            drawn
+                .chunks(4)
+                .zip(expected.chunks(4))
+                .filter(|(a, b)| {
+                    a.iter()
+                        .zip(b.iter())
+                        .any(|(a, b)| a.abs_diff(*b) > TOLERANCE)
+                })
+                .count()
+        })
+    });
+    if different > 0 {
+        let actual = env::temp_dir().join(format!("{}.png", name));
+        headless::save_png(&drawn, &actual).unwrap();
+        panic!(
+            "{} pixels differ from {}, see {}",
+            different,
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn golden_even_start() {
+    check(
+        "even_start",
+        &state(&Handicap::Even.sfen()),
+        &Settings::default(),
+    );
+}
+
+#[test]
+fn golden_selection() {
+    let mut state = state(&Handicap::Even.sfen());
+    state.click(square("2h"), || false).unwrap();
+    check("selection", &state, &Settings::default());
+}
+
+#[test]
+fn golden_last_move() {
+    let mut state = state(&Handicap::Even.sfen());
+    state.click(square("7g"), || false).unwrap();
+    state.click(square("7f"), || false).unwrap();
+    check("last_move", &state, &Settings::default());
+}
+
+#[test]
+fn golden_hands() {
+    // after an exchange of bishops, and a few pawns taken
+    let position = "lnsgk2nl/1r4gs1/p1pppp1pp/6p2/1p5P1/2P6/PP1PPPP1P/7R1/LNSGKGSNL b B2Pbp 22";
+    check("hands", &state(position), &Settings::default());
+    let mut flipped = Settings::default();
+    flipped.flip = true;
+    check("hands_flipped", &state(position), &flipped);
+}
+
+#[test]
+fn golden_checkmate() {
+    let mut state = state("4k4/9/4P4/9/9/9/9/9/4K4 b G 1");
+    state
+        .click(Target::Hand(shogai::piece::PieceType::Gold), || false)
+        .unwrap();
+    state.click(square("5b"), || false).unwrap();
+    let result = state.judge().unwrap();
+    state.end(result);
+    check("checkmate", &state, &Settings::default());
+}
@@ -0,0 +1,23 @@
+// Recorded games played again through the input handler, without a window: every script of
+// tests/replay (written by --record-input, or by hand) has to play to its end and find the
+// positions and moves it expects.
+
+use shogui::replay::Script;
+
+use std::fs;
+
+#[test]
+fn recorded_games_replay() {
+    let mut scripts: Vec<_> = fs::read_dir("tests/replay")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    for path in scripts {
+        if let Err(e) = Script::load(&path).and_then(|script| script.replay()) {
+            panic!("{}: {}", path.display(), e);
+        }
+    }
+}